@@ -0,0 +1,33 @@
+//! Aggregate documents, as written by `batch-extract -a` and `snapshot`: one object keyed by each
+//! file's path, holding that file's tags
+
+use crate::{paths, Error, StrResult};
+use json::JsonValue;
+use std::path::PathBuf;
+
+/// Whether a document is an aggregate rather than an album document or a single file's tags
+pub fn is_aggregate(doc: &JsonValue) -> bool {
+    doc.is_object()
+        && !doc.has_key("tracks")
+        && !doc.is_empty()
+        && doc.entries().all(|(_, entry)| entry.is_object())
+}
+
+/// The file each entry describes and the tags to write to it. Paths are taken as relative to the
+/// current directory, or the root if one was given, as they were when extracted.
+pub fn plan(doc: &JsonValue) -> Vec<(PathBuf, StrResult<JsonValue>)> {
+    doc.entries()
+        .map(|(key, entry)| {
+            let path = entry["_raw_path"]
+                .as_str()
+                .and_then(paths::path_from_raw)
+                .unwrap_or_else(|| PathBuf::from(key));
+            let result = if path.is_file() {
+                Ok(entry.clone())
+            } else {
+                Err(Error::Io("no such file".to_owned()))
+            };
+            (path, result)
+        })
+        .collect()
+}
@@ -0,0 +1,112 @@
+//! Album documents: one JSON object per directory, with the fields every track shares stored once
+//! at the top level and the rest in a `tracks` array
+
+use crate::{paths, position, Error, StrResult};
+use id3::TagLike;
+use json::JsonValue;
+use std::path::{Path, PathBuf};
+
+/// The name album documents are written under in each directory
+pub const ALBUM_FILE: &str = "album.json";
+
+/// Fields that identify a single track, so are never hoisted to album level even if they happen to match
+const PER_TRACK_FIELDS: [&str; 5] = ["TIT2", "TRCK", "TLEN", "TSRC", "TIT3"];
+
+/// Builds the album document for the tracks of one directory
+pub fn build_album(mut tracks: Vec<(PathBuf, JsonValue)>) -> JsonValue {
+    tracks.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut album = JsonValue::new_object();
+    if let Some((_, first)) = tracks.first() {
+        for (key, value) in first.entries() {
+            let shared = !key.starts_with('_')
+                && !PER_TRACK_FIELDS.contains(&key)
+                && tracks.iter().all(|(_, t)| t[key] == *value);
+            if shared {
+                album[key] = value.clone();
+            }
+        }
+    }
+
+    let mut list = JsonValue::new_array();
+    for (path, mut json) in tracks {
+        for (key, _) in album.entries() {
+            json.remove(key);
+        }
+        let name = path.file_name().map(Path::new).unwrap_or(&path);
+        let (file, raw_path) = paths::json_key(name);
+        json["_file"] = file.into();
+        if let Some(raw_path) = raw_path {
+            json["_raw_path"] = raw_path;
+        }
+        list.push(json).expect("list is an array");
+    }
+    album["tracks"] = list;
+    album
+}
+
+/// Finds the audio file a track entry describes: the `_file` it names, otherwise the file in the
+/// directory whose current TRCK has the same number
+fn find_track_file(
+    dir: &Path,
+    track: &JsonValue,
+    candidates: &[(PathBuf, Option<u32>)],
+) -> StrResult<PathBuf> {
+    if let Some(file) = track["_file"].as_str() {
+        let path = dir.join(file);
+        if path.is_file() {
+            return Ok(path);
+        }
+    }
+    let Some(number) = position::from_json(&track["TRCK"]).ok().map(|p| p.number) else {
+        return Err(Error::Other(
+            "track has neither an existing _file nor a TRCK to match on".to_owned(),
+        ));
+    };
+    match candidates.iter().find(|(_, n)| *n == Some(number)) {
+        Some((path, _)) => Ok(path.clone()),
+        None => Err(Error::Other(format!(
+            "no file in the directory has track number {number}"
+        ))),
+    }
+}
+
+/// Resolves each track of an album document to its file in `dir`, giving the complete tag
+/// document (album fields overlaid with the track's own) to write to it
+pub fn plan_album(dir: &Path, doc: &JsonValue) -> Vec<(PathBuf, StrResult<JsonValue>)> {
+    if !doc["tracks"].is_array() {
+        return vec![(
+            dir.join(ALBUM_FILE),
+            Err(Error::Parse("no tracks array found".to_owned())),
+        )];
+    }
+    let candidates: Vec<(PathBuf, Option<u32>)> =
+        paths::collect_audio_files(&[dir.to_owned()], true)
+            .into_iter()
+            .filter(|p| p.parent() == Some(dir))
+            .map(|p| {
+                let number = crate::read_tag(&p).ok().and_then(|t| t.track());
+                (p, number)
+            })
+            .collect();
+
+    let mut plan = vec![];
+    for (i, track) in doc["tracks"].members().enumerate() {
+        let path = match find_track_file(dir, track, &candidates) {
+            Ok(p) => p,
+            Err(e) => {
+                plan.push((dir.join(format!("<track {}>", i + 1)), Err(e)));
+                continue;
+            }
+        };
+        let mut merged = JsonValue::new_object();
+        for (key, value) in doc.entries().filter(|(k, _)| *k != "tracks") {
+            merged[key] = value.clone();
+        }
+        for (key, value) in track.entries() {
+            merged[key] = value.clone();
+        }
+        plan.push((path, Ok(merged)));
+    }
+    plan
+}
@@ -0,0 +1,381 @@
+//! APEv2 tags, as used by Monkey's Audio and Musepack. The tag sits at the end of the file, before
+//! any ID3v1 tag, as a list of named items bracketed by a header and a footer.
+
+use crate::backend::Backend;
+use crate::sniff::{self, Format};
+use crate::{image, splice, Error, StrResult};
+use id3::frame::{Comment, ExtendedText, Lyrics, Picture, PictureType};
+use id3::{Content, Frame, Tag, TagLike};
+use json::JsonValue;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const PREAMBLE: &[u8; 8] = b"APETAGEX";
+const VERSION: u32 = 2000;
+const HEADER_LEN: u64 = 32;
+const ID3V1_LEN: u64 = 128;
+/// Footer and header flags: the tag has a header, and this is the header
+const HAS_HEADER: u32 = 1 << 31;
+const IS_HEADER: u32 = 1 << 29;
+/// An item's value type, in bits 1 and 2 of its flags
+const BINARY: u32 = 1 << 1;
+const TYPE_MASK: u32 = 0b110;
+
+/// Item keys for the frames with a conventional equivalent. Keys are matched ignoring case.
+const ITEMS: &[(&str, &str)] = &[
+    ("TIT1", "Grouping"),
+    ("TIT2", "Title"),
+    ("TIT3", "Subtitle"),
+    ("TPE1", "Artist"),
+    ("TPE2", "Album Artist"),
+    ("TPE3", "Conductor"),
+    ("TALB", "Album"),
+    ("TRCK", "Track"),
+    ("TPOS", "Disc"),
+    ("TDRC", "Year"),
+    ("TCON", "Genre"),
+    ("TCOM", "Composer"),
+    ("TEXT", "Lyricist"),
+    ("TPUB", "Publisher"),
+    ("TPUB", "Label"),
+    ("TCOP", "Copyright"),
+    ("TBPM", "BPM"),
+    ("TLAN", "Language"),
+    ("TSRC", "ISRC"),
+    ("TMED", "Media"),
+    ("TSSE", "Encoder"),
+    ("TENC", "EncodedBy"),
+    ("TSOA", "AlbumSort"),
+    ("TSOP", "ArtistSort"),
+    ("TSOT", "TitleSort"),
+    ("COMM", "Comment"),
+    ("USLT", "Lyrics"),
+];
+
+/// Binary items holding cover art, as a file name and a NUL before the image
+const PICTURES: [(&str, PictureType); 2] = [
+    ("Cover Art (Front)", PictureType::CoverFront),
+    ("Cover Art (Back)", PictureType::CoverBack),
+];
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap_or_default())
+}
+
+/// Where the tag lies: its start and length including any header, and its items
+struct Found {
+    start: u64,
+    len: u64,
+    items: Vec<u8>,
+    count: u32,
+}
+
+/// The end of the audio: the file's length, less any ID3v1 tag
+fn audio_end(file: &mut File) -> std::io::Result<u64> {
+    let len = file.metadata()?.len();
+    if len >= ID3V1_LEN {
+        let mut marker = [0u8; 3];
+        file.seek(SeekFrom::Start(len - ID3V1_LEN))?;
+        file.read_exact(&mut marker)?;
+        if &marker == b"TAG" {
+            return Ok(len - ID3V1_LEN);
+        }
+    }
+    Ok(len)
+}
+
+fn find_tag(path: &Path) -> StrResult<(u64, Option<Found>)> {
+    let read_error =
+        |e: std::io::Error| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy()));
+    let mut file = File::open(path).map_err(read_error)?;
+    let end = audio_end(&mut file).map_err(read_error)?;
+    if end < HEADER_LEN {
+        return Ok((end, None));
+    }
+    let mut footer = [0u8; HEADER_LEN as usize];
+    file.seek(SeekFrom::Start(end - HEADER_LEN))
+        .and_then(|_| file.read_exact(&mut footer))
+        .map_err(read_error)?;
+    if &footer[..8] != PREAMBLE {
+        return Ok((end, None));
+    }
+    // The size covers the items and footer but not the header
+    let size = u64::from(le_u32(&footer[12..]));
+    let count = le_u32(&footer[16..]);
+    let flags = le_u32(&footer[20..]);
+    let header = if flags & HAS_HEADER != 0 {
+        HEADER_LEN
+    } else {
+        0
+    };
+    if size < HEADER_LEN || size + header > end {
+        return Err(Error::Parse(
+            "APE tag size runs past the start of the file".to_owned(),
+        ));
+    }
+    let mut items = vec![0u8; (size - HEADER_LEN) as usize];
+    file.seek(SeekFrom::Start(end - size))
+        .and_then(|_| file.read_exact(&mut items))
+        .map_err(read_error)?;
+    let found = Found {
+        start: end - size - header,
+        len: size + header,
+        items,
+        count,
+    };
+    Ok((end, Some(found)))
+}
+
+/// The items' keys, flags and values
+fn parse_items(found: &Found) -> StrResult<Vec<(String, u32, Vec<u8>)>> {
+    let truncated = || "Truncated APE tag item".to_owned();
+    let data = &found.items;
+    let mut items = vec![];
+    let mut pos = 0;
+    for _ in 0..found.count {
+        let header = data.get(pos..pos + 8).ok_or_else(truncated)?;
+        let len = le_u32(header) as usize;
+        let flags = le_u32(&header[4..]);
+        let key_len = data[pos + 8..]
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(truncated)?;
+        let key = String::from_utf8_lossy(&data[pos + 8..pos + 8 + key_len]).into_owned();
+        let start = pos + 8 + key_len + 1;
+        let value = data.get(start..start + len).ok_or_else(truncated)?;
+        items.push((key, flags, value.to_vec()));
+        pos = start + len;
+    }
+    Ok(items)
+}
+
+fn read_tag(path: &Path) -> StrResult<Tag> {
+    let mut tag = Tag::new();
+    let Some(found) = find_tag(path)?.1 else {
+        return Ok(tag);
+    };
+    for (key, flags, value) in parse_items(&found)? {
+        if let Some((_, picture_type)) = PICTURES.iter().find(|(k, _)| key.eq_ignore_ascii_case(k))
+        {
+            let split = value.iter().position(|b| *b == 0).map_or(0, |i| i + 1);
+            let data = value[split..].to_vec();
+            tag.add_frame(Picture {
+                mime_type: image::mime_type(&data).unwrap_or("image/jpeg").to_owned(),
+                picture_type: *picture_type,
+                description: String::from_utf8_lossy(&value[..split.saturating_sub(1)])
+                    .into_owned(),
+                data,
+            });
+            continue;
+        }
+        if flags & TYPE_MASK != 0 {
+            continue;
+        }
+        // Multiple values are separated by NULs, as in ID3v2.4 text frames
+        let text = String::from_utf8_lossy(&value).into_owned();
+        let id = ITEMS
+            .iter()
+            .find(|(_, k)| key.eq_ignore_ascii_case(k))
+            .map(|(id, _)| *id);
+        let content = match id {
+            Some("COMM") => Content::Comment(Comment {
+                lang: "XXX".to_owned(),
+                description: String::new(),
+                text,
+            }),
+            Some("USLT") => Content::Lyrics(Lyrics {
+                lang: "XXX".to_owned(),
+                description: String::new(),
+                text,
+            }),
+            Some(_) => Content::Text(text),
+            None => Content::ExtendedText(ExtendedText {
+                description: key,
+                value: text,
+            }),
+        };
+        tag.add_frame(Frame::with_content(id.unwrap_or("TXXX"), content));
+    }
+    Ok(tag)
+}
+
+fn item(out: &mut Vec<u8>, key: &str, flags: u32, value: &[u8]) {
+    out.extend((value.len() as u32).to_le_bytes());
+    out.extend(flags.to_le_bytes());
+    out.extend(key.as_bytes());
+    out.push(0);
+    out.extend(value);
+}
+
+fn header(size: u32, count: u32, flags: u32) -> Vec<u8> {
+    let mut out = PREAMBLE.to_vec();
+    for value in [VERSION, size, count, flags] {
+        out.extend(value.to_le_bytes());
+    }
+    out.extend([0; 8]);
+    out
+}
+
+/// The tag, with header and footer, for the frames APEv2 can hold. Others are reported and skipped.
+fn encode(path: &Path, tag: &Tag) -> Vec<u8> {
+    let mut items = vec![];
+    let mut count = 0;
+    let mut skipped = vec![];
+    for frame in tag.frames() {
+        let id = frame.id();
+        let key = ITEMS.iter().find(|(i, _)| *i == id).map(|(_, k)| *k);
+        match (frame.content(), key) {
+            (Content::Text(text), Some(key)) => item(&mut items, key, 0, text.as_bytes()),
+            (Content::Comment(c), Some(key)) => item(&mut items, key, 0, c.text.as_bytes()),
+            (Content::Lyrics(l), Some(key)) => item(&mut items, key, 0, l.text.as_bytes()),
+            (Content::ExtendedText(t), _) => {
+                item(&mut items, &t.description, 0, t.value.as_bytes())
+            }
+            (Content::Picture(p), _) => {
+                let key = PICTURES
+                    .iter()
+                    .find(|(_, t)| *t == p.picture_type)
+                    .map_or(PICTURES[0].0, |(k, _)| *k);
+                let mut value = p.description.as_bytes().to_vec();
+                value.push(0);
+                value.extend(&p.data);
+                item(&mut items, key, BINARY, &value);
+            }
+            _ => {
+                skipped.push(id.to_owned());
+                continue;
+            }
+        }
+        count += 1;
+    }
+    if !skipped.is_empty() {
+        eprintln!(
+            "{}: APEv2 has no item for {}, which were not written",
+            path.to_string_lossy(),
+            skipped.join(", ")
+        );
+    }
+    let size = (items.len() as u64 + HEADER_LEN) as u32;
+    let mut out = header(size, count, HAS_HEADER | IS_HEADER);
+    out.extend(items);
+    out.extend(header(size, count, HAS_HEADER));
+    out
+}
+
+/// Where the new tag goes, how many bytes it replaces there, and the tag
+fn plan(path: &Path, tag: &Tag) -> StrResult<(u64, u64, Vec<u8>)> {
+    let (end, found) = find_tag(path)?;
+    let data = encode(path, tag);
+    Ok(match found {
+        Some(found) => (found.start, found.len, data),
+        None => (end, 0, data),
+    })
+}
+
+pub struct Ape;
+
+impl Backend for Ape {
+    fn handles(&self, format: Format) -> bool {
+        matches!(format, Format::MonkeysAudio | Format::Musepack)
+    }
+
+    fn read_tag(&self, path: &Path) -> StrResult<Tag> {
+        read_tag(path)
+    }
+
+    fn write_tag(
+        &self,
+        path: &Path,
+        file: &mut File,
+        tag: &Tag,
+        buffer_size: usize,
+    ) -> StrResult<()> {
+        let (start, old_len, data) = plan(path, tag)?;
+        splice::splice_at(file, start, old_len, &data, buffer_size)
+            .map_err(|e| Error::Io(format!("Could not write tags: {e}")))
+    }
+
+    /// Only the format, as the audio's own headers aren't parsed
+    fn properties(&self, path: &Path) -> StrResult<JsonValue> {
+        let mut json = JsonValue::new_object();
+        json["format"] = if sniff::format(path) == Some(Format::Musepack) {
+            "musepack"
+        } else {
+            "monkeys_audio"
+        }
+        .into();
+        Ok(json)
+    }
+
+    fn tag_sizes(&self, path: &Path, tag: &Tag) -> StrResult<(u64, u64)> {
+        let (_, old_len, data) = plan(path, tag)?;
+        Ok((old_len, data.len() as u64))
+    }
+
+    fn audio_span(&self, path: &Path) -> StrResult<(u64, u64)> {
+        let (end, found) = find_tag(path)?;
+        Ok((0, found.map_or(end, |f| f.start)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::path::PathBuf;
+
+    /// A Monkey's Audio file with no tag but an ID3v1 one, which the APE tag must go before
+    fn ape() -> Vec<u8> {
+        let mut data = b"MAC \x96\x0f\0\0".to_vec();
+        data.extend([0x55; 200]);
+        let mut id3v1 = b"TAG".to_vec();
+        id3v1.resize(ID3V1_LEN as usize, 0);
+        data.extend(id3v1);
+        data
+    }
+
+    fn tagged(name: &str) -> (PathBuf, Vec<u8>) {
+        let path = testing::scratch_dir(name).join("t.ape");
+        std::fs::write(&path, ape()).unwrap();
+        crate::write_tag(&path, &testing::tag(), &crate::WriteOpts::default()).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        (path, data)
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        let (path, data) = tagged("ape-round-trip");
+        testing::assert_round_trip(&crate::read_tag(&path).unwrap());
+        assert!(data.starts_with(&ape()[..208]));
+        assert!(data.ends_with(&ape()[208..]));
+        assert_eq!(Ape.audio_span(&path).unwrap(), (0, 208));
+
+        // Rewriting replaces the tag rather than adding another
+        crate::write_tag(&path, &testing::tag(), &crate::WriteOpts::default()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+    }
+
+    #[test]
+    fn damaged_files_are_errors() {
+        let (path, data) = tagged("ape-damaged");
+        let footer = data.len() - ID3V1_LEN as usize - HEADER_LEN as usize;
+        let with = |pos: usize, value: u32| {
+            let mut damaged = data.clone();
+            damaged[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+            std::fs::write(&path, damaged).unwrap();
+            read_tag(&path)
+        };
+        // A size running past the start of the file, or more items than the tag holds
+        assert!(matches!(with(footer + 12, u32::MAX), Err(Error::Parse(_))));
+        assert!(with(footer + 12, 8).is_err());
+        assert!(with(footer + 16, 100).is_err());
+
+        testing::each_truncation(&path, &data, data.len(), |path, _| {
+            let _ = read_tag(path);
+        });
+        testing::each_corruption(&path, &data, data.len(), |path| {
+            let _ = read_tag(path);
+        });
+    }
+}
@@ -0,0 +1,165 @@
+//! An append-only log of the tags seen at each extraction, and a viewer for when they changed
+//!
+//! Each line of the log is a JSON object with the file's `path`, a `hash` of its frames and the
+//! `time` of the run in seconds since the Unix epoch.
+
+use crate::{lock, manifest, paths, sha256, Error, StrResult};
+use json::JsonValue;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Fingerprints the frames of an extracted tag, independent of the order they're stored in and
+/// ignoring informational keys
+pub fn tag_hash(json: &JsonValue) -> String {
+    let frames: BTreeMap<&str, String> = json
+        .entries()
+        .filter(|(key, _)| !key.starts_with('_'))
+        .map(|(key, value)| (key, json::stringify(value.clone())))
+        .collect();
+    let mut canonical = String::new();
+    for (key, value) in frames {
+        canonical.push_str(&json::stringify(key));
+        canonical.push(':');
+        canonical.push_str(&value);
+        canonical.push('\n');
+    }
+    sha256::hex_digest(canonical.as_bytes())
+}
+
+/// The name a file is logged under, absolute so that runs from different directories agree
+fn log_key(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    paths::json_key(&path).0
+}
+
+/// Appends a record of the file's current tag to the log
+pub fn append(log: &Path, path: &Path, json: &JsonValue) -> StrResult<()> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut record = JsonValue::new_object();
+    record["path"] = log_key(path).into();
+    record["hash"] = tag_hash(json).into();
+    record["time"] = time.into();
+
+    let change = manifest::Change::begin(log);
+    let mut file = match OpenOptions::new().create(true).append(true).open(log) {
+        Ok(f) => f,
+        Err(e) => Err(Error::Io(format!(
+            "Cannot open {}: {e}",
+            log.to_string_lossy()
+        )))?,
+    };
+    // Keep lines from concurrent runs from interleaving
+    lock::lock_exclusive(&file, log)?;
+    match writeln!(file, "{}", json::stringify(record)) {
+        Ok(()) => {
+            change.finish();
+            Ok(())
+        }
+        Err(e) => Err(Error::Io(format!(
+            "Cannot write to {}: {e}",
+            log.to_string_lossy()
+        ))),
+    }
+}
+
+/// Formats a Unix time as a UTC date and time
+fn format_time(secs: u64) -> String {
+    // Days to civil date, after Howard Hinnant's algorithm
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let time = secs % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[derive(Default)]
+struct FileHistory {
+    first_seen: u64,
+    last_seen: u64,
+    last_changed: Option<u64>,
+    changes: usize,
+    runs: usize,
+    hash: String,
+}
+
+/// Shows, for each file in the log (or only those given), when its tags were last seen to change
+pub fn history(log: &Path, files: &[PathBuf]) -> StrResult<()> {
+    let text = match std::fs::read_to_string(log) {
+        Ok(t) => t,
+        Err(e) => Err(Error::Io(format!(
+            "Cannot read {}: {e}",
+            log.to_string_lossy()
+        )))?,
+    };
+    let wanted: Vec<String> = files.iter().map(|f| log_key(f)).collect();
+
+    let mut histories: BTreeMap<String, FileHistory> = BTreeMap::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = match json::parse(line) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("Skipping line {} of the log: {e}", number + 1);
+                continue;
+            }
+        };
+        let (Some(path), Some(hash), Some(time)) = (
+            record["path"].as_str(),
+            record["hash"].as_str(),
+            record["time"].as_u64(),
+        ) else {
+            eprintln!("Skipping line {} of the log: incomplete record", number + 1);
+            continue;
+        };
+        if !wanted.is_empty() && !wanted.iter().any(|w| w == path) {
+            continue;
+        }
+        let entry = histories.entry(path.to_owned()).or_default();
+        if entry.runs == 0 {
+            entry.first_seen = time;
+        } else if entry.hash != hash {
+            entry.changes += 1;
+            entry.last_changed = Some(time);
+        }
+        entry.runs += 1;
+        entry.last_seen = time;
+        entry.hash = hash.to_owned();
+    }
+
+    for (path, h) in histories {
+        match h.last_changed {
+            Some(changed) => println!(
+                "{path}: last changed {} ({} changes over {} runs)",
+                format_time(changed),
+                h.changes,
+                h.runs
+            ),
+            None => println!(
+                "{path}: unchanged since {} ({} runs, last {})",
+                format_time(h.first_seen),
+                h.runs,
+                format_time(h.last_seen)
+            ),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! Artist names rewritten to the canonical forms of an authority list, so one composer isn't
+//! filed under "Tchaikovsky", "P. I. Tchaikovsky" and "Pyotr Tchaikovsky".
+//!
+//! The list is a JSON object mapping each canonical name to an array of its aliases:
+//!
+//! ```json
+//! { "Tchaikovsky, Pyotr Ilyich": ["Tchaikovsky", "P. I. Tchaikovsky"] }
+//! ```
+//!
+//! or a CSV file with a line per name, the canonical form first and its aliases after it, quoting
+//! any field with a comma in it. Names are matched ignoring case and whitespace.
+
+use crate::normalize::normalize_text;
+use crate::{Error, StrResult};
+use id3::{Content, Frame, Tag, TagLike};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The frames holding artist names
+pub const FRAMES: [&str; 2] = ["TPE1", "TPE2"];
+
+/// A name as it is looked up
+fn fold(name: &str) -> String {
+    normalize_text(name).to_lowercase()
+}
+
+/// Splits a CSV line into its fields, with double quotes around fields holding commas, and
+/// doubled within them for a literal quote
+fn csv_fields(line: &str) -> StrResult<Vec<String>> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(Error::Parse(format!("Unclosed quote in \"{line}\"")));
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// Canonical artist names, looked up by any of their forms
+#[derive(Default)]
+pub struct Authority {
+    names: HashMap<String, String>,
+}
+
+impl Authority {
+    fn add(&mut self, canonical: &str, aliases: &[&str]) {
+        for name in std::iter::once(&canonical).chain(aliases) {
+            self.names.insert(fold(name), canonical.to_owned());
+        }
+    }
+
+    /// Reads an authority list, as JSON if its name ends in `.json` and CSV otherwise
+    pub fn load(path: &Path) -> StrResult<Authority> {
+        let mut authority = Authority::default();
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+        {
+            let json = crate::read_json_file(path)?;
+            if !json.is_object() {
+                return Err(Error::Parse(
+                    "An authority list must map canonical names to arrays of aliases".to_owned(),
+                ));
+            }
+            for (canonical, aliases) in json.entries() {
+                let aliases: Option<Vec<&str>> = aliases.members().map(|a| a.as_str()).collect();
+                let aliases = aliases.ok_or_else(|| {
+                    Error::Parse(format!("The aliases of \"{canonical}\" must all be text"))
+                })?;
+                authority.add(canonical, &aliases);
+            }
+        } else {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy())))?;
+            for (n, line) in text.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let fields = csv_fields(line).map_err(|e| e.context(&format!("Line {}", n + 1)))?;
+                let fields: Vec<&str> = fields
+                    .iter()
+                    .map(|f| f.trim())
+                    .filter(|f| !f.is_empty())
+                    .collect();
+                if let Some((canonical, aliases)) = fields.split_first() {
+                    authority.add(canonical, aliases);
+                }
+            }
+        }
+        Ok(authority)
+    }
+
+    /// The canonical form of a name, if the list has it
+    pub fn canonical(&self, name: &str) -> Option<&str> {
+        self.names.get(&fold(name)).map(String::as_str)
+    }
+
+    /// Rewrites the artist frames to their canonical names, returning the names the list lacks.
+    /// Each of a frame's NUL-separated values is looked up on its own
+    pub fn rewrite_tag(&self, tag: &mut Tag) -> Vec<String> {
+        let mut unmatched = vec![];
+        let mut changes = vec![];
+        for id in FRAMES {
+            let Some(text) = tag.get(id).and_then(|f| f.content().text()) else {
+                continue;
+            };
+            let rewritten: Vec<&str> = text
+                .split('\0')
+                .map(|name| match self.canonical(name) {
+                    Some(canonical) => canonical,
+                    None => {
+                        if !name.trim().is_empty() && !unmatched.iter().any(|u| u == name) {
+                            unmatched.push(name.to_owned());
+                        }
+                        name
+                    }
+                })
+                .collect();
+            let rewritten = rewritten.join("\0");
+            if rewritten != text {
+                changes.push((id, rewritten));
+            }
+        }
+        for (id, text) in changes {
+            tag.add_frame(Frame::with_content(id, Content::Text(text)));
+        }
+        unmatched
+    }
+}
+
+/// Prints the names of a file that the authority list doesn't have
+pub fn report_unmatched(path: &Path, unmatched: &[String]) {
+    for name in unmatched {
+        eprintln!(
+            "{}: \"{name}\" is not in the authority list",
+            path.to_string_lossy()
+        );
+    }
+}
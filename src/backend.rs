@@ -0,0 +1,56 @@
+//! The containers whose tags are read into and written from an id3 `Tag`, besides MP3's own ID3v2.
+//! Supporting another format means implementing [`Backend`] and listing it in [`BACKENDS`].
+
+use crate::sniff::{self, Format};
+use crate::{ape, flac, mp4, ogg, riff, Error, StrResult};
+use id3::Tag;
+use json::JsonValue;
+use std::fs::File;
+use std::path::Path;
+
+pub trait Backend: Sync {
+    /// Whether this backend reads files of the format
+    fn handles(&self, format: Format) -> bool;
+
+    /// The file's tag, or an empty one if it has none
+    fn read_tag(&self, path: &Path) -> StrResult<Tag>;
+
+    /// Replaces the file's tag. `file` is open for reading and writing and locked.
+    fn write_tag(
+        &self,
+        path: &Path,
+        file: &mut File,
+        tag: &Tag,
+        buffer_size: usize,
+    ) -> StrResult<()>;
+
+    /// The audio properties reported under `_properties`, including the format's name
+    fn properties(&self, path: &Path) -> StrResult<JsonValue>;
+
+    /// How many bytes the tag takes now and would take after writing `tag`
+    fn tag_sizes(&self, path: &Path, tag: &Tag) -> StrResult<(u64, u64)>;
+
+    /// The start and end of the audio, which rewriting the tag leaves as it is
+    fn audio_span(&self, path: &Path) -> StrResult<(u64, u64)>;
+
+    /// The length of the audio in seconds, if the container says
+    fn duration(&self, path: &Path) -> Option<f64> {
+        self.properties(path).ok()?["duration"].as_f64()
+    }
+}
+
+/// The length of a file, for spans that run to its end
+pub fn file_len(path: &Path) -> StrResult<u64> {
+    std::fs::metadata(path)
+        .map(|m| m.len())
+        .map_err(|e| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy())))
+}
+
+static BACKENDS: [&dyn Backend; 5] = [&flac::Flac, &ogg::Ogg, &riff::Riff, &mp4::Mp4, &ape::Ape];
+
+/// The backend for a file, chosen by its content, or `None` for MP3s and anything else read as
+/// plain ID3v2
+pub fn for_path(path: &Path) -> Option<&'static dyn Backend> {
+    let format = sniff::format(path)?;
+    BACKENDS.iter().copied().find(|b| b.handles(format))
+}
@@ -0,0 +1,85 @@
+//! Standard base64 (RFC 4648, padded), for carrying binary frame data inside JSON
+
+use crate::{Error, StrResult};
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decodes base64, ignoring whitespace so that wrapped text is accepted
+pub fn decode(text: &str) -> StrResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    let mut padding = false;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        if c == b'=' {
+            padding = true;
+            continue;
+        }
+        let value = match ALPHABET.iter().position(|&a| a == c) {
+            Some(v) if !padding => v as u32,
+            _ => return Err(Error::Parse("Invalid base64 data".to_owned())),
+        };
+        buffer = buffer << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The test vectors of RFC 4648
+    const VECTORS: [(&str, &str); 7] = [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn known_answers() {
+        for (data, text) in VECTORS {
+            assert_eq!(encode(data.as_bytes()), text);
+            assert_eq!(decode(text).unwrap(), data.as_bytes());
+        }
+        assert_eq!(decode("Zm9v\r\nYmFy\n").unwrap(), b"foobar");
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&all)).unwrap(), all);
+    }
+
+    #[test]
+    fn invalid_text_is_an_error() {
+        for text in ["Zm9v!", "Zg==Zg", "Zm-v", "Zm9v\0"] {
+            assert!(matches!(decode(text), Err(Error::Parse(_))), "{text}");
+        }
+    }
+}
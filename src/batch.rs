@@ -0,0 +1,564 @@
+//! The outcome of an operation over many files, gathered rather than printed so that programs
+//! embedding the library can show failures their own way. The command line prints it with
+//! [`BatchReport::print`].
+//!
+//! A [`Cancel`] token stops an operation between files, from another thread such as a GUI's,
+//! leaving what was done so far in the report, and a [`Progress`] callback hears as each file is
+//! started and finished.
+//!
+//! The batch commands themselves are here too: [`extract`] writes or aggregates the documents of
+//! the files [`audio_files`] finds, [`list`] renders a line for each, and [`apply`] writes album
+//! documents and aggregates back to their tracks.
+
+use crate::frame_class::{self, FrameClass};
+use crate::keys::KeyMap;
+use crate::{
+    aggregate, album, audit, checksums, frames, genre, lyrics, paths, pictures, policy, slug,
+    template, Error, StrResult, TransformOpts, WriteOpts,
+};
+use json::JsonValue;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Shared between the caller and a running operation, which checks it before each file. Clones
+/// refer to the same token
+#[derive(Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// A token that is never cancelled, for callers with no way to cancel
+    pub fn never() -> Cancel {
+        Cancel::default()
+    }
+
+    /// Asks the operation to stop before its next file
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Where an operation has got to, with the file's position among the `total` it was given
+#[derive(Clone, Copy)]
+pub enum Progress<'a> {
+    /// The file is about to be handled
+    Started {
+        path: &'a Path,
+        index: usize,
+        total: usize,
+    },
+    /// The file has been handled, whether or not that succeeded
+    Finished {
+        path: &'a Path,
+        index: usize,
+        total: usize,
+    },
+}
+
+/// A progress callback for callers with nothing to show
+pub fn ignore_progress(_: Progress) {}
+
+/// Calls `each` on every audio file under `files` in turn, telling `progress` as each starts
+/// and finishes, until the token is cancelled. Returns whether it was
+pub fn for_each_file(
+    files: &[PathBuf],
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+    each: impl FnMut(&Path),
+) -> bool {
+    for_each_path(
+        &paths::collect_audio_files(files, true),
+        cancel,
+        progress,
+        each,
+    )
+}
+
+/// Like [`for_each_file`], but over exactly the files given
+pub fn for_each_path<P: AsRef<Path>>(
+    files: &[P],
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+    mut each: impl FnMut(&Path),
+) -> bool {
+    let total = files.len();
+    for (index, path) in files.iter().enumerate() {
+        let path = path.as_ref();
+        if cancel.is_cancelled() {
+            return true;
+        }
+        progress(Progress::Started { path, index, total });
+        each(path);
+        progress(Progress::Finished { path, index, total });
+    }
+    false
+}
+
+/// What happened to each file of a batch
+#[derive(Default)]
+pub struct BatchReport {
+    /// The files changed, each with a note of what was done, which may be empty
+    pub succeeded: Vec<(PathBuf, String)>,
+    /// The files that couldn't be handled, with why. The error's variant gives the kind of failure
+    pub failed: Vec<(PathBuf, Error)>,
+    /// Problems that didn't stop a file being handled
+    pub warnings: Vec<(PathBuf, String)>,
+    /// What a check found in each file, for operations that check files rather than change them
+    pub findings: Vec<(PathBuf, String)>,
+    /// Whether the operation was cancelled before it reached every file
+    pub cancelled: bool,
+}
+
+impl BatchReport {
+    /// Records a file as handled, with a note of what was done
+    pub fn succeed(&mut self, path: &Path, note: impl Into<String>) {
+        self.succeeded.push((path.to_owned(), note.into()));
+    }
+
+    /// Records a file that couldn't be handled
+    pub fn fail(&mut self, path: &Path, error: Error) {
+        self.failed.push((path.to_owned(), error));
+    }
+
+    /// Records a problem with a file that was handled anyway
+    pub fn warn(&mut self, path: &Path, warning: impl Into<String>) {
+        self.warnings.push((path.to_owned(), warning.into()));
+    }
+
+    /// Records something a check found in a file
+    pub fn find(&mut self, path: &Path, finding: impl Into<String>) {
+        self.findings.push((path.to_owned(), finding.into()));
+    }
+
+    /// Prints each file changed and each finding to stdout, and each warning and failure to stderr
+    pub fn print(&self) {
+        for (path, note) in self.succeeded.iter().chain(&self.findings) {
+            match note.as_str() {
+                "" => println!("{}", path.to_string_lossy()),
+                note => println!("{}: {note}", path.to_string_lossy()),
+            }
+        }
+        self.print_problems();
+    }
+
+    /// Prints the path of each file changed or with findings to stdout once, NUL-terminated, for
+    /// `xargs -0`, and each warning and failure to stderr
+    pub fn print0(&self) {
+        let mut printed: Vec<&Path> = vec![];
+        for (path, _) in self.succeeded.iter().chain(&self.findings) {
+            if !printed.contains(&path.as_path()) {
+                paths::print0(path);
+                printed.push(path);
+            }
+        }
+        self.print_problems();
+    }
+
+    /// Prints only the warnings and failures, to stderr, for operations whose output on stdout is
+    /// something else, such as a JSON document
+    pub fn print_problems(&self) {
+        for (path, warning) in &self.warnings {
+            eprintln!("{}: {warning}", path.to_string_lossy());
+        }
+        for (path, error) in &self.failed {
+            eprintln!("Could not handle {}: {error}", path.to_string_lossy());
+        }
+    }
+
+    /// Ok if every file was handled, and otherwise a partial failure saying how many files
+    /// couldn't be, as in "3 files could not be scrubbed"
+    pub fn into_result(self, verb: &str) -> StrResult<()> {
+        match self.failed.len() {
+            _ if self.cancelled => Err(Error::Partial(format!(
+                "Cancelled after {} files",
+                self.succeeded.len() + self.failed.len()
+            ))),
+            0 => Ok(()),
+            n => Err(Error::Partial(format!("{n} files could not be {verb}"))),
+        }
+    }
+
+    /// The result of a check: Ok if nothing was found and every file could be checked, and
+    /// otherwise how many problems there were, counting the files that couldn't be checked
+    pub fn into_check_result(self) -> StrResult<()> {
+        if self.cancelled {
+            return self.into_result("checked");
+        }
+        match self.findings.len() + self.failed.len() {
+            0 => Ok(()),
+            n => Err(Error::Other(format!("{n} problems found"))),
+        }
+    }
+
+    /// Adds another report's files to this one, as when several checks run over the same files
+    pub fn merge(&mut self, other: BatchReport) {
+        self.succeeded.extend(other.succeeded);
+        self.failed.extend(other.failed);
+        self.warnings.extend(other.warnings);
+        self.findings.extend(other.findings);
+        self.cancelled |= other.cancelled;
+    }
+}
+
+/// The audio files below the given paths, descending into directories if `recurse` is set, with
+/// extensions like .MP3 renamed to lowercase first if `rename_extensions` is. Finding none is an
+/// error
+pub fn audio_files(
+    files: &[PathBuf],
+    recurse: bool,
+    rename_extensions: bool,
+) -> StrResult<Vec<PathBuf>> {
+    let found = paths::collect_audio_files(files, recurse);
+    if found.is_empty() {
+        return Err(Error::Nothing("No audio files found".to_owned()));
+    }
+    if rename_extensions {
+        return Ok(found
+            .iter()
+            .map(|f| paths::lowercase_extension(f))
+            .collect());
+    }
+    Ok(found)
+}
+
+/// How [`extract`] treats each file
+#[derive(Default)]
+pub struct ExtractOpts {
+    /// Gather the documents into the aggregate returned, keyed by path, instead of writing a JSON
+    /// sidecar beside each file
+    pub aggregate: bool,
+    /// Make one album document per directory, with the frames every track shares stored once
+    pub album_mode: bool,
+    /// The names the documents' keys are written under
+    pub key_map: KeyMap,
+    /// Append a record of each file's tag hash and the time to this log
+    pub audit_log: Option<PathBuf>,
+    /// Also write a checksums.txt to each directory, giving every file's audio and tag hash
+    pub checksums: Option<checksums::Algorithm>,
+    /// Only extract this class of frame
+    pub only: Option<FrameClass>,
+    /// Add URL-safe slugs of the title and artist under "_slugs"
+    pub slugs: bool,
+    /// Write lyrics to a .txt or .lrc beside the audio, referring to it from the document
+    pub lyrics_sidecar: bool,
+    /// Give POPM ratings as stars from 0 to 5
+    pub stars: bool,
+    /// Give TCON genres stored as ID3v1 numbers by name
+    pub genre_names: bool,
+}
+
+/// A file's document with the options applied, writing the art and any lyrics sidecar beside it
+fn extract_one(
+    file: &Path,
+    opts: &ExtractOpts,
+    manifests: &mut checksums::Manifests,
+) -> StrResult<JsonValue> {
+    let (json, pictures) = crate::extract_tags_pic(file)?;
+    if let Some(log) = &opts.audit_log {
+        audit::append(log, file, &json)?;
+    }
+    if let Some(algorithm) = opts.checksums {
+        manifests
+            .add(file, &json, algorithm)
+            .map_err(|e| e.context("Could not checksum"))?;
+    }
+    let (mut json, pictures) = frame_class::keep_only(opts.only, json, pictures);
+    let dir = file.parent().unwrap_or(Path::new(""));
+    pictures::extract(&mut json, dir, &file.with_extension("jpeg"), &pictures)?;
+    if opts.stars {
+        frames::use_stars(&mut json);
+    }
+    if opts.genre_names {
+        genre::use_names(&mut json);
+    }
+    if opts.lyrics_sidecar {
+        lyrics::extract(&mut json, dir, file)?;
+    }
+    if opts.slugs {
+        json["_slugs"] = slug::slug_json(&json);
+    }
+    Ok(json)
+}
+
+/// Extracts each audio file's document, writing its sidecars beside it or, with
+/// `opts.aggregate`, into the aggregate returned. In album mode the albums are what succeed.
+/// Once cancelled, no album or checksum manifest is written, since it would lack the files not
+/// reached
+pub fn extract(
+    files: &[PathBuf],
+    opts: &ExtractOpts,
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+) -> (JsonValue, BatchReport) {
+    let mut blob = JsonValue::new_object();
+    let mut report = BatchReport::default();
+    let mut albums: BTreeMap<PathBuf, Vec<(PathBuf, JsonValue)>> = BTreeMap::new();
+    let mut manifests = checksums::Manifests::default();
+    report.cancelled = for_each_path(files, cancel, progress, |file| {
+        let json = match extract_one(file, opts, &mut manifests) {
+            Ok(json) => json,
+            Err(e) => return report.fail(file, e),
+        };
+        if opts.album_mode {
+            let dir = file.parent().unwrap_or(Path::new("")).to_owned();
+            albums.entry(dir).or_default().push((file.to_owned(), json));
+        } else if opts.aggregate {
+            crate::insert_keyed(&mut blob, file, opts.key_map.localize(json));
+            report.succeed(file, "");
+        } else {
+            let json = json::stringify_pretty(opts.key_map.localize(json), 4);
+            match crate::write_data_to_path(&file.with_extension("json"), json.as_bytes()) {
+                Ok(()) => report.succeed(file, ""),
+                Err(e) => report.fail(file, e),
+            }
+        }
+    });
+    if report.cancelled {
+        return (blob, report);
+    }
+    for (dir, tracks) in albums {
+        let album = opts.key_map.localize(album::build_album(tracks));
+        if opts.aggregate {
+            crate::insert_keyed(&mut blob, &dir, album);
+            report.succeed(&dir, "");
+        } else {
+            let json = json::stringify_pretty(album, 4);
+            let path = dir.join(album::ALBUM_FILE);
+            match crate::write_data_to_path(&path, json.as_bytes()) {
+                Ok(()) => report.succeed(&path, ""),
+                Err(e) => report.fail(&path, e),
+            }
+        }
+    }
+    manifests.write(&mut report);
+    (blob, report)
+}
+
+/// A line for each audio file from the row template, with {FRAME} placeholders such as {TPE1}.
+/// A mistake in the template is an error before any file is read
+pub fn list(
+    files: &[PathBuf],
+    row: &str,
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+) -> StrResult<(Vec<String>, BatchReport)> {
+    template::render(row, |_| None)?;
+    let mut lines = vec![];
+    let mut report = BatchReport::default();
+    report.cancelled = for_each_path(files, cancel, progress, |file| {
+        let line = crate::extract_tags_pic(file)
+            .and_then(|(json, _)| template::render(row, template::json_lookup(&json)));
+        match line {
+            Ok(line) => {
+                lines.push(line);
+                report.succeed(file, "");
+            }
+            Err(e) => report.fail(file, e),
+        }
+    });
+    Ok((lines, report))
+}
+
+/// How [`apply`] writes each track
+#[derive(Default)]
+pub struct ApplyOpts {
+    /// The names the documents' keys may be given under, besides the built-in ones
+    pub key_map: KeyMap,
+    /// Limits on, for instance, the size of the art each track is written with
+    pub policy: policy::Policy,
+    pub write: WriteOpts,
+    pub transform: TransformOpts,
+}
+
+/// Applies album documents, or directories containing an album.json, and aggregates to the tracks
+/// they describe. Files that can't be written are found and failed before any is changed.
+/// Documents describing no tracks at all are an error. Cancelling stops it before the next track,
+/// and progress is reported for the tracks
+pub fn apply(
+    docs: &[PathBuf],
+    opts: &ApplyOpts,
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+) -> StrResult<BatchReport> {
+    let mut report = BatchReport::default();
+    let mut planned = vec![];
+    for doc in docs {
+        let doc = if doc.is_dir() {
+            doc.join(album::ALBUM_FILE)
+        } else {
+            doc.to_owned()
+        };
+        let json = match crate::read_json_file(&doc) {
+            Ok(j) => opts.key_map.canonicalize(j),
+            Err(e) => {
+                report.fail(&doc, e);
+                continue;
+            }
+        };
+        let dir = doc.parent().unwrap_or(Path::new(""));
+        let plan = if aggregate::is_aggregate(&json) {
+            aggregate::plan(&json)
+        } else {
+            album::plan_album(dir, &json)
+        };
+        for (path, result) in plan {
+            match result {
+                Ok(json) => planned.push((path, json)),
+                Err(e) => report.fail(&path, e),
+            }
+        }
+    }
+
+    if planned.is_empty() && report.failed.is_empty() {
+        return Err(Error::Nothing("No tracks to apply".to_owned()));
+    }
+
+    // Find unwritable files before touching anything, rather than failing partway through
+    let mut writable = vec![];
+    for (path, json) in planned {
+        match paths::check_writable(&path) {
+            Ok(()) => writable.push((path, json)),
+            Err(e) => report.fail(&path, e.context("Skipped as not writable")),
+        }
+    }
+
+    let paths: Vec<&Path> = writable.iter().map(|(path, _)| path.as_path()).collect();
+    let mut documents = writable.iter().map(|(_, json)| json);
+    report.cancelled = for_each_path(&paths, cancel, progress, |path| {
+        let json = documents.next().expect("a document for each path");
+        let base = path.parent().unwrap_or(Path::new(""));
+        let result = crate::json_to_tag(json, base).and_then(|mut tag| {
+            crate::embed_sidecar_art(&mut tag, path)?;
+            crate::apply_transforms(&mut tag, path, &opts.transform)?;
+            // Last, so art a transform brought in is held to the policy too
+            opts.policy.art.enforce_tag(&mut tag)?;
+            crate::write_tag(path, &tag, &opts.write)
+        });
+        match result {
+            Ok(()) => report.succeed(path, ""),
+            Err(e) => report.fail(path, e),
+        }
+    });
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use id3::TagLike;
+
+    fn tracks(name: &str, count: usize) -> (PathBuf, Vec<PathBuf>) {
+        let dir = testing::scratch_dir(name);
+        let files: Vec<PathBuf> = (1..=count).map(|n| dir.join(format!("{n}.mp3"))).collect();
+        for file in &files {
+            testing::write_mp3(file);
+            let mut tag = id3::Tag::new();
+            tag.set_title("Before");
+            tag.write_to_path(file, id3::Version::Id3v24).unwrap();
+        }
+        (dir, files)
+    }
+
+    #[test]
+    fn extract_writes_sidecars() {
+        let (dir, files) = tracks("batch-extract", 2);
+        let (_, report) = extract(
+            &files,
+            &ExtractOpts::default(),
+            &Cancel::never(),
+            &ignore_progress,
+        );
+        assert_eq!(report.succeeded.len(), 2);
+        assert!(report.failed.is_empty() && !report.cancelled);
+        assert_eq!(
+            testing::file_names(&dir),
+            ["1.json", "1.mp3", "2.json", "2.mp3"]
+        );
+    }
+
+    #[test]
+    fn extract_reports_progress() {
+        let (_, files) = tracks("batch-progress", 2);
+        let events = std::cell::RefCell::new(vec![]);
+        let progress = |p: Progress| {
+            events.borrow_mut().push(match p {
+                Progress::Started { index, total, .. } => ("started", index, total),
+                Progress::Finished { index, total, .. } => ("finished", index, total),
+            })
+        };
+        let opts = ExtractOpts {
+            aggregate: true,
+            ..ExtractOpts::default()
+        };
+        let (blob, _) = extract(&files, &opts, &Cancel::never(), &progress);
+        assert_eq!(blob.len(), 2);
+        assert_eq!(
+            events.into_inner(),
+            [
+                ("started", 0, 2),
+                ("finished", 0, 2),
+                ("started", 1, 2),
+                ("finished", 1, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_extract_writes_nothing() {
+        let (dir, files) = tracks("batch-cancel", 3);
+        let cancel = Cancel::never();
+        cancel.cancel();
+        let opts = ExtractOpts {
+            album_mode: true,
+            ..ExtractOpts::default()
+        };
+        let (_, report) = extract(&files, &opts, &cancel, &ignore_progress);
+        assert!(report.cancelled && report.succeeded.is_empty());
+        assert_eq!(testing::file_names(&dir), ["1.mp3", "2.mp3", "3.mp3"]);
+        assert!(report.into_result("extracted").is_err());
+    }
+
+    #[test]
+    fn cancelled_apply_stops_before_writing() {
+        let (dir, files) = tracks("batch-apply-cancel", 2);
+        let mut aggregate = JsonValue::new_object();
+        for file in &files {
+            crate::insert_keyed(&mut aggregate, file, json::object! { "TIT2": "Title" });
+        }
+        let doc = dir.join("all.json");
+        std::fs::write(&doc, aggregate.dump()).unwrap();
+        let cancel = Cancel::never();
+        cancel.cancel();
+        let report = apply(&[doc], &ApplyOpts::default(), &cancel, &ignore_progress).unwrap();
+        assert!(report.cancelled && report.succeeded.is_empty());
+        assert_eq!(crate::read_tag(&files[0]).unwrap().title(), Some("Before"));
+    }
+
+    #[test]
+    fn art_policy_covers_pictures_in_the_document() {
+        let (dir, files) = tracks("batch-apply-policy", 2);
+        let picture = json::object! {
+            "type": "front-cover",
+            "data": crate::base64::encode(&testing::png()),
+        };
+        let mut aggregate = JsonValue::new_object();
+        let with_art = json::object! { "TIT2": "Art", "APIC": [picture] };
+        crate::insert_keyed(&mut aggregate, &files[0], with_art);
+        crate::insert_keyed(&mut aggregate, &files[1], json::object! { "TIT2": "None" });
+        let doc = dir.join("all.json");
+        std::fs::write(&doc, aggregate.dump()).unwrap();
+        let mut opts = ApplyOpts::default();
+        opts.policy.art.max_bytes = Some(8);
+        let report = apply(&[doc], &opts, &Cancel::never(), &ignore_progress).unwrap();
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, files[0]);
+        assert_eq!(crate::read_tag(&files[0]).unwrap().title(), Some("Before"));
+        assert_eq!(crate::read_tag(&files[1]).unwrap().title(), Some("None"));
+    }
+}
@@ -0,0 +1,232 @@
+//! The JSON form of CHAP frames: an array under "CHAP" of objects giving each chapter's ID,
+//! start and end in milliseconds, its text frames keyed by ID, and optionally an "image"
+//!
+//! ```json
+//! "CHAP": [{ "id": "chp0", "start": 0, "end": 61000, "TIT2": "Intro", "image": "intro.jpg" }]
+//! ```
+//!
+//! An image is either a path, relative to the JSON document, or a `data:` URI carrying the image
+//! as base64. Extraction always writes the latter so the sidecar is self-contained.
+//!
+//! CTOC frames, which list the chapters in order for players to navigate by, are an array under
+//! "CTOC" of objects giving each table's ID, whether it's the top-level one, whether its entries
+//! are ordered, the IDs of the chapters or nested tables it lists, and its text frames
+//!
+//! ```json
+//! "CTOC": [{ "id": "toc", "top_level": true, "ordered": true, "chapters": ["chp0", "chp1"] }]
+//! ```
+//!
+//! If chapters are applied without any CTOC, a top-level table listing them by start time is
+//! written, since many podcast players ignore chapters that no table refers to.
+
+use crate::{base64, image, paranoid, Error, StrResult};
+use id3::frame::{Chapter, Content, Picture, PictureType, TableOfContents};
+use id3::{Frame, Tag};
+use json::JsonValue;
+use std::path::Path;
+
+/// Marks an offset field as unused, per the ID3v2 chapter addendum
+const NO_OFFSET: u32 = 0xFFFF_FFFF;
+
+fn data_uri(picture: &Picture) -> String {
+    format!(
+        "data:{};base64,{}",
+        picture.mime_type,
+        base64::encode(&picture.data)
+    )
+}
+
+fn chapter_to_json(chapter: &Chapter) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    json["id"] = chapter.element_id.as_str().into();
+    json["start"] = chapter.start_time.into();
+    json["end"] = chapter.end_time.into();
+    if chapter.start_offset != NO_OFFSET {
+        json["start_offset"] = chapter.start_offset.into();
+    }
+    if chapter.end_offset != NO_OFFSET {
+        json["end_offset"] = chapter.end_offset.into();
+    }
+    for frame in &chapter.frames {
+        match frame.content() {
+            Content::Picture(picture) if !json.has_key("image") => {
+                json["image"] = data_uri(picture).into();
+            }
+            content => {
+                if let Some(text) = content.text() {
+                    json[frame.id()] = text.into();
+                }
+            }
+        }
+    }
+    json
+}
+
+/// The chapters of a tag in their JSON form, or None if it has none
+pub fn to_json(tag: &Tag) -> Option<JsonValue> {
+    let chapters: Vec<JsonValue> = tag.chapters().map(chapter_to_json).collect();
+    (!chapters.is_empty()).then(|| chapters.into())
+}
+
+fn load_image(reference: &str, base: &Path) -> StrResult<Picture> {
+    let mut declared = None;
+    let data = match reference.strip_prefix("data:") {
+        Some(uri) => {
+            let Some((mime, data)) = uri.split_once(";base64,") else {
+                return Err(Error::Parse(
+                    "Chapter image data URIs must be base64".to_owned(),
+                ));
+            };
+            declared = Some(mime.to_owned()).filter(|m| !m.is_empty());
+            base64::decode(data)?
+        }
+        None => {
+            paranoid::check_reference(Path::new(reference))?;
+            let path = base.join(reference);
+            paranoid::check_path(&path)?;
+            match std::fs::read(&path) {
+                Ok(data) => data,
+                Err(e) => Err(Error::Io(format!(
+                    "Cannot read chapter image {}: {e}",
+                    path.to_string_lossy()
+                )))?,
+            }
+        }
+    };
+    Ok(Picture {
+        mime_type: match image::mime_type(&data) {
+            Some(mime) => mime.to_owned(),
+            None => declared.unwrap_or_else(|| "image/jpeg".to_owned()),
+        },
+        picture_type: PictureType::Other,
+        description: String::new(),
+        data,
+    })
+}
+
+fn time(json: &JsonValue, key: &str, index: usize) -> StrResult<u32> {
+    json[key].as_u32().ok_or_else(|| {
+        Error::Parse(format!(
+            "Chapter {index} needs a \"{key}\" time in milliseconds"
+        ))
+    })
+}
+
+/// Builds CHAP frames from their JSON form, resolving image paths against `base`
+pub fn from_json(json: &JsonValue, base: &Path) -> StrResult<Vec<Chapter>> {
+    if !json.is_array() {
+        return Err(Error::Parse("CHAP must be an array of chapters".to_owned()));
+    }
+    let mut chapters = vec![];
+    for (index, entry) in json.members().enumerate() {
+        let element_id = match entry["id"].as_str() {
+            Some(id) => id.to_owned(),
+            None => format!("chp{index}"),
+        };
+        let mut frames = vec![];
+        for (key, value) in entry.entries() {
+            match (key, value.as_str()) {
+                ("image", Some(reference)) => frames.push(Frame::with_content(
+                    "APIC",
+                    Content::Picture(load_image(reference, base)?),
+                )),
+                (key, Some(text)) if key.len() == 4 && key.starts_with(['T', 't']) => {
+                    frames.push(Frame::text(key.to_ascii_uppercase(), text))
+                }
+                _ => {}
+            }
+        }
+        chapters.push(Chapter {
+            element_id,
+            start_time: time(entry, "start", index)?,
+            end_time: time(entry, "end", index)?,
+            start_offset: entry["start_offset"].as_u32().unwrap_or(NO_OFFSET),
+            end_offset: entry["end_offset"].as_u32().unwrap_or(NO_OFFSET),
+            frames,
+        });
+    }
+    Ok(chapters)
+}
+
+fn toc_to_json(toc: &TableOfContents) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    json["id"] = toc.element_id.as_str().into();
+    json["top_level"] = toc.top_level.into();
+    json["ordered"] = toc.ordered.into();
+    json["chapters"] = toc.elements.clone().into();
+    for frame in &toc.frames {
+        if let Some(text) = frame.content().text() {
+            json[frame.id()] = text.into();
+        }
+    }
+    json
+}
+
+/// The tables of contents of a tag in their JSON form, or None if it has none
+pub fn tocs_to_json(tag: &Tag) -> Option<JsonValue> {
+    let tocs: Vec<JsonValue> = tag.tables_of_contents().map(toc_to_json).collect();
+    (!tocs.is_empty()).then(|| tocs.into())
+}
+
+/// A top-level table listing the chapters in order of their start times
+pub fn default_toc(chapters: &[Chapter]) -> TableOfContents {
+    let mut ordered: Vec<&Chapter> = chapters.iter().collect();
+    ordered.sort_by_key(|c| c.start_time);
+    TableOfContents {
+        element_id: "toc".to_owned(),
+        top_level: true,
+        ordered: true,
+        elements: ordered.iter().map(|c| c.element_id.clone()).collect(),
+        frames: vec![],
+    }
+}
+
+/// Builds CTOC frames from their JSON form, checking that every entry they list is one of the
+/// `chapters` or another table
+pub fn tocs_from_json(json: &JsonValue, chapters: &[Chapter]) -> StrResult<Vec<TableOfContents>> {
+    if !json.is_array() {
+        return Err(Error::Parse(
+            "CTOC must be an array of tables of contents".to_owned(),
+        ));
+    }
+    let mut tocs = vec![];
+    for (index, entry) in json.members().enumerate() {
+        let element_id = match entry["id"].as_str() {
+            Some(id) => id.to_owned(),
+            None => format!("toc{index}"),
+        };
+        let Some(elements) = entry["chapters"]
+            .members()
+            .map(|e| e.as_str().map(str::to_owned))
+            .collect::<Option<Vec<String>>>()
+        else {
+            return Err(Error::Parse(format!(
+                "Table of contents {index} must list its chapters by ID"
+            )));
+        };
+        let frames = entry
+            .entries()
+            .filter(|(key, _)| key.len() == 4 && key.starts_with(['T', 't']))
+            .filter_map(|(key, value)| Some(Frame::text(key.to_ascii_uppercase(), value.as_str()?)))
+            .collect();
+        tocs.push(TableOfContents {
+            element_id,
+            top_level: entry["top_level"].as_bool().unwrap_or(index == 0),
+            ordered: entry["ordered"].as_bool().unwrap_or(true),
+            elements,
+            frames,
+        });
+    }
+    for toc in &tocs {
+        let known = |id: &String| {
+            chapters.iter().any(|c| &c.element_id == id) || tocs.iter().any(|t| &t.element_id == id)
+        };
+        if let Some(missing) = toc.elements.iter().find(|id| !known(id)) {
+            return Err(Error::Parse(format!(
+                "Table of contents {} lists \"{missing}\", which is not a chapter or table",
+                toc.element_id
+            )));
+        }
+    }
+    Ok(tocs)
+}
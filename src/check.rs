@@ -0,0 +1,235 @@
+//! Consistency checks over a library
+
+use crate::batch::BatchReport;
+use crate::{album, header, manifest, paranoid, paths, pictures, sniff, Error};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Extensions of the files tag2json writes next to audio files
+const SIDECAR_EXTENSIONS: [&str; 7] = ["json", "jpeg", "jpg", "png", "gif", "webp", "avif"];
+
+/// Names, less their extension, that art is commonly kept under for a whole directory rather than
+/// for one track, so are never taken to be orphaned sidecars
+const DIRECTORY_ART: [&str; 6] = ["cover", "folder", "front", "back", "albumart", "artwork"];
+
+fn is_image(path: &Path) -> bool {
+    is_sidecar(path) && !paths::has_extension(path, "json")
+}
+
+/// The stem the sidecar's audio would have: its name less any extension, and for images less the
+/// picture type of art beyond the first, as in `track.back-cover.jpg`
+fn audio_stem(sidecar: &Path) -> Option<String> {
+    let stem = sidecar.file_stem()?.to_string_lossy();
+    Some(match is_image(sidecar) {
+        true => pictures::without_type_suffix(&stem).to_owned(),
+        false => stem.into_owned(),
+    })
+}
+
+/// The audio files in the sidecar's directory it could have been extracted from: those whose name,
+/// less any extension, matches the sidecar's [`audio_stem`], ignoring case, allowing for the
+/// `track..json` form single-file extraction writes. Extensionless audio counts, as does any
+/// extension sniffed as audio
+pub fn audio_candidates(sidecar: &Path) -> Vec<PathBuf> {
+    let Some(stem) = audio_stem(sidecar) else {
+        return vec![];
+    };
+    let stems = [stem.as_str(), stem.strip_suffix('.').unwrap_or(&stem)];
+    let matches = |name: Option<&OsStr>| {
+        name.is_some_and(|n| {
+            stems
+                .iter()
+                .any(|s| n.to_string_lossy().eq_ignore_ascii_case(s))
+        })
+    };
+    let dir = match sidecar.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = dir.read_dir() else {
+        return vec![];
+    };
+    let mut candidates: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|e| sidecar.with_file_name(e.file_name()))
+        .filter(|p| p != sidecar && !is_sidecar(p) && p.is_file())
+        .filter(|p| matches(p.file_stem()) || matches(p.file_name()))
+        .filter(|p| sniff::format(p).is_some())
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+fn is_sidecar(path: &Path) -> bool {
+    path.file_name() != Some(OsStr::new(album::ALBUM_FILE))
+        && SIDECAR_EXTENSIONS
+            .iter()
+            .any(|e| paths::has_extension(path, e))
+}
+
+/// Whether the sidecar was written for a track that is no longer there. Directory-wide art such
+/// as `cover.jpg` never is
+fn is_orphan(sidecar: &Path) -> bool {
+    let directory_art = is_image(sidecar)
+        && sidecar.file_stem().is_some_and(|stem| {
+            DIRECTORY_ART
+                .iter()
+                .any(|name| stem.to_string_lossy().eq_ignore_ascii_case(name))
+        });
+    !directory_art && audio_candidates(sidecar).is_empty()
+}
+
+fn has_json_sidecar(audio: &Path) -> bool {
+    let double = audio.with_extension(".json");
+    audio.with_extension("json").exists() || double.exists()
+}
+
+/// Every file below the given paths, audio or not
+pub fn all_files(paths: &[PathBuf], found: &mut Vec<PathBuf>) {
+    for path in paths {
+        if paranoid::skip_link(path) {
+            continue;
+        }
+        if path.is_dir() {
+            match path.read_dir() {
+                Ok(entries) => {
+                    let mut children: Vec<_> =
+                        entries.filter_map(Result::ok).map(|e| e.path()).collect();
+                    children.sort();
+                    all_files(&children, found);
+                }
+                Err(e) => eprintln!("Could not read {}: {e}", path.to_string_lossy()),
+            }
+        } else if path.is_file() {
+            found.push(path.clone());
+        }
+    }
+}
+
+/// Finds sidecars without audio and audio without a JSON sidecar, optionally deleting the former
+/// and regenerating the latter. What was left in place is reported as findings
+pub fn check_orphans(files: &[PathBuf], delete: bool, regenerate: bool) -> BatchReport {
+    let mut everything = vec![];
+    all_files(files, &mut everything);
+
+    let mut report = BatchReport::default();
+    for sidecar in everything.iter().filter(|p| is_sidecar(p)) {
+        if !is_orphan(sidecar) {
+            continue;
+        }
+        if delete {
+            let change = manifest::Change::begin(sidecar);
+            match std::fs::remove_file(sidecar) {
+                Ok(()) => {
+                    change.finish();
+                    report.succeed(sidecar, "deleted orphan");
+                }
+                Err(e) => report.fail(sidecar, Error::Io(format!("Could not delete: {e}"))),
+            }
+        } else {
+            report.find(sidecar, "orphan");
+        }
+    }
+
+    for audio in paths::collect_audio_files(files, true) {
+        if has_json_sidecar(&audio) {
+            continue;
+        }
+        if regenerate {
+            match crate::extract_sidecars(&audio) {
+                Ok(()) => report.succeed(&audio, "regenerated"),
+                Err(e) => report.fail(&audio, e.context("Could not extract")),
+            }
+        } else {
+            report.find(&audio, "missing sidecar");
+        }
+    }
+    report
+}
+
+/// Finds files whose tag stores a CRC that no longer matches its data, a sign of corruption
+pub fn check_crc(files: &[PathBuf]) -> BatchReport {
+    let mut report = BatchReport::default();
+    for audio in paths::collect_audio_files(files, true) {
+        match header::crc_status(&audio) {
+            Ok(Some((stored, actual))) if stored != actual => report.find(
+                &audio,
+                format!("crc mismatch, likely corrupt (stored {stored:08x}, actual {actual:08x})"),
+            ),
+            Ok(_) => {}
+            Err(e) => report.fail(&audio, e),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use id3::frame::{Picture, PictureType};
+    use id3::{TagLike, Version};
+
+    fn picture(picture_type: PictureType, mime_type: &str, data: Vec<u8>) -> Picture {
+        Picture {
+            mime_type: mime_type.to_owned(),
+            picture_type,
+            description: String::new(),
+            data,
+        }
+    }
+
+    #[test]
+    fn orphans_keep_every_picture_of_a_track() {
+        let dir = testing::scratch_dir("orphans");
+        let track = dir.join("t.mp3");
+        testing::write_mp3(&track);
+        let mut tag = id3::Tag::new();
+        tag.set_title("Title");
+        tag.add_frame(picture(
+            PictureType::CoverFront,
+            "image/jpeg",
+            testing::jpeg(),
+        ));
+        tag.add_frame(picture(PictureType::CoverBack, "image/png", testing::png()));
+        tag.add_frame(picture(PictureType::Artist, "image/png", testing::png()));
+        tag.write_to_path(&track, Version::Id3v24).unwrap();
+        crate::extract_sidecars(&track).unwrap();
+        let extracted = testing::file_names(&dir);
+        assert!(extracted.contains(&"t.back-cover.png".to_owned()));
+
+        for name in [
+            "cover.jpg",
+            "Folder.png",
+            "gone.json",
+            "gone.back-cover.png",
+            "gone.jpeg",
+        ] {
+            std::fs::write(dir.join(name), testing::png()).unwrap();
+        }
+        let report = check_orphans(std::slice::from_ref(&dir), true, false);
+        let mut deleted: Vec<_> = report
+            .succeeded
+            .iter()
+            .map(|(p, _)| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        deleted.sort();
+        assert_eq!(deleted, ["gone.back-cover.png", "gone.jpeg", "gone.json"]);
+        assert!(report.failed.is_empty() && report.findings.is_empty());
+
+        let mut kept = extracted;
+        kept.extend(["Folder.png".to_owned(), "cover.jpg".to_owned()]);
+        kept.sort();
+        assert_eq!(testing::file_names(&dir), kept);
+    }
+
+    #[test]
+    fn picture_types_are_stripped_from_stems() {
+        let stem = |name: &str| audio_stem(Path::new(name)).unwrap();
+        assert_eq!(stem("t.back-cover.png"), "t");
+        assert_eq!(stem("t.artist-2.jpg"), "t");
+        assert_eq!(stem("track..band-logo.jpg"), "track.");
+        assert_eq!(stem("live.other.json"), "live.other");
+        assert_eq!(stem("a.b.jpg"), "a.b");
+    }
+}
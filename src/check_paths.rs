@@ -0,0 +1,144 @@
+//! Reports files whose tag-derived names would be unusable, before anything is renamed
+
+use crate::{normalize, paths, template, Error, StrResult};
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The file systems whose naming rules a generated name must satisfy
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// Only `/` and NUL are forbidden, and names are case sensitive
+    Posix,
+    /// NTFS/FAT rules: reserved characters and device names, no trailing dots or spaces, case insensitive
+    Windows,
+    /// Names must be valid everywhere
+    All,
+}
+
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Returns why a single path component is invalid on the target, if it is
+pub fn component_problem(name: &str, target: Target) -> Option<String> {
+    if name.is_empty() {
+        return Some("empty name component".to_owned());
+    }
+    if name.contains('/') || name.contains('\0') {
+        return Some(format!("\"{name}\" contains '/' or NUL"));
+    }
+    if name.len() > 255 {
+        return Some(format!("\"{name}\" is longer than 255 bytes"));
+    }
+    if target == Target::Posix {
+        return None;
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| "<>:\"\\|?*".contains(*c) || c.is_control())
+    {
+        return Some(format!(
+            "\"{name}\" contains {c:?}, which is invalid on Windows"
+        ));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some(format!("\"{name}\" ends with a dot or space"));
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return Some(format!("\"{name}\" is a reserved device name on Windows"));
+    }
+    None
+}
+
+/// The form of a path two files collide by on the target. Names differing only in case clash on
+/// case-insensitive file systems, and composed and decomposed accents on macOS, which normalizes
+/// them
+pub fn collision_key(path: &Path, target: Target) -> String {
+    let path = path.to_string_lossy();
+    match target {
+        Target::Posix => path.into_owned(),
+        Target::Windows => path.to_lowercase(),
+        Target::All => normalize::compose(&path).to_lowercase(),
+    }
+}
+
+/// Renders the template for one file, giving the path it would be renamed to. Each `/`-separated
+/// part of the template is rendered on its own, so a slash inside a tag value is caught as invalid.
+pub fn canonical_path(file: &Path, template_str: &str) -> StrResult<(PathBuf, Vec<String>)> {
+    let (relative, components) = relative_path(file, template_str)?;
+    let parent = file.parent().unwrap_or(Path::new(""));
+    Ok((parent.join(relative), components))
+}
+
+/// Renders the template for one file as a relative path, keeping the file's extension, along with
+/// the rendered components
+pub fn relative_path(file: &Path, template_str: &str) -> StrResult<(PathBuf, Vec<String>)> {
+    let (json, _) = crate::extract_tags_pic(file)?;
+    let mut components = vec![];
+    let mut relative = PathBuf::new();
+    let parts: Vec<&str> = template_str.split('/').collect();
+    for (i, part) in parts.iter().enumerate() {
+        let mut name = template::render(part, template::json_lookup(&json))?;
+        if i == parts.len() - 1 {
+            if let Some(ext) = file.extension() {
+                name.push('.');
+                name.push_str(&ext.to_string_lossy());
+            }
+        }
+        relative.push(&name);
+        components.push(name);
+    }
+    Ok((relative, components))
+}
+
+pub fn check_paths(
+    files: &[PathBuf],
+    template_str: &str,
+    target: Target,
+    print0: bool,
+) -> StrResult<()> {
+    let mut problem_count = 0;
+    let mut report = |file: &Path, problem: String| {
+        if print0 {
+            paths::print0(file);
+        } else {
+            println!("{}: {problem}", file.to_string_lossy());
+        }
+        problem_count += 1;
+    };
+
+    let mut claimed: HashMap<String, PathBuf> = HashMap::new();
+    for file in paths::collect_audio_files(files, true) {
+        let (canonical, components) = match canonical_path(&file, template_str) {
+            Ok(c) => c,
+            Err(e) => {
+                report(&file, e.to_string());
+                continue;
+            }
+        };
+        for component in &components {
+            if let Some(problem) = component_problem(component, target) {
+                report(&file, problem);
+            }
+        }
+
+        let key = collision_key(&canonical, target);
+        if let Some(other) = claimed.get(&key) {
+            let problem = format!("would collide with {}", other.to_string_lossy());
+            report(&file, problem);
+        } else if canonical.exists() && canonical != file {
+            let problem = format!("{} already exists", canonical.to_string_lossy());
+            report(&file, problem);
+        } else {
+            claimed.insert(key, file);
+        }
+    }
+
+    match problem_count {
+        0 => Ok(()),
+        n => Err(Error::Other(format!("{n} naming problems found"))),
+    }
+}
@@ -0,0 +1,101 @@
+//! Fixity manifests written alongside batch extraction: for each file, a hash of its audio and a
+//! hash of its tag, so either changing can be told apart from the other.
+//!
+//! A manifest is written to each directory as `checksums.txt`, one file per line:
+//!
+//! ```text
+//! <audio hash>  <tag hash>  <file name>
+//! ```
+//!
+//! The tag hash is the one the audit log records, over the frames as extracted.
+
+use crate::batch::BatchReport;
+use crate::{audit, backend, header, sha256, write_data_to_path, Error, StrResult};
+use clap::ValueEnum;
+use json::JsonValue;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILE: &str = "checksums.txt";
+
+const ID3V1_LEN: u64 = 128;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Algorithm {
+    Sha256,
+}
+
+/// Where the audio of a plain ID3v2 file lies: after the tag and its padding, and before any ID3v1
+/// tag at the end
+fn mp3_span(path: &Path, file: &mut File) -> StrResult<(u64, u64)> {
+    let (tag_len, padding) = header::tag_span(path)?;
+    let mut end = backend::file_len(path)?;
+    if end >= tag_len + padding + ID3V1_LEN {
+        let mut marker = [0u8; 3];
+        let found = file
+            .seek(SeekFrom::Start(end - ID3V1_LEN))
+            .and_then(|_| file.read_exact(&mut marker));
+        if found.is_ok() && &marker == b"TAG" {
+            end -= ID3V1_LEN;
+        }
+    }
+    Ok((tag_len + padding, end))
+}
+
+/// The hash of a file's audio, leaving out its tags so that retagging doesn't change it
+pub fn audio_hash(path: &Path, algorithm: Algorithm) -> StrResult<String> {
+    let read_error =
+        |e: std::io::Error| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy()));
+    let mut file = File::open(path).map_err(read_error)?;
+    let (start, end) = match backend::for_path(path) {
+        Some(backend) => backend.audio_span(path)?,
+        None => mp3_span(path, &mut file)?,
+    };
+    file.seek(SeekFrom::Start(start)).map_err(read_error)?;
+    let Algorithm::Sha256 = algorithm;
+    let mut hasher = sha256::Sha256::default();
+    let mut reader = file.take(end.saturating_sub(start));
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer).map_err(read_error)? {
+            0 => break,
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+    Ok(sha256::to_hex(&hasher.finish()))
+}
+
+/// The manifest lines gathered during a batch, by directory
+#[derive(Default)]
+pub struct Manifests {
+    dirs: BTreeMap<PathBuf, BTreeMap<String, String>>,
+}
+
+impl Manifests {
+    /// Records a file's checksums, given the JSON extracted from it
+    pub fn add(&mut self, path: &Path, json: &JsonValue, algorithm: Algorithm) -> StrResult<()> {
+        let audio = audio_hash(path, algorithm)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let line = format!("{audio}  {}  {name}\n", audit::tag_hash(json));
+        let dir = path.parent().unwrap_or(Path::new("")).to_owned();
+        self.dirs
+            .entry(dir)
+            .or_default()
+            .insert(name.into_owned(), line);
+        Ok(())
+    }
+
+    /// Writes each directory's manifest in file name order, replacing any from an earlier run.
+    /// Manifests that can't be written are failures in the report
+    pub fn write(self, report: &mut BatchReport) {
+        for (dir, lines) in self.dirs {
+            let manifest: String = lines.into_values().collect();
+            let path = dir.join(MANIFEST_FILE);
+            if let Err(e) = write_data_to_path(&path, manifest.as_bytes()) {
+                report.fail(&path, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Moving JSON through the system clipboard by way of the platform's own clipboard commands
+
+use crate::{Error, StrResult};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[cfg(target_os = "macos")]
+const COPY: &[&[&str]] = &[&["pbcopy"]];
+#[cfg(target_os = "macos")]
+const PASTE: &[&[&str]] = &[&["pbpaste"]];
+
+#[cfg(windows)]
+const COPY: &[&[&str]] = &[&["clip"]];
+#[cfg(windows)]
+const PASTE: &[&[&str]] = &[&["powershell", "-NoProfile", "-Command", "Get-Clipboard -Raw"]];
+
+#[cfg(not(any(target_os = "macos", windows)))]
+const COPY: &[&[&str]] = &[
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+];
+#[cfg(not(any(target_os = "macos", windows)))]
+const PASTE: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-o"],
+    &["xsel", "--clipboard", "--output"],
+];
+
+fn tried(commands: &[&[&str]]) -> String {
+    let names: Vec<_> = commands.iter().map(|c| c[0]).collect();
+    names.join(", ")
+}
+
+/// Places `text` on the clipboard using the first clipboard command that can be started
+pub fn copy(text: &str) -> StrResult<()> {
+    for command in COPY {
+        let Ok(mut child) = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .spawn()
+        else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(text.as_bytes())
+                .map_err(|e| Error::Io(format!("Cannot write to {}: {e}", command[0])))?;
+        }
+        return match child.wait() {
+            Ok(s) if s.success() => Ok(()),
+            Ok(s) => Err(Error::Other(format!("{} exited with {s}", command[0]))),
+            Err(e) => Err(Error::Io(format!("Cannot run {}: {e}", command[0]))),
+        };
+    }
+    Err(Error::Unsupported(format!(
+        "No clipboard command found (tried {})",
+        tried(COPY)
+    )))
+}
+
+/// Reads the clipboard's text using the first clipboard command that can be started
+pub fn paste() -> StrResult<String> {
+    for command in PASTE {
+        let Ok(output) = Command::new(command[0])
+            .args(&command[1..])
+            .stderr(Stdio::inherit())
+            .output()
+        else {
+            continue;
+        };
+        if !output.status.success() {
+            Err(format!("{} exited with {}", command[0], output.status))?;
+        }
+        return String::from_utf8(output.stdout)
+            .map_err(|_| Error::Parse("Clipboard does not contain text".to_owned()));
+    }
+    Err(Error::Unsupported(format!(
+        "No clipboard command found (tried {})",
+        tried(PASTE)
+    )))
+}
@@ -0,0 +1,112 @@
+//! What old players can't show: the frames and values a constrained target would lose, found
+//! before the files are copied to it
+
+use crate::batch::BatchReport;
+use crate::paths;
+use clap::ValueEnum;
+use id3::{Content, Frame, Tag};
+use std::path::PathBuf;
+
+/// The tag formats of older players
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Player {
+    /// ID3v2.3 in Latin-1 only, as many car head units and portable players read it
+    #[value(name = "id3v23-latin1")]
+    Id3v23Latin1,
+    /// ID3v2.3 with UTF-16 text
+    Id3v23,
+    /// ID3v1: title, artist, album, year, comment, track and genre, in 30 bytes of Latin-1 each
+    Id3v1,
+}
+
+/// Frames added in ID3v2.4, which ID3v2.3 readers skip. TDRC is left out, as it maps onto TYER,
+/// TDAT and TIME
+const V24_ONLY: [&str; 17] = [
+    "ASPI", "EQU2", "RVA2", "SEEK", "SIGN", "TDEN", "TDOR", "TDRL", "TDTG", "TIPL", "TMCL", "TMOO",
+    "TPRO", "TSOA", "TSOP", "TSOT", "TSST",
+];
+
+/// The frames ID3v1 has a field for, and the bytes each field holds
+const V1_FIELDS: [(&str, usize); 6] = [
+    ("TIT2", 30),
+    ("TPE1", 30),
+    ("TALB", 30),
+    ("TDRC", 4),
+    ("COMM", 28),
+    ("TRCK", 3),
+];
+
+/// The text of a frame that holds text, including comments and lyrics
+fn text_of(frame: &Frame) -> Option<&str> {
+    match frame.content() {
+        Content::Comment(c) => Some(&c.text),
+        Content::Lyrics(l) => Some(&l.text),
+        Content::ExtendedText(t) => Some(&t.value),
+        content => content.text(),
+    }
+}
+
+fn latin1_problem(frame: &Frame) -> Option<String> {
+    let text = text_of(frame)?;
+    let c = text.chars().find(|c| u32::from(*c) > 0xFF)?;
+    Some(format!(
+        "{} has {c:?}, which Latin-1 can't represent",
+        frame.id()
+    ))
+}
+
+/// Why the target can't represent each frame of the tag that it can't
+pub fn problems(tag: &Tag, player: Player) -> Vec<String> {
+    let mut problems = vec![];
+    for frame in tag.frames() {
+        let id = frame.id();
+        if player == Player::Id3v1 {
+            let Some((_, limit)) = V1_FIELDS.iter().find(|(f, _)| *f == id) else {
+                if id != "TCON" {
+                    problems.push(format!("{id} has no ID3v1 field"));
+                }
+                continue;
+            };
+            let text = text_of(frame).unwrap_or("");
+            let value = match id {
+                "TDRC" => text.get(..4).unwrap_or(text),
+                "TRCK" => text.split('/').next().unwrap_or(text),
+                _ => text,
+            };
+            if id == "TRCK" && value.trim().parse::<u8>().is_err() {
+                problems.push(format!("TRCK \"{text}\" is not a track number up to 255"));
+            } else if value.chars().count() > *limit {
+                problems.push(format!("{id} is cut to {limit} characters"));
+            }
+            problems.extend(latin1_problem(frame));
+            continue;
+        }
+        if V24_ONLY.contains(&id) {
+            problems.push(format!("{id} is new in ID3v2.4"));
+            continue;
+        }
+        if frame.content().text().is_some_and(|t| t.contains('\0')) {
+            problems.push(format!("{id} has several values, only the first is shown"));
+        }
+        if player == Player::Id3v23Latin1 {
+            problems.extend(latin1_problem(frame));
+        }
+    }
+    problems
+}
+
+/// Finds, for each file, what the player wouldn't show of its tag
+pub fn check_player(files: &[PathBuf], player: Player) -> BatchReport {
+    let mut report = BatchReport::default();
+    for audio in paths::collect_audio_files(files, true) {
+        match crate::read_tag(&audio) {
+            Ok(tag) => {
+                for problem in problems(&tag, player) {
+                    report.find(&audio, problem);
+                }
+            }
+            Err(e) => report.fail(&audio, e),
+        }
+    }
+    report
+}
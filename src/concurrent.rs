@@ -0,0 +1,159 @@
+//! Extracting and applying tags on several files at once, for services that handle many requests.
+//! The work runs on a pool of threads of a given size, since the tag IO underneath blocks, and
+//! [`in_background`] turns any of it into a future that an async runtime can await without
+//! blocking its own threads
+
+use crate::batch::{BatchReport, Cancel};
+use crate::{StrResult, TransformOpts, WriteOpts};
+use json::JsonValue;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+/// Calls `each` on every item from at most `limit` threads, giving the results in the items'
+/// order. Items not started before the token is cancelled are None
+fn run_pool<T: Sync, R: Send>(
+    items: &[T],
+    limit: NonZeroUsize,
+    cancel: &Cancel,
+    each: impl Fn(&T) -> R + Sync,
+) -> Vec<Option<R>> {
+    let next = AtomicUsize::new(0);
+    let results: Vec<_> = items.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..limit.get().min(items.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= items.len() || cancel.is_cancelled() {
+                    break;
+                }
+                let result = each(&items[index]);
+                *results[index].lock().unwrap() = Some(result);
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|r| r.into_inner().unwrap())
+        .collect()
+}
+
+/// Extracts the tags of each file, as [`crate::extract_tags`] gives them, from at most `limit`
+/// files at a time. The documents are in the order the files were given, leaving out those that
+/// failed, which are in the report
+pub fn extract_tags_concurrent(
+    files: &[PathBuf],
+    limit: NonZeroUsize,
+    cancel: &Cancel,
+) -> (Vec<(PathBuf, JsonValue)>, BatchReport) {
+    let results = run_pool(files, limit, cancel, |path| crate::extract_tags(path));
+    let mut documents = Vec::new();
+    let mut report = BatchReport::default();
+    for (path, result) in files.iter().zip(results) {
+        match result {
+            Some(Ok((json, _))) => {
+                report.succeed(path, "");
+                documents.push((path.clone(), json));
+            }
+            Some(Err(e)) => report.fail(path, e),
+            None => report.cancelled = true,
+        }
+    }
+    (documents, report)
+}
+
+/// Writes each document's tags to its file with [`crate::apply_tags`], to at most `limit` files
+/// at a time. Files the documents refer to are found relative to `base`
+pub fn apply_tags_concurrent(
+    jobs: &[(PathBuf, JsonValue)],
+    base: &Path,
+    write: &WriteOpts,
+    transform: &TransformOpts,
+    limit: NonZeroUsize,
+    cancel: &Cancel,
+) -> BatchReport {
+    let results = run_pool(jobs, limit, cancel, |(path, json)| {
+        crate::apply_tags(path, json, base, write, transform)
+    });
+    let mut report = BatchReport::default();
+    for ((path, _), result) in jobs.iter().zip(results) {
+        match result {
+            Some(Ok(())) => report.succeed(path, ""),
+            Some(Err(e)) => report.fail(path, e),
+            None => report.cancelled = true,
+        }
+    }
+    report
+}
+
+struct Shared<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// The result of work running on its own thread, which completes when the work finishes.
+/// A panic in the work is resumed in the task awaiting it. Dropping it doesn't stop the work;
+/// cancel the token given to it for that
+pub struct Background<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Future for Background<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(Ok(result)) => Poll::Ready(result),
+            Some(Err(panic)) => panic::resume_unwind(panic),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Runs `work` on a new thread, as in
+/// `in_background(move || apply_tags_concurrent(&jobs, &base, &write, &transform, limit, &cancel))`,
+/// returning a future for its result. It works with any runtime, as it needs nothing from one
+pub fn in_background<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Background<T> {
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+    let theirs = Arc::clone(&shared);
+    thread::spawn(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(work));
+        let mut shared = theirs.lock().unwrap();
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+    Background { shared }
+}
+
+/// Like [`in_background`] for a single file's [`crate::apply_tags`]
+pub fn apply_tags_async(
+    path: PathBuf,
+    json: JsonValue,
+    base: PathBuf,
+    write: WriteOpts,
+    transform: TransformOpts,
+) -> Background<StrResult<()>> {
+    in_background(move || crate::apply_tags(&path, &json, &base, &write, &transform))
+}
+
+/// Like [`in_background`] for a single file's [`crate::extract_tags`]
+pub fn extract_tags_async(path: PathBuf) -> Background<StrResult<JsonValue>> {
+    in_background(move || crate::extract_tags(&path).map(|(json, _)| json))
+}
@@ -0,0 +1,95 @@
+//! The ways a command can fail, each with its own exit status so scripts can tell them apart
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A file couldn't be opened, read or written
+    Io(String),
+    /// A file is locked by another process
+    Locked(String),
+    /// A tag or JSON document is malformed
+    Parse(String),
+    /// The file's format, or something in it, isn't one that can be handled
+    Unsupported(String),
+    /// Some of the files in a batch failed, although the rest succeeded
+    Partial(String),
+    /// There was nothing to do, such as no files matching
+    Nothing(String),
+    /// Anything else, such as options that don't make sense together
+    Other(String),
+}
+
+impl Error {
+    /// The process exit status for the error, as listed in the usage text
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Other(_) => 1,
+            // 2 is taken by clap for usage errors
+            Error::Io(_) => 3,
+            Error::Locked(_) => 4,
+            Error::Parse(_) => 5,
+            Error::Unsupported(_) => 6,
+            Error::Partial(_) => 7,
+            Error::Nothing(_) => 8,
+        }
+    }
+
+    /// The same kind of error, with what was being attempted put before the message
+    pub fn context(self, doing: &str) -> Error {
+        let wrap = |m: String| format!("{doing}: {m}");
+        match self {
+            Error::Io(m) => Error::Io(wrap(m)),
+            Error::Locked(m) => Error::Locked(wrap(m)),
+            Error::Parse(m) => Error::Parse(wrap(m)),
+            Error::Unsupported(m) => Error::Unsupported(wrap(m)),
+            Error::Partial(m) => Error::Partial(wrap(m)),
+            Error::Nothing(m) => Error::Nothing(wrap(m)),
+            Error::Other(m) => Error::Other(wrap(m)),
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Error::Io(m)
+            | Error::Locked(m)
+            | Error::Parse(m)
+            | Error::Unsupported(m)
+            | Error::Partial(m)
+            | Error::Nothing(m)
+            | Error::Other(m) => m,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Messages built with `format!` that aren't classified any further
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Other(message.to_owned())
+    }
+}
+
+/// Errors from the id3 crate, by whether the file couldn't be read or its tag is broken
+impl From<id3::Error> for Error {
+    fn from(e: id3::Error) -> Self {
+        match e.kind {
+            id3::ErrorKind::Io(_) => Error::Io(e.to_string()),
+            id3::ErrorKind::UnsupportedFeature => Error::Unsupported(e.to_string()),
+            _ => Error::Parse(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Estimating how much a dry run's writes would grow or shrink each file, and the batch overall
+
+use crate::{backend, header, splice, StrResult};
+use id3::Tag;
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+
+static FILES: AtomicUsize = AtomicUsize::new(0);
+static TOTAL_CHANGE: AtomicI64 = AtomicI64::new(0);
+
+fn art_bytes(tag: &Tag) -> usize {
+    tag.pictures().map(|p| p.data.len()).sum()
+}
+
+/// Prints how writing `tag` would change the file's size, and adds it to the batch total
+pub fn record(path: &Path, tag: &Tag, crc: bool) -> StrResult<()> {
+    let (old_tag, padding, new_len) = if let Some(backend) = backend::for_path(path) {
+        let (old_len, new_len) = backend.tag_sizes(path, tag)?;
+        (old_len, 0, new_len)
+    } else {
+        let (old_tag, padding) = header::tag_span(path)?;
+        let (_, encoded) = splice::encode_id3(path, tag, crc)?;
+        (old_tag, padding, encoded.len() as u64)
+    };
+    let old_art = crate::read_tag(path).map_or(0, |t| art_bytes(&t));
+    let change = new_len as i64 - (old_tag + padding) as i64;
+    println!(
+        "{}: tag {old_tag} + {padding} padding -> {new_len} bytes, art {old_art} -> {} bytes, {change:+} bytes",
+        path.to_string_lossy(),
+        art_bytes(tag),
+    );
+    FILES.fetch_add(1, Ordering::Relaxed);
+    TOTAL_CHANGE.fetch_add(change, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Prints the total change across every file recorded, if any were
+pub fn print_total() {
+    let files = FILES.load(Ordering::Relaxed);
+    if files > 0 {
+        let change = TOTAL_CHANGE.load(Ordering::Relaxed);
+        eprintln!("Dry run: {files} files would change by {change:+} bytes in total");
+    }
+}
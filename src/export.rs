@@ -0,0 +1,386 @@
+//! Exporting tags to formats meant for other programs and people: playlists, catalogues and
+//! podcast feeds
+
+use crate::batch::BatchReport;
+use crate::{backend, paths, properties, report, timespec, Error, StrResult};
+use id3::{Tag, TagLike};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// The duration of a track in seconds, from the audio stream if possible and TLEN otherwise
+pub fn duration_secs(path: &Path, tag: &Tag) -> Option<f64> {
+    if let Some(duration) = backend::for_path(path).and_then(|b| b.duration(path)) {
+        return Some(duration);
+    }
+    if let Ok(Some(props)) = properties::read_properties(path) {
+        if let Some(duration) = props.duration {
+            return Some(duration);
+        }
+    }
+    tag.duration().map(|ms| ms as f64 / 1000.0)
+}
+
+/// Expresses `path` relative to `base` where both are in the same tree, falling back to the path as given
+pub fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let (Ok(path_abs), Ok(base_abs)) = (path.canonicalize(), base.canonicalize()) else {
+        return path.to_owned();
+    };
+    let path_parts: Vec<Component> = path_abs.components().collect();
+    let base_parts: Vec<Component> = base_abs.components().collect();
+    let common = path_parts
+        .iter()
+        .zip(&base_parts)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return path.to_owned();
+    }
+    let mut relative = PathBuf::new();
+    for _ in common..base_parts.len() {
+        relative.push("..");
+    }
+    for part in &path_parts[common..] {
+        relative.push(part);
+    }
+    relative
+}
+
+/// Whether the track is in the language, if one is given, going by TLAN and any lyrics
+fn in_language(tag: &Tag, language: Option<&str>) -> bool {
+    language.is_none_or(|l| report::languages(tag).contains(&l.to_lowercase()))
+}
+
+/// Builds an extended M3U playlist of the files, with `#EXTALB`/`#EXTART` lines for players that
+/// read them. Entries are written relative to `base` if given, and only tracks in `language` are
+/// listed if that's given. Files whose tags can't be read are listed by name, with a warning.
+pub fn m3u(
+    files: &[PathBuf],
+    base: Option<&Path>,
+    language: Option<&str>,
+) -> (String, BatchReport) {
+    let mut out = String::from("#EXTM3U\n");
+    let mut report = BatchReport::default();
+    for file in paths::collect_audio_files(files, true) {
+        let tag = match crate::read_tag(&file) {
+            Ok(t) => t,
+            Err(e) => {
+                report.warn(&file, format!("Listed without its tags: {e}"));
+                Tag::new()
+            }
+        };
+        if !in_language(&tag, language) {
+            continue;
+        }
+        let duration = duration_secs(&file, &tag)
+            .map(|d| d.round() as i64)
+            .unwrap_or(-1);
+        let title = tag.title().map(str::to_owned).unwrap_or_else(|| {
+            let stem = file.file_stem().unwrap_or_default();
+            stem.to_string_lossy().into_owned()
+        });
+        let display = match tag.artist() {
+            Some(artist) => format!("{artist} - {title}"),
+            None => title,
+        };
+        out.push_str(&format!(
+            "#EXTINF:{duration},{}\n",
+            display.replace('\n', " ")
+        ));
+        if let Some(album) = tag.album() {
+            out.push_str(&format!("#EXTALB:{album}\n"));
+        }
+        if let Some(artist) = tag.album_artist().or(tag.artist()) {
+            out.push_str(&format!("#EXTART:{artist}\n"));
+        }
+        let entry = match base {
+            Some(base) => relative_to(&file, base),
+            None => file.clone(),
+        };
+        out.push_str(&entry.to_string_lossy());
+        out.push('\n');
+        report.succeed(&file, "");
+    }
+    (out, report)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            '\0' => out.push_str(" / "),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Percent-encodes a relative path for use in an `href` or `src` attribute
+fn escape_url(path: &Path) -> String {
+    let mut out = String::new();
+    for (i, part) in path.components().enumerate() {
+        if i > 0 {
+            out.push('/');
+        }
+        for &byte in part.as_os_str().as_encoded_bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    out.push(byte as char)
+                }
+                b => out.push_str(&format!("%{b:02X}")),
+            }
+        }
+    }
+    out
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+.album { display: flex; gap: 1.5em; margin-bottom: 2em; }
+.album img, .album .nocover { width: 160px; height: 160px; object-fit: cover; background: #ddd; flex: none; }
+.album h2 { margin: 0; }
+.album h3 { margin: 0.2em 0 0.8em; font-weight: normal; color: #555; }
+table { border-collapse: collapse; }
+td { padding: 0.1em 0.8em 0.1em 0; vertical-align: top; }
+td.num, td.time { text-align: right; color: #777; }";
+
+/// Writes a static `index.html` into `out` listing each directory of audio below `dir` as an album,
+/// with its cover copied into `out/covers` and shown as a thumbnail. As with [`m3u`], tracks not in
+/// `language` are left out if it's given. Files whose tags can't be read are left out and reported.
+pub fn html(dir: &Path, out: &Path, language: Option<&str>) -> StrResult<BatchReport> {
+    let mut albums: BTreeMap<PathBuf, Vec<(u32, PathBuf, Tag)>> = BTreeMap::new();
+    let mut report = BatchReport::default();
+    for file in paths::collect_audio_files(&[dir.to_owned()], true) {
+        let tag = match crate::read_tag(&file) {
+            Ok(t) => t,
+            Err(e) => {
+                report.fail(&file, e);
+                continue;
+            }
+        };
+        if !in_language(&tag, language) {
+            continue;
+        }
+        let parent = file.parent().map(Path::to_owned).unwrap_or_default();
+        let number = tag.track().unwrap_or(u32::MAX);
+        report.succeed(&file, "");
+        albums.entry(parent).or_default().push((number, file, tag));
+    }
+
+    let covers = out.join("covers");
+    std::fs::create_dir_all(&covers)
+        .map_err(|e| Error::Io(format!("Cannot create {}: {e}", covers.to_string_lossy())))?;
+
+    let mut body = String::new();
+    for (index, (album_dir, mut tracks)) in albums.into_iter().enumerate() {
+        tracks.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+        let first = &tracks[0].2;
+        let name = first.album().map(str::to_owned).unwrap_or_else(|| {
+            let dir = album_dir.canonicalize().unwrap_or(album_dir.clone());
+            let name = dir.file_name().unwrap_or_default();
+            name.to_string_lossy().into_owned()
+        });
+        let artist = first.album_artist().or(first.artist()).unwrap_or_default();
+
+        let cover = tracks.iter().find_map(|(_, _, tag)| tag.pictures().next());
+        let cover_html = match cover {
+            Some(pic) => {
+                let ext = crate::image::extension(&pic.mime_type);
+                let file_name = format!("{index:04}.{ext}");
+                crate::write_data_to_path(&covers.join(&file_name), &pic.data)?;
+                format!(
+                    "<img src=\"covers/{file_name}\" alt=\"{}\" loading=\"lazy\">",
+                    escape_html(&name)
+                )
+            }
+            None => "<div class=\"nocover\"></div>".to_owned(),
+        };
+
+        let mut rows = String::new();
+        for (number, file, tag) in &tracks {
+            let title = tag.title().map(str::to_owned).unwrap_or_else(|| {
+                let stem = file.file_stem().unwrap_or_default();
+                stem.to_string_lossy().into_owned()
+            });
+            let track_artist = match tag.artist() {
+                Some(a) if a != artist => escape_html(a),
+                _ => String::new(),
+            };
+            let number = if *number == u32::MAX {
+                String::new()
+            } else {
+                number.to_string()
+            };
+            let time = duration_secs(file, tag)
+                .map(format_duration)
+                .unwrap_or_default();
+            let href = escape_url(&relative_to(file, out));
+            rows.push_str(&format!(
+                "<tr><td class=\"num\">{number}</td><td><a href=\"{href}\">{}</a></td><td>{track_artist}</td><td class=\"time\">{time}</td></tr>\n",
+                escape_html(&title)
+            ));
+        }
+
+        body.push_str(&format!(
+            "<div class=\"album\">\n{cover_html}\n<div>\n<h2>{}</h2>\n<h3>{}</h3>\n<table>\n{rows}</table>\n</div>\n</div>\n",
+            escape_html(&name),
+            escape_html(artist)
+        ));
+    }
+
+    let page = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Catalogue</title>\n<style>\n{HTML_STYLE}\n</style>\n</head>\n<body>\n{body}</body>\n</html>\n"
+    );
+    crate::write_data_to_path(&out.join("index.html"), page.as_bytes())?;
+    Ok(report)
+}
+
+/// Where a feed template's episodes go. Without it they go at the end of the channel
+const RSS_MARKER: &str = "<!-- episodes -->";
+
+/// The MIME type of an enclosure, by the file's extension
+fn audio_mime_type(path: &Path) -> &'static str {
+    const TYPES: [(&str, &str); 9] = [
+        ("mp3", "audio/mpeg"),
+        ("m4a", "audio/mp4"),
+        ("m4b", "audio/mp4"),
+        ("mp4", "audio/mp4"),
+        ("ogg", "audio/ogg"),
+        ("oga", "audio/ogg"),
+        ("opus", "audio/ogg"),
+        ("flac", "audio/flac"),
+        ("wav", "audio/wav"),
+    ];
+    TYPES
+        .iter()
+        .find(|(ext, _)| paths::has_extension(path, ext))
+        .map_or("application/octet-stream", |(_, mime)| mime)
+}
+
+/// A time in milliseconds as the `HH:MM:SS.mmm` Podlove chapters use
+fn chapter_time(ms: u32) -> String {
+    let secs = ms / 1000;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        ms % 1000
+    )
+}
+
+/// The `<item>` for one episode, with its enclosure at `url`
+fn rss_item(file: &Path, tag: &Tag, url: &str, published: Option<SystemTime>) -> String {
+    let title = tag.title().map(str::to_owned).unwrap_or_else(|| {
+        let stem = file.file_stem().unwrap_or_default();
+        stem.to_string_lossy().into_owned()
+    });
+    let mut item = format!("<item>\n  <title>{}</title>\n", escape_html(&title));
+    let description = tag
+        .comments()
+        .map(|c| c.text.as_str())
+        .chain(tag.lyrics().map(|l| l.text.as_str()))
+        .find(|text| !text.trim().is_empty());
+    if let Some(description) = description {
+        item.push_str(&format!(
+            "  <description>{}</description>\n",
+            escape_html(description)
+        ));
+    }
+    let length = file.metadata().map(|m| m.len()).unwrap_or(0);
+    item.push_str(&format!(
+        "  <enclosure url=\"{}\" length=\"{length}\" type=\"{}\"/>\n  <guid>{}</guid>\n",
+        escape_html(url),
+        audio_mime_type(file),
+        escape_html(url)
+    ));
+    if let Some(published) = published {
+        item.push_str(&format!(
+            "  <pubDate>{}</pubDate>\n",
+            timespec::rfc2822(published)
+        ));
+    }
+    if let Some(secs) = duration_secs(file, tag) {
+        let secs = secs.round() as u64;
+        item.push_str(&format!(
+            "  <itunes:duration>{}:{:02}:{:02}</itunes:duration>\n",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        ));
+    }
+    let mut chapters: Vec<_> = tag.chapters().collect();
+    if !chapters.is_empty() {
+        chapters.sort_by_key(|c| c.start_time);
+        item.push_str("  <psc:chapters version=\"1.2\">\n");
+        for chapter in chapters {
+            let title = chapter
+                .frames
+                .iter()
+                .find(|f| f.id() == "TIT2")
+                .and_then(|f| f.content().text())
+                .unwrap_or(&chapter.element_id);
+            item.push_str(&format!(
+                "    <psc:chapter start=\"{}\" title=\"{}\"/>\n",
+                chapter_time(chapter.start_time),
+                escape_html(title)
+            ));
+        }
+        item.push_str("  </psc:chapters>\n");
+    }
+    item.push_str("</item>\n");
+    item
+}
+
+/// Fills a podcast feed template with an `<item>` per audio file below `dir`, newest first by
+/// TDRL or TDRC, with enclosures under `base_url`. The items replace an `<!-- episodes -->`
+/// comment in the template, or else go before `</channel>`; the template declares the `itunes`
+/// and `psc` namespaces the durations and chapters use. Files whose tags can't be read are left
+/// out and reported.
+pub fn rss(template: &str, dir: &Path, base_url: &str) -> StrResult<(String, BatchReport)> {
+    let mut episodes = vec![];
+    let mut report = BatchReport::default();
+    for file in paths::collect_audio_files(&[dir.to_owned()], true) {
+        let tag = match crate::read_tag(&file) {
+            Ok(t) => t,
+            Err(e) => {
+                report.fail(&file, e);
+                continue;
+            }
+        };
+        let published = ["TDRL", "TDRC"]
+            .iter()
+            .filter_map(|id| tag.get(id)?.content().text())
+            .find_map(|date| timespec::parse_date(date).ok());
+        let url = format!(
+            "{}/{}",
+            base_url.trim_end_matches('/'),
+            escape_url(&relative_to(&file, dir))
+        );
+        episodes.push((published, rss_item(&file, &tag, &url, published)));
+        report.succeed(&file, "");
+    }
+    episodes.sort_by_key(|e| std::cmp::Reverse(e.0));
+    let items: String = episodes.into_iter().map(|(_, item)| item).collect();
+    if template.contains(RSS_MARKER) {
+        return Ok((template.replacen(RSS_MARKER, &items, 1), report));
+    }
+    match template.rfind("</channel>") {
+        Some(end) => Ok((
+            format!("{}{items}{}", &template[..end], &template[end..]),
+            report,
+        )),
+        None => Err(Error::Parse(format!(
+            "The feed template has no {RSS_MARKER} or </channel> to put the episodes in"
+        ))),
+    }
+}
@@ -0,0 +1,82 @@
+//! Filters on the files a batch takes in, by modification time, size and duration
+
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+static FILTER: OnceLock<Filter> = OnceLock::new();
+
+pub struct Filter {
+    pub modified_since: Option<SystemTime>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub min_duration: Option<Duration>,
+    pub max_duration: Option<Duration>,
+}
+
+/// Applies the filter to every batch for the rest of the run
+pub fn set(filter: Filter) {
+    let _ = FILTER.set(filter);
+}
+
+/// A size in bytes, with an optional k, M or G suffix in powers of 1024
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("{s:?} is not a size like 500k or 20M"))?;
+    let unit = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return Err(format!("Unknown unit {unit:?} in {s:?}; use k, M or G")),
+    };
+    Ok(number * unit)
+}
+
+/// Whether a file passes the filter, saying why on stderr when it doesn't for a reason other
+/// than falling outside the bounds given
+pub fn matches(path: &Path) -> bool {
+    let Some(filter) = FILTER.get() else {
+        return true;
+    };
+    let metadata = match path.metadata() {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Skipping {}: {e}", path.to_string_lossy());
+            return false;
+        }
+    };
+    if let Some(since) = filter.modified_since {
+        match metadata.modified() {
+            Ok(modified) if modified < since => return false,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!(
+                    "Skipping {}: no modification time: {e}",
+                    path.to_string_lossy()
+                );
+                return false;
+            }
+        }
+    }
+    let size = metadata.len();
+    if filter.min_size.is_some_and(|min| size < min)
+        || filter.max_size.is_some_and(|max| size > max)
+    {
+        return false;
+    }
+    if filter.min_duration.is_none() && filter.max_duration.is_none() {
+        return true;
+    }
+    let tag = crate::read_tag(path).unwrap_or_default();
+    let Some(duration) = crate::export::duration_secs(path, &tag) else {
+        eprintln!("Skipping {}: duration unknown", path.to_string_lossy());
+        return false;
+    };
+    let duration = Duration::from_secs_f64(duration);
+    !(filter.min_duration.is_some_and(|min| duration < min)
+        || filter.max_duration.is_some_and(|max| duration > max))
+}
@@ -0,0 +1,611 @@
+//! FLAC files, whose tags are Vorbis comments and PICTURE metadata blocks. They're read into and
+//! written from the same id3 `Tag` used for MP3s, so extracted JSON looks the same for both.
+
+use crate::backend::{self, Backend};
+use crate::sniff::Format;
+use crate::{base64, image, splice, Error, StrResult};
+use id3::frame::{Comment, ExtendedText, Lyrics, Picture, PictureType};
+use id3::{Content, Frame, Tag, TagLike};
+use json::JsonValue;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"fLaC";
+const STREAMINFO: u8 = 0;
+const PADDING: u8 = 1;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+/// Padding left after the metadata whenever the audio has to be moved anyway, so later edits needn't
+const NEW_PADDING: usize = 8192;
+const MAX_BLOCK_LEN: usize = (1 << 24) - 1;
+
+/// Vorbis comment fields for the frames with a conventional equivalent, as written by most taggers.
+/// Where several fields are in use for one frame, the first is written and all are read.
+const FIELDS: &[(&str, &str)] = &[
+    ("TIT1", "GROUPING"),
+    ("TIT2", "TITLE"),
+    ("TIT3", "SUBTITLE"),
+    ("TPE1", "ARTIST"),
+    ("TPE2", "ALBUMARTIST"),
+    ("TPE2", "ALBUM ARTIST"),
+    ("TPE3", "CONDUCTOR"),
+    ("TPE4", "REMIXER"),
+    ("TALB", "ALBUM"),
+    ("TRCK", "TRACKNUMBER"),
+    ("TPOS", "DISCNUMBER"),
+    ("TDRC", "DATE"),
+    ("TDOR", "ORIGINALDATE"),
+    ("TCON", "GENRE"),
+    ("TCOM", "COMPOSER"),
+    ("TEXT", "LYRICIST"),
+    ("TPUB", "LABEL"),
+    ("TPUB", "ORGANIZATION"),
+    ("TPUB", "PUBLISHER"),
+    ("TCOP", "COPYRIGHT"),
+    ("TBPM", "BPM"),
+    ("TKEY", "INITIALKEY"),
+    ("TMOO", "MOOD"),
+    ("TLAN", "LANGUAGE"),
+    ("TSRC", "ISRC"),
+    ("TENC", "ENCODEDBY"),
+    ("TSSE", "ENCODER"),
+    ("TMED", "MEDIA"),
+    ("TOPE", "ORIGINALARTIST"),
+    ("TSOA", "ALBUMSORT"),
+    ("TSOP", "ARTISTSORT"),
+    ("TSOT", "TITLESORT"),
+    ("TSO2", "ALBUMARTISTSORT"),
+    ("TSOC", "COMPOSERSORT"),
+    ("TCMP", "COMPILATION"),
+    ("COMM", "COMMENT"),
+    ("COMM", "DESCRIPTION"),
+    ("USLT", "LYRICS"),
+    ("USLT", "UNSYNCEDLYRICS"),
+];
+
+/// Fields holding the total that ID3 stores after a slash in TRCK and TPOS
+const TOTALS: [(&str, &str, &str); 2] = [
+    ("TRCK", "TRACKTOTAL", "TOTALTRACKS"),
+    ("TPOS", "DISCTOTAL", "TOTALDISCS"),
+];
+
+struct Block {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+/// The metadata of a FLAC file: any ID3v2 tag wrongly prepended to it, then the blocks
+struct Layout {
+    prefix: Vec<u8>,
+    blocks: Vec<Block>,
+    /// Where the audio frames begin
+    audio_start: u64,
+}
+
+fn be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| acc << 8 | *b as u64)
+}
+
+fn le_u32(bytes: &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?))
+}
+
+fn read_layout(path: &Path) -> StrResult<Layout> {
+    let read_error =
+        |e: std::io::Error| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy()));
+    let mut file = File::open(path).map_err(read_error)?;
+    let mut header = [0u8; 10];
+    file.read_exact(&mut header).map_err(read_error)?;
+    let mut prefix_len = 0;
+    if &header[..3] == b"ID3" {
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        prefix_len = 10
+            + header[6..10]
+                .iter()
+                .fold(0, |acc, b| acc << 7 | (*b & 0x7F) as u64)
+            + footer;
+    }
+    let mut prefix = vec![0u8; prefix_len as usize];
+    file.seek(SeekFrom::Start(0)).map_err(read_error)?;
+    file.read_exact(&mut prefix).map_err(read_error)?;
+
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic).map_err(read_error)?;
+    if &magic != MAGIC {
+        return Err(Error::Unsupported("Not a FLAC file".to_owned()));
+    }
+    let mut blocks = vec![];
+    loop {
+        let mut block_header = [0u8; 4];
+        file.read_exact(&mut block_header).map_err(read_error)?;
+        let kind = block_header[0] & 0x7F;
+        if kind == 127 {
+            return Err(Error::Parse("Invalid FLAC metadata block type".to_owned()));
+        }
+        let mut data = vec![0u8; be(&block_header[1..]) as usize];
+        file.read_exact(&mut data).map_err(read_error)?;
+        blocks.push(Block { kind, data });
+        if block_header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+    if blocks.first().is_none_or(|b| b.kind != STREAMINFO) {
+        return Err(Error::Parse(
+            "FLAC metadata does not start with STREAMINFO".to_owned(),
+        ));
+    }
+    let audio_start = file.stream_position().map_err(read_error)?;
+    Ok(Layout {
+        prefix,
+        blocks,
+        audio_start,
+    })
+}
+
+/// The vendor string and `NAME=value` fields of a Vorbis comment block
+pub fn parse_comments(data: &[u8]) -> StrResult<(String, Vec<(String, String)>)> {
+    let truncated = || "Truncated Vorbis comment block".to_owned();
+    let vendor_len = le_u32(data).ok_or_else(truncated)? as usize;
+    let vendor = data.get(4..4 + vendor_len).ok_or_else(truncated)?;
+    let mut pos = 4 + vendor_len;
+    let count = le_u32(&data[pos..]).ok_or_else(truncated)?;
+    pos += 4;
+    let mut fields = vec![];
+    for _ in 0..count {
+        let len = le_u32(&data[pos..]).ok_or_else(truncated)? as usize;
+        let entry = data.get(pos + 4..pos + 4 + len).ok_or_else(truncated)?;
+        pos += 4 + len;
+        let entry = String::from_utf8_lossy(entry);
+        if let Some((name, value)) = entry.split_once('=') {
+            fields.push((name.to_ascii_uppercase(), value.to_owned()));
+        }
+    }
+    Ok((String::from_utf8_lossy(vendor).into_owned(), fields))
+}
+
+fn picture_type(code: u32) -> PictureType {
+    use PictureType::*;
+    const TYPES: [PictureType; 21] = [
+        Other,
+        Icon,
+        OtherIcon,
+        CoverFront,
+        CoverBack,
+        Leaflet,
+        Media,
+        LeadArtist,
+        Artist,
+        Conductor,
+        Band,
+        Composer,
+        Lyricist,
+        RecordingLocation,
+        DuringRecording,
+        DuringPerformance,
+        ScreenCapture,
+        BrightFish,
+        Illustration,
+        BandLogo,
+        PublisherLogo,
+    ];
+    match TYPES.get(code as usize) {
+        Some(t) => *t,
+        None => Undefined(code.min(255) as u8),
+    }
+}
+
+fn parse_picture(data: &[u8]) -> StrResult<Picture> {
+    let truncated = || "Truncated FLAC picture block".to_owned();
+    let field = |pos: usize| data.get(pos..pos + 4).map(be).ok_or_else(truncated);
+    let kind = field(0)?;
+    let mime_len = field(4)? as usize;
+    let mime = data.get(8..8 + mime_len).ok_or_else(truncated)?;
+    let mut pos = 8 + mime_len;
+    let description_len = field(pos)? as usize;
+    let description = data
+        .get(pos + 4..pos + 4 + description_len)
+        .ok_or_else(truncated)?;
+    // Width, height, colour depth and palette size, which the id3 model has no place for
+    pos += 4 + description_len + 16;
+    let data_len = field(pos)? as usize;
+    let picture = data
+        .get(pos + 4..pos + 4 + data_len)
+        .ok_or_else(truncated)?;
+    Ok(Picture {
+        mime_type: String::from_utf8_lossy(mime).into_owned(),
+        picture_type: picture_type(kind as u32),
+        description: String::from_utf8_lossy(description).into_owned(),
+        data: picture.to_vec(),
+    })
+}
+
+/// The frame ID a field is read into, if it isn't user-defined text
+fn frame_for_field(field: &str) -> Option<&'static str> {
+    let mapped = FIELDS.iter().find(|(_, f)| *f == field).map(|(id, _)| *id);
+    mapped.or_else(|| FIELDS_BY_ID.iter().find(|id| **id == field).copied())
+}
+
+/// Frames with no conventional field, which are stored under their own ID
+const FIELDS_BY_ID: &[&str] = &[
+    "TDEN", "TDLY", "TDRL", "TDTG", "TFLT", "TIPL", "TLEN", "TMCL", "TOAL", "TOFN", "TOLY", "TOWN",
+    "TPRO", "TRSN", "TRSO", "TSST", "WCOM", "WCOP", "WOAF", "WOAR", "WOAS", "WORS", "WPAY", "WPUB",
+];
+
+fn read_tag(path: &Path) -> StrResult<Tag> {
+    let layout = read_layout(path)?;
+    let mut tag = Tag::new();
+    let mut fields = vec![];
+    for block in &layout.blocks {
+        match block.kind {
+            VORBIS_COMMENT => fields.extend(parse_comments(&block.data)?.1),
+            PICTURE => {
+                tag.add_frame(parse_picture(&block.data)?);
+            }
+            _ => {}
+        }
+    }
+    add_fields(&mut tag, fields, path);
+    Ok(tag)
+}
+
+/// Adds Vorbis comment fields to a tag as frames, including any pictures embedded as
+/// METADATA_BLOCK_PICTURE fields the way Ogg files carry them
+pub fn add_fields(tag: &mut Tag, fields: Vec<(String, String)>, path: &Path) {
+    // Field values in the order they first appear, gathered so repeated fields become one multi-valued frame
+    let mut values: Vec<(String, Vec<String>)> = vec![];
+    let mut totals: Vec<(&str, String)> = vec![];
+    for (field, value) in fields {
+        if let Some((id, _, _)) = TOTALS.iter().find(|(_, a, b)| field == *a || field == *b) {
+            totals.push((id, value));
+            continue;
+        }
+        if field == "METADATA_BLOCK_PICTURE" {
+            match base64::decode(&value).and_then(|d| parse_picture(&d)) {
+                Ok(picture) => {
+                    tag.add_frame(picture);
+                }
+                Err(e) => eprintln!(
+                    "Skipping unreadable picture in {}: {e}",
+                    path.to_string_lossy()
+                ),
+            }
+            continue;
+        }
+        let key = match frame_for_field(&field) {
+            Some(id) => id.to_owned(),
+            None => format!("TXXX:{field}"),
+        };
+        match values.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => existing.push(value),
+            None => values.push((key, vec![value])),
+        }
+    }
+    for (key, vals) in values {
+        let content = match key.as_str() {
+            "COMM" => Content::Comment(Comment {
+                lang: "XXX".to_owned(),
+                description: String::new(),
+                text: vals.join("\n"),
+            }),
+            "USLT" => Content::Lyrics(Lyrics {
+                lang: "XXX".to_owned(),
+                description: String::new(),
+                text: vals.join("\n"),
+            }),
+            id if id.starts_with('W') => Content::Link(vals.join(" ")),
+            id => match id.strip_prefix("TXXX:") {
+                Some(description) => Content::ExtendedText(ExtendedText {
+                    description: description.to_owned(),
+                    value: vals.join("\0"),
+                }),
+                None => Content::Text(vals.join("\0")),
+            },
+        };
+        let id = if key.starts_with("TXXX:") {
+            "TXXX"
+        } else {
+            key.as_str()
+        };
+        tag.add_frame(Frame::with_content(id, content));
+    }
+    for (id, total) in totals {
+        let number = tag
+            .get(id)
+            .and_then(|f| f.content().text())
+            .unwrap_or_default();
+        let number = number.split('/').next().unwrap_or_default().to_owned();
+        tag.set_text(id, format!("{number}/{total}"));
+    }
+}
+
+/// The `NAME=value` fields a tag is written as, and the names of any frames FLAC can't store
+pub fn fields_of(tag: &Tag) -> (Vec<(String, String)>, Vec<String>) {
+    let mut fields = vec![];
+    let mut skipped = vec![];
+    for frame in tag.frames() {
+        let id = frame.id();
+        let field = FIELDS
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map(|(_, f)| (*f).to_owned())
+            .or_else(|| FIELDS_BY_ID.contains(&id).then(|| id.to_owned()));
+        match (frame.content(), field) {
+            (Content::Text(text), Some(field)) => {
+                for value in text.split('\0') {
+                    match TOTALS.iter().find(|(i, _, _)| *i == id) {
+                        Some((_, total_field, _)) => {
+                            let (number, total) = value.split_once('/').unwrap_or((value, ""));
+                            fields.push((field.clone(), number.to_owned()));
+                            if !total.is_empty() {
+                                fields.push(((*total_field).to_owned(), total.to_owned()));
+                            }
+                        }
+                        None => fields.push((field.clone(), value.to_owned())),
+                    }
+                }
+            }
+            (Content::Link(link), Some(field)) => fields.push((field, link.clone())),
+            (Content::Comment(c), Some(field)) => fields.push((field, c.text.clone())),
+            (Content::Lyrics(l), Some(field)) => fields.push((field, l.text.clone())),
+            (Content::ExtendedText(t), _) => {
+                for value in t.value.split('\0') {
+                    fields.push((t.description.to_ascii_uppercase(), value.to_owned()));
+                }
+            }
+            (Content::Picture(_), _) => {}
+            _ => skipped.push(id.to_owned()),
+        }
+    }
+    (fields, skipped)
+}
+
+pub fn comment_block(vendor: &str, fields: &[(String, String)]) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend((vendor.len() as u32).to_le_bytes());
+    data.extend(vendor.as_bytes());
+    data.extend((fields.len() as u32).to_le_bytes());
+    for (name, value) in fields {
+        let entry = format!("{name}={value}");
+        data.extend((entry.len() as u32).to_le_bytes());
+        data.extend(entry.as_bytes());
+    }
+    data
+}
+
+pub fn picture_block(picture: &Picture) -> Vec<u8> {
+    let (width, height) = image::dimensions(&picture.data).unwrap_or((0, 0));
+    let mut data = vec![];
+    data.extend((u8::from(picture.picture_type) as u32).to_be_bytes());
+    data.extend((picture.mime_type.len() as u32).to_be_bytes());
+    data.extend(picture.mime_type.as_bytes());
+    data.extend((picture.description.len() as u32).to_be_bytes());
+    data.extend(picture.description.as_bytes());
+    // Colour depth and palette size are left as unknown
+    for value in [width, height, 0, 0, picture.data.len() as u32] {
+        data.extend(value.to_be_bytes());
+    }
+    data.extend(&picture.data);
+    data
+}
+
+/// Builds the new start of the file for a tag, returning it with the length of what it replaces.
+/// The old padding is reused when the metadata fits, so the audio needn't move.
+fn encode(path: &Path, tag: &Tag) -> StrResult<(u64, Vec<u8>)> {
+    let layout = read_layout(path)?;
+    let vendor = layout
+        .blocks
+        .iter()
+        .find(|b| b.kind == VORBIS_COMMENT)
+        .and_then(|b| parse_comments(&b.data).ok())
+        .map_or_else(|| "tag2json".to_owned(), |(vendor, _)| vendor);
+    let (fields, skipped) = fields_of(tag);
+    if !skipped.is_empty() {
+        eprintln!(
+            "{}: FLAC has no place for {}, which were not written",
+            path.to_string_lossy(),
+            skipped.join(", ")
+        );
+    }
+
+    let mut blocks: Vec<Block> = layout
+        .blocks
+        .into_iter()
+        .filter(|b| ![VORBIS_COMMENT, PICTURE, PADDING].contains(&b.kind))
+        .collect();
+    blocks.push(Block {
+        kind: VORBIS_COMMENT,
+        data: comment_block(&vendor, &fields),
+    });
+    for picture in tag.pictures() {
+        blocks.push(Block {
+            kind: PICTURE,
+            data: picture_block(picture),
+        });
+    }
+    if let Some(block) = blocks.iter().find(|b| b.data.len() > MAX_BLOCK_LEN) {
+        return Err(Error::Unsupported(format!(
+            "A FLAC metadata block can't hold {} bytes",
+            block.data.len()
+        )));
+    }
+
+    let old_len = layout.audio_start;
+    let used =
+        layout.prefix.len() + MAGIC.len() + blocks.iter().map(|b| 4 + b.data.len()).sum::<usize>();
+    let padding = match (old_len as usize).checked_sub(used + 4) {
+        Some(fits) => fits.min(MAX_BLOCK_LEN),
+        None => NEW_PADDING,
+    };
+    blocks.push(Block {
+        kind: PADDING,
+        data: vec![0; padding],
+    });
+
+    let mut head = layout.prefix;
+    head.extend(MAGIC);
+    let last = blocks.len() - 1;
+    for (i, block) in blocks.iter().enumerate() {
+        let flag = if i == last { 0x80 } else { 0 };
+        head.push(flag | block.kind);
+        head.extend(&(block.data.len() as u32).to_be_bytes()[1..]);
+        head.extend(&block.data);
+    }
+    Ok((old_len, head))
+}
+
+fn write_tag(path: &Path, file: &mut File, tag: &Tag, buffer_size: usize) -> StrResult<()> {
+    let (old_len, head) = encode(path, tag)?;
+    splice::splice(file, old_len, &head, buffer_size)
+        .map_err(|e| Error::Io(format!("Could not write tags: {e}")))
+}
+
+/// The audio format as given in the STREAMINFO block
+struct StreamInfo {
+    sample_rate: u32,
+    channels: u8,
+    bits_per_sample: u8,
+    total_samples: u64,
+}
+
+impl StreamInfo {
+    fn duration(&self) -> Option<f64> {
+        (self.sample_rate > 0 && self.total_samples > 0)
+            .then(|| self.total_samples as f64 / self.sample_rate as f64)
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut json = JsonValue::new_object();
+        json["format"] = "flac".into();
+        json["sample_rate"] = self.sample_rate.into();
+        json["channels"] = self.channels.into();
+        json["bits_per_sample"] = self.bits_per_sample.into();
+        if let Some(duration) = self.duration() {
+            json["duration"] = ((duration * 1000.0).round() / 1000.0).into();
+        }
+        json
+    }
+}
+
+fn read_stream_info(path: &Path) -> StrResult<StreamInfo> {
+    let layout = read_layout(path)?;
+    let data = &layout.blocks[0].data;
+    if data.len() < 18 {
+        return Err(Error::Parse("Truncated STREAMINFO block".to_owned()));
+    }
+    // After the block and frame size limits: 20 bits of sample rate, 3 of channels less one,
+    // 5 of bits per sample less one, then 36 of total samples
+    let packed = be(&data[10..18]);
+    Ok(StreamInfo {
+        sample_rate: (packed >> 44) as u32,
+        channels: ((packed >> 41) & 0x7) as u8 + 1,
+        bits_per_sample: ((packed >> 36) & 0x1F) as u8 + 1,
+        total_samples: packed & 0xF_FFFF_FFFF,
+    })
+}
+
+pub struct Flac;
+
+impl Backend for Flac {
+    fn handles(&self, format: Format) -> bool {
+        format == Format::Flac
+    }
+
+    fn read_tag(&self, path: &Path) -> StrResult<Tag> {
+        read_tag(path)
+    }
+
+    fn write_tag(
+        &self,
+        path: &Path,
+        file: &mut File,
+        tag: &Tag,
+        buffer_size: usize,
+    ) -> StrResult<()> {
+        write_tag(path, file, tag, buffer_size)
+    }
+
+    fn properties(&self, path: &Path) -> StrResult<JsonValue> {
+        Ok(read_stream_info(path)?.to_json())
+    }
+
+    /// Padding is reused rather than reclaimed, so the metadata is counted as a whole
+    fn tag_sizes(&self, path: &Path, tag: &Tag) -> StrResult<(u64, u64)> {
+        let (old_len, head) = encode(path, tag)?;
+        Ok((old_len, head.len() as u64))
+    }
+
+    fn audio_span(&self, path: &Path) -> StrResult<(u64, u64)> {
+        Ok((read_layout(path)?.audio_start, backend::file_len(path)?))
+    }
+
+    fn duration(&self, path: &Path) -> Option<f64> {
+        read_stream_info(path).ok()?.duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const AUDIO: &[u8] = &[0xFF, 0xF8, 0x69, 0x08, 0x00, 0x2A, 0x11, 0x22, 0x33, 0x44];
+
+    /// A FLAC file of one second of 16-bit stereo at 44.1 kHz, as far as STREAMINFO says
+    fn flac() -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend([0x80 | STREAMINFO, 0, 0, 34]);
+        data.extend([0x10, 0, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        let packed: u64 = 44100 << 44 | 1 << 41 | 15 << 36 | 44100;
+        data.extend(packed.to_be_bytes());
+        data.extend([0; 16]);
+        data.extend(AUDIO);
+        data
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        let dir = testing::scratch_dir("flac-round-trip");
+        let path = dir.join("t.flac");
+        std::fs::write(&path, flac()).unwrap();
+        assert!(read_tag(&path).unwrap().frames().next().is_none());
+
+        crate::write_tag(&path, &testing::tag(), &crate::WriteOpts::default()).unwrap();
+        testing::assert_round_trip(&crate::read_tag(&path).unwrap());
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.ends_with(AUDIO));
+        let properties = Flac.properties(&path).unwrap();
+        assert_eq!(properties["sample_rate"], 44100);
+        assert_eq!(properties["channels"], 2);
+        assert_eq!(properties["bits_per_sample"], 16);
+        assert_eq!(properties["duration"], 1.0);
+
+        // The padding left the first time takes the change, so the audio stays put
+        let mut tag = testing::tag();
+        tag.set_title("A longer title than before");
+        crate::write_tag(&path, &tag, &crate::WriteOpts::default()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap().len(), data.len());
+        assert_eq!(read_tag(&path).unwrap().title(), tag.title());
+    }
+
+    #[test]
+    fn damaged_files_are_errors() {
+        let dir = testing::scratch_dir("flac-damaged");
+        let path = dir.join("t.flac");
+        std::fs::write(&path, flac()).unwrap();
+        crate::write_tag(&path, &testing::tag(), &crate::WriteOpts::default()).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let audio_start = read_layout(&path).unwrap().audio_start as usize;
+
+        testing::each_truncation(&path, &data, audio_start, |path, len| {
+            assert!(read_tag(path).is_err(), "{len} bytes");
+            assert!(read_stream_info(path).is_err(), "{len} bytes");
+        });
+        testing::each_corruption(&path, &data, 200, |path| {
+            let _ = read_tag(path);
+            let _ = read_stream_info(path);
+        });
+        assert!(parse_comments(&[1, 0, 0, 0]).is_err());
+        assert!(parse_picture(&[0, 0, 0, 3, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+    }
+}
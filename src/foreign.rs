@@ -0,0 +1,116 @@
+//! ID3v2 tags prepended to streams that don't use them, such as FLAC or raw AAC, by tools that
+//! treat every file as an MP3. Players may refuse such files or ignore the tag.
+
+use crate::batch::BatchReport;
+use crate::{header, manifest, paranoid, paths, pictures, splice, Error, StrResult};
+use clap::ValueEnum;
+use id3::Tag;
+use json::JsonValue;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Files that shouldn't begin with an ID3v2 tag
+const FOREIGN_EXTENSIONS: [&str; 5] = ["flac", "aac", "m4a", "ogg", "opus"];
+
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// List the files with a foreign tag
+    Report,
+    /// Print the foreign tags as JSON, keyed by path
+    Read,
+    /// Save each tag to JSON and art sidecars, then strip it from the file
+    Migrate,
+    /// Remove the tag, leaving the stream as it should be
+    Strip,
+}
+
+/// What the stream after the tag is, judged by its first bytes
+fn stream_kind(start: &[u8]) -> &'static str {
+    match start {
+        [b'f', b'L', b'a', b'C', ..] => "FLAC",
+        [b'O', b'g', b'g', b'S', ..] => "Ogg",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "MP4",
+        // ADTS sync word with the MPEG layer bits zero
+        [0xFF, b, ..] if b & 0xF6 == 0xF0 => "AAC (ADTS)",
+        [0xFF, b, ..] if b & 0xE0 == 0xE0 => "MPEG audio",
+        _ => "unrecognised",
+    }
+}
+
+/// Finds a prepended tag, returning its length including padding and what follows it
+fn detect(path: &Path) -> StrResult<Option<(u64, &'static str)>> {
+    let (tag_len, padding) = header::tag_span(path)?;
+    if tag_len == 0 {
+        return Ok(None);
+    }
+    let mut start = [0u8; 8];
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| Error::Io(format!("Cannot open {}: {e}", path.to_string_lossy())))?;
+    let read = file
+        .seek(SeekFrom::Start(tag_len + padding))
+        .and_then(|_| file.read(&mut start))
+        .map_err(|e| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy())))?;
+    Ok(Some((tag_len + padding, stream_kind(&start[..read]))))
+}
+
+/// Rewrites the file without its first `len` bytes
+fn strip(path: &Path, len: u64) -> StrResult<()> {
+    let change = manifest::Change::begin(path);
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| Error::Io(format!("Cannot open {}: {e}", path.to_string_lossy())))?;
+    splice::splice(&mut file, len, &[], splice::DEFAULT_BUFFER_SIZE)
+        .map_err(|e| Error::Io(format!("Cannot write {}: {e}", path.to_string_lossy())))?;
+    drop(file);
+    change.finish();
+    Ok(())
+}
+
+fn read_tag(path: &Path) -> StrResult<Tag> {
+    paranoid::check_input(path)?;
+    let file = std::fs::File::open(path)
+        .map_err(|e| Error::Io(format!("Cannot open {}: {e}", path.to_string_lossy())))?;
+    Tag::read_from2(file).map_err(|e| Error::Parse(format!("Unable to read tag: {e}")))
+}
+
+/// Finds the files with a foreign tag and does `action` to each, reporting what was done. For
+/// [`Action::Read`], the tags are returned as an aggregate object keyed by path
+pub fn foreign_tags(files: &[PathBuf], action: Action) -> (JsonValue, BatchReport) {
+    let mut blob = JsonValue::new_object();
+    let mut report = BatchReport::default();
+    for file in paths::collect_files(files, true, &FOREIGN_EXTENSIONS) {
+        let (len, kind) = match detect(&file) {
+            Ok(Some(d)) => d,
+            Ok(None) => continue,
+            Err(e) => {
+                report.fail(&file, e);
+                continue;
+            }
+        };
+        let result = match action {
+            Action::Report => Ok(format!("{len} byte ID3v2 tag before {kind} stream")),
+            Action::Read => read_tag(&file).map(|tag| {
+                crate::insert_keyed(&mut blob, &file, crate::tag_to_json(&tag));
+                String::new()
+            }),
+            Action::Migrate => read_tag(&file).and_then(|tag| {
+                if let Some(picture) = tag.pictures().next() {
+                    let art = pictures::primary_path(&file.with_extension("jpeg"), picture);
+                    crate::write_data_to_path(&art, &picture.data)?;
+                }
+                let json = json::stringify_pretty(crate::tag_to_json(&tag), 4);
+                crate::write_data_to_path(&file.with_extension("json"), json.as_bytes())?;
+                strip(&file, len)?;
+                Ok("migrated".to_owned())
+            }),
+            Action::Strip => strip(&file, len).map(|()| "stripped".to_owned()),
+        };
+        match result {
+            Ok(note) => report.succeed(&file, note),
+            Err(e) => report.fail(&file, e),
+        }
+    }
+    (blob, report)
+}
@@ -0,0 +1,52 @@
+//! Broad categories of frame, for extracting only part of a tag
+
+use id3::frame::Picture;
+use json::JsonValue;
+
+/// A category of frame, by the kind of data it holds
+#[derive(Clone, Copy, PartialEq)]
+pub enum FrameClass {
+    /// T*** frames, including user-defined text, and comments and lyrics
+    Text,
+    /// W*** link frames
+    Url,
+    /// Pictures and other binary payloads, including chapters since they can carry images, and
+    /// every other frame
+    Binary,
+}
+
+/// The class of an extracted JSON key. Informational keys have none and are always kept
+pub fn class_of(key: &str) -> Option<FrameClass> {
+    if key.starts_with('_') {
+        None
+    } else if key.starts_with('W') {
+        Some(FrameClass::Url)
+    } else if key.starts_with('T') || key == "COMM" || key == "USLT" {
+        Some(FrameClass::Text)
+    } else {
+        Some(FrameClass::Binary)
+    }
+}
+
+/// Drops the frames of an extracted document, and the art, outside the class. Everything is kept
+/// if there is no class to keep
+pub fn keep_only(
+    only: Option<FrameClass>,
+    json: JsonValue,
+    pictures: Vec<Picture>,
+) -> (JsonValue, Vec<Picture>) {
+    let Some(only) = only else {
+        return (json, pictures);
+    };
+    let mut kept = JsonValue::new_object();
+    for (key, value) in json.entries() {
+        if class_of(key).is_none_or(|c| c == only) {
+            kept[key] = value.clone();
+        }
+    }
+    let pictures = match only {
+        FrameClass::Binary => pictures,
+        _ => vec![],
+    };
+    (kept, pictures)
+}
@@ -0,0 +1,135 @@
+//! What each frame ID means, for output aimed at people who don't know the ID3 specification
+
+/// The standard ID3v2.3 and ID3v2.4 frames with a short description of each
+const DESCRIPTIONS: &[(&str, &str)] = &[
+    ("AENC", "Audio encryption"),
+    ("APIC", "Attached picture"),
+    ("ASPI", "Audio seek point index"),
+    ("CHAP", "Chapter"),
+    ("COMM", "Comment"),
+    ("COMR", "Commercial information"),
+    ("CTOC", "Table of contents"),
+    ("ENCR", "Encryption method registration"),
+    ("EQU2", "Equalisation"),
+    ("EQUA", "Equalisation"),
+    ("ETCO", "Event timing codes"),
+    ("GEOB", "General encapsulated object"),
+    ("GRID", "Group identification registration"),
+    ("GRP1", "Grouping (iTunes)"),
+    ("IPLS", "Involved people list"),
+    ("LINK", "Linked information"),
+    ("MCDI", "Music CD identifier"),
+    ("MLLT", "MPEG location lookup table"),
+    ("MVIN", "Movement number (iTunes)"),
+    ("MVNM", "Movement name (iTunes)"),
+    ("OWNE", "Ownership"),
+    ("PCNT", "Play counter"),
+    ("PCST", "Podcast flag (iTunes)"),
+    ("POPM", "Popularimeter (rating)"),
+    ("POSS", "Position synchronisation"),
+    ("PRIV", "Private data"),
+    ("RBUF", "Recommended buffer size"),
+    ("RVA2", "Relative volume adjustment"),
+    ("RVAD", "Relative volume adjustment"),
+    ("RVRB", "Reverb"),
+    ("SEEK", "Seek"),
+    ("SIGN", "Signature"),
+    ("SYLT", "Synchronised lyrics"),
+    ("SYTC", "Synchronised tempo codes"),
+    ("TALB", "Album"),
+    ("TBPM", "Beats per minute"),
+    ("TCMP", "Compilation (iTunes)"),
+    ("TCOM", "Composer"),
+    ("TCON", "Genre"),
+    ("TCOP", "Copyright"),
+    ("TDAT", "Date (DDMM)"),
+    ("TDEN", "Encoding time"),
+    ("TDLY", "Playlist delay"),
+    ("TDOR", "Original release time"),
+    ("TDRC", "Recording time"),
+    ("TDRL", "Release time"),
+    ("TDTG", "Tagging time"),
+    ("TENC", "Encoded by"),
+    ("TEXT", "Lyricist"),
+    ("TFLT", "File type"),
+    ("TIME", "Time (HHMM)"),
+    ("TIPL", "Involved people"),
+    ("TIT1", "Grouping"),
+    ("TIT2", "Title"),
+    ("TIT3", "Subtitle"),
+    ("TKEY", "Initial key"),
+    ("TLAN", "Language"),
+    ("TLEN", "Length (ms)"),
+    ("TMCL", "Musician credits"),
+    ("TMED", "Media type"),
+    ("TMOO", "Mood"),
+    ("TOAL", "Original album"),
+    ("TOFN", "Original filename"),
+    ("TOLY", "Original lyricist"),
+    ("TOPE", "Original artist"),
+    ("TORY", "Original release year"),
+    ("TOWN", "File owner"),
+    ("TPE1", "Artist"),
+    ("TPE2", "Album artist"),
+    ("TPE3", "Conductor"),
+    ("TPE4", "Remixer"),
+    ("TPOS", "Disc number"),
+    ("TPRO", "Produced notice"),
+    ("TPUB", "Publisher"),
+    ("TRCK", "Track number"),
+    ("TRDA", "Recording dates"),
+    ("TRSN", "Internet radio station name"),
+    ("TRSO", "Internet radio station owner"),
+    ("TSIZ", "Size"),
+    ("TSO2", "Album artist sort order (iTunes)"),
+    ("TSOA", "Album sort order"),
+    ("TSOC", "Composer sort order (iTunes)"),
+    ("TSOP", "Artist sort order"),
+    ("TSOT", "Title sort order"),
+    ("TSRC", "ISRC"),
+    ("TSSE", "Encoder settings"),
+    ("TSST", "Set subtitle"),
+    ("TXXX", "User-defined text"),
+    ("TYER", "Year"),
+    ("UFID", "Unique file identifier"),
+    ("USER", "Terms of use"),
+    ("USLT", "Unsynchronised lyrics"),
+    ("WCOM", "Commercial information URL"),
+    ("WCOP", "Copyright URL"),
+    ("WOAF", "Audio file URL"),
+    ("WOAR", "Artist URL"),
+    ("WOAS", "Audio source URL"),
+    ("WORS", "Radio station URL"),
+    ("WPAY", "Payment URL"),
+    ("WPUB", "Publisher URL"),
+    ("WXXX", "User-defined URL"),
+];
+
+/// Frames removed in ID3v2.4, with the frame that replaces each if there is one
+const DEPRECATED: &[(&str, Option<&str>)] = &[
+    ("EQUA", Some("EQU2")),
+    ("IPLS", Some("TIPL")),
+    ("RVAD", Some("RVA2")),
+    ("TDAT", Some("TDRC")),
+    ("TIME", Some("TDRC")),
+    ("TORY", Some("TDOR")),
+    ("TRDA", Some("TDRC")),
+    ("TSIZ", None),
+    ("TYER", Some("TDRC")),
+];
+
+pub fn describe(id: &str) -> Option<&'static str> {
+    DESCRIPTIONS.iter().find(|(i, _)| *i == id).map(|(_, d)| *d)
+}
+
+/// A note on why the frame shouldn't be used, if it's deprecated
+pub fn deprecation(id: &str) -> Option<String> {
+    if id.len() == 3 {
+        return Some("ID3v2.2 frame".to_owned());
+    }
+    let (_, replacement) = DEPRECATED.iter().find(|(i, _)| *i == id)?;
+    Some(match replacement {
+        Some(new) => format!("deprecated in ID3v2.4, use {new}"),
+        None => "deprecated in ID3v2.4".to_owned(),
+    })
+}
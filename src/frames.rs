@@ -0,0 +1,457 @@
+//! The JSON form of frames that aren't plain text or links: an array under the frame ID of an
+//! object per frame. Comments, lyrics, user-defined text and ratings have their fields spelled out
+//!
+//! ```json
+//! "COMM": [{ "lang": "eng", "description": "", "text": "Recorded live" }],
+//! "TXXX": [{ "description": "REPLAYGAIN_TRACK_GAIN", "value": "-6.2 dB" }],
+//! "WXXX": [{ "description": "Bandcamp", "url": "https://example.bandcamp.com/album/x" }],
+//! "POPM": [{ "email": "me@example.com", "rating": 196, "counter": 12 }],
+//! "SYLT": [{ "lang": "eng", "description": "", "type": "lyrics", "text": "[00:12.30]First line\n" }]
+//! ```
+//!
+//! A POPM rating can instead be given as `"stars"` from 0 to 5, which extraction writes with
+//! `--stars`. Synchronized lyrics are given as LRC text, when their timestamps are in
+//! milliseconds. Frames identified by an owner carry their payload as base64
+//!
+//! ```json
+//! "UFID": [{ "owner": "http://musicbrainz.org", "data_base64": "MDRmMmQ..." }],
+//! "PRIV": [{ "owner": "WM/MediaClassPrimaryID", "data_base64": "vHz..." }],
+//! "GEOB": [{ "mime_type": "text/plain", "filename": "notes.txt", "description": "", "data_base64": "..." }]
+//! ```
+//!
+//! The involved people and musician credits lists are an entry per role and name, which apply
+//! gathers back into one frame
+//!
+//! ```json
+//! "TIPL": [{ "role": "producer", "name": "Steve Albini" }, { "role": "mix", "name": "..." }],
+//! "TMCL": [{ "role": "bass", "name": "Kim Deal" }]
+//! ```
+//!
+//! A play counter is just its count, `"PCNT": 42`, and any other frame is kept as its encoded
+//! body, `{ "data": "<base64>" }`, so it survives a round trip even when its contents aren't
+//! understood. Pictures and chapters have modules of their own.
+//!
+//! When applying, a single frame can also be given as the object alone, and a user-defined text
+//! frame as `"TXXX:DESCRIPTION": "value"`, the form templates and policy files name them by. A
+//! user-defined link can likewise be `"WXXX:DESCRIPTION": "url"`.
+
+use crate::{base64, lrc, release_ids, Error, StrResult};
+use id3::frame::{
+    Comment, EncapsulatedObject, ExtendedLink, ExtendedText, InvolvedPeopleList,
+    InvolvedPeopleListItem, Lyrics, Popularimeter, Private, SynchronisedLyrics,
+    SynchronisedLyricsType, TimestampFormat, UniqueFileIdentifier, Unknown,
+};
+use id3::{Content, Frame, Tag, TagLike, Version};
+use json::JsonValue;
+
+/// The keys with structured forms of their own elsewhere
+const ELSEWHERE: [&str; 3] = ["APIC", "CHAP", "CTOC"];
+
+/// The frames listing people by role, given as an entry per person rather than per frame
+const PEOPLE_LISTS: [&str; 2] = ["TIPL", "TMCL"];
+
+/// Names for what a SYLT frame's timed text is
+const SYLT_TYPES: [(SynchronisedLyricsType, &str); 7] = [
+    (SynchronisedLyricsType::Other, "other"),
+    (SynchronisedLyricsType::Lyrics, "lyrics"),
+    (SynchronisedLyricsType::Transcription, "transcription"),
+    (SynchronisedLyricsType::PartName, "part-name"),
+    (SynchronisedLyricsType::Event, "event"),
+    (SynchronisedLyricsType::Chord, "chord"),
+    (SynchronisedLyricsType::Trivia, "trivia"),
+];
+
+/// The POPM rating for each number of stars, as Windows Media Player and most taggers write them
+const STAR_RATINGS: [u8; 6] = [0, 1, 64, 128, 196, 255];
+
+/// The stars out of five a POPM rating shows as. Ratings other than the usual ones count as the
+/// nearest, and only 0 is unrated
+pub fn stars(rating: u8) -> u8 {
+    if rating == 0 {
+        return 0;
+    }
+    (1..=5u8)
+        .min_by_key(|&n| STAR_RATINGS[usize::from(n)].abs_diff(rating))
+        .unwrap_or(0)
+}
+
+/// Replaces the rating of each extracted POPM entry with its [`stars`], for people who think in
+/// stars rather than bytes
+pub fn use_stars(json: &mut JsonValue) {
+    for entry in json["POPM"].members_mut() {
+        let mut starred = JsonValue::new_object();
+        for (key, value) in entry.entries() {
+            match (key, value.as_u8()) {
+                ("rating", Some(rating)) => starred["stars"] = stars(rating).into(),
+                _ => starred[key] = value.clone(),
+            }
+        }
+        *entry = starred;
+    }
+}
+
+/// The frame's body as ID3v2.4 encodes it, found by writing it alone into a tag
+fn encoded_body(frame: &Frame) -> Option<Vec<u8>> {
+    if let Content::Unknown(unknown) = frame.content() {
+        return Some(unknown.data.clone());
+    }
+    let mut tag = Tag::new();
+    tag.add_frame(Frame::with_content(frame.id(), frame.content().clone()));
+    let mut out = vec![];
+    tag.write_to(&mut out, Version::Id3v24).ok()?;
+    // After the tag header come the frame's ID, syncsafe size and flags
+    let size = out
+        .get(14..18)?
+        .iter()
+        .fold(0usize, |size, b| size << 7 | usize::from(b & 0x7F));
+    out.get(20..20 + size).map(<[u8]>::to_vec)
+}
+
+/// The count of a PCNT frame, a big-endian number of at least four bytes, or None if it's
+/// malformed or too large to be a JSON number
+pub fn play_count(frame: &Frame) -> Option<u64> {
+    let Content::Unknown(unknown) = frame.content() else {
+        return None;
+    };
+    let bytes = &unknown.data;
+    let significant = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    if frame.id() != "PCNT" || bytes.len() < 4 || bytes.len() - significant > 8 {
+        return None;
+    }
+    Some(bytes.iter().fold(0, |count, b| count << 8 | u64::from(*b)))
+}
+
+/// A PCNT frame holding the count, in as few bytes as the four-byte minimum allows
+pub fn play_counter(count: u64) -> Frame {
+    let bytes = count.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(8).min(4);
+    let unknown = Unknown {
+        data: bytes[start..].to_vec(),
+        version: Version::Id3v24,
+    };
+    Frame::with_content("PCNT", Content::Unknown(unknown))
+}
+
+fn frame_to_json(frame: &Frame) -> Option<JsonValue> {
+    if play_count(frame).is_some() {
+        return None;
+    }
+    let mut json = JsonValue::new_object();
+    match frame.content() {
+        Content::Text(_)
+        | Content::Link(_)
+        | Content::Picture(_)
+        | Content::Chapter(_)
+        | Content::TableOfContents(_) => return None,
+        Content::ExtendedText(t) if release_ids::is_identifier(&t.description) => return None,
+        Content::UniqueFileIdentifier(u) if release_ids::recording_id(u).is_some() => return None,
+        Content::Comment(Comment {
+            lang,
+            description,
+            text,
+        })
+        | Content::Lyrics(Lyrics {
+            lang,
+            description,
+            text,
+        }) => {
+            json["lang"] = lang.as_str().into();
+            json["description"] = description.as_str().into();
+            json["text"] = text.as_str().into();
+        }
+        Content::ExtendedText(t) => {
+            json["description"] = t.description.as_str().into();
+            json["value"] = t.value.as_str().into();
+        }
+        Content::ExtendedLink(l) => {
+            json["description"] = l.description.as_str().into();
+            json["url"] = l.link.as_str().into();
+        }
+        Content::SynchronisedLyrics(s) if s.timestamp_format == TimestampFormat::Ms => {
+            json["lang"] = s.lang.as_str().into();
+            json["description"] = s.description.as_str().into();
+            json["type"] = SYLT_TYPES
+                .iter()
+                .find(|(t, _)| *t == s.content_type)
+                .map_or("other", |(_, name)| name)
+                .into();
+            json["text"] = lrc::to_lrc(&s.content).into();
+        }
+        Content::UniqueFileIdentifier(u) => {
+            json["owner"] = u.owner_identifier.as_str().into();
+            json["data_base64"] = base64::encode(&u.identifier).into();
+        }
+        Content::Private(p) => {
+            json["owner"] = p.owner_identifier.as_str().into();
+            json["data_base64"] = base64::encode(&p.private_data).into();
+        }
+        Content::EncapsulatedObject(o) => {
+            json["mime_type"] = o.mime_type.as_str().into();
+            json["filename"] = o.filename.as_str().into();
+            json["description"] = o.description.as_str().into();
+            json["data_base64"] = base64::encode(&o.data).into();
+        }
+        Content::Popularimeter(p) => {
+            json["email"] = p.user.as_str().into();
+            json["rating"] = p.rating.into();
+            json["counter"] = p.counter.into();
+        }
+        _ => json["data"] = base64::encode(&encoded_body(frame)?).into(),
+    }
+    Some(json)
+}
+
+/// The tag's structured frames as JSON keys and arrays, in the order their IDs first appear
+pub fn to_json(tag: &Tag) -> Vec<(String, JsonValue)> {
+    let mut grouped: Vec<(String, JsonValue)> = vec![];
+    for frame in tag.frames() {
+        let json: Vec<JsonValue> = match frame.content() {
+            Content::InvolvedPeopleList(list) => list
+                .items
+                .iter()
+                .map(|item| {
+                    let mut json = JsonValue::new_object();
+                    json["role"] = item.involvement.as_str().into();
+                    json["name"] = item.involvee.as_str().into();
+                    json
+                })
+                .collect(),
+            _ => frame_to_json(frame).into_iter().collect(),
+        };
+        if json.is_empty() {
+            continue;
+        }
+        match grouped.iter_mut().find(|(id, _)| id == frame.id()) {
+            Some((_, entries)) => {
+                for json in json {
+                    let _ = entries.push(json);
+                }
+            }
+            None => grouped.push((frame.id().to_owned(), json.into())),
+        }
+    }
+    grouped
+}
+
+/// Whether a JSON key and value are one of the arrays [`to_json`] gives, or a lone entry. An
+/// array of strings is the values of a text frame instead
+pub fn is_structured(key: &str, value: &JsonValue) -> bool {
+    (value.is_array() || value.is_object())
+        && text_values(value).is_none()
+        && !ELSEWHERE.contains(&key)
+        && !key.starts_with('_')
+}
+
+/// A text frame as JSON: a string, or an array of strings when it has several NUL-separated
+/// values, such as two artists
+pub fn text_to_json(text: &str) -> JsonValue {
+    match text.contains('\0') {
+        true => text.split('\0').collect::<Vec<_>>().into(),
+        false => text.into(),
+    }
+}
+
+/// The values of a text frame as [`text_to_json`] gives them, if the JSON is one
+pub fn text_values(value: &JsonValue) -> Option<Vec<&str>> {
+    match value {
+        JsonValue::Array(values) if !values.is_empty() => {
+            values.iter().map(|v| v.as_str()).collect()
+        }
+        value => Some(vec![value.as_str()?]),
+    }
+}
+
+/// The values of a text frame's JSON joined into one string by `separator`, for display
+pub fn joined_text(value: &JsonValue, separator: &str) -> Option<String> {
+    text_values(value).map(|values| values.join(separator))
+}
+
+fn text_field(entry: &JsonValue, name: &str) -> StrResult<String> {
+    match &entry[name] {
+        JsonValue::Null => Ok(String::new()),
+        value => value
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| Error::Parse(format!("\"{name}\" must be text"))),
+    }
+}
+
+fn number_field(entry: &JsonValue, name: &str) -> StrResult<u64> {
+    match &entry[name] {
+        JsonValue::Null => Ok(0),
+        value => value
+            .as_u64()
+            .ok_or_else(|| Error::Parse(format!("\"{name}\" must be a whole number"))),
+    }
+}
+
+/// The POPM rating of an entry giving either the rating itself or a number of stars
+fn popm_rating(entry: &JsonValue) -> StrResult<u8> {
+    match (entry.has_key("rating"), entry.has_key("stars")) {
+        (true, true) => Err(Error::Parse(
+            "Give either a rating or stars, not both".to_owned(),
+        )),
+        (_, true) => match number_field(entry, "stars")? {
+            stars @ 0..=5 => Ok(STAR_RATINGS[stars as usize]),
+            _ => Err(Error::Parse("POPM stars must be from 0 to 5".to_owned())),
+        },
+        _ => u8::try_from(number_field(entry, "rating")?)
+            .map_err(|_| Error::Parse("POPM rating must be from 0 to 255".to_owned())),
+    }
+}
+
+fn frame_from_json(id: &str, entry: &JsonValue) -> StrResult<Frame> {
+    if let Some(data) = entry["data"].as_str() {
+        let unknown = Unknown {
+            data: base64::decode(data)?,
+            version: Version::Id3v24,
+        };
+        return Ok(Frame::with_content(id, Content::Unknown(unknown)));
+    }
+    let lang = || -> StrResult<String> {
+        match text_field(entry, "lang")? {
+            lang if lang.is_empty() => Ok("XXX".to_owned()),
+            lang if lang.len() == 3 => Ok(lang),
+            lang => Err(Error::Parse(format!(
+                "Language \"{lang}\" must be a three-letter code"
+            ))),
+        }
+    };
+    let payload = || base64::decode(&text_field(entry, "data_base64")?);
+    let content = match id {
+        "COMM" => Content::Comment(Comment {
+            lang: lang()?,
+            description: text_field(entry, "description")?,
+            text: text_field(entry, "text")?,
+        }),
+        "USLT" => Content::Lyrics(Lyrics {
+            lang: lang()?,
+            description: text_field(entry, "description")?,
+            text: text_field(entry, "text")?,
+        }),
+        "TXXX" => Content::ExtendedText(ExtendedText {
+            description: text_field(entry, "description")?,
+            value: text_field(entry, "value")?,
+        }),
+        "WXXX" => Content::ExtendedLink(ExtendedLink {
+            description: text_field(entry, "description")?,
+            link: text_field(entry, "url")?,
+        }),
+        "SYLT" => {
+            let kind = text_field(entry, "type")?;
+            let content_type = match SYLT_TYPES
+                .iter()
+                .find(|(_, n)| n.eq_ignore_ascii_case(&kind))
+            {
+                Some((t, _)) => *t,
+                None if kind.is_empty() => SynchronisedLyricsType::Lyrics,
+                None => return Err(Error::Parse(format!("\"{kind}\" is not a SYLT type"))),
+            };
+            Content::SynchronisedLyrics(SynchronisedLyrics {
+                lang: lang()?,
+                timestamp_format: TimestampFormat::Ms,
+                content_type,
+                description: text_field(entry, "description")?,
+                content: lrc::parse(&text_field(entry, "text")?)?,
+            })
+        }
+        "UFID" => Content::UniqueFileIdentifier(UniqueFileIdentifier {
+            owner_identifier: text_field(entry, "owner")?,
+            identifier: payload()?,
+        }),
+        "PRIV" => Content::Private(Private {
+            owner_identifier: text_field(entry, "owner")?,
+            private_data: payload()?,
+        }),
+        "GEOB" => Content::EncapsulatedObject(EncapsulatedObject {
+            mime_type: text_field(entry, "mime_type")?,
+            filename: text_field(entry, "filename")?,
+            description: text_field(entry, "description")?,
+            data: payload()?,
+        }),
+        "POPM" => Content::Popularimeter(Popularimeter {
+            user: text_field(entry, "email")?,
+            rating: popm_rating(entry)?,
+            counter: number_field(entry, "counter")?,
+        }),
+        _ => {
+            return Err(Error::Parse(format!(
+                "{id} entries need their body as base64 under \"data\""
+            )))
+        }
+    };
+    Ok(Frame::with_content(id, content))
+}
+
+/// A `COMM` or `USLT` frame given as just its text, which is taken to be in English with no
+/// description, or `None` for other frames
+pub fn from_text(id: &str, text: &str) -> Option<Frame> {
+    let (lang, description, text) = ("eng".to_owned(), String::new(), text.to_owned());
+    let content = match id {
+        "COMM" => Content::Comment(Comment {
+            lang,
+            description,
+            text,
+        }),
+        "USLT" => Content::Lyrics(Lyrics {
+            lang,
+            description,
+            text,
+        }),
+        _ => return None,
+    };
+    Some(Frame::with_content(id, content))
+}
+
+/// The one frame the role and name entries of a people list make up
+fn people_from_json(id: &str, entries: &JsonValue) -> StrResult<Frame> {
+    let entries: Vec<&JsonValue> = match entries.is_object() {
+        true => vec![entries],
+        false => entries.members().collect(),
+    };
+    let items = entries
+        .into_iter()
+        .map(|entry| {
+            Ok(InvolvedPeopleListItem {
+                involvement: text_field(entry, "role")?,
+                involvee: text_field(entry, "name")?,
+            })
+        })
+        .collect::<StrResult<_>>()?;
+    let list = InvolvedPeopleList { items };
+    Ok(Frame::with_content(id, Content::InvolvedPeopleList(list)))
+}
+
+/// The frames an array under a frame ID describes, or the one frame an object on its own does
+pub fn from_json(id: &str, entries: &JsonValue) -> StrResult<Vec<Frame>> {
+    let raw = entries.has_key("data") || entries.members().any(|e| e.has_key("data"));
+    if PEOPLE_LISTS.contains(&id) && !raw {
+        return Ok(vec![
+            people_from_json(id, entries).map_err(|e| e.context(id))?
+        ]);
+    }
+    if entries.is_object() {
+        return Ok(vec![
+            frame_from_json(id, entries).map_err(|e| e.context(id))?
+        ]);
+    }
+    entries
+        .members()
+        .map(|entry| frame_from_json(id, entry).map_err(|e| e.context(id)))
+        .collect()
+}
+
+/// The TXXX or WXXX frame a `TXXX:DESCRIPTION` or `WXXX:DESCRIPTION` key names, given its value
+pub fn user_defined(key: &str, value: &str) -> Option<Frame> {
+    if let Some(description) = key.strip_prefix("WXXX:").filter(|d| !d.is_empty()) {
+        return Some(Frame::from(ExtendedLink {
+            description: description.to_owned(),
+            link: value.to_owned(),
+        }));
+    }
+    let description = key.strip_prefix("TXXX:").filter(|d| !d.is_empty())?;
+    Some(Frame::from(ExtendedText {
+        description: description.to_owned(),
+        value: value.to_owned(),
+    }))
+}
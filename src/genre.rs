@@ -0,0 +1,239 @@
+//! The ID3v1 genre numbers that TCON frames still carry, as `(17)` in ID3v2.3 or `17` in ID3v2.4,
+//! decoded to the names they stand for, and encoded again for players that only read the numbers
+//!
+//! An ID3v2.3 frame can hold several references and a refinement after them, as in
+//! `(4)(9)Eurodisco`. Each becomes a value of its own, with the refinement a last value unless it
+//! repeats a name already given. `(RX)` and `(CR)` stand for Remix and Cover, and `((` escapes a
+//! genre name starting with a parenthesis.
+
+use crate::frames;
+use id3::frame::Content;
+use id3::{Frame, Tag, TagLike};
+use json::JsonValue;
+
+/// The genres of ID3v1 and the Winamp extensions, by number
+const GENRES: [&str; 148] = [
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "Alternative Rock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native US",
+    "Cabaret",
+    "New Wave",
+    "Psychadelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+    "Folk",
+    "Folk-Rock",
+    "National Folk",
+    "Swing",
+    "Fast Fusion",
+    "Bebob",
+    "Latin",
+    "Revival",
+    "Celtic",
+    "Bluegrass",
+    "Avantgarde",
+    "Gothic Rock",
+    "Progressive Rock",
+    "Psychedelic Rock",
+    "Symphonic Rock",
+    "Slow Rock",
+    "Big Band",
+    "Chorus",
+    "Easy Listening",
+    "Acoustic",
+    "Humour",
+    "Speech",
+    "Chanson",
+    "Opera",
+    "Chamber Music",
+    "Sonata",
+    "Symphony",
+    "Booty Bass",
+    "Primus",
+    "Porn Groove",
+    "Satire",
+    "Slow Jam",
+    "Club",
+    "Tango",
+    "Samba",
+    "Folklore",
+    "Ballad",
+    "Power Ballad",
+    "Rhytmic Soul",
+    "Freestyle",
+    "Duet",
+    "Punk Rock",
+    "Drum Solo",
+    "Acapella",
+    "Euro-House",
+    "Dance Hall",
+    "Goa",
+    "Drum & Bass",
+    "Club-House",
+    "Hardcore",
+    "Terror",
+    "Indie",
+    "BritPop",
+    "Negerpunk",
+    "Polsk Punk",
+    "Beat",
+    "Christian Gangsta",
+    "Heavy Metal",
+    "Black Metal",
+    "Crossover",
+    "Contemporary C",
+    "Christian Rock",
+    "Merengue",
+    "Salsa",
+    "Thrash Metal",
+    "Anime",
+    "JPop",
+    "SynthPop",
+];
+
+fn name(number: &str) -> Option<&'static str> {
+    match number {
+        "RX" => Some("Remix"),
+        "CR" => Some("Cover"),
+        number => GENRES.get(number.parse::<usize>().ok()?).copied(),
+    }
+}
+
+/// The genre names one value of a TCON frame stands for. Anything that isn't a reference is kept
+/// as it is
+fn decode_value(value: &str) -> Vec<String> {
+    if let Some(name) = name(value) {
+        return vec![name.to_owned()];
+    }
+    let mut names: Vec<String> = vec![];
+    let mut rest = value;
+    while let Some(inner) = rest.strip_prefix('(') {
+        if inner.starts_with('(') {
+            break;
+        }
+        let Some((number, after)) = inner.split_once(')') else {
+            break;
+        };
+        let Some(name) = name(number) else {
+            break;
+        };
+        names.push(name.to_owned());
+        rest = after;
+    }
+    let refinement = match rest.strip_prefix("((") {
+        Some(escaped) => format!("({escaped}"),
+        None => rest.to_owned(),
+    };
+    if !refinement.is_empty() && !names.iter().any(|n| n.eq_ignore_ascii_case(&refinement)) {
+        names.push(refinement);
+    }
+    names
+}
+
+/// The names the TCON text stands for, with its values separated by NUL
+pub fn decode(text: &str) -> String {
+    let names: Vec<String> = text.split('\0').flat_map(decode_value).collect();
+    names.join("\0")
+}
+
+/// Replaces the numeric references in an extracted document's TCON with genre names
+pub fn use_names(json: &mut JsonValue) {
+    let Some(values) = frames::text_values(&json["TCON"]) else {
+        return;
+    };
+    let decoded = decode(&values.join("\0"));
+    json["TCON"] = frames::text_to_json(&decoded);
+}
+
+/// Writes each genre name with an ID3v1 number as that number, the ID3v2.4 form, keeping any
+/// other genre as text
+pub fn encode_tag(tag: &mut Tag) {
+    let Some(text) = tag.get("TCON").and_then(|f| f.content().text()) else {
+        return;
+    };
+    let encoded: Vec<String> = decode(text)
+        .split('\0')
+        .map(
+            |genre| match GENRES.iter().position(|g| g.eq_ignore_ascii_case(genre)) {
+                Some(number) => number.to_string(),
+                None => genre.to_owned(),
+            },
+        )
+        .collect();
+    let encoded = encoded.join("\0");
+    if encoded != text {
+        tag.add_frame(Frame::with_content("TCON", Content::Text(encoded)));
+    }
+}
@@ -0,0 +1,73 @@
+//! Searching tag values across a library
+
+use crate::regex::Regex;
+use crate::report::Format;
+use crate::{frames, paths, Error, StrResult};
+use json::JsonValue;
+use std::path::PathBuf;
+
+/// Lists the files with a value matching the pattern, in the chosen frames or any text frame
+pub fn grep(
+    pattern: &str,
+    files: &[PathBuf],
+    frames: &[String],
+    ignore_case: bool,
+    files_only: bool,
+    print0: bool,
+    format: Format,
+) -> StrResult<()> {
+    let regex = Regex::new(pattern, ignore_case)?;
+    let mut results = JsonValue::new_object();
+    let mut matched = 0;
+    for file in paths::collect_audio_files(files, true) {
+        let json = match crate::extract_tags_pic(&file) {
+            Ok((json, _)) => json,
+            Err(e) => {
+                eprintln!("Could not handle {}: {e}", file.to_string_lossy());
+                continue;
+            }
+        };
+        let mut hits = JsonValue::new_object();
+        for (key, value) in json.entries() {
+            let Some(values) = frames::text_values(value) else {
+                continue;
+            };
+            let selected = match frames.is_empty() {
+                true => !key.starts_with('_'),
+                false => frames.iter().any(|f| f.eq_ignore_ascii_case(key)),
+            };
+            // Multiple values are matched one at a time, so anchors apply to each
+            if selected && values.iter().any(|v| regex.is_match(v)) {
+                hits[key] = value.clone();
+            }
+        }
+        if hits.is_empty() {
+            continue;
+        }
+        matched += 1;
+        let (name, _) = paths::json_key(&file);
+        match (format, files_only) {
+            (Format::Text, true) if print0 => paths::print0(&file),
+            (Format::Text, true) => println!("{name}"),
+            (Format::Text, false) => {
+                for (key, value) in hits.entries() {
+                    let value = frames::joined_text(value, " / ").unwrap_or_default();
+                    println!("{name}: {key}: {value}");
+                }
+            }
+            (Format::Json, true) => results[name.as_str()] = JsonValue::Boolean(true),
+            (Format::Json, false) => results[name.as_str()] = hits,
+        }
+    }
+    if format == Format::Json {
+        if files_only {
+            let names: Vec<String> = results.entries().map(|(k, _)| k.to_owned()).collect();
+            results = names.into();
+        }
+        println!("{}", json::stringify_pretty(results, 4));
+    }
+    match matched {
+        0 => Err(Error::Nothing("No matches".to_owned())),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,322 @@
+//! Reading the ID3v2 header directly, for the layout details the id3 crate doesn't expose
+
+use crate::{Error, StrResult};
+use json::JsonValue;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// The size of the space the current ID3v2 tag occupies at the start of the file, split into the
+/// tag itself and the zero padding after it, which a rewrite reclaims
+pub fn tag_span(path: &Path) -> StrResult<(u64, u64)> {
+    let read_error =
+        |e: std::io::Error| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy()));
+    let file = std::fs::File::open(path).map_err(read_error)?;
+    let file_len = file.metadata().map_err(read_error)?.len();
+    let mut file = BufReader::new(file);
+    let mut header = [0u8; 10];
+    if file.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+        return Ok((0, 0));
+    }
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |acc, b| acc << 7 | (*b & 0x7F) as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    let tag_len = (10 + size + footer).min(file_len);
+    file.seek(SeekFrom::Start(tag_len)).map_err(read_error)?;
+    let padding = file
+        .bytes()
+        .take_while(|b| b.as_ref().is_ok_and(|b| *b == 0))
+        .count() as u64;
+    Ok((tag_len, padding))
+}
+
+/// The CRC-32 of ISO 3309, as ID3v2 uses: polynomial 0x04C11DB7 reflected, with the register and
+/// result inverted
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data
+        .iter()
+        .fold(!0, |crc, b| crc >> 8 ^ CRC_TABLE[(crc as u8 ^ b) as usize])
+}
+
+/// The length of the ID3v2.4 extended header [`add_crc`] inserts: its size, one flag byte, and
+/// the CRC as five syncsafe bytes after their length
+const CRC_HEADER_LEN: usize = 12;
+
+/// Inserts an extended header holding a CRC into an encoded ID3v2.4 tag with none. The CRC itself
+/// is left as zero for [`fill_crc`] to set once the tag's padding is final.
+pub fn add_crc(encoded: &mut Vec<u8>) {
+    if encoded.len() < 10 || encoded[3] != 4 || encoded[5] & 0x40 != 0 {
+        return;
+    }
+    let mut ext = syncsafe_bytes(CRC_HEADER_LEN as u64, 4);
+    ext.extend([1, 0x20, 5]);
+    ext.extend([0; 5]);
+    encoded.splice(10..10, ext);
+    encoded[5] |= 0x40;
+    let size = syncsafe(&encoded[6..10]) + CRC_HEADER_LEN as u64;
+    encoded[6..10].copy_from_slice(&syncsafe_bytes(size, 4));
+}
+
+/// Sets the CRC in an extended header written by [`add_crc`], over everything after it
+pub fn fill_crc(encoded: &mut [u8]) {
+    let start = 10 + CRC_HEADER_LEN;
+    if encoded.len() < start || encoded[5] & 0x40 == 0 || encoded[10..14] != syncsafe_bytes(12, 4) {
+        return;
+    }
+    let crc = crc32(&encoded[start..]);
+    encoded[start - 5..start].copy_from_slice(&syncsafe_bytes(u64::from(crc), 5));
+}
+
+fn syncsafe_bytes(value: u64, len: usize) -> Vec<u8> {
+    (0..len)
+        .rev()
+        .map(|i| (value >> (7 * i) & 0x7F) as u8)
+        .collect()
+}
+
+/// The CRC stored in the tag at the start of the file and the one its data actually has, or
+/// `None` if it has no tag or the tag stores no CRC
+pub fn crc_status(path: &Path) -> StrResult<Option<(u32, u32)>> {
+    let read_error =
+        |e: std::io::Error| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy()));
+    let mut file = std::fs::File::open(path).map_err(read_error)?;
+    let mut header = [0u8; 10];
+    if file.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" || header[5] & 0x40 == 0 {
+        return Ok(None);
+    }
+    let mut body = vec![];
+    file.take(syncsafe(&header[6..10]))
+        .read_to_end(&mut body)
+        .map_err(read_error)?;
+    let ext = extended_header(header[3], &body)?;
+    let Some(stored) = ext["crc"]
+        .as_str()
+        .and_then(|c| u32::from_str_radix(c, 16).ok())
+    else {
+        return Ok(None);
+    };
+    let start = ext["size"].as_usize().unwrap_or(0).min(body.len());
+    // ID3v2.3 leaves the padding out of the CRC, where ID3v2.4 includes it
+    let end = match ext["padding"].as_usize() {
+        Some(padding) if header[3] == 3 => body.len().saturating_sub(padding).max(start),
+        _ => body.len(),
+    };
+    Ok(Some((stored, crc32(&body[start..end]))))
+}
+
+/// What each pair or bit of the ID3v2.4 restrictions byte limits, and the meaning of each value
+const RESTRICTIONS: [(&str, u8, u8, &[&str]); 5] = [
+    (
+        "tag_size",
+        6,
+        0b11,
+        &[
+            "128 frames, 1 MB",
+            "64 frames, 128 KB",
+            "32 frames, 40 KB",
+            "32 frames, 4 KB",
+        ],
+    ),
+    ("text_encoding", 5, 0b1, &["any", "ISO-8859-1 or UTF-8"]),
+    (
+        "text_size",
+        3,
+        0b11,
+        &["any", "1024 characters", "128 characters", "30 characters"],
+    ),
+    ("image_encoding", 2, 0b1, &["any", "PNG or JPEG"]),
+    (
+        "image_size",
+        0,
+        0b11,
+        &[
+            "any",
+            "256x256 or smaller",
+            "64x64 or smaller",
+            "exactly 64x64",
+        ],
+    ),
+];
+
+fn syncsafe(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0u64, |acc, b| acc << 7 | (*b & 0x7F) as u64)
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |acc, b| acc << 8 | *b as u32)
+}
+
+/// The fields of the extended header, which starts the body of a tag whose header flags one
+fn extended_header(major: u8, body: &[u8]) -> StrResult<JsonValue> {
+    let truncated = || "Extended header is truncated".to_owned();
+    let mut json = JsonValue::new_object();
+    if major == 3 {
+        // The size excludes itself, and the CRC follows a fixed set of fields
+        let fixed = body.get(..10).ok_or_else(truncated)?;
+        json["size"] = (be_u32(&fixed[..4]) + 4).into();
+        let crc = fixed[4] & 0x80 != 0;
+        json["padding"] = be_u32(&fixed[6..10]).into();
+        if crc {
+            let crc = body.get(10..14).ok_or_else(truncated)?;
+            json["crc"] = format!("{:08x}", be_u32(crc)).into();
+        }
+        return Ok(json);
+    }
+    let fixed = body.get(..6).ok_or_else(truncated)?;
+    json["size"] = syncsafe(&fixed[..4]).into();
+    let flags = fixed[5];
+    json["update"] = (flags & 0x40 != 0).into();
+    // Each flag that carries data is followed, in flag order, by its length and then the data
+    let mut pos = 4 + 1 + usize::from(fixed[4]);
+    let data = |pos: &mut usize| -> StrResult<Vec<u8>> {
+        let len = usize::from(*body.get(*pos).ok_or_else(truncated)?);
+        let value = body.get(*pos + 1..*pos + 1 + len).ok_or_else(truncated)?;
+        *pos += 1 + len;
+        Ok(value.to_vec())
+    };
+    if flags & 0x40 != 0 {
+        data(&mut pos)?;
+    }
+    if flags & 0x20 != 0 {
+        let crc = syncsafe(&data(&mut pos)?);
+        json["crc"] = format!("{crc:08x}").into();
+    }
+    if flags & 0x10 != 0 {
+        let byte = *data(&mut pos)?.first().ok_or_else(truncated)?;
+        let mut restrictions = JsonValue::new_object();
+        for (name, shift, mask, meanings) in RESTRICTIONS {
+            restrictions[name] = meanings[usize::from(byte >> shift & mask)].into();
+        }
+        json["restrictions"] = restrictions;
+    }
+    Ok(json)
+}
+
+/// A description of the ID3v2 tag at the start of the file: its version, size, header flags and
+/// extended header, as stored and without parsing any frames
+pub fn inspect(path: &Path) -> StrResult<JsonValue> {
+    let read_error =
+        |e: std::io::Error| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy()));
+    let mut file = std::fs::File::open(path).map_err(read_error)?;
+    let mut header = [0u8; 10];
+    if file.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+        return Err(Error::Unsupported(
+            "No ID3v2 tag at the start of the file".to_owned(),
+        ));
+    }
+    let (major, flags) = (header[3], header[5]);
+    let size = syncsafe(&header[6..10]);
+    let (_, padding) = tag_span(path)?;
+    let mut json = JsonValue::new_object();
+    json["version"] = format!("2.{major}.{}", header[4]).into();
+    json["size"] = size.into();
+    json["padding_after"] = padding.into();
+
+    let mut names = JsonValue::new_object();
+    let defined: &[(&str, u8)] = match major {
+        2 => &[("unsynchronisation", 0x80), ("compression", 0x40)],
+        3 => &[
+            ("unsynchronisation", 0x80),
+            ("extended_header", 0x40),
+            ("experimental", 0x20),
+        ],
+        _ => &[
+            ("unsynchronisation", 0x80),
+            ("extended_header", 0x40),
+            ("experimental", 0x20),
+            ("footer", 0x10),
+        ],
+    };
+    for (name, bit) in defined {
+        names[*name] = (flags & bit != 0).into();
+    }
+    let undefined = flags & !defined.iter().fold(0, |all, (_, bit)| all | bit);
+    if undefined != 0 {
+        names["undefined"] = format!("{undefined:#04x}").into();
+    }
+    json["flags"] = names;
+
+    if major > 2 && flags & 0x40 != 0 {
+        // Enough for the largest extended header either version defines
+        let mut body = vec![];
+        file.take(32).read_to_end(&mut body).map_err(read_error)?;
+        if let Some((stored, actual)) = crc_status(path)? {
+            json["crc_matches"] = (stored == actual).into();
+        }
+        json["extended_header"] = match extended_header(major, &body) {
+            Ok(ext) => ext,
+            Err(e) => e.to_string().into(),
+        };
+    }
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use id3::TagLike;
+
+    #[test]
+    fn crc_known_answer() {
+        // The check value of CRC-32/ISO-HDLC
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn stored_crc_detects_corruption() {
+        let dir = testing::scratch_dir("header-crc");
+        let track = dir.join("t.mp3");
+        testing::write_mp3(&track);
+        let mut tag = id3::Tag::new();
+        tag.set_title("Title");
+        let opts = crate::WriteOpts {
+            crc: true,
+            ..crate::WriteOpts::default()
+        };
+        crate::write_tag(&track, &tag, &opts).unwrap();
+        let (stored, actual) = crc_status(&track).unwrap().unwrap();
+        assert_eq!(stored, actual);
+        assert_eq!(inspect(&track).unwrap()["crc_matches"], true);
+
+        let mut data = std::fs::read(&track).unwrap();
+        let title = data.windows(5).position(|w| w == b"Title").unwrap();
+        data[title] = b't';
+        std::fs::write(&track, &data).unwrap();
+        let (stored, actual) = crc_status(&track).unwrap().unwrap();
+        assert_ne!(stored, actual);
+    }
+
+    #[test]
+    fn truncated_headers_are_errors() {
+        let body = [0u8, 0, 0, 12, 1, 0x20, 5, 0, 0, 0, 0, 0];
+        for len in 0..body.len() {
+            assert!(extended_header(4, &body[..len]).is_err(), "{len}");
+        }
+        assert!(extended_header(4, &body).is_ok());
+        assert!(extended_header(3, &[0, 0, 0, 6, 0x80, 0, 0, 0, 0, 0, 1, 2]).is_err());
+    }
+}
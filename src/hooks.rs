@@ -0,0 +1,31 @@
+//! Running a user command after a file's tags have been changed
+
+use std::path::Path;
+use std::process::Command;
+
+/// Runs `command` through the shell with `{path}` standing for the modified file. On Unix the path
+/// is passed as a positional parameter rather than spliced into the command, so names containing
+/// quotes or `$` can't be misinterpreted by the shell.
+pub fn run_on_change(command: &str, path: &Path) {
+    #[cfg(unix)]
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command.replace("{path}", "\"$1\""))
+        .arg("tag2json")
+        .arg(path)
+        .status();
+    #[cfg(windows)]
+    let status = Command::new("cmd")
+        .arg("/C")
+        .arg(command.replace("{path}", &format!("\"{}\"", path.to_string_lossy())))
+        .status();
+
+    match status {
+        Ok(s) if s.success() => {}
+        Ok(s) => eprintln!("Change hook for {} exited with {s}", path.to_string_lossy()),
+        Err(e) => eprintln!(
+            "Could not run change hook for {}: {e}",
+            path.to_string_lossy()
+        ),
+    }
+}
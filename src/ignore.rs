@@ -0,0 +1,57 @@
+//! Frames left out when comparing tags, for volatile frames such as play counts that change on
+//! every listen. Entries are frame IDs, or `TXXX:DESCRIPTION` for a single user-defined text frame
+
+use id3::{Content, Frame};
+use json::JsonValue;
+
+/// Whether a frame is the one an entry names. TXXX descriptions are matched ignoring case
+pub fn matches(frame: &Frame, entry: &str) -> bool {
+    match (entry.strip_prefix("TXXX:"), frame.content()) {
+        (Some(name), Content::ExtendedText(t)) => t.description.eq_ignore_ascii_case(name),
+        (Some(_), _) => false,
+        (None, _) => frame.id() == entry,
+    }
+}
+
+/// Whether a frame is named by any of the entries
+pub fn is_ignored(frame: &Frame, ignore: &[String]) -> bool {
+    ignore.iter().any(|entry| matches(frame, entry))
+}
+
+/// The extracted JSON without the ignored frames. Ignored TXXX descriptions are taken out of the
+/// TXXX array, and out of `TXXX:DESCRIPTION` keys, leaving the other user-defined frames
+pub fn strip(json: &JsonValue, ignore: &[String]) -> JsonValue {
+    if ignore.is_empty() || !json.is_object() {
+        return json.clone();
+    }
+    let descriptions: Vec<&str> = ignore
+        .iter()
+        .filter_map(|entry| entry.strip_prefix("TXXX:"))
+        .collect();
+    let ignored_description =
+        |d: &str| descriptions.iter().any(|name| name.eq_ignore_ascii_case(d));
+    let mut kept = JsonValue::new_object();
+    for (key, value) in json.entries() {
+        if ignore.iter().any(|entry| entry == key) {
+            continue;
+        }
+        if let Some(description) = key.strip_prefix("TXXX:") {
+            if ignored_description(description) {
+                continue;
+            }
+        }
+        if key == "TXXX" && value.is_array() {
+            let entries: Vec<JsonValue> = value
+                .members()
+                .filter(|e| !e["description"].as_str().is_some_and(ignored_description))
+                .cloned()
+                .collect();
+            if !entries.is_empty() {
+                kept[key] = entries.into();
+            }
+            continue;
+        }
+        kept[key] = value.clone();
+    }
+    kept
+}
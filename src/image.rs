@@ -0,0 +1,192 @@
+//! Recognising embedded images well enough to label and measure them, without decoding them.
+//! Converting them is left to ImageMagick.
+
+use crate::{Error, StrResult};
+use clap::ValueEnum;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// The MIME type of an image from its leading bytes
+pub fn mime_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        Some("image/webp")
+    } else if data.get(4..8) == Some(b"ftyp") && is_avif(data) {
+        Some("image/avif")
+    } else {
+        None
+    }
+}
+
+/// Whether an ISO media file's `ftyp` box names an AVIF brand, major or compatible
+fn is_avif(data: &[u8]) -> bool {
+    let len = be32(data, 0).unwrap_or(0) as usize;
+    let Some(brands) = data.get(8..len.min(data.len())) else {
+        return false;
+    };
+    // The major brand and minor version, then the compatible brands
+    brands
+        .chunks_exact(4)
+        .enumerate()
+        .any(|(i, brand)| i != 1 && (brand == b"avif" || brand == b"avis"))
+}
+
+/// Whether players commonly fail to show the format, so it's worth converting on embed
+pub fn is_modern(mime_type: &str) -> bool {
+    matches!(mime_type, "image/webp" | "image/avif")
+}
+
+/// The file extension conventional for a MIME type, treating anything unrecognised as JPEG
+pub fn extension(mime_type: &str) -> &'static str {
+    match mime_type {
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/avif" => "avif",
+        _ => "jpg",
+    }
+}
+
+fn be16(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]) as u32)
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Walks the JPEG marker segments to the first start-of-frame, which holds the image size
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    loop {
+        while *data.get(pos)? != 0xFF {
+            pos += 1;
+        }
+        while *data.get(pos)? == 0xFF {
+            pos += 1;
+        }
+        let marker = *data.get(pos)?;
+        pos += 1;
+        match marker {
+            // Markers without a length
+            0x01 | 0xD0..=0xD7 => continue,
+            // SOF0-SOF15, except DHT, JPG and DAC which share the range
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                let height = be16(data, pos + 3)?;
+                let width = be16(data, pos + 5)?;
+                return Some((width, height));
+            }
+            0xD9 | 0xDA => return None,
+            _ => pos += be16(data, pos)? as usize,
+        }
+    }
+}
+
+fn le_u24(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 3)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+}
+
+/// The size from the first chunk, whichever of the lossy, lossless and extended forms it is
+fn webp_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    match data.get(12..16)? {
+        b"VP8 " => {
+            let width = u16::from_le_bytes([*data.get(26)?, *data.get(27)?]) & 0x3FFF;
+            let height = u16::from_le_bytes([*data.get(28)?, *data.get(29)?]) & 0x3FFF;
+            Some((width as u32, height as u32))
+        }
+        b"VP8L" => {
+            let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+            Some(((bits & 0x3FFF) + 1, (bits >> 14 & 0x3FFF) + 1))
+        }
+        b"VP8X" => Some((le_u24(data, 24)? + 1, le_u24(data, 27)? + 1)),
+        _ => None,
+    }
+}
+
+/// The size from the first image spatial extents (`ispe`) property
+fn avif_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let at = data.windows(4).position(|w| w == b"ispe")?;
+    // After the box type come a version and flags, then the width and height
+    Some((be32(data, at + 8)?, be32(data, at + 12)?))
+}
+
+/// The width and height of an image in pixels, if it's in a format that can be measured
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    match mime_type(data)? {
+        "image/jpeg" => jpeg_dimensions(data),
+        "image/png" => Some((be32(data, 16)?, be32(data, 20)?)),
+        "image/gif" => {
+            let width = u16::from_le_bytes([*data.get(6)?, *data.get(7)?]);
+            let height = u16::from_le_bytes([*data.get(8)?, *data.get(9)?]);
+            Some((width as u32, height as u32))
+        }
+        "image/webp" => webp_dimensions(data),
+        "image/avif" => avif_dimensions(data),
+        _ => None,
+    }
+}
+
+/// A format modern pictures can be converted to
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Target {
+    Jpeg,
+    Png,
+}
+
+impl Target {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Target::Jpeg => "image/jpeg",
+            Target::Png => "image/png",
+        }
+    }
+}
+
+/// Runs ImageMagick with the image on its standard input, giving its output. `args` follow the
+/// input, which is always `-`
+pub fn magick(data: &[u8], args: &[&str]) -> StrResult<Vec<u8>> {
+    for program in ["magick", "convert"] {
+        let Ok(mut child) = Command::new(program)
+            .arg("-")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+        else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(data)
+                .map_err(|e| Error::Io(format!("Cannot pass art to {program}: {e}")))?;
+        }
+        return match child.wait_with_output() {
+            Ok(out) if out.status.success() => Ok(out.stdout),
+            Ok(out) => Err(Error::Other(format!(
+                "{program} exited with {}",
+                out.status
+            ))),
+            Err(e) => Err(Error::Other(format!("Cannot run {program}: {e}"))),
+        };
+    }
+    Err(Error::Unsupported(
+        "Converting art needs ImageMagick (magick or convert) on the PATH".to_owned(),
+    ))
+}
+
+/// Re-encodes an image in the target format
+pub fn convert(data: &[u8], target: Target) -> StrResult<Vec<u8>> {
+    let output = match target {
+        Target::Jpeg => "jpg:-",
+        Target::Png => "png:-",
+    };
+    magick(data, &[output])
+}
@@ -0,0 +1,94 @@
+//! International Standard Recording Codes, as stored in TSRC: validation and sequential assignment
+
+use crate::batch::BatchReport;
+use crate::{paths, Error, StrResult, WriteOpts};
+use id3::{Tag, TagLike};
+use std::path::PathBuf;
+
+/// The largest designation code, the last five digits of an ISRC
+const MAX_DESIGNATION: u32 = 99_999;
+
+/// Puts an ISRC in the canonical twelve-character form, accepting the hyphenated display form
+/// (`CC-XXX-YY-NNNNN`) and lowercase. Returns None if it isn't a well-formed ISRC
+pub fn normalize(code: &str) -> Option<String> {
+    let code: String = code
+        .trim()
+        .chars()
+        .filter(|c| *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let bytes = code.as_bytes();
+    let well_formed = bytes.len() == 12
+        && bytes[0..2].iter().all(u8::is_ascii_uppercase)
+        && bytes[2..5].iter().all(u8::is_ascii_alphanumeric)
+        && bytes[5..12].iter().all(u8::is_ascii_digit);
+    well_formed.then_some(code)
+}
+
+/// Like [`normalize`], but explaining what's wrong with a malformed code
+pub fn parse(code: &str) -> StrResult<String> {
+    normalize(code).ok_or_else(|| {
+        Error::Parse(format!(
+            "\"{code}\" is not an ISRC of the form CC-XXX-YY-NNNNN"
+        ))
+    })
+}
+
+/// Sorts tracks into album order: disc, then track number, then path
+fn album_order(tracks: &mut [(PathBuf, Tag)]) {
+    tracks.sort_by(|(a_path, a), (b_path, b)| {
+        let key = |t: &Tag| (t.disc().unwrap_or(0), t.track().unwrap_or(u32::MAX));
+        key(a).cmp(&key(b)).then(a_path.cmp(b_path))
+    });
+}
+
+/// Gives each track the next ISRC in sequence from `start`, in album order. Tracks that already
+/// have a TSRC keep it, without using up a code, unless `overwrite` is set. A track that can't be
+/// written, or for which the codes run out, stops the run, so no codes are given out of order
+pub fn assign(
+    files: &[PathBuf],
+    start: &str,
+    overwrite: bool,
+    opts: &WriteOpts,
+) -> StrResult<BatchReport> {
+    let Some(start) = normalize(start) else {
+        return Err(Error::Parse(format!(
+            "\"{start}\" is not an ISRC of the form CC-XXX-YY-NNNNN"
+        )));
+    };
+    let prefix = &start[..7];
+    let mut designation: u32 = start[7..].parse().unwrap_or(0);
+
+    let mut tracks = vec![];
+    for file in paths::collect_audio_files(files, true) {
+        match crate::read_tag(&file) {
+            Ok(tag) => tracks.push((file, tag)),
+            Err(e) => Err(e.context(&format!("Could not handle {}", file.to_string_lossy())))?,
+        }
+    }
+    album_order(&mut tracks);
+
+    let mut report = BatchReport::default();
+    for (path, mut tag) in tracks {
+        if !overwrite && tag.get("TSRC").is_some() {
+            report.warn(&path, "keeping existing ISRC");
+            continue;
+        }
+        if designation > MAX_DESIGNATION {
+            let error = format!("Ran out of designation codes under {prefix}");
+            report.fail(&path, Error::Other(error));
+            break;
+        }
+        let code = format!("{prefix}{designation:05}");
+        designation += 1;
+        tag.set_text("TSRC", &code);
+        match crate::write_tag(&path, &tag, opts) {
+            Ok(()) => report.succeed(&path, code),
+            Err(e) => {
+                report.fail(&path, e);
+                break;
+            }
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,391 @@
+//! Human-readable names for frame IDs, in several languages, so sidecars can be edited without
+//! knowing that TPE1 means artist
+
+use crate::{Error, StrResult};
+use clap::ValueEnum;
+use json::JsonValue;
+use std::collections::HashMap;
+use std::path::Path;
+
+const EN: &[(&str, &str)] = &[
+    ("TIT1", "grouping"),
+    ("TIT2", "title"),
+    ("TIT3", "subtitle"),
+    ("TPE1", "artist"),
+    ("TPE2", "album_artist"),
+    ("TPE3", "conductor"),
+    ("TPE4", "remixer"),
+    ("TALB", "album"),
+    ("TRCK", "track"),
+    ("TPOS", "disc"),
+    ("TDRC", "year"),
+    ("TDOR", "original_year"),
+    ("TCON", "genre"),
+    ("TCOM", "composer"),
+    ("TEXT", "lyricist"),
+    ("TPUB", "publisher"),
+    ("TCOP", "copyright"),
+    ("TBPM", "bpm"),
+    ("TKEY", "key"),
+    ("TMOO", "mood"),
+    ("TLAN", "language"),
+    ("TSRC", "isrc"),
+    ("TENC", "encoded_by"),
+    ("TSSE", "encoder_settings"),
+    ("TOPE", "original_artist"),
+    ("TSOA", "album_sort"),
+    ("TSOP", "artist_sort"),
+    ("TSOT", "title_sort"),
+    ("TSO2", "album_artist_sort"),
+    ("TXXX:BARCODE", "barcode"),
+    ("TXXX:CATALOGNUMBER", "catalog_number"),
+    ("TXXX:MusicBrainz Artist Id", "musicbrainz_artist_id"),
+    ("TXXX:MusicBrainz Album Id", "musicbrainz_album_id"),
+    (
+        "TXXX:MusicBrainz Album Artist Id",
+        "musicbrainz_album_artist_id",
+    ),
+    (
+        "TXXX:MusicBrainz Release Group Id",
+        "musicbrainz_release_group_id",
+    ),
+    (
+        "TXXX:MusicBrainz Release Track Id",
+        "musicbrainz_release_track_id",
+    ),
+    ("TXXX:MusicBrainz Work Id", "musicbrainz_work_id"),
+    ("UFID:http://musicbrainz.org", "musicbrainz_recording_id"),
+    ("COMM", "comment"),
+];
+
+const DE: &[(&str, &str)] = &[
+    ("TIT1", "gruppierung"),
+    ("TIT2", "titel"),
+    ("TIT3", "untertitel"),
+    ("TPE1", "interpret"),
+    ("TPE2", "albuminterpret"),
+    ("TPE3", "dirigent"),
+    ("TPE4", "remixer"),
+    ("TALB", "album"),
+    ("TRCK", "titelnummer"),
+    ("TPOS", "cd"),
+    ("TDRC", "jahr"),
+    ("TDOR", "originaljahr"),
+    ("TCON", "genre"),
+    ("TCOM", "komponist"),
+    ("TEXT", "texter"),
+    ("TPUB", "verlag"),
+    ("TCOP", "urheberrecht"),
+    ("TBPM", "bpm"),
+    ("TKEY", "tonart"),
+    ("TMOO", "stimmung"),
+    ("TLAN", "sprache"),
+    ("TSRC", "isrc"),
+    ("TENC", "kodiert_von"),
+    ("TSSE", "kodierungseinstellungen"),
+    ("TOPE", "originalinterpret"),
+    ("TSOA", "album_sortierung"),
+    ("TSOP", "interpret_sortierung"),
+    ("TSOT", "titel_sortierung"),
+    ("TSO2", "albuminterpret_sortierung"),
+    ("TXXX:BARCODE", "barcode"),
+    ("TXXX:CATALOGNUMBER", "katalognummer"),
+    ("TXXX:MusicBrainz Artist Id", "musicbrainz_artist_id"),
+    ("TXXX:MusicBrainz Album Id", "musicbrainz_album_id"),
+    (
+        "TXXX:MusicBrainz Album Artist Id",
+        "musicbrainz_album_artist_id",
+    ),
+    (
+        "TXXX:MusicBrainz Release Group Id",
+        "musicbrainz_release_group_id",
+    ),
+    (
+        "TXXX:MusicBrainz Release Track Id",
+        "musicbrainz_release_track_id",
+    ),
+    ("TXXX:MusicBrainz Work Id", "musicbrainz_work_id"),
+    ("UFID:http://musicbrainz.org", "musicbrainz_recording_id"),
+    ("COMM", "kommentar"),
+];
+
+const JA: &[(&str, &str)] = &[
+    ("TIT1", "グループ"),
+    ("TIT2", "タイトル"),
+    ("TIT3", "サブタイトル"),
+    ("TPE1", "アーティスト"),
+    ("TPE2", "アルバムアーティスト"),
+    ("TPE3", "指揮者"),
+    ("TPE4", "リミキサー"),
+    ("TALB", "アルバム"),
+    ("TRCK", "トラック番号"),
+    ("TPOS", "ディスク番号"),
+    ("TDRC", "年"),
+    ("TDOR", "オリジナル年"),
+    ("TCON", "ジャンル"),
+    ("TCOM", "作曲者"),
+    ("TEXT", "作詞者"),
+    ("TPUB", "出版社"),
+    ("TCOP", "著作権"),
+    ("TBPM", "テンポ"),
+    ("TKEY", "調"),
+    ("TMOO", "ムード"),
+    ("TLAN", "言語"),
+    ("TSRC", "isrc"),
+    ("TENC", "エンコード者"),
+    ("TSSE", "エンコード設定"),
+    ("TOPE", "オリジナルアーティスト"),
+    ("TSOA", "アルバム読み"),
+    ("TSOP", "アーティスト読み"),
+    ("TSOT", "タイトル読み"),
+    ("TSO2", "アルバムアーティスト読み"),
+    ("TXXX:BARCODE", "バーコード"),
+    ("TXXX:CATALOGNUMBER", "カタログ番号"),
+    ("TXXX:MusicBrainz Artist Id", "musicbrainz_artist_id"),
+    ("TXXX:MusicBrainz Album Id", "musicbrainz_album_id"),
+    (
+        "TXXX:MusicBrainz Album Artist Id",
+        "musicbrainz_album_artist_id",
+    ),
+    (
+        "TXXX:MusicBrainz Release Group Id",
+        "musicbrainz_release_group_id",
+    ),
+    (
+        "TXXX:MusicBrainz Release Track Id",
+        "musicbrainz_release_track_id",
+    ),
+    ("TXXX:MusicBrainz Work Id", "musicbrainz_work_id"),
+    ("UFID:http://musicbrainz.org", "musicbrainz_recording_id"),
+    ("COMM", "コメント"),
+];
+
+const LANGUAGES: &[(&str, &[(&str, &str)])] = &[("en", EN), ("de", DE), ("ja", JA)];
+
+/// The case extracted keys are written in
+#[derive(ValueEnum, Clone, Copy)]
+pub enum KeyCase {
+    Upper,
+    Lower,
+}
+
+/// Whether a key looks like a frame ID (or a `TXXX:DESCRIPTION` key) in the wrong case, which
+/// hand-written sidecars often have. Only the ID is folded: descriptions are matched exactly by
+/// other taggers, so they are kept as given
+fn folded_frame_id(key: &str) -> Option<String> {
+    let (id, rest) = match key.split_once(':') {
+        Some((id, rest)) => (id, Some(rest)),
+        None => (key, None),
+    };
+    let is_id = id.len() == 4 && id.bytes().all(|b| b.is_ascii_alphanumeric());
+    if !is_id {
+        return None;
+    }
+    let folded = match rest {
+        Some(rest) => format!("{}:{rest}", id.to_ascii_uppercase()),
+        None => id.to_ascii_uppercase(),
+    };
+    (folded != key).then_some(folded)
+}
+
+/// A mapping between frame IDs and the names used for them in JSON documents
+#[derive(Default)]
+pub struct KeyMap {
+    to_name: HashMap<String, String>,
+    to_id: HashMap<String, String>,
+    /// The frame IDs of each name that several share, in the order the mapping gives them
+    merged: Vec<(String, Vec<String>)>,
+    case: Option<KeyCase>,
+}
+
+impl KeyMap {
+    fn from_pairs<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> KeyMap {
+        let mut map = KeyMap::default();
+        for (id, name) in pairs {
+            map.to_name.insert(id.to_owned(), name.to_owned());
+            match map.to_id.get(name) {
+                None => {
+                    map.to_id.insert(name.to_owned(), id.to_owned());
+                }
+                Some(first) => match map.merged.iter_mut().find(|(n, _)| n == name) {
+                    Some((_, ids)) => ids.push(id.to_owned()),
+                    None => map
+                        .merged
+                        .push((name.to_owned(), vec![first.clone(), id.to_owned()])),
+                },
+            }
+        }
+        map
+    }
+
+    /// Loads the key set named by `spec`, either a built-in language code or the path to a JSON
+    /// object mapping frame IDs to names. `None` keeps raw frame IDs. Frames mapped to the same
+    /// name are merged: the name holds the first of them the document has, in the order the
+    /// mapping lists them, and is applied back to the first listed.
+    pub fn load(spec: Option<&str>) -> StrResult<KeyMap> {
+        let Some(spec) = spec else {
+            return Ok(KeyMap::default());
+        };
+        if let Some((_, pairs)) = LANGUAGES.iter().find(|(code, _)| *code == spec) {
+            return Ok(KeyMap::from_pairs(pairs.iter().copied()));
+        }
+        let path = Path::new(spec);
+        if !path.is_file() {
+            let codes: Vec<_> = LANGUAGES.iter().map(|(c, _)| *c).collect();
+            return Err(Error::Parse(format!(
+                "Key set \"{spec}\" is neither a built-in language ({}) nor a mapping file",
+                codes.join(", ")
+            )));
+        }
+        let json = crate::read_json_file(path)?;
+        if !json.is_object() {
+            return Err(Error::Parse(
+                "Key mapping file must contain an object of frame ID to name".to_owned(),
+            ));
+        }
+        let mut pairs = vec![];
+        for (id, name) in json.entries() {
+            match name.as_str() {
+                Some(name) => pairs.push((id, name)),
+                None => {
+                    return Err(Error::Parse(format!(
+                        "Key mapping for {id} is not a string"
+                    )))
+                }
+            }
+        }
+        Ok(KeyMap::from_pairs(pairs))
+    }
+
+    /// Writes localized keys in the given case rather than as the mapping spells them
+    pub fn with_case(self, case: Option<KeyCase>) -> KeyMap {
+        KeyMap { case, ..self }
+    }
+
+    /// Drops the frames a merged name doesn't take, those after the first the document has
+    fn merge(&self, mut json: JsonValue) -> JsonValue {
+        if !json.is_object() {
+            return json;
+        }
+        for (_, ids) in &self.merged {
+            let mut present = ids.iter().filter(|id| json.has_key(id));
+            present.next();
+            let shadowed: Vec<String> = present.cloned().collect();
+            for id in shadowed {
+                json.remove(&id);
+            }
+        }
+        if json["tracks"].is_array() {
+            let tracks = json["tracks"].members().map(|t| self.merge(t.clone()));
+            json["tracks"] = JsonValue::Array(tracks.collect());
+        }
+        json
+    }
+
+    /// Renames the frame IDs of a document (and of each entry in its `tracks`) to their mapped names
+    pub fn localize(&self, json: JsonValue) -> JsonValue {
+        let json = self.merge(json);
+        rename_keys(json, |key| {
+            let name = self.to_name.get(key).map_or(key, String::as_str);
+            Some(match self.case {
+                None => name.to_owned(),
+                Some(KeyCase::Upper) => name.to_uppercase(),
+                Some(KeyCase::Lower) => name.to_lowercase(),
+            })
+        })
+    }
+
+    /// Renames mapped names back to frame IDs. Names from every built-in language are always
+    /// recognised, so a sidecar written with any of them can be applied without extra options.
+    /// Names and frame IDs are both matched regardless of case.
+    pub fn canonicalize(&self, json: JsonValue) -> JsonValue {
+        rename_keys(json, |key| {
+            if let Some(id) = self.to_id.get(key) {
+                return Some(id.clone());
+            }
+            let mapped = self
+                .to_id
+                .iter()
+                .map(|(name, id)| (name.as_str(), id.as_str()))
+                .chain(
+                    LANGUAGES
+                        .iter()
+                        .flat_map(|(_, pairs)| pairs.iter().map(|(id, name)| (*name, *id))),
+                )
+                .find(|(name, _)| name.to_lowercase() == key.to_lowercase());
+            match mapped {
+                Some((_, id)) => Some(id.to_owned()),
+                None => folded_frame_id(key),
+            }
+        })
+    }
+}
+
+fn rename_keys(json: JsonValue, rename: impl Fn(&str) -> Option<String> + Copy) -> JsonValue {
+    if !json.is_object() {
+        return json;
+    }
+    let mut out = JsonValue::new_object();
+    for (key, value) in json.entries() {
+        if key == "tracks" && value.is_array() {
+            let tracks = value.members().map(|t| rename_keys(t.clone(), rename));
+            out[key] = JsonValue::Array(tracks.collect());
+        } else if key.starts_with('_') {
+            out[key] = value.clone();
+        } else {
+            let key = rename(key).unwrap_or_else(|| key.to_owned());
+            out[key.as_str()] = value.clone();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::frame::{Comment, Content, ExtendedText, Frame, UniqueFileIdentifier};
+    use id3::{Tag, TagLike};
+
+    const ALBUM_ID: &str = "f5093c06-23e3-404f-aeaa-40f72885ee3a";
+    const RECORDING_ID: &str = "a1b2c3d4-0000-4000-8000-00000000abcd";
+
+    #[test]
+    fn musicbrainz_ids_and_comments_have_friendly_names() {
+        let mut tag = Tag::new();
+        tag.add_frame(ExtendedText {
+            description: "MusicBrainz Album Id".to_owned(),
+            value: ALBUM_ID.to_owned(),
+        });
+        let ufid = UniqueFileIdentifier {
+            owner_identifier: "http://musicbrainz.org".to_owned(),
+            identifier: RECORDING_ID.as_bytes().to_vec(),
+        };
+        tag.add_frame(Frame::with_content(
+            "UFID",
+            Content::UniqueFileIdentifier(ufid),
+        ));
+        tag.add_frame(Comment {
+            lang: "eng".to_owned(),
+            description: String::new(),
+            text: "Live".to_owned(),
+        });
+
+        let en = KeyMap::load(Some("en")).unwrap();
+        let json = en.localize(crate::tag_to_json(&tag));
+        assert_eq!(json["musicbrainz_album_id"], ALBUM_ID);
+        assert_eq!(json["musicbrainz_recording_id"], RECORDING_ID);
+        assert_eq!(json["comment"][0]["text"], "Live");
+        assert!(!json.has_key("TXXX") && !json.has_key("UFID"));
+
+        let applied = crate::json_to_tag(&en.canonicalize(json), Path::new("")).unwrap();
+        let mut frames: Vec<&Frame> = applied.frames().collect();
+        let mut expected: Vec<&Frame> = tag.frames().collect();
+        frames.sort_by_key(|f| f.id());
+        expected.sort_by_key(|f| f.id());
+        assert_eq!(frames, expected);
+
+        let written = json::object! { "comment": "Live", "MUSICBRAINZ_RECORDING_ID": "nope" };
+        let result = crate::json_to_tag(&KeyMap::default().canonicalize(written), Path::new(""));
+        assert!(matches!(result, Err(Error::Parse(_))));
+    }
+}
@@ -0,0 +1,643 @@
+//! Conversion between the tags of audio files and JSON documents. [`extract_tags_pic`] reads a
+//! file's tags as JSON, [`json_to_tag`] and [`write_tag`] turn a document back into tags on disk,
+//! and [`apply_tags`] does both at once. MP3, FLAC, Ogg, WAV, AIFF, MP4 and APEv2 files are all
+//! read into an id3 [`Tag`], so documents use ID3v2 frame IDs as keys whatever the container.
+//!
+//! Whole libraries are walked with [`paths::collect_audio_files`]. [`batch::extract`] and
+//! [`batch::apply`] are the batch commands, and [`snapshot::snapshot`] extracts every file found
+//! into one aggregate document keyed by path.
+
+use clap::Args;
+use id3::frame::Picture;
+use id3::{Frame, Tag, TagLike};
+use json::JsonValue;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub mod aggregate;
+pub mod album;
+pub mod ape;
+pub mod audit;
+pub mod authority;
+pub mod backend;
+pub mod base64;
+pub mod batch;
+pub mod chapters;
+pub mod check;
+pub mod check_paths;
+pub mod checksums;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod compat;
+pub mod compose;
+#[cfg(feature = "async")]
+pub mod concurrent;
+pub mod error;
+pub mod estimate;
+pub mod export;
+pub mod filter;
+pub mod flac;
+pub mod foreign;
+pub mod frame_class;
+pub mod frame_info;
+pub mod frames;
+pub mod genre;
+pub mod grep;
+pub mod header;
+pub mod hooks;
+pub mod ignore;
+pub mod image;
+pub mod isrc;
+pub mod keys;
+pub mod lint;
+pub mod lock;
+pub mod lrc;
+pub mod lyrics;
+pub mod manifest;
+pub mod migrate;
+pub mod model;
+pub mod mp4;
+pub mod normalize;
+pub mod ogg;
+pub mod order;
+pub mod paranoid;
+pub mod paths;
+pub mod pictures;
+pub mod policy;
+pub mod position;
+pub mod preserve;
+pub mod propagate;
+pub mod properties;
+pub mod regex;
+pub mod release_ids;
+pub mod reorganize;
+pub mod report;
+pub mod riff;
+pub mod rules;
+pub mod scrub;
+pub mod sha256;
+pub mod shard;
+pub mod slug;
+pub mod snapshot;
+pub mod sniff;
+pub mod splice;
+pub mod template;
+#[cfg(test)]
+mod testing;
+pub mod timespec;
+pub mod typography;
+pub mod watch;
+
+pub use error::Error;
+
+pub type StrResult<T> = Result<T, Error>;
+
+/// How tags are written, whichever command is writing them
+#[derive(Args, Clone)]
+pub struct WriteOpts {
+    /// Re-read the tag after writing and fail if any frame differs from what was intended
+    #[arg(long, default_value_t = false)]
+    pub verify: bool,
+    /// Comma-separated frames --verify doesn't check, such as PCNT,POPM,TXXX:LastPlayed, for
+    /// frames a player may update as soon as the file is written
+    #[arg(long, value_delimiter = ',', requires = "verify")]
+    pub ignore_frames: Vec<String>,
+    /// Keep the file's access and modification times as they were before the tags were written
+    #[arg(long, default_value_t = false)]
+    pub preserve_mtime: bool,
+    /// A shell command to run after each file's tags are written, with {path} replaced by the file
+    #[arg(long)]
+    pub on_change: Option<String>,
+    /// Write nothing, but report how much each file would grow or shrink and the total
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Bytes of audio moved at a time when a tag changes size, bounding memory use on very large files
+    #[arg(long, default_value_t = splice::DEFAULT_BUFFER_SIZE)]
+    pub buffer_size: usize,
+    /// Comma-separated frame IDs in the order to write them, with * standing for every frame not
+    /// listed, such as TIT2,TPE1,*,APIC. Unlisted frames go last if there's no *
+    #[arg(long, value_delimiter = ',')]
+    pub frame_order: Vec<String>,
+    /// Store a CRC of the tag in its extended header, so corruption can be detected later. MP3 only
+    #[arg(long, default_value_t = false)]
+    pub crc: bool,
+}
+
+/// Changes made to tag values between reading the JSON and writing the tag
+#[derive(Args, Clone, Default)]
+pub struct TransformOpts {
+    /// Keep the file's existing frames, including its art, replacing only those the JSON gives
+    #[arg(long, default_value_t = false)]
+    pub merge: bool,
+    /// Normalize text frames: compose Unicode to NFC, trim, and collapse runs of whitespace
+    #[arg(long, default_value_t = false)]
+    pub normalize: bool,
+    /// Rewrite TPE1 and TPE2 to the canonical names in this authority list, a JSON object or CSV
+    /// file of canonical names and their aliases, and report names it lacks
+    #[arg(long)]
+    pub authority: Option<PathBuf>,
+    /// Rewrite quotes, dashes and ellipses in a consistent house style
+    #[arg(long, value_enum)]
+    pub typography: Option<typography::Style>,
+    /// Comma-separated frame IDs to apply --typography to. Defaults to every text frame
+    #[arg(long, value_delimiter = ',')]
+    pub typography_frames: Vec<String>,
+    /// Write TCON genres that have an ID3v1 number as that number, for players that only read
+    /// the numbers. Other genres are kept as text
+    #[arg(long, default_value_t = false)]
+    pub genre_codes: bool,
+    /// Set or remove frames per this JSON rules file, for the files whose tag and path meet each
+    /// rule's conditions
+    #[arg(long)]
+    pub rules: Option<PathBuf>,
+    /// Store URL-safe slugs of the title and artist in TITLE_SLUG and ARTIST_SLUG TXXX frames
+    #[arg(long, default_value_t = false)]
+    pub slugs: bool,
+    /// Expand {FRAME} placeholders in values from the other frames being applied or, failing that, the file's existing tag. Literal braces are written {{ and }}
+    #[arg(long, default_value_t = false)]
+    pub templates: bool,
+    /// Convert WebP and AVIF pictures to this format, for players that can't show them. Needs ImageMagick
+    #[arg(long, value_enum)]
+    pub convert_art: Option<image::Target>,
+}
+
+impl Default for WriteOpts {
+    fn default() -> Self {
+        WriteOpts {
+            verify: false,
+            ignore_frames: vec![],
+            preserve_mtime: false,
+            on_change: None,
+            dry_run: false,
+            buffer_size: splice::DEFAULT_BUFFER_SIZE,
+            frame_order: vec![],
+            crc: false,
+        }
+    }
+}
+
+/// Writes a sidecar or other output file, replacing it under a lock
+pub fn write_data_to_path(path: &PathBuf, data: &[u8]) -> StrResult<()> {
+    paranoid::check_path(path)?;
+    let change = manifest::Change::begin(path);
+    // Truncate only once the lock is held, so a concurrent writer's output isn't clobbered midway
+    let mut file = match OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(e) => Err(Error::Io(format!(
+            "Cannot open {}: {e}",
+            path.to_string_lossy()
+        )))?,
+    };
+    lock::lock_exclusive(&file, path)?;
+    if let Err(e) = file.set_len(0).and_then(|_| file.write_all(data)) {
+        return Err(Error::Io(format!("Cannot write JSON: {e}",)));
+    };
+    change.finish();
+    Ok(())
+}
+
+/// The frames of a tag as a JSON object
+pub fn tag_to_json(tag: &Tag) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    for frame in tag.frames() {
+        if let Some(text) = frame.content().text() {
+            json[frame.id()] = if position::FRAMES.contains(&frame.id()) {
+                position::to_json(text)
+            } else {
+                frames::text_to_json(text)
+            };
+        } else if let Some(url) = frame.content().link() {
+            json[frame.id()] = url.into();
+        }
+    }
+    for (key, value) in release_ids::extract(tag) {
+        json[key.as_str()] = value.into();
+    }
+    if let Some(count) = tag.get("PCNT").and_then(frames::play_count) {
+        json["PCNT"] = count.into();
+    }
+    for (key, value) in frames::to_json(tag) {
+        json[key.as_str()] = value;
+    }
+    if let Some(chapters) = chapters::to_json(tag) {
+        json["CHAP"] = chapters;
+    }
+    if let Some(tocs) = chapters::tocs_to_json(tag) {
+        json["CTOC"] = tocs;
+    }
+    json
+}
+
+/// Reads the tag of any supported file, with other containers' fields converted to ID3 frames
+pub fn read_tag(path: &Path) -> StrResult<Tag> {
+    if let Some(backend) = backend::for_path(path) {
+        return backend.read_tag(path);
+    }
+    Tag::read_from_path(path).map_err(Error::from)
+}
+
+/// Like [`read_tag`], but with an untagged file giving an empty tag rather than an error
+pub fn read_tag_or_empty(path: &Path) -> StrResult<Tag> {
+    if let Some(backend) = backend::for_path(path) {
+        return backend.read_tag(path);
+    }
+    match Tag::read_from_path(path) {
+        Ok(tag) => Ok(tag),
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => Ok(Tag::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A file's tags as a JSON object, with its audio properties under `_properties`, and its
+/// pictures. [`pictures::extract`] writes them out and lists them in the JSON
+pub fn extract_tags_pic(id3_file: &Path) -> StrResult<(JsonValue, Vec<Picture>)> {
+    let (json, tag) = extract_tags(id3_file)?;
+    Ok((json, tag.pictures().cloned().collect()))
+}
+
+/// Like [`extract_tags_pic`], but giving the tag the JSON was made from rather than its pictures
+pub fn extract_tags(id3_file: &Path) -> StrResult<(JsonValue, Tag)> {
+    paranoid::check_input(id3_file)?;
+    let tag = match read_tag(id3_file) {
+        Ok(t) => t,
+        Err(e) => Err(e.context("Unable to open id3 file"))?, // No need to include the path because we know its valid already
+    };
+    let mut tag = tag;
+    migrate::warn_unmappable(&migrate::migrate_v22(&mut tag), &id3_file.to_string_lossy());
+    let mut json = tag_to_json(&tag);
+    if let Ok(Some((stored, actual))) = header::crc_status(id3_file) {
+        if stored != actual {
+            eprintln!(
+                "{}: tag CRC is {actual:08x} but {stored:08x} is stored; the tag may be corrupt",
+                id3_file.to_string_lossy()
+            );
+        }
+    }
+    if let Some(backend) = backend::for_path(id3_file) {
+        json["_properties"] = backend.properties(id3_file)?;
+    } else if let Some(props) = properties::read_properties(id3_file)? {
+        let mut props = props.to_json();
+        if let Some(settings) = tag.get("TSSE").and_then(|f| f.content().text()) {
+            props["encoder_settings"] = settings.into();
+        }
+        // Keys beginning with an underscore are informational and ignored by apply
+        json["_properties"] = props;
+    }
+    Ok((json, tag))
+}
+
+/// Compares the tag now on disk against the one that was written, listing every frame that didn't survive
+fn verify_written_tag(path: &Path, intended: &Tag, ignore: &[String]) -> StrResult<()> {
+    let written = match read_tag(path) {
+        Ok(t) => t,
+        Err(e) => Err(e.context("Verification failed, cannot re-read tag"))?,
+    };
+    let mut problems = vec![];
+    for frame in intended.frames() {
+        if ignore::is_ignored(frame, ignore) {
+            continue;
+        }
+        if written
+            .frames()
+            .any(|f| f.id() == frame.id() && f.content() == frame.content())
+        {
+            continue;
+        }
+        match written.frames().find(|f| f.id() == frame.id()) {
+            None => problems.push(format!("{} is missing", frame.id())),
+            Some(f) if f.content() != frame.content() => {
+                problems.push(format!("{} was written as \"{}\"", frame.id(), f.content()))
+            }
+            Some(_) => {}
+        }
+    }
+    for frame in written.frames() {
+        if intended.get(frame.id()).is_none() && !ignore::is_ignored(frame, ignore) {
+            problems.push(format!("{} was not expected", frame.id()));
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Other(format!(
+            "Verification of {} failed: {}",
+            path.to_string_lossy(),
+            problems.join(", ")
+        )))
+    }
+}
+
+/// Reads and parses a JSON document, such as a sidecar to apply
+pub fn read_json_file(path: &Path) -> StrResult<JsonValue> {
+    paranoid::check_path(path)?;
+    let json = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => Err(Error::Io(format!("Unable to open json file: {e}")))?,
+    };
+    match json::parse(&json) {
+        Ok(j) => Ok(j),
+        Err(e) => Err(Error::Parse(format!("Unable to parse JSON: {e}"))),
+    }
+}
+
+/// Builds a tag from an extracted JSON object. Keys starting with an underscore are informational and skipped.
+/// Files the object refers to, such as chapter images, are found relative to `base`
+pub fn json_to_tag(json: &JsonValue, base: &Path) -> StrResult<Tag> {
+    if !json.is_object() {
+        return Err(Error::Parse("No root object found".to_string()));
+    }
+
+    let mut tag = Tag::new();
+
+    for (key, val) in json.entries() {
+        if let Some(frame) = release_ids::frame_for(key, &val.to_string()) {
+            tag.add_frame(frame?);
+        } else if val.is_object() && position::FRAMES.contains(&key) {
+            let position = position::from_json(val).map_err(|e| e.context(key))?;
+            tag.add_frame(Frame::text(key, position.to_string()));
+        } else if key == "PCNT" && val.is_number() {
+            let count = val
+                .as_u64()
+                .ok_or_else(|| Error::Parse(format!("PCNT must be a whole number, not {val}")))?;
+            tag.add_frame(frames::play_counter(count));
+        } else if let Some(frame) = val.as_str().and_then(|v| frames::user_defined(key, v)) {
+            tag.add_frame(frame);
+        } else if frames::is_structured(key, val) {
+            let Some(id) = migrate::checked_id(key) else {
+                eprintln!("Skipping \"{key}\", which is not a known key or frame ID");
+                continue;
+            };
+            let val = &match id.as_str() {
+                "USLT" | "SYLT" => lyrics::inline_files(val, base).map_err(|e| e.context(&id))?,
+                _ => val.clone(),
+            };
+            for frame in frames::from_json(&id, val)? {
+                tag.add_frame(frame);
+            }
+        } else if let Some(values) = frames::text_values(val).filter(|_| !key.starts_with('_')) {
+            let Some(id) = migrate::checked_id(key) else {
+                eprintln!("Skipping \"{key}\", which is not a known key or frame ID");
+                continue;
+            };
+            if values.len() > 1 && !id.starts_with('T') {
+                return Err(Error::Parse(format!("{id} can only hold one value")));
+            }
+            if let Some(frame) = frames::from_text(&id, values[0]) {
+                tag.add_frame(frame);
+                continue;
+            }
+            let mut values: Vec<String> = values.into_iter().map(str::to_owned).collect();
+            for text in &mut values {
+                if id == "TSRC" {
+                    *text = isrc::parse(text)?;
+                } else if position::FRAMES.contains(&id.as_str()) {
+                    *text = position::parse(text)
+                        .map_err(|e| e.context(&id))?
+                        .to_string();
+                }
+            }
+            // ID3v2.4 separates values with NUL, which is how the tag is written
+            let text = values.join("\0");
+            let frame = if id.starts_with('W') {
+                Frame::link(id, text)
+            } else {
+                Frame::text(id, text)
+            };
+            tag.add_frame(frame);
+        }
+    }
+    if json.has_key("CHAP") || json.has_key("CTOC") {
+        let chapters = if json.has_key("CHAP") {
+            chapters::from_json(&json["CHAP"], base)?
+        } else {
+            vec![]
+        };
+        let tocs = if json.has_key("CTOC") {
+            chapters::tocs_from_json(&json["CTOC"], &chapters)?
+        } else if chapters.is_empty() {
+            vec![]
+        } else {
+            vec![chapters::default_toc(&chapters)]
+        };
+        for toc in tocs {
+            tag.add_frame(toc);
+        }
+        for chapter in chapters {
+            tag.add_frame(chapter);
+        }
+    }
+    if json.has_key("APIC") {
+        for picture in pictures::from_json(&json["APIC"], base)? {
+            tag.add_frame(picture);
+        }
+    }
+    Ok(tag)
+}
+
+/// A front cover picture holding the image, with its MIME type sniffed from the data
+pub fn cover_picture(data: Vec<u8>) -> Picture {
+    Picture {
+        mime_type: image::mime_type(&data).unwrap_or("image/jpeg").to_owned(),
+        data,
+        description: "".to_owned(),
+        picture_type: id3::frame::PictureType::CoverFront,
+    }
+}
+
+/// The image sidecar batch extraction would have written for a track, if one exists
+fn art_sidecar(track: &Path) -> Option<PathBuf> {
+    ["jpeg", "jpg", "png", "gif", "webp", "avif"]
+        .iter()
+        .map(|ext| track.with_extension(ext))
+        .find(|p| p.is_file())
+}
+
+/// Embeds a track's image sidecar, if it has one
+pub fn embed_sidecar_art(tag: &mut Tag, track: &Path) -> StrResult<()> {
+    let Some(art) = art_sidecar(track) else {
+        return Ok(());
+    };
+    let data = match std::fs::read(&art) {
+        Ok(data) => data,
+        Err(e) => Err(Error::Io(format!(
+            "Cannot read {}: {e}",
+            art.to_string_lossy()
+        )))?,
+    };
+    tag.add_frame(cover_picture(data));
+    Ok(())
+}
+
+/// Applies the chosen transforms to a tag about to be written to `path`
+pub fn apply_transforms(tag: &mut Tag, path: &Path, opts: &TransformOpts) -> StrResult<()> {
+    if opts.merge {
+        *tag = merge_into_existing(tag, path)?;
+    }
+    // First, so the other transforms see the expanded text
+    if opts.templates {
+        let existing = read_tag(path).unwrap_or_default();
+        template::expand_tag(tag, &existing)?;
+    }
+    if opts.normalize {
+        normalize::normalize_tag(tag);
+    }
+    if let Some(list) = &opts.authority {
+        let unmatched = authority::Authority::load(list)?.rewrite_tag(tag);
+        authority::report_unmatched(path, &unmatched);
+    }
+    if let Some(style) = opts.typography {
+        typography::restyle_tag(tag, style, &opts.typography_frames);
+    }
+    if opts.genre_codes {
+        genre::encode_tag(tag);
+    }
+    if let Some(rules) = &opts.rules {
+        rules::Rules::load(rules)?.apply(tag, path)?;
+    }
+    // Last, so the slugs reflect the final text
+    if opts.slugs {
+        slug::add_slug_frames(tag);
+    }
+    if let Some(target) = opts.convert_art {
+        convert_modern_art(tag, target)?;
+    }
+    Ok(())
+}
+
+/// The file's existing tag with the frames of `tag` in place of those they conflict with, such as
+/// the same text frame, the TXXX of the same description or the picture of the same type
+fn merge_into_existing(tag: &Tag, path: &Path) -> StrResult<Tag> {
+    let mut merged = read_tag_or_empty(path)?;
+    // id3 keeps several of these side by side unless their URLs match, so clear them outright
+    for id in ["WCOM", "WOAR"] {
+        if tag.get(id).is_some() {
+            merged.remove(id);
+        }
+    }
+    for frame in tag.frames() {
+        merged.add_frame(frame.clone());
+    }
+    Ok(merged)
+}
+
+/// Re-encodes the tag's WebP and AVIF pictures in the target format
+fn convert_modern_art(tag: &mut Tag, target: image::Target) -> StrResult<()> {
+    let modern: Vec<Picture> = tag
+        .pictures()
+        .filter(|p| image::is_modern(image::mime_type(&p.data).unwrap_or(&p.mime_type)))
+        .cloned()
+        .collect();
+    for mut picture in modern {
+        picture.data = image::convert(&picture.data, target)
+            .map_err(|e| e.context("Could not convert art"))?;
+        picture.mime_type = target.mime_type().to_owned();
+        tag.add_frame(picture);
+    }
+    Ok(())
+}
+
+/// Replaces the tag of the given file, holding a lock on it for the duration
+pub fn write_tag(path: &Path, tag: &Tag, opts: &WriteOpts) -> StrResult<()> {
+    // Tags are always written as ID3v2.4, so older frames need converting first
+    let mut tag = tag.clone();
+    migrate::warn_unmappable(&migrate::migrate_v24(&mut tag), &path.to_string_lossy());
+    order::reorder(&mut tag, &opts.frame_order);
+    let tag = &tag;
+    if opts.dry_run {
+        return estimate::record(path, tag, opts.crc);
+    }
+    paranoid::check_input(path)?;
+    let change = manifest::Change::begin(path);
+    let state = preserve::FileState::capture(path)?;
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(f) => f,
+        Err(e) => Err(Error::Io(format!(
+            "Cannot open {}: {e}",
+            path.to_string_lossy()
+        )))?,
+    };
+    lock::lock_exclusive(&file, path)?;
+    if let Some(backend) = backend::for_path(path) {
+        backend.write_tag(path, &mut file, tag, opts.buffer_size)?;
+    } else {
+        splice::replace_tag(path, &mut file, tag, opts.buffer_size, opts.crc)?;
+    }
+    drop(file);
+    state.restore(path, opts.preserve_mtime)?;
+    change.finish();
+    if opts.verify {
+        verify_written_tag(path, tag, &opts.ignore_frames)?;
+    }
+    if let Some(command) = &opts.on_change {
+        hooks::run_on_change(command, path);
+    }
+    Ok(())
+}
+
+/// Writes a JSON document's tags to the file, replacing its existing tag unless
+/// `transform.merge` is set. Files the document refers to are found relative to `base`.
+pub fn apply_tags(
+    path: &Path,
+    json: &JsonValue,
+    base: &Path,
+    write: &WriteOpts,
+    transform: &TransformOpts,
+) -> StrResult<()> {
+    let mut tag = json_to_tag(json, base)?;
+    apply_transforms(&mut tag, path, transform)?;
+    write_tag(path, &tag, write)
+}
+
+/// Writes the JSON and art sidecars for a file under the names batch extraction uses
+pub fn extract_sidecars(file: &Path) -> StrResult<()> {
+    let (mut json, pictures) = extract_tags_pic(file)?;
+    let dir = file.parent().unwrap_or(Path::new(""));
+    pictures::extract(&mut json, dir, &file.with_extension("jpeg"), &pictures)?;
+    let json = json::stringify_pretty(json, 4);
+    write_data_to_path(&file.with_extension("json"), json.as_bytes())
+}
+
+/// Adds an entry to an aggregate blob, keeping the exact path alongside if the key had to be lossy
+pub fn insert_keyed(blob: &mut JsonValue, path: &Path, mut json: JsonValue) {
+    let (key, raw_path) = paths::json_key(path);
+    if let Some(raw_path) = raw_path {
+        json["_raw_path"] = raw_path;
+    }
+    blob[key.as_str()] = json;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_and_lyrics_can_be_plain_text() {
+        let dir = testing::scratch_dir("plain-comment");
+        let track = dir.join("t.mp3");
+        testing::write_mp3(&track);
+        let json = json::object! { "TIT2": "Title", "COMM": "Recorded live", "USLT": "La la" };
+        let tag = json_to_tag(&json, &dir).unwrap();
+        write_tag(&track, &tag, &WriteOpts::default()).unwrap();
+
+        let written = read_tag(&track).unwrap();
+        let comment = written.comments().next().unwrap();
+        assert_eq!(
+            (&*comment.lang, &*comment.description, &*comment.text),
+            ("eng", "", "Recorded live")
+        );
+        let lyrics = written.lyrics().next().unwrap();
+        assert_eq!((&*lyrics.lang, &*lyrics.text), ("eng", "La la"));
+
+        let several = json::object! { "COMM": ["one", "two"] };
+        assert!(matches!(json_to_tag(&several, &dir), Err(Error::Parse(_))));
+    }
+}
@@ -0,0 +1,136 @@
+//! Checks for suspicious frame values, with automatic fixes for the purely mechanical problems
+
+use crate::batch::BatchReport;
+use crate::{paths, StrResult, WriteOpts};
+use id3::{Content, Frame, Tag, TagLike};
+use std::path::{Path, PathBuf};
+
+/// A problem found in a single frame
+pub struct Finding {
+    pub frame: String,
+    pub rule: &'static str,
+    pub message: String,
+    /// The corrected value, for rules whose fix needs no judgement
+    pub fix: Option<String>,
+}
+
+const YEAR_FRAMES: [&str; 4] = ["TDRC", "TYER", "TDOR", "TORY"];
+const MAX_TITLE_LEN: usize = 250;
+
+fn collapse_spaces(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if !(c == ' ' && out.ends_with(' ')) {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Runs every rule against one text frame. NUL is allowed since it separates multiple values
+pub fn lint_text(id: &str, text: &str) -> Vec<Finding> {
+    let mut findings = vec![];
+    let mut finding = |rule, message: String, fix| {
+        findings.push(Finding {
+            frame: id.to_owned(),
+            rule,
+            message,
+            fix,
+        })
+    };
+
+    if YEAR_FRAMES.contains(&id) {
+        let year: Option<i32> = text.get(0..4).and_then(|y| y.parse().ok());
+        match year {
+            Some(y) if (1900..=2100).contains(&y) => {}
+            _ => finding(
+                "year-range",
+                format!("\"{text}\" is not a year from 1900 to 2100"),
+                None,
+            ),
+        }
+    }
+    if id == "TRCK" && text.split('/').next().map(str::trim) == Some("0") {
+        finding("track-zero", "track number is 0".to_owned(), None);
+    }
+    if id == "TSRC" {
+        match crate::isrc::parse(text) {
+            Err(message) => finding("isrc-format", message.to_string(), None),
+            Ok(code) if code != text => {
+                let message = "ISRC should be stored as 12 characters without hyphens".to_owned();
+                finding("isrc-format", message, Some(code));
+            }
+            Ok(_) => {}
+        }
+    }
+    if id == "TIT2" && text.chars().count() > MAX_TITLE_LEN {
+        let message = format!("title is longer than {MAX_TITLE_LEN} characters");
+        finding("title-length", message, None);
+    }
+
+    let mut fixed = text.to_owned();
+    if text.chars().any(|c| c.is_control() && c != '\0') {
+        fixed = fixed
+            .chars()
+            .filter(|c| !c.is_control() || *c == '\0')
+            .collect();
+        finding(
+            "control-chars",
+            "value contains control characters".to_owned(),
+            Some(fixed.clone()),
+        );
+    }
+    if fixed.contains("  ") {
+        fixed = collapse_spaces(&fixed);
+        finding(
+            "double-spaces",
+            "value contains repeated spaces".to_owned(),
+            Some(fixed),
+        );
+    }
+    findings
+}
+
+pub fn lint_tag(tag: &Tag) -> Vec<Finding> {
+    tag.frames()
+        .filter_map(|f| f.content().text().map(|t| lint_text(f.id(), t)))
+        .flatten()
+        .collect()
+}
+
+fn lint_file(path: &Path, fix: bool, opts: &WriteOpts, report: &mut BatchReport) -> StrResult<()> {
+    let mut tag = match crate::read_tag(path) {
+        Ok(t) => t,
+        Err(e) => Err(e.context("Unable to open id3 file"))?,
+    };
+    let findings = lint_tag(&tag);
+    let note = |f: &Finding| format!("{}: {}: {}", f.frame, f.rule, f.message);
+    let (fixed, unfixed): (Vec<_>, Vec<_>) = findings.iter().partition(|f| fix && f.fix.is_some());
+    for finding in unfixed {
+        report.find(path, note(finding));
+    }
+    if fixed.is_empty() {
+        return Ok(());
+    }
+    // Later findings for a frame build on the earlier fixes, so the last one holds the final value
+    for finding in &fixed {
+        let value = finding.fix.clone().unwrap_or_default();
+        tag.add_frame(Frame::with_content(&finding.frame, Content::Text(value)));
+    }
+    crate::write_tag(path, &tag, opts)?;
+    for finding in fixed {
+        report.succeed(path, format!("{} (fixed)", note(finding)));
+    }
+    Ok(())
+}
+
+/// Lints every file, reporting the problems fixed as changes and the rest as findings
+pub fn lint(files: &[PathBuf], fix: bool, opts: &WriteOpts) -> BatchReport {
+    let mut report = BatchReport::default();
+    for file in paths::collect_audio_files(files, true) {
+        if let Err(e) = lint_file(&file, fix, opts, &mut report) {
+            report.fail(&file, e);
+        }
+    }
+    report
+}
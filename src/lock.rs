@@ -0,0 +1,39 @@
+//! Advisory file locking, so that concurrent runs don't interleave writes to the same file
+
+use crate::{Error, StrResult};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+
+/// Blocks until an exclusive lock on `file` is held, telling the user if another process has it
+pub fn lock_exclusive(file: &File, path: &Path) -> StrResult<()> {
+    match file.try_lock() {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => {
+            eprintln!("Waiting for lock on {}", path.to_string_lossy());
+            file.lock()
+                .map_err(|e| Error::Locked(format!("Cannot lock {}: {e}", path.to_string_lossy())))
+        }
+        Err(TryLockError::Error(e)) => Err(Error::Locked(format!(
+            "Cannot lock {}: {e}",
+            path.to_string_lossy()
+        ))),
+    }
+}
+
+/// Opens (creating if needed) a lock file that is held for as long as the returned handle lives
+pub fn global_lock(path: &Path) -> StrResult<File> {
+    let file = match OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+    {
+        Ok(f) => f,
+        Err(e) => Err(Error::Io(format!(
+            "Cannot open lock file {}: {e}",
+            path.to_string_lossy()
+        )))?,
+    };
+    lock_exclusive(&file, path)?;
+    Ok(file)
+}
@@ -0,0 +1,92 @@
+//! LRC, the timestamped lyrics format karaoke and lyrics apps read, converted to and from the
+//! timed syllables or lines of a SYLT frame
+//!
+//! ```text
+//! [00:12.30]First line
+//! [00:17.05]Second line
+//! ```
+//!
+//! A line can carry several timestamps, for a refrain repeated word for word, and `[offset:+250]`
+//! makes every line after it appear that many milliseconds sooner. Other ID tags such as `[ar:...]`
+//! are ignored, since the tag already holds the artist and title.
+
+use crate::{Error, StrResult};
+
+/// The text of a SYLT frame's millisecond timestamps and lines as LRC
+pub fn to_lrc(content: &[(u32, String)]) -> String {
+    let mut lrc = String::new();
+    for (ms, text) in content {
+        let minutes = ms / 60_000;
+        let seconds = ms / 1000 % 60;
+        let hundredths = ms % 1000 / 10;
+        lrc.push_str(&format!(
+            "[{minutes:02}:{seconds:02}.{hundredths:02}]{text}\n"
+        ));
+    }
+    lrc
+}
+
+/// A timestamp such as `01:23.45`, `01:23.456` or `01:23`, in milliseconds
+fn parse_timestamp(stamp: &str) -> Option<u32> {
+    let (minutes, rest) = stamp.split_once(':')?;
+    let (seconds, fraction) = rest.split_once('.').unwrap_or((rest, ""));
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(minutes) || !digits(seconds) || !(fraction.is_empty() || digits(fraction)) {
+        return None;
+    }
+    let ms = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<u32>().ok()? * 100,
+        2 => fraction.parse::<u32>().ok()? * 10,
+        _ => fraction[..3].parse().ok()?,
+    };
+    let minutes: u32 = minutes.parse().ok()?;
+    let seconds: u32 = seconds.parse().ok()?;
+    minutes
+        .checked_mul(60_000)?
+        .checked_add(seconds.checked_mul(1000)?)?
+        .checked_add(ms)
+}
+
+/// The timestamps and lines of LRC text, in time order
+pub fn parse(lrc: &str) -> StrResult<Vec<(u32, String)>> {
+    let mut content = vec![];
+    let mut offset: i64 = 0;
+    for (n, line) in lrc.lines().enumerate() {
+        let mut rest = line.trim();
+        let mut stamps = vec![];
+        while let Some(tag) = rest.strip_prefix('[') {
+            let Some((inner, after)) = tag.split_once(']') else {
+                break;
+            };
+            match parse_timestamp(inner) {
+                Some(ms) => stamps.push(ms),
+                None => match inner.split_once(':') {
+                    Some(("offset", value)) => {
+                        offset = value.trim().parse().map_err(|_| {
+                            Error::Parse(format!("Line {}: \"{value}\" is not an offset", n + 1))
+                        })?
+                    }
+                    Some(_) => {}
+                    None => break,
+                },
+            }
+            rest = after;
+        }
+        if stamps.is_empty() {
+            if rest.is_empty() || line.trim_start().starts_with('[') {
+                continue;
+            }
+            return Err(Error::Parse(format!(
+                "Line {} has no [mm:ss.xx] timestamp: \"{line}\"",
+                n + 1
+            )));
+        }
+        for ms in stamps {
+            let ms = (i64::from(ms) - offset).clamp(0, i64::from(u32::MAX)) as u32;
+            content.push((ms, rest.to_owned()));
+        }
+    }
+    content.sort_by_key(|(ms, _)| *ms);
+    Ok(content)
+}
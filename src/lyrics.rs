@@ -0,0 +1,111 @@
+//! Lyrics kept in sidecar files beside the audio instead of inline in the JSON. A USLT or SYLT
+//! entry then gives the file in place of its text, relative to the JSON document
+//!
+//! ```json
+//! "USLT": [{ "lang": "eng", "description": "", "file": "track.txt" }],
+//! "SYLT": [{ "lang": "eng", "description": "", "type": "lyrics", "file": "track.lrc" }]
+//! ```
+//!
+//! Synchronized lyrics are written as `.lrc`, as are unsynchronized lyrics with LRC timestamps,
+//! which some taggers store in USLT.
+
+use crate::{paranoid, write_data_to_path, Error, StrResult};
+use json::JsonValue;
+use std::path::{Path, PathBuf};
+
+/// The frames whose text can be kept in a sidecar
+const FRAMES: [&str; 2] = ["USLT", "SYLT"];
+
+/// Whether the lyrics carry LRC timestamps such as `[01:23.45]` at the start of their lines
+fn is_lrc(text: &str) -> bool {
+    text.lines().any(|line| {
+        let b = line.trim_start().as_bytes();
+        b.len() >= 6
+            && b[0] == b'['
+            && b[1].is_ascii_digit()
+            && b[2].is_ascii_digit()
+            && b[3] == b':'
+            && b[4].is_ascii_digit()
+    })
+}
+
+/// Writes the text of each USLT and SYLT entry in the JSON to a sidecar named after `audio`, the
+/// first as `track.txt` or `track.lrc` and any others by language, such as `track.deu.lrc`,
+/// replacing the text with the file's path relative to `json_dir`
+pub fn extract(json: &mut JsonValue, json_dir: &Path, audio: &Path) -> StrResult<()> {
+    let stem = audio.with_extension("").into_os_string();
+    let stem = stem.to_string_lossy();
+    let mut written: Vec<PathBuf> = vec![];
+    for id in FRAMES {
+        if !json[id].is_array() {
+            continue;
+        }
+        for entry in json[id].members_mut() {
+            let Some(text) = entry["text"].as_str().map(str::to_owned) else {
+                continue;
+            };
+            let ext = if id == "SYLT" || is_lrc(&text) {
+                "lrc"
+            } else {
+                "txt"
+            };
+            let mut path = PathBuf::from(format!("{stem}.{ext}"));
+            if written.contains(&path) {
+                let lang = entry["lang"].as_str().unwrap_or("XXX").to_ascii_lowercase();
+                path = PathBuf::from(format!("{stem}.{lang}.{ext}"));
+                let mut n = 1;
+                while written.contains(&path) {
+                    n += 1;
+                    path = PathBuf::from(format!("{stem}.{lang}-{n}.{ext}"));
+                }
+            }
+            write_data_to_path(&path, text.as_bytes())?;
+            let file = match path.strip_prefix(json_dir) {
+                Ok(relative) => relative.to_owned(),
+                Err(_) => path.canonicalize().unwrap_or_else(|_| path.clone()),
+            };
+            entry.remove("text");
+            entry["file"] = file.to_string_lossy().as_ref().into();
+            written.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_file(reference: &str, base: &Path) -> StrResult<String> {
+    paranoid::check_reference(Path::new(reference))?;
+    let path = base.join(reference);
+    paranoid::check_path(&path)?;
+    std::fs::read_to_string(&path).map_err(|e| {
+        Error::Io(format!(
+            "Cannot read lyrics {}: {e}",
+            path.to_string_lossy()
+        ))
+    })
+}
+
+fn inline_file(entry: &mut JsonValue, base: &Path) -> StrResult<()> {
+    if entry.has_key("text") {
+        return Ok(());
+    }
+    if let Some(file) = entry["file"].as_str() {
+        let text = read_file(file, base)?;
+        entry.remove("file");
+        entry["text"] = text.into();
+    }
+    Ok(())
+}
+
+/// The USLT or SYLT entries, as an array or a lone object, with the lyrics of any that name a file read
+/// in as their text. Files are found relative to `base`, and inline text wins when an entry has both
+pub fn inline_files(entries: &JsonValue, base: &Path) -> StrResult<JsonValue> {
+    let mut entries = entries.clone();
+    if entries.is_object() {
+        inline_file(&mut entries, base)?;
+    } else {
+        for entry in entries.members_mut() {
+            inline_file(entry, base)?;
+        }
+    }
+    Ok(entries)
+}
@@ -1,23 +1,76 @@
 use clap::*;
 use id3::frame::Picture;
-use id3::{Frame, Tag, TagLike};
+use id3::{Content, TagLike};
 use json::JsonValue;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
-
-type StrResult<T> = Result<T, String>;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+use tag2json::Error;
+use tag2json::*;
 
 #[derive(Args, Clone)]
 struct BatchOpts {
     /// The files to extract
     files: Vec<PathBuf>,
+    /// Also extract the files listed in this file, one per line, or read the list from stdin if it is -
+    #[arg(long)]
+    files_from: Option<PathBuf>,
+    /// The --files-from list is NUL-separated, as from find -print0
+    #[arg(short = '0', long, default_value_t = false, requires = "files_from")]
+    null: bool,
     /// Aggregates output into a single JSON blob on stdout rather than individual files
     #[arg(short, long, default_value_t = false)]
     aggregate_output: bool,
+    /// Split the aggregate output into one file per artist, initial or directory instead of printing it
+    #[arg(long, value_enum, requires = "aggregate_output")]
+    shard_by: Option<shard::ShardBy>,
+    /// The directory to write shards into
+    #[arg(long, default_value = ".", requires = "shard_by")]
+    shard_dir: PathBuf,
     /// Recurses into any found directories
     #[arg(short, long, default_value_t = true)]
     recurse: bool,
+    /// Rename audio files with extensions like .MP3 or .Mp3 to lowercase before extracting
+    #[arg(long, default_value_t = false)]
+    rename_extensions: bool,
+    /// Emit one album document per directory, with fields shared by every track stored once and the rest under "tracks"
+    #[arg(long, default_value_t = false)]
+    album_mode: bool,
+    /// Name keys in a built-in language (en, de, ja) or per a JSON file mapping frame IDs to names, instead of raw frame IDs
+    #[arg(long)]
+    keys: Option<String>,
+    /// Name keys in English, such as title, artist and album, instead of raw frame IDs. Short for --keys en
+    #[arg(long, default_value_t = false, conflicts_with = "keys")]
+    friendly_keys: bool,
+    /// Write JSON sidecars, or print one line per track to stdout without writing anything
+    #[arg(long, value_enum, default_value_t = report::Format::Json)]
+    format: report::Format,
+    /// The line printed for each track in text format, with {FRAME} placeholders such as {TPE1}
+    #[arg(long, default_value = "{TPE1} - {TIT2}")]
+    row: String,
+    /// Append a record of each file's tag hash and the time to this log, for the history command
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+    /// Add URL-safe slugs of the title and artist to the JSON under "_slugs", without touching the files
+    #[arg(long, default_value_t = false)]
+    slugs: bool,
+    /// Write keys in this case. Apply accepts frame IDs and names in any case
+    #[arg(long, value_enum)]
+    key_case: Option<keys::KeyCase>,
+    /// Also write a checksums.txt to each directory, giving every file's audio and tag hash
+    #[arg(long, value_enum, value_name = "ALGORITHM")]
+    emit_checksums: Option<checksums::Algorithm>,
+    /// Write lyrics to a .txt, or .lrc if they have timestamps, beside the audio, and refer to it from the JSON instead of including the text
+    #[arg(long, default_value_t = false)]
+    lyrics_sidecar: bool,
+    /// Give POPM ratings as stars from 0 to 5 instead of the 0-255 value stored
+    #[arg(long, default_value_t = false)]
+    stars: bool,
+    /// Give TCON genres stored as ID3v1 numbers, such as (17), by name
+    #[arg(long, default_value_t = false)]
+    genre_names: bool,
+    #[command(flatten)]
+    classes: ClassOpts,
 }
 
 #[derive(Args, Clone)]
@@ -28,22 +81,511 @@ struct SingleOpts {
     json: Option<PathBuf>,
     /// The path of the album art.
     art: Option<PathBuf>,
+    /// Name keys in a built-in language (en, de, ja) or per a JSON file mapping frame IDs to names, instead of raw frame IDs
+    #[arg(long)]
+    keys: Option<String>,
+    /// Name keys in English, such as title, artist and album, instead of raw frame IDs. Short for --keys en
+    #[arg(long, default_value_t = false, conflicts_with = "keys")]
+    friendly_keys: bool,
+}
+
+#[derive(Args, Clone)]
+#[group(multiple = false)]
+struct ClassOpts {
+    /// Only extract text frames
+    #[arg(long, default_value_t = false)]
+    only_text: bool,
+    /// Only extract URL link frames
+    #[arg(long, default_value_t = false)]
+    only_urls: bool,
+    /// Only extract album art and other binary frames such as chapters
+    #[arg(long, default_value_t = false)]
+    only_binary: bool,
+}
+
+impl ClassOpts {
+    fn only(&self) -> Option<frame_class::FrameClass> {
+        use frame_class::FrameClass::*;
+        [
+            (self.only_text, Text),
+            (self.only_urls, Url),
+            (self.only_binary, Binary),
+        ]
+        .into_iter()
+        .find_map(|(set, class)| set.then_some(class))
+    }
+
+    /// Drops the frames, and the art, outside the chosen class
+    fn filter(&self, json: JsonValue, pictures: Vec<Picture>) -> (JsonValue, Vec<Picture>) {
+        frame_class::keep_only(self.only(), json, pictures)
+    }
+}
+
+#[derive(Args, Clone)]
+struct ExtractOpts {
+    #[command(flatten)]
+    single: SingleOpts,
+    /// Only output frames that differ from this earlier sidecar or aggregate snapshot, with removed frames as null
+    #[arg(long)]
+    since: Option<PathBuf>,
+    /// Comma-separated frames --since doesn't count as changed, such as PCNT,POPM,TXXX:LastPlayed
+    #[arg(long, value_delimiter = ',', requires = "since")]
+    ignore_frames: Vec<String>,
+    /// Append a record of the file's tag hash and the time to this log, for the history command
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+    /// Add URL-safe slugs of the title and artist to the JSON under "_slugs", without touching the file
+    #[arg(long, default_value_t = false)]
+    slugs: bool,
+    /// Write keys in this case. Apply accepts frame IDs and names in any case
+    #[arg(long, value_enum)]
+    key_case: Option<keys::KeyCase>,
+    /// Put every picture in the JSON under "APIC", as base64 with its MIME type, picture type and
+    /// description, instead of writing the first to an image file
+    #[arg(long, default_value_t = false, conflicts_with = "art")]
+    embed_art: bool,
+    /// Write lyrics to a .txt, or .lrc if they have timestamps, beside the audio, and refer to it from the JSON instead of including the text
+    #[arg(long, default_value_t = false)]
+    lyrics_sidecar: bool,
+    /// Give POPM ratings as stars from 0 to 5 instead of the 0-255 value stored
+    #[arg(long, default_value_t = false)]
+    stars: bool,
+    /// Give TCON genres stored as ID3v1 numbers, such as (17), by name
+    #[arg(long, default_value_t = false)]
+    genre_names: bool,
+    #[command(flatten)]
+    classes: ClassOpts,
+}
+
+#[derive(Args, Clone)]
+struct ShowOpts {
+    #[arg(value_parser = file_exists)]
+    id3: PathBuf,
+    /// Name keys in a built-in language (en, de, ja) or per a JSON file mapping frame IDs to names, instead of raw frame IDs
+    #[arg(long)]
+    keys: Option<String>,
+    /// Name keys in English, such as title, artist and album, instead of raw frame IDs. Short for --keys en
+    #[arg(long, default_value_t = false, conflicts_with = "keys")]
+    friendly_keys: bool,
+    #[command(flatten)]
+    classes: ClassOpts,
+    /// Print a table of the frames with what each one means, flagging deprecated frames, instead of JSON
+    #[arg(long, default_value_t = false, conflicts_with_all = ["keys", "friendly_keys"])]
+    table: bool,
+    /// Print the tag's header flags, extended header and any CRC it stores, instead of the frames
+    #[arg(long, default_value_t = false, conflicts_with_all = ["keys", "friendly_keys", "table"])]
+    inspect: bool,
+    /// Copy the JSON to the system clipboard instead of printing it
+    #[cfg(feature = "clipboard")]
+    #[arg(long, default_value_t = false, conflicts_with = "table")]
+    copy: bool,
+}
+
+#[derive(Args, Clone)]
+struct ApplyOpts {
+    #[command(flatten)]
+    single: SingleOpts,
+    /// Read the JSON from the system clipboard rather than a file
+    #[cfg(feature = "clipboard")]
+    #[arg(long, default_value_t = false, conflicts_with_all = ["json", "common"])]
+    from_clipboard: bool,
+    /// Apply this JSON file to every file given, taking all the positional arguments as audio files
+    #[arg(long = "json", value_name = "JSON")]
+    common: Option<PathBuf>,
+    /// Further audio files, when applying one JSON file to several with --json
+    #[arg(requires = "common")]
+    more: Vec<PathBuf>,
+    /// Sets a key over the document's value, as in --set TIT2="Live Version". Can be repeated
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_assignment)]
+    overrides: Vec<(String, String)>,
+    #[command(flatten)]
+    write: WriteOpts,
+    #[command(flatten)]
+    transform: TransformOpts,
+}
+
+#[derive(Args, Clone)]
+struct BatchApplyOpts {
+    /// Album documents or aggregates from batch-extract -a to apply, or directories containing an album.json
+    docs: Vec<PathBuf>,
+    /// A JSON file mapping frame IDs to the names used in the documents. Built-in language key sets are always recognised
+    #[arg(long)]
+    keys: Option<String>,
+    /// A JSON policy file limiting, for instance, the size of the art written to each track
+    #[arg(long)]
+    policy: Option<PathBuf>,
+    #[command(flatten)]
+    write: WriteOpts,
+    #[command(flatten)]
+    transform: TransformOpts,
+}
+
+#[derive(Args, Clone)]
+struct ScrubOpts {
+    /// The files or directories to scrub
+    files: Vec<PathBuf>,
+    /// Which frames to strip
+    #[arg(long, value_enum, default_value_t = scrub::Profile::Publish)]
+    profile: scrub::Profile,
+    /// A JSON policy file whose "publish" section replaces the frames stripped ("strip") or spares some of them ("keep")
+    #[arg(long)]
+    policy: Option<PathBuf>,
+    #[command(flatten)]
+    write: WriteOpts,
+}
+
+#[derive(Args, Clone)]
+struct ApplyRulesOpts {
+    /// The files or directories to apply the rules to
+    files: Vec<PathBuf>,
+    /// A JSON file of rules, each setting or removing frames on the files meeting its conditions
+    #[arg(long)]
+    rules: PathBuf,
+    #[command(flatten)]
+    write: WriteOpts,
+}
+
+#[derive(Args, Clone)]
+struct CleanTextOpts {
+    /// The files or directories to normalize
+    files: Vec<PathBuf>,
+    /// Also rewrite TPE1 and TPE2 to the canonical names in this authority list, reporting names it lacks
+    #[arg(long)]
+    authority: Option<PathBuf>,
+    #[command(flatten)]
+    write: WriteOpts,
+}
+
+#[derive(Args, Clone)]
+struct CheckPathsOpts {
+    /// The files or directories to check
+    files: Vec<PathBuf>,
+    /// The naming template, with frame IDs in braces (e.g. "{TPE1}/{TALB}/{TRCK} {TIT2}"). Names are relative to each file's directory
+    #[arg(short, long)]
+    template: String,
+    /// Which file systems' naming rules the generated names must satisfy
+    #[arg(long, value_enum, default_value_t = check_paths::Target::All)]
+    target: check_paths::Target,
+    /// Print only the paths of the files with problems, each ended by a NUL, for xargs -0
+    #[arg(short = '0', long, default_value_t = false)]
+    print0: bool,
+}
+
+#[derive(Args, Clone)]
+struct ReorganizeOpts {
+    /// The files or directories to sort
+    files: Vec<PathBuf>,
+    /// The naming template, with frame IDs in braces (e.g. "{TPE2}/{TALB}/{TRCK} {TIT2}"). Names are relative to --dest
+    #[arg(short, long)]
+    template: String,
+    /// The root of the tree to sort the files into
+    #[arg(long)]
+    dest: PathBuf,
+    /// Whether to move, symlink or copy each file into the tree
+    #[arg(long, value_enum, default_value_t = reorganize::Action::Move)]
+    action: reorganize::Action,
+    /// What to do when a file's place is already taken
+    #[arg(long, value_enum, default_value_t = reorganize::Collision::Skip)]
+    on_collision: reorganize::Collision,
+    /// Which file systems' naming rules the generated names must satisfy
+    #[arg(long, value_enum, default_value_t = check_paths::Target::All)]
+    target: check_paths::Target,
+    /// Print where each file would go without moving anything
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+}
+
+#[derive(Args, Clone)]
+struct LintOpts {
+    /// The files or directories to check
+    files: Vec<PathBuf>,
+    /// Correct the problems that have a mechanical fix (control characters, repeated spaces) and write the tags back
+    #[arg(long, default_value_t = false)]
+    fix: bool,
+    #[command(flatten)]
+    write: WriteOpts,
+}
+
+#[derive(Args, Clone)]
+struct ReportOpts {
+    /// The files or directories to survey
+    files: Vec<PathBuf>,
+    /// Count how many files carry each frame ID, with example values
+    #[arg(long, default_value_t = false)]
+    frames: bool,
+    /// List how the frame IDs of ID3v2.2 tags map to modern ones, and any that can't be mapped
+    #[arg(long, default_value_t = false)]
+    migration: bool,
+    /// Show the distribution of files by one or more properties (comma separated)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    histogram: Vec<report::Histogram>,
+    #[arg(long, value_enum, default_value_t = report::Format::Text)]
+    format: report::Format,
+}
+
+#[derive(Args, Clone)]
+struct CheckOpts {
+    /// The files or directories to check
+    files: Vec<PathBuf>,
+    /// Find JSON and art sidecars whose audio file is gone, and audio files without a JSON sidecar
+    #[arg(long, default_value_t = false)]
+    orphans: bool,
+    /// Delete the orphaned sidecars found by --orphans
+    #[arg(long, default_value_t = false, requires = "orphans")]
+    delete: bool,
+    /// Extract sidecars for the audio files --orphans finds without one
+    #[arg(long, default_value_t = false, requires = "orphans")]
+    regenerate: bool,
+    /// Find tags whose stored CRC doesn't match their contents
+    #[arg(long, default_value_t = false)]
+    crc: bool,
+    /// Report the frames and values an older player's tag format can't represent
+    #[arg(long, value_enum, value_name = "PLAYER")]
+    target_player: Option<compat::Player>,
+    /// Print only the paths found, each ended by a NUL, for xargs -0
+    #[arg(short = '0', long, default_value_t = false)]
+    print0: bool,
+}
+
+#[derive(Args, Clone)]
+struct ExportM3uOpts {
+    /// The files or directories to list, in order
+    files: Vec<PathBuf>,
+    /// Write the playlist here, with entries relative to it, instead of to stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Only list tracks in this language, as an ISO 639-2 code such as eng or fra from TLAN or the lyrics
+    #[arg(long)]
+    language: Option<String>,
+}
+
+#[derive(Args, Clone)]
+struct ExportHtmlOpts {
+    /// The library to catalogue, with one album per directory
+    dir: PathBuf,
+    /// The directory to write index.html and the cover images into
+    out: PathBuf,
+    /// Only catalogue tracks in this language, as an ISO 639-2 code
+    #[arg(long)]
+    language: Option<String>,
+}
+
+#[derive(Args, Clone)]
+struct ExportRssOpts {
+    /// The feed to fill in, with its channel details and an <!-- episodes --> comment where the items go
+    template: PathBuf,
+    /// The directory of episodes
+    dir: PathBuf,
+    /// The URL the episodes are published under, which their paths below the directory are added to
+    #[arg(long)]
+    base_url: String,
+    /// Write the feed here instead of to stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Clone)]
+struct HistoryOpts {
+    /// The audit log written by extract or batch-extract with --audit-log
+    log: PathBuf,
+    /// Only show these files
+    files: Vec<PathBuf>,
+}
+
+#[derive(Args, Clone)]
+struct AssignIsrcOpts {
+    /// The album's files, or the directory holding them
+    files: Vec<PathBuf>,
+    /// The ISRC for the first track, as CC-XXX-YY-NNNNN or without hyphens. Later tracks count up from it
+    #[arg(long)]
+    start: String,
+    /// Replace ISRCs tracks already have, rather than skipping them
+    #[arg(long, default_value_t = false)]
+    overwrite: bool,
+    #[command(flatten)]
+    write: WriteOpts,
+}
+
+#[derive(Args, Clone)]
+struct PropagateOpts {
+    /// The file to copy frames from
+    #[arg(long, value_parser = file_exists)]
+    from: PathBuf,
+    /// Comma-separated frame IDs to copy, such as TALB,TPE2,TDRC,APIC
+    #[arg(long, value_delimiter = ',', required = true)]
+    fields: Vec<String>,
+    /// The files or directories to fill in
+    files: Vec<PathBuf>,
+    #[command(flatten)]
+    write: WriteOpts,
+}
+
+#[derive(Args, Clone)]
+struct ForeignTagsOpts {
+    /// The FLAC, AAC, MP4 or Ogg files, or directories of them, to look through
+    files: Vec<PathBuf>,
+    #[arg(long, value_enum, default_value_t = foreign::Action::Report)]
+    action: foreign::Action,
+}
+
+#[derive(Args, Clone)]
+struct GrepOpts {
+    /// A regular expression to search tag values for
+    pattern: String,
+    /// The files or directories to search
+    files: Vec<PathBuf>,
+    /// Comma-separated frame IDs or keys to search. Defaults to every text frame
+    #[arg(long, value_delimiter = ',')]
+    frames: Vec<String>,
+    /// Match regardless of case
+    #[arg(short, long, default_value_t = false)]
+    ignore_case: bool,
+    /// Only list the names of matching files
+    #[arg(short = 'l', long, default_value_t = false)]
+    files_with_matches: bool,
+    /// End each file name printed by -l with a NUL rather than a newline, for xargs -0
+    #[arg(
+        short = '0',
+        long,
+        default_value_t = false,
+        requires = "files_with_matches"
+    )]
+    print0: bool,
+    #[arg(long, value_enum, default_value_t = report::Format::Text)]
+    format: report::Format,
+}
+
+#[derive(Args, Clone)]
+struct SnapshotOpts {
+    /// The files or directories to include
+    files: Vec<PathBuf>,
+    /// Write the snapshot here instead of to stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Clone)]
+struct SnapshotDiffOpts {
+    /// The earlier snapshot
+    old: PathBuf,
+    /// The later snapshot
+    new: PathBuf,
+    /// Comma-separated frames to leave out of the comparison, such as PCNT,POPM,TXXX:LastPlayed
+    #[arg(long, value_delimiter = ',')]
+    ignore_frames: Vec<String>,
+    #[arg(long, value_enum, default_value_t = report::Format::Text)]
+    format: report::Format,
+}
+
+#[derive(Args, Clone)]
+struct WatchOpts {
+    /// The directories to watch for changed sidecars
+    dirs: Vec<PathBuf>,
+    /// Seconds between scans
+    #[arg(long, default_value_t = 10)]
+    interval: u64,
+    /// Serve /healthz and /metrics over HTTP on this address, such as 127.0.0.1:9310
+    #[arg(long)]
+    listen: Option<String>,
+    /// A JSON file mapping frame IDs to the names used in the sidecars. Built-in language key sets are always recognised
+    #[arg(long)]
+    keys: Option<String>,
+    #[command(flatten)]
+    write: WriteOpts,
 }
 
 #[derive(Subcommand)]
 enum Mode {
     /// Output the tags and album art if present from the given audio file. Missing paths are derived from the id3 filename and existing files overwritten
-    Extract(SingleOpts),
+    Extract(ExtractOpts),
+    /// Print the tags of the given audio file as JSON
+    Show(ShowOpts),
     /// Given a JSON file containing tags, apply the tags to the given audio file
-    Apply(SingleOpts),
+    Apply(ApplyOpts),
     /// Given a list of filenames, extract the tags and albums to correspondingly named files
     BatchExtract(BatchOpts),
+    /// Report files whose names generated from a template would collide or be invalid, without renaming anything
+    CheckPaths(CheckPathsOpts),
+    /// Sort files into a directory tree named from their tags, such as Artist/Album/, by a naming template
+    Reorganize(ReorganizeOpts),
+    /// Apply album documents to the tracks in their directories, matching tracks by filename or track number, or push an aggregate from batch-extract -a back to its files
+    BatchApply(BatchApplyOpts),
+    /// Flag suspicious values such as implausible years, track 0, overlong titles, control characters and repeated spaces
+    Lint(LintOpts),
+    /// Normalize text frames in place: NFC composition, trimmed ends and single spaces
+    CleanText(CleanTextOpts),
+    /// Strip frames that may hold private information, such as comments, PRIV, UFID, GEOB and POPM, before files are shared
+    Scrub(ScrubOpts),
+    /// Set or remove frames in place on the files meeting the conditions of each rule in a rules file
+    ApplyRules(ApplyRulesOpts),
+    /// Summarise the tags across a library
+    Report(ReportOpts),
+    /// Check a library for problems
+    Check(CheckOpts),
+    /// Write an extended M3U playlist with durations, artists and albums taken from the tags
+    ExportM3u(ExportM3uOpts),
+    /// Generate a static HTML page of the albums in a directory, with covers and track listings
+    ExportHtml(ExportHtmlOpts),
+    /// Generate a podcast RSS feed from a template, with an item per episode giving its title, description, duration and chapters
+    ExportRss(ExportRssOpts),
+    /// Show when each file's tags last changed, according to an audit log
+    History(HistoryOpts),
+    /// Assign consecutive ISRCs to an album's tracks in track order
+    AssignIsrc(AssignIsrcOpts),
+    /// Copy frames from one file to every other file that lacks them
+    Propagate(PropagateOpts),
+    /// Find ID3v2 tags wrongly prepended to non-MP3 files, and read, migrate or strip them
+    ForeignTags(ForeignTagsOpts),
+    /// List files whose tag values match a regular expression
+    Grep(GrepOpts),
+    /// Record the tags of a whole library as one aggregate JSON document, writing no sidecars
+    Snapshot(SnapshotOpts),
+    /// List the files added, removed and changed between two snapshots, with each changed frame
+    SnapshotDiff(SnapshotDiffOpts),
+    /// Keep running, applying JSON sidecars to their audio files whenever they change
+    Watch(WatchOpts),
 }
 
+const EXIT_CODES: &str =
+    "Exit status: 0 success, 1 other errors, 2 invalid arguments, 3 I/O errors, \
+4 lock failures, 5 malformed tags or JSON, 6 unsupported formats, 7 some files in a batch failed, \
+8 nothing to do";
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
+#[command(after_help = EXIT_CODES)]
 pub struct Cli {
+    /// Hold an exclusive lock on this file for the whole run, serialising any other tag2json processes given the same path
+    #[arg(long, global = true)]
+    lock: Option<PathBuf>,
+    /// Resolve every path against this directory and emit paths and aggregate keys relative to it, so runs on different hosts or containers produce identical output
+    #[arg(long, global = true)]
+    root: Option<PathBuf>,
+    /// For untrusted files: reject tags that break the ID3v2 specification's limits before parsing them, and never follow symbolic links or read or write through paths that resolve elsewhere
+    #[arg(long, global = true, default_value_t = false)]
+    paranoid: bool,
+    /// Write a JSON list of every file the run created, modified, moved or deleted to this path, with each one's SHA-256 before and after
+    #[arg(long, global = true)]
+    manifest: Option<PathBuf>,
+    /// Only include files modified at or after this UTC date, such as 2024-01-01 or 2024-01-01T12:00
+    #[arg(long, global = true, value_parser = timespec::parse_date)]
+    modified_since: Option<SystemTime>,
+    /// Only include files modified within this long before now, such as 12h, 7d or 2w
+    #[arg(long, global = true, value_parser = timespec::parse_age, conflicts_with = "modified_since")]
+    modified_within: Option<Duration>,
+    /// Only include files of at least this size, such as 500k or 2M
+    #[arg(long, global = true, value_parser = filter::parse_size)]
+    min_size: Option<u64>,
+    /// Only include files of at most this size
+    #[arg(long, global = true, value_parser = filter::parse_size)]
+    max_size: Option<u64>,
+    /// Only include tracks lasting at least this long, such as 5s or 2m, skipping any whose duration is unknown
+    #[arg(long, global = true, value_parser = timespec::parse_age)]
+    min_duration: Option<Duration>,
+    /// Only include tracks lasting at most this long
+    #[arg(long, global = true, value_parser = timespec::parse_age)]
+    max_duration: Option<Duration>,
     #[command(subcommand)]
     mode: Mode,
 }
@@ -57,150 +599,594 @@ fn file_exists(path_str: &str) -> Result<PathBuf, String> {
     }
 }
 
-fn write_data_to_path(path: &PathBuf, data: &[u8]) -> StrResult<()> {
-    let mut file = match File::create(path) {
-        Ok(file) => file,
-        Err(e) => Err(format!("Cannot open {}: {e}", path.to_string_lossy()))?,
-    };
-    if let Err(e) = file.write_all(data) {
-        return Err(format!("Cannot write JSON: {e}",));
-    };
-    Ok(())
+fn parse_assignment(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(format!("{s:?} is not KEY=VALUE")),
+    }
 }
 
-fn extract_tags_pic(id3_file: &PathBuf) -> StrResult<(JsonValue, Option<Vec<u8>>)> {
-    let tag = match Tag::read_from_path(id3_file) {
-        Ok(t) => t,
-        Err(e) => Err(format!("Unable to open id3 file: {e}"))?, // No need to include the path because we know its valid already
-    };
-    let mut json = JsonValue::new_object();
-    for frame in tag.frames() {
-        if let Some(text) = frame.content().text() {
-            json[frame.id()] = JsonValue::String(text.to_owned());
-        }
+/// Reads the JSON document to apply from a file, or from stdin if the path is `-`, along with the
+/// directory the files it refers to are found relative to
+fn read_document(path: &Path) -> StrResult<(JsonValue, PathBuf)> {
+    if path != Path::new("-") {
+        let base = path.parent().unwrap_or(Path::new("")).to_owned();
+        return Ok((read_json_file(path)?, base));
+    }
+    let mut text = String::new();
+    if let Err(e) = std::io::Read::read_to_string(&mut std::io::stdin(), &mut text) {
+        return Err(Error::Io(format!("Unable to read JSON from stdin: {e}")));
+    }
+    match json::parse(&text) {
+        Ok(json) => Ok((json, PathBuf::from("."))),
+        Err(e) => Err(Error::Parse(format!(
+            "Unable to parse JSON from stdin: {e}"
+        ))),
     }
-    let data = tag.pictures().next().map(|p| p.data.clone());
-    Ok((json, data))
+}
+
+/// The document with each --set override in place of its value
+fn with_overrides(mut json: JsonValue, overrides: &[(String, String)]) -> JsonValue {
+    for (key, value) in overrides {
+        json[key.as_str()] = value.as_str().into();
+    }
+    json
+}
+
+/// The key names asked for, with --friendly-keys meaning the built-in English ones
+fn key_names(keys: &Option<String>, friendly: bool) -> Option<&str> {
+    keys.as_deref().or(friendly.then_some("en"))
 }
 
 /// Write the ID3 tags from the given file out as JSON. Also extract the album art to the given path if available
-fn extract_file(opts: SingleOpts) -> StrResult<()> {
+fn extract_file(opts: ExtractOpts) -> StrResult<()> {
+    let ExtractOpts {
+        single: opts,
+        since,
+        ignore_frames,
+        audit_log,
+        slugs,
+        key_case,
+        embed_art,
+        lyrics_sidecar,
+        stars,
+        genre_names,
+        classes,
+    } = opts;
     let art_path = opts.art.unwrap_or_else(|| opts.id3.with_extension(".jpg"));
     let json_path = opts
         .json
         .unwrap_or_else(|| opts.id3.with_extension(".json"));
 
-    let (json, data) = extract_tags_pic(&opts.id3)?;
-    let pretty_json = json::stringify_pretty(json, 4);
-
-    write_data_to_path(&json_path, pretty_json.as_bytes())?;
-
-    if let Some(data) = data {
-        write_data_to_path(&art_path, &data)?;
+    let key_map =
+        keys::KeyMap::load(key_names(&opts.keys, opts.friendly_keys))?.with_case(key_case);
+    let (json, tag) = extract_tags(&opts.id3)?;
+    if let Some(log) = &audit_log {
+        audit::append(log, &opts.id3, &json)?;
+    }
+    let (mut json, pictures) = classes.filter(json, tag.pictures().cloned().collect());
+    if embed_art {
+        if let Some(embedded) = pictures::to_json(&pictures) {
+            json["APIC"] = embedded;
+        }
+        if let Some(art) = pictures::provenance(&pictures) {
+            json["_art"] = art;
+        }
+    } else {
+        let json_dir = json_path.parent().unwrap_or(Path::new(""));
+        pictures::extract(&mut json, json_dir, &art_path, &pictures)?;
+    }
+    if stars {
+        frames::use_stars(&mut json);
+    }
+    if genre_names {
+        genre::use_names(&mut json);
+    }
+    if lyrics_sidecar {
+        let json_dir = json_path.parent().unwrap_or(Path::new(""));
+        lyrics::extract(&mut json, json_dir, &opts.id3)?;
     }
+    if slugs {
+        json["_slugs"] = slug::slug_json(&json);
+    }
+    if let Some(since) = since {
+        let snapshot = read_json_file(&since)?;
+        let previous = key_map.canonicalize(snapshot::entry_for(&snapshot, &opts.id3).clone());
+        json = snapshot::changed_frames(
+            &ignore::strip(&json, &ignore_frames),
+            &ignore::strip(&previous, &ignore_frames),
+        );
+    }
+    let pretty_json = json::stringify_pretty(key_map.localize(json), 4);
 
-    Ok(())
+    write_data_to_path(&json_path, pretty_json.as_bytes())
 }
 
-fn apply_tags(opts: SingleOpts) -> StrResult<()> {
-    let json_path = opts
-        .json
-        .unwrap_or_else(|| opts.id3.with_extension(".json"));
-    let json = match std::fs::read_to_string(json_path) {
-        Ok(s) => s,
-        Err(e) => Err(format!("Unable to open json file: {e}"))?,
+/// Prints a line per frame as it is stored in the file, before any migration
+fn show_table(path: &Path, classes: &ClassOpts) -> StrResult<()> {
+    paranoid::check_input(path)?;
+    let tag = match read_tag(path) {
+        Ok(tag) => tag,
+        Err(e) => Err(e.context("Unable to read tag"))?,
     };
-    let json = match json::parse(&json) {
-        Ok(j) => j,
-        Err(e) => Err(format!("Unable to parse JSON: {e}"))?,
-    };
-
-    if !json.is_object() {
-        return Err("No root object found".to_string());
+    let only = classes.only();
+    let mut rows = vec![];
+    for frame in tag.frames() {
+        let id = match frame.content() {
+            Content::ExtendedText(t) => format!("TXXX:{}", t.description),
+            Content::ExtendedLink(l) => format!("WXXX:{}", l.description),
+            _ => frame.id().to_owned(),
+        };
+        if only.is_some_and(|only| frame_class::class_of(frame.id()) != Some(only)) {
+            continue;
+        }
+        let value = match frame.content() {
+            Content::Picture(p) => format!("<{}, {} bytes>", p.mime_type, p.data.len()),
+            Content::ExtendedText(t) => t.value.clone(),
+            Content::ExtendedLink(l) => l.link.clone(),
+            c => c.to_string(),
+        };
+        let mut meaning = frame_info::describe(frame.id())
+            .unwrap_or("Unknown")
+            .to_owned();
+        if let Some(note) = frame_info::deprecation(frame.id()) {
+            meaning = format!("{meaning} [{note}]");
+        }
+        rows.push((id, meaning, value.replace('\0', " / ").replace('\n', " ")));
     }
+    let id_width = rows.iter().map(|r| r.0.chars().count()).max().unwrap_or(0);
+    let meaning_width = rows.iter().map(|r| r.1.chars().count()).max().unwrap_or(0);
+    for (id, meaning, value) in rows {
+        println!("{id:<id_width$}  {meaning:<meaning_width$}  {value}");
+    }
+    Ok(())
+}
 
-    let mut tag = Tag::new();
+fn show_tags(opts: ShowOpts) -> StrResult<()> {
+    if opts.table {
+        return show_table(&opts.id3, &opts.classes);
+    }
+    if opts.inspect {
+        paranoid::check_input(&opts.id3)?;
+        println!("{}", json::stringify_pretty(header::inspect(&opts.id3)?, 4));
+        return Ok(());
+    }
+    let key_map = keys::KeyMap::load(key_names(&opts.keys, opts.friendly_keys))?;
+    let (json, _) = extract_tags_pic(&opts.id3)?;
+    let (json, _) = opts.classes.filter(json, vec![]);
+    let pretty_json = json::stringify_pretty(key_map.localize(json), 4);
+    #[cfg(feature = "clipboard")]
+    if opts.copy {
+        return clipboard::copy(&pretty_json);
+    }
+    println!("{pretty_json}");
+    Ok(())
+}
 
-    for (key, val) in json.entries() {
-        if val.is_string() {
-            let frame = Frame::text(key, val.to_string());
-            tag.add_frame(frame);
-        }
+fn apply_file(opts: ApplyOpts) -> StrResult<()> {
+    #[cfg(feature = "clipboard")]
+    let pasted = opts.from_clipboard.then(clipboard::paste).transpose()?;
+    #[cfg(not(feature = "clipboard"))]
+    let pasted: Option<String> = None;
+    let ApplyOpts {
+        single: opts,
+        common,
+        more,
+        overrides,
+        write,
+        transform,
+        ..
+    } = opts;
+    if let Some(common) = common {
+        let targets = [Some(opts.id3), opts.json, opts.art];
+        let targets: Vec<_> = targets.into_iter().flatten().chain(more).collect();
+        let (json, base) = read_document(&common)?;
+        let json = keys::KeyMap::load(key_names(&opts.keys, opts.friendly_keys))?
+            .canonicalize(with_overrides(json, &overrides));
+        return apply_common(&json, &base, &targets, &write, &transform);
     }
+    let key_map = keys::KeyMap::load(key_names(&opts.keys, opts.friendly_keys))?;
+    let (json, base) = match pasted {
+        Some(text) => match json::parse(&text) {
+            Ok(j) => (j, PathBuf::from(".")),
+            Err(e) => Err(Error::Parse(format!(
+                "Unable to parse JSON from clipboard: {e}"
+            )))?,
+        },
+        None => {
+            let json_path = opts
+                .json
+                .unwrap_or_else(|| opts.id3.with_extension(".json"));
+            read_document(&json_path)?
+        }
+    };
+    let json = key_map.canonicalize(with_overrides(json, &overrides));
+    let mut tag = json_to_tag(&json, &base)?;
 
     if let Some(album_path) = opts.art {
         if album_path.exists() {
             let data = match std::fs::read(&album_path) {
                 Ok(data) => data,
-                Err(e) => Err(format!("Cannot read album art data: {e}"))?,
+                Err(e) => Err(Error::Io(format!("Cannot read album art data: {e}")))?,
             };
-            let picture = Picture {
-                data,
-                description: "".to_owned(),
-                picture_type: id3::frame::PictureType::CoverFront,
-                mime_type: "image/jpeg".to_owned(),
-            };
-            tag.add_frame(picture);
+            tag.add_frame(cover_picture(data));
         } else {
-            return Err(format!(
+            return Err(Error::Io(format!(
                 "Provided album path does not exist: {}",
                 album_path.to_string_lossy()
-            ));
+            )));
         }
     }
+    apply_transforms(&mut tag, &opts.id3, &transform)?;
+
+    write_tag(&opts.id3, &tag, &write)
+}
 
-    if let Err(e) = tag.write_to_path(opts.id3, id3::Version::Id3v24) {
-        return Err(format!("Could not write tags: {e}"));
+/// Applies the same document to each of the audio files, carrying on past those that fail
+fn apply_common(
+    json: &JsonValue,
+    base: &Path,
+    files: &[PathBuf],
+    write: &WriteOpts,
+    transform: &TransformOpts,
+) -> StrResult<()> {
+    let mut failed = 0;
+    for file in files {
+        if let Err(e) = apply_tags(file, json, base, write, transform) {
+            eprintln!("Could not handle {}: {e}", file.to_string_lossy());
+            failed += 1;
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(Error::Partial(format!("{n} files could not be tagged"))),
     }
-    Ok(())
 }
 
-fn batch_extract(blob: &mut JsonValue, opt: &BatchOpts) -> StrResult<()> {
-    for file in &opt.files {
-        if file.is_dir() && opt.recurse {
-            let contents = file.read_dir().unwrap();
-            let files = contents.filter_map(Result::ok).map(|d| d.path()).collect();
-            let opt = BatchOpts { files, ..*opt };
-            batch_extract(blob, &opt)?;
-        } else if file.is_file() {
-            if !file.to_string_lossy().ends_with("mp3") {
-                continue;
-            }
-            let (json, pic) = match extract_tags_pic(file) {
-                Ok((j, p)) => (j, p),
-                Err(s) => {
-                    eprintln!("Could not handle {}: {}", file.to_string_lossy(), s);
-                    continue;
-                }
-            };
-            if let Some(pic) = pic {
-                write_data_to_path(&file.with_extension("jpeg"), &pic)?;
-            }
-            if opt.aggregate_output {
-                let path = file.to_string_lossy();
-                blob[&*path] = json;
-            } else {
-                let json = json::stringify_pretty(json, 4);
-                write_data_to_path(&file.with_extension("json"), json.as_bytes())?;
-            }
+fn batch_apply(opts: &BatchApplyOpts) -> StrResult<()> {
+    let apply = batch::ApplyOpts {
+        key_map: keys::KeyMap::load(opts.keys.as_deref())?,
+        policy: match &opts.policy {
+            Some(path) => policy::Policy::load(path)?,
+            None => policy::Policy::default(),
+        },
+        write: opts.write.clone(),
+        transform: opts.transform.clone(),
+    };
+    let report = batch::apply(
+        &opts.docs,
+        &apply,
+        &batch::Cancel::never(),
+        &batch::ignore_progress,
+    )?;
+    report.print_problems();
+    eprintln!(
+        "Applied tags to {} files, {} failed",
+        report.succeeded.len(),
+        report.failed.len()
+    );
+    report.into_result("tagged")
+}
+
+impl BatchOpts {
+    /// The audio files named on the command line and in any --files-from list
+    fn audio_files(&self) -> StrResult<Vec<PathBuf>> {
+        let mut files = self.files.clone();
+        if let Some(list) = &self.files_from {
+            files.extend(paths::read_file_list(list, self.null)?);
+        }
+        batch::audio_files(&files, self.recurse, self.rename_extensions)
+    }
+
+    fn extract_opts(&self) -> StrResult<batch::ExtractOpts> {
+        Ok(batch::ExtractOpts {
+            aggregate: self.aggregate_output,
+            album_mode: self.album_mode,
+            key_map: keys::KeyMap::load(key_names(&self.keys, self.friendly_keys))?
+                .with_case(self.key_case),
+            audit_log: self.audit_log.clone(),
+            checksums: self.emit_checksums,
+            only: self.classes.only(),
+            slugs: self.slugs,
+            lyrics_sidecar: self.lyrics_sidecar,
+            stars: self.stars,
+            genre_names: self.genre_names,
+        })
+    }
+}
+
+/// Prints a line per file from the row template, for human-readable listings
+fn batch_list(opt: &BatchOpts) -> StrResult<()> {
+    if opt.aggregate_output || opt.album_mode {
+        Err("Text format cannot be combined with aggregate or album output")?;
+    }
+    let (lines, report) = batch::list(
+        &opt.audio_files()?,
+        &opt.row,
+        &batch::Cancel::never(),
+        &batch::ignore_progress,
+    )?;
+    for line in lines {
+        println!("{line}");
+    }
+    report.print_problems();
+    report.into_result("listed")
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::from(e.exit_code())
         }
     }
-    Ok(())
 }
 
-fn main() -> Result<(), String> {
+fn run() -> StrResult<()> {
     let cli = Cli::parse();
-    match cli.mode {
+    if cli.paranoid {
+        paranoid::enable();
+    }
+    if let Some(root) = &cli.root {
+        paths::set_root(root)?;
+    }
+    if cli.manifest.is_some() {
+        manifest::enable();
+    }
+    filter::set(filter::Filter {
+        modified_since: match cli.modified_within {
+            Some(age) => SystemTime::now().checked_sub(age),
+            None => cli.modified_since,
+        },
+        min_size: cli.min_size,
+        max_size: cli.max_size,
+        min_duration: cli.min_duration,
+        max_duration: cli.max_duration,
+    });
+    let _lock = cli.lock.as_deref().map(lock::global_lock).transpose()?;
+    let result = run_mode(cli.mode);
+    estimate::print_total();
+    // Even a failed run may have written some files, which the manifest must still list
+    if let Some(path) = &cli.manifest {
+        match (manifest::write(path), &result) {
+            (Err(e), Ok(())) => return Err(e),
+            (Err(e), Err(_)) => eprintln!("Error: {e}"),
+            (Ok(()), _) => {}
+        }
+    }
+    result
+}
+
+/// Runs the subcommand. Its errors come back to [`run`] rather than ending the process, so that
+/// the manifest is still written
+fn run_mode(mode: Mode) -> StrResult<()> {
+    match mode {
         Mode::Extract(opts) => extract_file(opts),
-        Mode::Apply(opts) => apply_tags(opts),
+        Mode::Show(opts) => show_tags(opts),
+        Mode::Apply(opts) => apply_file(opts),
+        Mode::BatchExtract(opt) if opt.format == report::Format::Text => batch_list(&opt),
         Mode::BatchExtract(opt) => {
-            let mut blob = JsonValue::new_object();
-            batch_extract(&mut blob, &opt)?;
-            if opt.aggregate_output {
+            let (blob, report) = batch::extract(
+                &opt.audio_files()?,
+                &opt.extract_opts()?,
+                &batch::Cancel::never(),
+                &batch::ignore_progress,
+            );
+            report.print_problems();
+            if let Some(by) = opt.shard_by {
+                let key_map = keys::KeyMap::load(key_names(&opt.keys, opt.friendly_keys))?;
+                let mut canonical = JsonValue::new_object();
+                for (key, entry) in blob.entries() {
+                    canonical[key] = key_map.canonicalize(entry.clone());
+                }
+                shard::write_shards(blob, &canonical, by, &opt.shard_dir)?;
+            } else if opt.aggregate_output {
                 let json = json::stringify_pretty(blob, 4);
                 println!("{}", json);
             }
-            Ok(())
+            report.into_result("extracted")
+        }
+        Mode::BatchApply(opts) => batch_apply(&opts),
+        Mode::CleanText(opts) => {
+            let report = normalize::clean_text(
+                &opts.files,
+                opts.authority.as_deref(),
+                &opts.write,
+                &batch::Cancel::never(),
+                &batch::ignore_progress,
+            )?;
+            report.print();
+            report.into_result("normalized")
+        }
+        Mode::Scrub(opts) => {
+            let policy = match &opts.policy {
+                Some(path) => policy::Policy::load(path)?,
+                None => policy::Policy::default(),
+            };
+            let report = scrub::scrub(
+                &opts.files,
+                opts.profile,
+                &policy,
+                &opts.write,
+                &batch::Cancel::never(),
+                &batch::ignore_progress,
+            );
+            report.print();
+            report.into_result("scrubbed")
+        }
+        Mode::ApplyRules(opts) => {
+            let rules = rules::Rules::load(&opts.rules)?;
+            let report = rules::apply_rules(
+                &opts.files,
+                &rules,
+                &opts.write,
+                &batch::Cancel::never(),
+                &batch::ignore_progress,
+            );
+            report.print();
+            report.into_result("changed")
+        }
+        Mode::ExportM3u(opts) => {
+            let base = opts.output.as_deref().and_then(Path::parent);
+            let (playlist, report) = export::m3u(&opts.files, base, opts.language.as_deref());
+            report.print_problems();
+            match &opts.output {
+                Some(path) => write_data_to_path(path, playlist.as_bytes())?,
+                None => print!("{playlist}"),
+            }
+            report.into_result("listed")
+        }
+        Mode::ExportHtml(opts) => {
+            let report = export::html(&opts.dir, &opts.out, opts.language.as_deref())?;
+            report.print_problems();
+            report.into_result("listed")
+        }
+        Mode::ExportRss(opts) => {
+            let template = std::fs::read_to_string(&opts.template).map_err(|e| {
+                Error::Io(format!(
+                    "Cannot read {}: {e}",
+                    opts.template.to_string_lossy()
+                ))
+            })?;
+            let (feed, report) = export::rss(&template, &opts.dir, &opts.base_url)?;
+            report.print_problems();
+            match &opts.output {
+                Some(path) => write_data_to_path(path, feed.as_bytes())?,
+                None => print!("{feed}"),
+            }
+            report.into_result("listed")
+        }
+        Mode::History(opts) => audit::history(&opts.log, &opts.files),
+        Mode::ForeignTags(opts) => {
+            let (blob, report) = foreign::foreign_tags(&opts.files, opts.action);
+            if opts.action == foreign::Action::Read {
+                report.print_problems();
+                println!("{}", json::stringify_pretty(blob, 4));
+            } else {
+                report.print();
+                let found = report.succeeded.len() + report.failed.len();
+                eprintln!("{found} files with foreign ID3v2 tags");
+            }
+            report.into_result("handled")
+        }
+        Mode::Grep(opts) => grep::grep(
+            &opts.pattern,
+            &opts.files,
+            &opts.frames,
+            opts.ignore_case,
+            opts.files_with_matches,
+            opts.print0,
+            opts.format,
+        ),
+        Mode::Snapshot(opts) => {
+            let (json, report) = snapshot::snapshot(
+                &opts.files,
+                &batch::Cancel::never(),
+                &batch::ignore_progress,
+            );
+            report.print_problems();
+            let json = json::stringify_pretty(json, 4);
+            match &opts.output {
+                Some(path) => write_data_to_path(path, json.as_bytes())?,
+                None => println!("{json}"),
+            }
+            report.into_result("extracted")
+        }
+        Mode::SnapshotDiff(opts) => snapshot::diff(
+            &read_json_file(&opts.old)?,
+            &read_json_file(&opts.new)?,
+            &opts.ignore_frames,
+            opts.format,
+        ),
+        Mode::Watch(opts) => watch::watch(
+            &opts.dirs,
+            Duration::from_secs(opts.interval),
+            opts.listen.as_deref(),
+            opts.keys.as_deref(),
+            &opts.write,
+        ),
+        Mode::Propagate(opts) => {
+            let report = propagate::propagate(
+                &opts.from,
+                &opts.fields,
+                &opts.files,
+                &opts.write,
+                &batch::Cancel::never(),
+                &batch::ignore_progress,
+            )?;
+            report.print();
+            eprintln!("Filled in frames on {} files", report.succeeded.len());
+            report.into_result("filled in")
+        }
+        Mode::AssignIsrc(opts) => {
+            let report = isrc::assign(&opts.files, &opts.start, opts.overwrite, &opts.write)?;
+            report.print();
+            report.into_result("given an ISRC")
+        }
+        Mode::Check(opts) if !opts.orphans && !opts.crc && opts.target_player.is_none() => {
+            Err("Choose a check to run, such as --orphans, --crc or --target-player".into())
+        }
+        Mode::Check(opts) => {
+            let mut report = batch::BatchReport::default();
+            if opts.orphans {
+                report.merge(check::check_orphans(
+                    &opts.files,
+                    opts.delete,
+                    opts.regenerate,
+                ));
+            }
+            if opts.crc {
+                report.merge(check::check_crc(&opts.files));
+            }
+            if let Some(player) = opts.target_player {
+                report.merge(compat::check_player(&opts.files, player));
+            }
+            match opts.print0 {
+                true => report.print0(),
+                false => report.print(),
+            }
+            report.into_check_result()
+        }
+        Mode::Report(opts) => {
+            if !opts.frames && !opts.migration && opts.histogram.is_empty() {
+                return Err("Choose a report to produce, such as --frames or --histogram".into());
+            }
+            // Files the migration report couldn't read don't stop the other reports
+            let migration = match opts.migration {
+                true => migrate::migration_report(&opts.files),
+                false => Ok(()),
+            };
+            if opts.frames {
+                report::frame_report(&opts.files, opts.format)?;
+            }
+            if !opts.histogram.is_empty() {
+                report::histogram_report(&opts.files, &opts.histogram, opts.format)?;
+            }
+            migration
+        }
+        Mode::Lint(opts) => {
+            let report = lint::lint(&opts.files, opts.fix, &opts.write);
+            report.print();
+            report.into_check_result()
+        }
+        Mode::CheckPaths(opts) => {
+            check_paths::check_paths(&opts.files, &opts.template, opts.target, opts.print0)
+        }
+        Mode::Reorganize(opts) => {
+            let report = reorganize::reorganize(
+                &opts.files,
+                &opts.template,
+                &opts.dest,
+                opts.action,
+                opts.on_collision,
+                opts.target,
+                opts.dry_run,
+                &batch::Cancel::never(),
+                &batch::ignore_progress,
+            );
+            report.print();
+            report.into_result("placed")
         }
     }
 }
@@ -0,0 +1,106 @@
+//! A record of every file a run creates, modifies, moves or deletes, with its SHA-256 before and
+//! after, so other tools can audit the run or roll it back exactly
+//!
+//! ```json
+//! [
+//!     { "path": "Album/01.mp3", "before": "9f86d08...", "after": "60303ae..." },
+//!     { "path": "Album/01.json", "before": null, "after": "2c26b46..." },
+//!     { "path": "Sorted/Artist/01.mp3", "moved_from": "01.mp3", "before": "...", "after": "..." }
+//! ]
+//! ```
+//!
+//! A file that didn't exist before has a `before` of null, and one that was deleted an `after` of
+//! null. A file moved, copied or linked into place gives its source under `moved_from`,
+//! `copied_from` or `linked_from`, with `before` the source's hash. Paths are given as aggregate
+//! keys are, relative to `--root` if one is set.
+
+use crate::{paths, sha256, StrResult};
+use json::JsonValue;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+static ENTRIES: Mutex<Option<Vec<JsonValue>>> = Mutex::new(None);
+
+/// Starts recording the files written, for the rest of the run
+pub fn enable() {
+    *ENTRIES.lock().unwrap() = Some(vec![]);
+}
+
+fn enabled() -> bool {
+    ENTRIES.lock().unwrap().is_some()
+}
+
+fn hash(path: &Path) -> Option<String> {
+    std::fs::read(path)
+        .ok()
+        .map(|data| sha256::hex_digest(&data))
+}
+
+fn key(path: &Path) -> JsonValue {
+    match paths::json_key(path) {
+        (key, None) => key.into(),
+        (_, Some(raw)) => raw,
+    }
+}
+
+/// A file about to be written, holding its hash from before
+pub struct Change {
+    recording: bool,
+    path: PathBuf,
+    source: Option<(&'static str, PathBuf)>,
+    before: Option<String>,
+}
+
+impl Change {
+    /// Notes the file's state before it is created, modified or deleted. Does nothing unless
+    /// recording was enabled
+    pub fn begin(path: &Path) -> Change {
+        let recording = enabled();
+        Change {
+            recording,
+            path: path.to_owned(),
+            source: None,
+            before: recording.then(|| hash(path)).flatten(),
+        }
+    }
+
+    /// Notes the state of a file about to be put at `to`, recorded under the key `how`, such
+    /// as `moved_from` or `copied_from`
+    pub fn begin_from(from: &Path, to: &Path, how: &'static str) -> Change {
+        let recording = enabled();
+        Change {
+            recording,
+            path: to.to_owned(),
+            source: Some((how, from.to_owned())),
+            before: recording.then(|| hash(from)).flatten(),
+        }
+    }
+
+    /// Records the file's state now that it has been written, if the write changed anything
+    pub fn finish(self) {
+        if !self.recording {
+            return;
+        }
+        let after = hash(&self.path);
+        if self.source.is_none() && after == self.before {
+            return;
+        }
+        let mut entry = JsonValue::new_object();
+        entry["path"] = key(&self.path);
+        if let Some((how, from)) = &self.source {
+            entry[*how] = key(from);
+        }
+        entry["before"] = self.before.into();
+        entry["after"] = after.into();
+        if let Some(entries) = ENTRIES.lock().unwrap().as_mut() {
+            entries.push(entry);
+        }
+    }
+}
+
+/// Writes the files recorded so far to the manifest, which isn't itself recorded
+pub fn write(path: &Path) -> StrResult<()> {
+    let entries = ENTRIES.lock().unwrap().take().unwrap_or_default();
+    let json = json::stringify_pretty(JsonValue::Array(entries), 4);
+    crate::write_data_to_path(&path.to_owned(), json.as_bytes())
+}
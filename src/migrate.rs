@@ -0,0 +1,178 @@
+//! Bringing frames from older tag versions up to date: ID3v2.2 three-letter IDs to their modern
+//! equivalents on extraction, and frames dropped in ID3v2.4 to their replacements on apply
+
+use crate::{paths, Error, StrResult};
+use id3::{Content, Frame, Tag, TagLike, Version};
+use std::path::PathBuf;
+
+/// ID3v2.2 IDs, mostly iTunes extensions, that the id3 crate leaves unconverted
+const EXTRA_V22: [(&str, &str); 7] = [
+    ("TCP", "TCMP"),
+    ("TST", "TSOT"),
+    ("TSA", "TSOA"),
+    ("TSP", "TSOP"),
+    ("TS2", "TSO2"),
+    ("TSC", "TSOC"),
+    ("PCS", "PCST"),
+];
+
+/// ID3v2.3 frames replaced in ID3v2.4, other than the date frames merged into TDRC
+const V24_REPLACEMENTS: [(&str, &str); 2] = [("TORY", "TDOR"), ("IPLS", "TIPL")];
+
+/// A frame renamed or removed by a migration. `to` is None for frames with no equivalent, or whose
+/// value couldn't be carried over
+pub struct Change {
+    pub from: String,
+    pub to: Option<String>,
+}
+
+fn renamed(frame: &Frame, id: &str) -> Frame {
+    Frame::with_content(id, frame.content().clone())
+}
+
+/// Renames ID3v2.2 frames the id3 crate couldn't map, and removes any that have no modern ID
+pub fn migrate_v22(tag: &mut Tag) -> Vec<Change> {
+    let mut changes = vec![];
+    let old: Vec<Frame> = tag
+        .frames()
+        .filter(|f| f.id().len() == 3)
+        .cloned()
+        .collect();
+    for frame in old {
+        tag.remove(frame.id());
+        let to = EXTRA_V22.iter().find(|(v22, _)| *v22 == frame.id());
+        if let Some((_, modern)) = to {
+            tag.add_frame(renamed(&frame, modern));
+        }
+        changes.push(Change {
+            from: frame.id().to_owned(),
+            to: to.map(|(_, modern)| (*modern).to_owned()),
+        });
+    }
+    changes
+}
+
+fn text_of(tag: &Tag, id: &str) -> Option<String> {
+    tag.get(id)?.content().text().map(str::to_owned)
+}
+
+/// Replaces frames that don't exist in ID3v2.4 with their equivalents there. TYER, TDAT and TIME
+/// are combined into a TDRC timestamp; TSIZ has no equivalent and is dropped
+pub fn migrate_v24(tag: &mut Tag) -> Vec<Change> {
+    let mut changes = migrate_v22(tag);
+    if let Some(year) = text_of(tag, "TYER") {
+        let mut date = year;
+        // TDAT is DDMM and TIME is HHMM, and anything else can't be placed in the timestamp
+        let four_digits = |t: &String| t.len() == 4 && t.bytes().all(|b| b.is_ascii_digit());
+        let mut unusable = vec![];
+        match text_of(tag, "TDAT") {
+            Some(day_month) if four_digits(&day_month) => {
+                date = format!("{date}-{}-{}", &day_month[2..], &day_month[..2]);
+                match text_of(tag, "TIME") {
+                    Some(time) if four_digits(&time) => {
+                        date = format!("{date}T{}:{}", &time[..2], &time[2..]);
+                    }
+                    Some(_) => unusable.push("TIME"),
+                    None => {}
+                }
+            }
+            Some(_) => unusable.extend(["TDAT", "TIME"]),
+            None => {}
+        }
+        if tag.get("TDRC").is_none() {
+            tag.set_text("TDRC", date);
+        }
+        for id in ["TYER", "TDAT", "TIME"] {
+            if tag.remove(id).is_empty() {
+                continue;
+            }
+            changes.push(Change {
+                from: id.to_owned(),
+                to: (!unusable.contains(&id)).then(|| "TDRC".to_owned()),
+            });
+        }
+    }
+    for (old, new) in V24_REPLACEMENTS {
+        for frame in tag.remove(old) {
+            if tag.get(new).is_none() {
+                tag.add_frame(renamed(&frame, new));
+            }
+            changes.push(Change {
+                from: old.to_owned(),
+                to: Some(new.to_owned()),
+            });
+        }
+    }
+    for id in ["TSIZ", "TRDA"] {
+        if !tag.remove(id).is_empty() {
+            changes.push(Change {
+                from: id.to_owned(),
+                to: None,
+            });
+        }
+    }
+    changes
+}
+
+/// Warns about each frame that was dropped for want of an equivalent, or of a value its equivalent
+/// could hold
+pub fn warn_unmappable(changes: &[Change], context: &str) {
+    for change in changes.iter().filter(|c| c.to.is_none()) {
+        eprintln!(
+            "{context}: {} could not be carried over and was dropped",
+            change.from
+        );
+    }
+}
+
+/// Lists, for each file with an ID3v2.2 tag, how its frame IDs map to modern ones
+pub fn migration_report(files: &[PathBuf]) -> StrResult<()> {
+    let mut failed = 0;
+    for file in paths::collect_audio_files(files, true) {
+        let mut tag = match crate::read_tag(&file) {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("Could not handle {}: {e}", file.to_string_lossy());
+                failed += 1;
+                continue;
+            }
+        };
+        if tag.version() != Version::Id3v22 {
+            continue;
+        }
+        println!("{}:", file.to_string_lossy());
+        for frame in tag.frames().filter(|f| f.id().len() == 4) {
+            if let Some(v22) = frame.id_for_version(Version::Id3v22) {
+                println!("  {v22} -> {}", frame.id());
+            }
+        }
+        for change in migrate_v22(&mut tag) {
+            match change.to {
+                Some(to) => println!("  {} -> {to}", change.from),
+                None => println!("  {} cannot be mapped", change.from),
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(Error::Partial(format!("{n} files could not be read"))),
+    }
+}
+
+/// A frame ID given in JSON, checked to be usable in a frame: three letters for an ID3v2.2 ID,
+/// which is converted where possible, or four for a current one
+pub fn checked_id(key: &str) -> Option<String> {
+    let valid = (key.len() == 3 || key.len() == 4)
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit());
+    if !valid {
+        return None;
+    }
+    let frame = Frame::with_content(key, Content::Text(String::new()));
+    match EXTRA_V22.iter().find(|(v22, _)| *v22 == frame.id()) {
+        Some((_, modern)) => Some((*modern).to_owned()),
+        None if frame.id().len() == 4 => Some(frame.id().to_owned()),
+        None => None,
+    }
+}
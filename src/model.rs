@@ -0,0 +1,342 @@
+//! A typed model of the JSON documents described in the README, for programs embedding the
+//! library that would rather work with structs than pick keys out of a `JsonValue`:
+//!
+//! ```no_run
+//! # use tag2json::model::Document;
+//! # let tag = id3::Tag::new();
+//! let mut doc = Document::from_tag(&tag);
+//! doc.text.insert("TIT2".to_owned(), vec!["Live Version".to_owned()]);
+//! let json: json::JsonValue = (&doc).into();
+//! ```
+//!
+//! Converting a document's JSON to a [`Document`] and back loses nothing but the order of its
+//! keys, and writes a lone value in an array as just the value, as extraction does. Keys the
+//! model has no field for, such as `CHAP` or `PRIV`, and keys whose value doesn't have the
+//! documented shape are kept in `other` as they were. The JSON stays the documented form;
+//! this is a view of it rather than a second schema.
+
+use crate::position::{self, Position};
+use crate::{frames, Error, StrResult};
+use id3::Tag;
+use json::JsonValue;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// One file's tags
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Document {
+    /// Text and link frames, and the release IDs such as `BARCODE`, by key, each with its values.
+    /// `TXXX:DESCRIPTION` keys are kept here as given
+    pub text: BTreeMap<String, Vec<String>>,
+    /// `TRCK`
+    pub track: Option<Position>,
+    /// `TPOS`
+    pub disc: Option<Position>,
+    /// `PCNT`
+    pub play_count: Option<u64>,
+    /// `TIPL`, the people involved and their roles
+    pub involved_people: Vec<Credit>,
+    /// `TMCL`, the musicians and their instruments
+    pub musicians: Vec<Credit>,
+    /// `APIC`
+    pub pictures: Vec<PictureEntry>,
+    /// `COMM`
+    pub comments: Vec<Comment>,
+    /// `TXXX`
+    pub user_text: Vec<UserText>,
+    /// `WXXX`
+    pub user_links: Vec<UserLink>,
+    /// Every other key, as it is in the JSON
+    pub other: BTreeMap<String, JsonValue>,
+    /// The informational keys starting with an underscore, such as `_properties`, which applying
+    /// ignores
+    pub info: BTreeMap<String, JsonValue>,
+}
+
+/// An entry of `TIPL` or `TMCL`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Credit {
+    pub role: String,
+    pub name: String,
+}
+
+/// Where a picture's image is
+#[derive(Clone, Debug, PartialEq)]
+pub enum PictureSource {
+    /// A file, relative to the document
+    File(String),
+    /// The image itself, as base64
+    Data(String),
+}
+
+/// An entry of `APIC`. Fields left out of the JSON are None, and take their defaults on apply
+#[derive(Clone, Debug, PartialEq)]
+pub struct PictureEntry {
+    /// Such as `front-cover`
+    pub picture_type: Option<String>,
+    pub mime_type: Option<String>,
+    pub description: Option<String>,
+    pub source: PictureSource,
+}
+
+/// An entry of `COMM`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub lang: String,
+    pub description: String,
+    pub text: String,
+}
+
+/// An entry of `TXXX`
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserText {
+    pub description: String,
+    pub value: String,
+}
+
+/// An entry of `WXXX`
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserLink {
+    pub description: String,
+    pub url: String,
+}
+
+/// The text fields of an object that has exactly these, or None if it has others or any isn't text
+fn text_fields<const N: usize>(entry: &JsonValue, names: [&str; N]) -> Option<[String; N]> {
+    if !entry.is_object() || entry.entries().any(|(k, _)| !names.contains(&k)) {
+        return None;
+    }
+    let mut fields = names.map(|_| String::new());
+    for (field, name) in fields.iter_mut().zip(names) {
+        *field = entry[name].as_str()?.to_owned();
+    }
+    Some(fields)
+}
+
+/// Parses each frame of an array, or a lone frame given as just its object, failing if any doesn't
+/// have the expected shape
+fn each<T>(value: &JsonValue, parse: impl Fn(&JsonValue) -> Option<T>) -> Option<Vec<T>> {
+    match value {
+        JsonValue::Array(entries) if !entries.is_empty() => entries.iter().map(parse).collect(),
+        JsonValue::Object(_) => Some(vec![parse(value)?]),
+        _ => None,
+    }
+}
+
+fn credit(entry: &JsonValue) -> Option<Credit> {
+    let [role, name] = text_fields(entry, ["role", "name"])?;
+    Some(Credit { role, name })
+}
+
+fn picture(entry: &JsonValue) -> Option<PictureEntry> {
+    const FIELDS: [&str; 5] = ["type", "mime_type", "description", "file", "data"];
+    if !entry.is_object() || entry.entries().any(|(k, _)| !FIELDS.contains(&k)) {
+        return None;
+    }
+    let optional = |name: &str| match &entry[name] {
+        JsonValue::Null => Some(None),
+        value => value.as_str().map(|s| Some(s.to_owned())),
+    };
+    let source = match (optional("file")?, optional("data")?) {
+        (Some(file), None) => PictureSource::File(file),
+        (None, Some(data)) => PictureSource::Data(data),
+        _ => return None,
+    };
+    Some(PictureEntry {
+        picture_type: optional("type")?,
+        mime_type: optional("mime_type")?,
+        description: optional("description")?,
+        source,
+    })
+}
+
+fn comment(entry: &JsonValue) -> Option<Comment> {
+    let [lang, description, text] = text_fields(entry, ["lang", "description", "text"])?;
+    Some(Comment {
+        lang,
+        description,
+        text,
+    })
+}
+
+fn user_text(entry: &JsonValue) -> Option<UserText> {
+    let [description, value] = text_fields(entry, ["description", "value"])?;
+    Some(UserText { description, value })
+}
+
+fn user_link(entry: &JsonValue) -> Option<UserLink> {
+    let [description, url] = text_fields(entry, ["description", "url"])?;
+    Some(UserLink { description, url })
+}
+
+fn position(value: &JsonValue) -> Option<Position> {
+    let fields_known = value.entries().all(|(k, _)| k == "number" || k == "total");
+    (value.is_object() && fields_known)
+        .then(|| position::from_json(value).ok())
+        .flatten()
+}
+
+impl Document {
+    /// The document for a tag, as extraction gives it with the pictures embedded
+    pub fn from_tag(tag: &Tag) -> Document {
+        let json = crate::tag_to_json(tag);
+        Document::try_from(&json).expect("tag_to_json gives an object")
+    }
+
+    /// Builds the tag the document describes, with the files it refers to found relative to `base`
+    pub fn to_tag(&self, base: &Path) -> StrResult<Tag> {
+        crate::json_to_tag(&self.into(), base)
+    }
+
+    /// Fills in the typed field for a key, returning false if the value doesn't fit it
+    fn set(&mut self, key: &str, value: &JsonValue) -> bool {
+        fn fill<T>(field: &mut Vec<T>, parsed: Option<Vec<T>>) -> bool {
+            parsed.map(|parsed| *field = parsed).is_some()
+        }
+        match key {
+            "TRCK" => position(value).map(|p| self.track = Some(p)).is_some(),
+            "TPOS" => position(value).map(|p| self.disc = Some(p)).is_some(),
+            "PCNT" => value.as_u64().map(|n| self.play_count = Some(n)).is_some(),
+            "TIPL" => fill(&mut self.involved_people, each(value, credit)),
+            "TMCL" => fill(&mut self.musicians, each(value, credit)),
+            "APIC" => fill(&mut self.pictures, each(value, picture)),
+            "COMM" => fill(&mut self.comments, each(value, comment)),
+            "TXXX" => fill(&mut self.user_text, each(value, user_text)),
+            "WXXX" => fill(&mut self.user_links, each(value, user_link)),
+            _ => match frames::text_values(value) {
+                Some(values) => {
+                    let values = values.into_iter().map(str::to_owned).collect();
+                    self.text.insert(key.to_owned(), values);
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+/// Reads a document's JSON, which must be an object
+impl TryFrom<&JsonValue> for Document {
+    type Error = Error;
+
+    fn try_from(json: &JsonValue) -> StrResult<Document> {
+        if !json.is_object() {
+            return Err(Error::Parse("No root object found".to_owned()));
+        }
+        let mut doc = Document::default();
+        for (key, value) in json.entries() {
+            if key.starts_with('_') {
+                doc.info.insert(key.to_owned(), value.clone());
+            } else if !doc.set(key, value) {
+                doc.other.insert(key.to_owned(), value.clone());
+            }
+        }
+        Ok(doc)
+    }
+}
+
+fn object<const N: usize>(fields: [(&str, &str); N]) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    for (name, value) in fields {
+        json[name] = value.into();
+    }
+    json
+}
+
+/// The array of a typed list, or None if it's empty and the key should be left out
+fn array<T>(entries: &[T], to_json: impl Fn(&T) -> JsonValue) -> Option<JsonValue> {
+    (!entries.is_empty()).then(|| JsonValue::Array(entries.iter().map(to_json).collect()))
+}
+
+fn position_json(position: &Position) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    json["number"] = position.number.into();
+    if let Some(total) = position.total {
+        json["total"] = total.into();
+    }
+    json
+}
+
+fn picture_json(picture: &PictureEntry) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    let fields = [
+        ("type", &picture.picture_type),
+        ("mime_type", &picture.mime_type),
+        ("description", &picture.description),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            json[name] = value.as_str().into();
+        }
+    }
+    match &picture.source {
+        PictureSource::File(file) => json["file"] = file.as_str().into(),
+        PictureSource::Data(data) => json["data"] = data.as_str().into(),
+    }
+    json
+}
+
+/// The document's JSON, in the form extraction writes
+impl From<&Document> for JsonValue {
+    fn from(doc: &Document) -> JsonValue {
+        let mut json = JsonValue::new_object();
+        for (key, values) in &doc.text {
+            json[key.as_str()] = match values.as_slice() {
+                [value] => value.as_str().into(),
+                values => values.into(),
+            };
+        }
+        if let Some(track) = &doc.track {
+            json["TRCK"] = position_json(track);
+        }
+        if let Some(disc) = &doc.disc {
+            json["TPOS"] = position_json(disc);
+        }
+        if let Some(count) = doc.play_count {
+            json["PCNT"] = count.into();
+        }
+        let credit = |c: &Credit| object([("role", &c.role), ("name", &c.name)]);
+        let lists = [
+            ("TIPL", array(&doc.involved_people, credit)),
+            ("TMCL", array(&doc.musicians, credit)),
+            ("APIC", array(&doc.pictures, picture_json)),
+            (
+                "COMM",
+                array(&doc.comments, |c| {
+                    object([
+                        ("lang", &c.lang),
+                        ("description", &c.description),
+                        ("text", &c.text),
+                    ])
+                }),
+            ),
+            (
+                "TXXX",
+                array(&doc.user_text, |t| {
+                    object([("description", &t.description), ("value", &t.value)])
+                }),
+            ),
+            (
+                "WXXX",
+                array(&doc.user_links, |l| {
+                    object([("description", &l.description), ("url", &l.url)])
+                }),
+            ),
+        ];
+        for (key, list) in lists {
+            if let Some(list) = list {
+                json[key] = list;
+            }
+        }
+        for (key, value) in doc.other.iter().chain(&doc.info) {
+            json[key.as_str()] = value.clone();
+        }
+        json
+    }
+}
+
+impl From<Document> for JsonValue {
+    fn from(doc: Document) -> JsonValue {
+        (&doc).into()
+    }
+}
@@ -0,0 +1,715 @@
+//! MP4 and M4A files, whose tags are iTunes-style items in `moov/udta/meta/ilst`. Like FLAC
+//! they're read into and written from an id3 `Tag`, so the JSON keys are the same frame IDs.
+
+use crate::backend::Backend;
+use crate::sniff::Format;
+use crate::{image, splice, Error, StrResult};
+use id3::frame::{Comment, ExtendedText, Lyrics, Picture, PictureType};
+use id3::{Content, Frame, Tag, TagLike};
+use json::JsonValue;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Item atoms holding plain text, and the frame each corresponds to
+const TEXT_ITEMS: &[(&[u8; 4], &str)] = &[
+    (b"\xa9nam", "TIT2"),
+    (b"\xa9ART", "TPE1"),
+    (b"aART", "TPE2"),
+    (b"\xa9alb", "TALB"),
+    (b"\xa9day", "TDRC"),
+    (b"\xa9gen", "TCON"),
+    (b"\xa9wrt", "TCOM"),
+    (b"\xa9grp", "TIT1"),
+    (b"\xa9too", "TSSE"),
+    (b"\xa9enc", "TENC"),
+    (b"cprt", "TCOP"),
+    (b"soal", "TSOA"),
+    (b"soar", "TSOP"),
+    (b"sonm", "TSOT"),
+    (b"soaa", "TSO2"),
+    (b"soco", "TSOC"),
+    (b"\xa9mvn", "MVNM"),
+];
+
+/// Where iTunes keeps free-form items such as BARCODE, which become TXXX frames
+const ITUNES_MEAN: &str = "com.apple.iTunes";
+
+/// Atoms holding other atoms, as opposed to data. `meta` also has a version and flags first.
+const CONTAINERS: [&[u8; 4]; 10] = [
+    b"moov", b"trak", b"mdia", b"minf", b"stbl", b"udta", b"meta", b"ilst", b"edts", b"dinf",
+];
+
+#[derive(Clone)]
+enum Atom {
+    Leaf([u8; 4], Vec<u8>),
+    /// A container with any bytes that come before its children
+    Container([u8; 4], Vec<u8>, Vec<Atom>),
+}
+
+impl Atom {
+    fn kind(&self) -> &[u8; 4] {
+        match self {
+            Atom::Leaf(kind, _) | Atom::Container(kind, _, _) => kind,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut body = vec![];
+        match self {
+            Atom::Leaf(_, data) => body.extend(data),
+            Atom::Container(_, prefix, children) => {
+                body.extend(prefix);
+                for child in children {
+                    child.encode(&mut body);
+                }
+            }
+        }
+        match u32::try_from(body.len() + 8) {
+            Ok(size) => out.extend(size.to_be_bytes()),
+            Err(_) => {
+                out.extend(1u32.to_be_bytes());
+                out.extend(self.kind());
+                out.extend((body.len() as u64 + 16).to_be_bytes());
+                out.extend(body);
+                return;
+            }
+        }
+        out.extend(self.kind());
+        out.extend(body);
+    }
+
+    fn children_mut(&mut self) -> Option<&mut Vec<Atom>> {
+        match self {
+            Atom::Container(_, _, children) => Some(children),
+            Atom::Leaf(..) => None,
+        }
+    }
+}
+
+fn be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| acc << 8 | *b as u64)
+}
+
+/// Splits data into atoms, descending into the known containers
+fn parse_atoms(data: &[u8]) -> StrResult<Vec<Atom>> {
+    let mut atoms = vec![];
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let mut size = be(&data[pos..pos + 4]) as usize;
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap_or_default();
+        let mut header = 8;
+        if size == 1 {
+            size = data
+                .get(pos + 8..pos + 16)
+                .map(be)
+                .ok_or("Truncated MP4 atom")? as usize;
+            header = 16;
+        } else if size == 0 {
+            size = data.len() - pos;
+        }
+        let end = pos.saturating_add(size);
+        let body = data.get(pos + header..end).ok_or_else(|| {
+            format!(
+                "MP4 atom {} runs past its parent",
+                String::from_utf8_lossy(&kind)
+            )
+        })?;
+        if CONTAINERS.contains(&&kind) {
+            // QuickTime's own meta atom has no version and flags before its children
+            let prefix = if &kind == b"meta" && body.get(4..8) != Some(b"hdlr") {
+                4
+            } else {
+                0
+            };
+            let prefix = body.get(..prefix).ok_or("Truncated MP4 meta atom")?;
+            let children = parse_atoms(&body[prefix.len()..])?;
+            atoms.push(Atom::Container(kind, prefix.to_vec(), children));
+        } else {
+            atoms.push(Atom::Leaf(kind, body.to_vec()));
+        }
+        pos += size.max(header);
+    }
+    Ok(atoms)
+}
+
+/// A top-level atom's place in the file
+struct TopLevel {
+    kind: [u8; 4],
+    start: u64,
+    len: u64,
+}
+
+fn top_level(file: &mut File) -> StrResult<Vec<TopLevel>> {
+    let io_err = |e: std::io::Error| Error::Io(format!("Cannot read MP4 structure: {e}"));
+    let file_len = file.metadata().map_err(io_err)?.len();
+    let mut atoms = vec![];
+    let mut pos = 0;
+    while pos + 8 <= file_len {
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(pos)).map_err(io_err)?;
+        file.read_exact(&mut header[..8]).map_err(io_err)?;
+        let kind: [u8; 4] = header[4..8].try_into().unwrap_or_default();
+        let len = match be(&header[..4]) {
+            0 => file_len - pos,
+            1 => {
+                file.read_exact(&mut header[8..]).map_err(io_err)?;
+                be(&header[8..])
+            }
+            len => len,
+        };
+        if len < 8 || len > file_len - pos {
+            return Err(Error::Parse(format!(
+                "MP4 atom {} at {pos} has an invalid size",
+                String::from_utf8_lossy(&kind)
+            )));
+        }
+        atoms.push(TopLevel {
+            kind,
+            start: pos,
+            len,
+        });
+        pos += len;
+    }
+    Ok(atoms)
+}
+
+/// The file's `moov` atom, parsed, with its place in the file and what comes after it
+fn read_moov(path: &Path) -> StrResult<(File, Vec<TopLevel>, usize, Atom)> {
+    let mut file = File::open(path)
+        .map_err(|e| Error::Io(format!("Cannot open {}: {e}", path.to_string_lossy())))?;
+    let atoms = top_level(&mut file)?;
+    if atoms.first().is_none_or(|a| &a.kind != b"ftyp") {
+        return Err(Error::Unsupported("Not an MP4 file".to_owned()));
+    }
+    let index = atoms
+        .iter()
+        .position(|a| &a.kind == b"moov")
+        .ok_or("MP4 file has no moov atom")?;
+    let mut data = vec![0u8; atoms[index].len as usize];
+    file.seek(SeekFrom::Start(atoms[index].start))
+        .and_then(|_| file.read_exact(&mut data))
+        .map_err(|e| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy())))?;
+    let moov = parse_atoms(&data)?.pop().ok_or("Empty moov atom")?;
+    Ok((file, atoms, index, moov))
+}
+
+fn find<'a>(atoms: &'a [Atom], kind: &[u8; 4]) -> Option<&'a Atom> {
+    atoms.iter().find(|a| a.kind() == kind)
+}
+
+fn children(atom: &Atom) -> &[Atom] {
+    match atom {
+        Atom::Container(_, _, children) => children,
+        Atom::Leaf(..) => &[],
+    }
+}
+
+fn ilst(moov: &Atom) -> &[Atom] {
+    let udta = find(children(moov), b"udta");
+    let meta = udta.and_then(|u| find(children(u), b"meta"));
+    meta.and_then(|m| find(children(m), b"ilst"))
+        .map_or(&[], children)
+}
+
+/// An item's `data` atoms as their type code and value
+fn item_values(item: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let Ok(parts) = parse_atoms(item) else {
+        return vec![];
+    };
+    parts
+        .into_iter()
+        .filter_map(|part| match part {
+            Atom::Leaf(kind, data) if &kind == b"data" && data.len() >= 8 => {
+                Some((be(&data[1..4]) as u32, data[8..].to_vec()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The `mean` and `name` of a free-form `----` item
+fn freeform_name(item: &[u8]) -> Option<(String, String)> {
+    let parts = parse_atoms(item).ok()?;
+    let text = |kind: &[u8; 4]| match find(&parts, kind)? {
+        Atom::Leaf(_, data) if data.len() >= 4 => {
+            Some(String::from_utf8_lossy(&data[4..]).into_owned())
+        }
+        _ => None,
+    };
+    Some((text(b"mean")?, text(b"name")?))
+}
+
+fn text_values(values: &[(u32, Vec<u8>)]) -> String {
+    let texts: Vec<_> = values
+        .iter()
+        .map(|(_, v)| String::from_utf8_lossy(v))
+        .collect();
+    texts.join("\0")
+}
+
+/// A `trkn` or `disk` pair: a number and, if known, the total
+fn number_pair(values: &[(u32, Vec<u8>)]) -> Option<String> {
+    let value = &values.first()?.1;
+    let number = be(value.get(2..4)?);
+    let total = value.get(4..6).map_or(0, be);
+    Some(match total {
+        0 => number.to_string(),
+        total => format!("{number}/{total}"),
+    })
+}
+
+/// Whether an item is one this module reads, and so rewrites, rather than keeping as it was
+fn is_mapped(item: &Atom) -> bool {
+    let kind = item.kind();
+    if kind == b"----" {
+        let Atom::Leaf(_, data) = item else {
+            return false;
+        };
+        return freeform_name(data).is_some_and(|(mean, _)| mean == ITUNES_MEAN);
+    }
+    TEXT_ITEMS.iter().any(|(k, _)| *k == kind)
+        || [
+            b"trkn", b"disk", b"tmpo", b"cpil", b"gnre", b"covr", b"\xa9cmt", b"\xa9lyr",
+        ]
+        .contains(&kind)
+}
+
+fn read_tag(path: &Path) -> StrResult<Tag> {
+    let (_, _, _, moov) = read_moov(path)?;
+    let mut tag = Tag::new();
+    for item in ilst(&moov) {
+        let Atom::Leaf(kind, data) = item else {
+            continue;
+        };
+        let values = item_values(data);
+        if values.is_empty() {
+            continue;
+        }
+        if let Some((_, id)) = TEXT_ITEMS.iter().find(|(k, _)| *k == kind) {
+            tag.add_frame(Frame::text(*id, text_values(&values)));
+            continue;
+        }
+        match kind {
+            b"trkn" | b"disk" => {
+                let id = if kind == b"trkn" { "TRCK" } else { "TPOS" };
+                if let Some(pair) = number_pair(&values) {
+                    tag.set_text(id, pair);
+                }
+            }
+            b"tmpo" => tag.set_text("TBPM", be(&values[0].1).to_string()),
+            b"cpil" => tag.set_text("TCMP", be(&values[0].1).to_string()),
+            // ID3v1 genre numbers, offset by one
+            b"gnre" => tag.set_text("TCON", format!("({})", be(&values[0].1).saturating_sub(1))),
+            b"\xa9cmt" => {
+                tag.add_frame(Comment {
+                    lang: "XXX".to_owned(),
+                    description: String::new(),
+                    text: text_values(&values).replace('\0', "\n"),
+                });
+            }
+            b"\xa9lyr" => {
+                tag.add_frame(Lyrics {
+                    lang: "XXX".to_owned(),
+                    description: String::new(),
+                    text: text_values(&values).replace('\0', "\n"),
+                });
+            }
+            b"covr" => {
+                for (code, image) in values {
+                    let mime = match code {
+                        14 => "image/png",
+                        27 => "image/bmp",
+                        _ => image::mime_type(&image).unwrap_or("image/jpeg"),
+                    };
+                    tag.add_frame(Picture {
+                        mime_type: mime.to_owned(),
+                        picture_type: PictureType::CoverFront,
+                        description: String::new(),
+                        data: image,
+                    });
+                }
+            }
+            b"----" => {
+                if let Some((_, name)) = freeform_name(data).filter(|(m, _)| m == ITUNES_MEAN) {
+                    match name.as_str() {
+                        "ISRC" => tag.set_text("TSRC", text_values(&values)),
+                        _ => {
+                            tag.add_frame(ExtendedText {
+                                description: name,
+                                value: text_values(&values),
+                            });
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(tag)
+}
+
+fn data_atom(code: u32, value: &[u8]) -> Atom {
+    let mut data = code.to_be_bytes().to_vec();
+    data.extend([0; 4]);
+    data.extend(value);
+    Atom::Leaf(*b"data", data)
+}
+
+fn item(kind: &[u8; 4], values: Vec<Atom>) -> Atom {
+    let mut data = vec![];
+    for value in values {
+        value.encode(&mut data);
+    }
+    Atom::Leaf(*kind, data)
+}
+
+fn text_item(kind: &[u8; 4], text: &str) -> Atom {
+    item(
+        kind,
+        text.split('\0')
+            .map(|t| data_atom(1, t.as_bytes()))
+            .collect(),
+    )
+}
+
+fn freeform_item(name: &str, text: &str) -> Atom {
+    let mut parts = vec![];
+    for (kind, value) in [(b"mean", ITUNES_MEAN), (b"name", name)] {
+        let mut data = vec![0; 4];
+        data.extend(value.as_bytes());
+        parts.push(Atom::Leaf(*kind, data));
+    }
+    parts.extend(text.split('\0').map(|t| data_atom(1, t.as_bytes())));
+    item(b"----", parts)
+}
+
+fn pair_item(kind: &[u8; 4], text: &str) -> Option<Atom> {
+    let (number, total) = text.split_once('/').unwrap_or((text, "0"));
+    let number: u16 = number.trim().parse().ok()?;
+    let total: u16 = total.trim().parse().unwrap_or(0);
+    let mut value = vec![0, 0];
+    value.extend(number.to_be_bytes());
+    value.extend(total.to_be_bytes());
+    if kind == b"trkn" {
+        value.extend([0, 0]);
+    }
+    Some(item(kind, vec![data_atom(0, &value)]))
+}
+
+/// The ilst items for a tag, and the IDs of frames MP4 has no item for
+fn items_of(tag: &Tag) -> (Vec<Atom>, Vec<String>) {
+    let mut items = vec![];
+    let mut skipped = vec![];
+    let mut covers = vec![];
+    for frame in tag.frames() {
+        let id = frame.id();
+        let text_kind = TEXT_ITEMS.iter().find(|(_, i)| *i == id).map(|(k, _)| *k);
+        let atom = match (frame.content(), text_kind) {
+            (Content::Text(text), Some(kind)) => Some(text_item(kind, text)),
+            (Content::Text(text), None) => match id {
+                "TRCK" => pair_item(b"trkn", text),
+                "TPOS" => pair_item(b"disk", text),
+                "TBPM" => text
+                    .trim()
+                    .parse::<u16>()
+                    .ok()
+                    .map(|bpm| item(b"tmpo", vec![data_atom(21, &bpm.to_be_bytes())])),
+                "TCMP" => Some(item(
+                    b"cpil",
+                    vec![data_atom(21, &[u8::from(text.trim() == "1")])],
+                )),
+                "TSRC" => Some(freeform_item("ISRC", text)),
+                "COMM" => Some(text_item(b"\xa9cmt", text)),
+                "USLT" => Some(text_item(b"\xa9lyr", text)),
+                _ => None,
+            },
+            (Content::ExtendedText(t), _) => Some(freeform_item(&t.description, &t.value)),
+            (Content::Comment(c), _) => Some(text_item(b"\xa9cmt", &c.text)),
+            (Content::Lyrics(l), _) => Some(text_item(b"\xa9lyr", &l.text)),
+            (Content::Picture(p), _) => {
+                let code = match p.mime_type.as_str() {
+                    "image/png" => 14,
+                    "image/bmp" => 27,
+                    _ => 13,
+                };
+                covers.push(data_atom(code, &p.data));
+                continue;
+            }
+            _ => None,
+        };
+        match atom {
+            Some(atom) => items.push(atom),
+            None => skipped.push(id.to_owned()),
+        }
+    }
+    if !covers.is_empty() {
+        items.push(item(b"covr", covers));
+    }
+    (items, skipped)
+}
+
+fn child_mut<'a>(parent: &'a mut Atom, kind: &[u8; 4], new: impl FnOnce() -> Atom) -> &'a mut Atom {
+    let children = parent.children_mut().expect("only containers are searched");
+    let index = match children.iter().position(|a| a.kind() == kind) {
+        Some(index) => index,
+        None => {
+            children.push(new());
+            children.len() - 1
+        }
+    };
+    &mut children[index]
+}
+
+/// Moves every chunk offset in the sample tables by `delta`, for when the media data has shifted
+fn shift_chunk_offsets(atom: &mut Atom, delta: i64) {
+    match atom {
+        Atom::Container(_, _, children) => {
+            for child in children {
+                shift_chunk_offsets(child, delta);
+            }
+        }
+        Atom::Leaf(kind, data) if kind == b"stco" || kind == b"co64" => {
+            let width = if kind == b"stco" { 4 } else { 8 };
+            let count = data.get(4..8).map_or(0, be) as usize;
+            for i in 0..count {
+                let pos = 8 + i * width;
+                let Some(bytes) = data.get_mut(pos..pos + width) else {
+                    break;
+                };
+                let moved = (be(bytes) as i64 + delta) as u64;
+                bytes.copy_from_slice(&moved.to_be_bytes()[8 - width..]);
+            }
+        }
+        Atom::Leaf(..) => {}
+    }
+}
+
+/// The replacement for the `moov` atom and any free space after it, as where it starts, how
+/// many bytes it replaces and the new bytes. Free space is used up before the media is moved.
+fn encode(path: &Path, tag: &Tag) -> StrResult<(u64, u64, Vec<u8>)> {
+    let (_, atoms, index, mut moov) = read_moov(path)?;
+    let (new_items, skipped) = items_of(tag);
+    if !skipped.is_empty() {
+        eprintln!(
+            "{}: MP4 has no item for {}, which were not written",
+            path.to_string_lossy(),
+            skipped.join(", ")
+        );
+    }
+    let udta = child_mut(&mut moov, b"udta", || {
+        Atom::Container(*b"udta", vec![], vec![])
+    });
+    let meta = child_mut(udta, b"meta", || {
+        let mut handler = vec![0; 8];
+        handler.extend(b"mdirappl");
+        handler.extend([0; 9]);
+        Atom::Container(*b"meta", vec![0; 4], vec![Atom::Leaf(*b"hdlr", handler)])
+    });
+    let ilst = child_mut(meta, b"ilst", || Atom::Container(*b"ilst", vec![], vec![]));
+    let items = ilst.children_mut().expect("ilst is a container");
+    // Items this module doesn't understand are kept as they were
+    let kept: Vec<Atom> = items.drain(..).filter(|item| !is_mapped(item)).collect();
+    items.extend(new_items);
+    items.extend(kept);
+
+    let old = &atoms[index];
+    let free = atoms
+        .get(index + 1)
+        .filter(|a| &a.kind == b"free" || &a.kind == b"skip")
+        .map_or(0, |a| a.len);
+    let media_after = atoms[index + 1..].iter().any(|a| &a.kind == b"mdat");
+
+    let mut encoded = vec![];
+    moov.encode(&mut encoded);
+    let available = old.len + free;
+    let new_len = encoded.len() as u64;
+    // Fill the rest of the old space with a free atom if that's possible, so nothing moves
+    if new_len == available || new_len + 8 <= available {
+        if new_len < available {
+            let gap = available - new_len;
+            encoded.extend((gap as u32).to_be_bytes());
+            encoded.extend(b"free");
+            encoded.resize(available as usize, 0);
+        }
+        return Ok((old.start, available, encoded));
+    }
+    if media_after {
+        let delta = new_len as i64 - old.len as i64 - free as i64;
+        shift_chunk_offsets(&mut moov, delta);
+        encoded.clear();
+        moov.encode(&mut encoded);
+    }
+    Ok((old.start, available, encoded))
+}
+
+fn write_tag(path: &Path, file: &mut File, tag: &Tag, buffer_size: usize) -> StrResult<()> {
+    let (start, old_len, data) = encode(path, tag)?;
+    splice::splice_at(file, start, old_len, &data, buffer_size)
+        .map_err(|e| Error::Io(format!("Could not write tags: {e}")))
+}
+
+/// The duration from the movie header, in seconds
+fn duration_secs(path: &Path) -> StrResult<Option<f64>> {
+    let (_, _, _, moov) = read_moov(path)?;
+    let Some(Atom::Leaf(_, mvhd)) = find(children(&moov), b"mvhd") else {
+        return Ok(None);
+    };
+    let (timescale, duration) = match mvhd.first() {
+        Some(1) => (mvhd.get(20..24).map(be), mvhd.get(24..32).map(be)),
+        _ => (mvhd.get(12..16).map(be), mvhd.get(16..20).map(be)),
+    };
+    Ok(match (timescale, duration) {
+        (Some(scale), Some(duration)) if scale > 0 => Some(duration as f64 / scale as f64),
+        _ => None,
+    })
+}
+
+fn properties_json(path: &Path) -> StrResult<JsonValue> {
+    let mut json = JsonValue::new_object();
+    json["format"] = "mp4".into();
+    if let Some(duration) = duration_secs(path)? {
+        json["duration"] = ((duration * 1000.0).round() / 1000.0).into();
+    }
+    Ok(json)
+}
+
+pub struct Mp4;
+
+impl Backend for Mp4 {
+    fn handles(&self, format: Format) -> bool {
+        format == Format::Mp4
+    }
+
+    fn read_tag(&self, path: &Path) -> StrResult<Tag> {
+        read_tag(path)
+    }
+
+    fn write_tag(
+        &self,
+        path: &Path,
+        file: &mut File,
+        tag: &Tag,
+        buffer_size: usize,
+    ) -> StrResult<()> {
+        write_tag(path, file, tag, buffer_size)
+    }
+
+    fn properties(&self, path: &Path) -> StrResult<JsonValue> {
+        properties_json(path)
+    }
+
+    fn tag_sizes(&self, path: &Path, tag: &Tag) -> StrResult<(u64, u64)> {
+        let (_, old_len, data) = encode(path, tag)?;
+        Ok((old_len, data.len() as u64))
+    }
+
+    /// The first `mdat` atom, which a rewrite moves but leaves unchanged
+    fn audio_span(&self, path: &Path) -> StrResult<(u64, u64)> {
+        let (_, atoms, _, _) = read_moov(path)?;
+        let mdat = atoms
+            .iter()
+            .find(|a| &a.kind == b"mdat")
+            .ok_or("MP4 file has no mdat atom")?;
+        Ok((mdat.start, mdat.start + mdat.len))
+    }
+
+    fn duration(&self, path: &Path) -> Option<f64> {
+        duration_secs(path).ok().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const AUDIO: &[u8] = b"\x21\x10\x05\x20\xa4\x1b\xff\xc0 not really AAC";
+
+    fn container(kind: &[u8; 4], children: Vec<Atom>) -> Atom {
+        Atom::Container(*kind, vec![], children)
+    }
+
+    /// An M4A file of two seconds, whose one chunk of audio the sample table points at
+    fn m4a() -> Vec<u8> {
+        let mut mvhd = vec![0; 100];
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&2000u32.to_be_bytes());
+        let moov = |offset: u32| {
+            let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stco.extend(offset.to_be_bytes());
+            let stbl = container(b"stbl", vec![Atom::Leaf(*b"stco", stco)]);
+            let minf = container(b"minf", vec![stbl]);
+            let trak = container(b"trak", vec![container(b"mdia", vec![minf])]);
+            container(b"moov", vec![Atom::Leaf(*b"mvhd", mvhd.clone()), trak])
+        };
+        let mut data = vec![];
+        Atom::Leaf(*b"ftyp", b"M4A \0\0\0\0M4A isom".to_vec()).encode(&mut data);
+        let mut encoded = vec![];
+        moov(0).encode(&mut encoded);
+        let offset = (data.len() + encoded.len() + 8) as u32;
+        moov(offset).encode(&mut data);
+        Atom::Leaf(*b"mdat", AUDIO.to_vec()).encode(&mut data);
+        data
+    }
+
+    /// Where the sample table says the audio is
+    fn chunk_offset(path: &Path) -> usize {
+        let (_, _, _, moov) = read_moov(path).unwrap();
+        let trak = find(children(&moov), b"trak").unwrap();
+        let mdia = find(children(trak), b"mdia").unwrap();
+        let minf = find(children(mdia), b"minf").unwrap();
+        let stbl = find(children(minf), b"stbl").unwrap();
+        match find(children(stbl), b"stco") {
+            Some(Atom::Leaf(_, stco)) => be(&stco[8..12]) as usize,
+            _ => panic!("no stco"),
+        }
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        let dir = testing::scratch_dir("mp4-round-trip");
+        let path = dir.join("t.m4a");
+        std::fs::write(&path, m4a()).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[chunk_offset(&path)..][..AUDIO.len()], AUDIO);
+
+        crate::write_tag(&path, &testing::tag(), &crate::WriteOpts::default()).unwrap();
+        testing::assert_round_trip(&crate::read_tag(&path).unwrap());
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[chunk_offset(&path)..][..AUDIO.len()], AUDIO);
+        assert_eq!(Mp4.properties(&path).unwrap()["duration"], 2.0);
+        let (start, end) = Mp4.audio_span(&path).unwrap();
+        assert_eq!(&data[start as usize + 8..end as usize], AUDIO);
+    }
+
+    #[test]
+    fn damaged_files_are_errors() {
+        let dir = testing::scratch_dir("mp4-damaged");
+        let path = dir.join("t.m4a");
+        std::fs::write(&path, m4a()).unwrap();
+        crate::write_tag(&path, &testing::tag(), &crate::WriteOpts::default()).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let (_, atoms, index, _) = read_moov(&path).unwrap();
+        let moov_end = (atoms[index].start + atoms[index].len) as usize;
+
+        testing::each_truncation(&path, &data, moov_end, |path, len| {
+            assert!(read_tag(path).is_err(), "{len} bytes");
+        });
+        testing::each_corruption(&path, &data, moov_end, |path| {
+            let _ = read_tag(path);
+            let _ = duration_secs(path);
+        });
+        // A size that would run past the end of memory, let alone the data, after another atom
+        let mut huge = vec![];
+        Atom::Leaf(*b"free", vec![]).encode(&mut huge);
+        huge.extend(1u32.to_be_bytes());
+        huge.extend(b"moov");
+        huge.extend(u64::MAX.to_be_bytes());
+        assert!(parse_atoms(&huge).is_err());
+        let mut file = data[..moov_end].to_vec();
+        file.extend(&huge[8..]);
+        std::fs::write(&path, file).unwrap();
+        assert!(read_tag(&path).is_err());
+    }
+}
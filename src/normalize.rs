@@ -0,0 +1,230 @@
+//! Text normalization: canonical composition, trimming and collapsing whitespace, so that values
+//! which look identical also compare identically when players group by them
+
+use crate::authority::Authority;
+use crate::batch::{self, BatchReport, Cancel, Progress};
+use crate::compose::{COMBINING_CLASSES, COMPOSITIONS, DECOMPOSITIONS};
+use crate::{StrResult, WriteOpts};
+use id3::{Content, Frame, Tag, TagLike};
+use std::path::{Path, PathBuf};
+
+const HANGUL_S_BASE: u32 = 0xAC00;
+const HANGUL_L_BASE: u32 = 0x1100;
+const HANGUL_V_BASE: u32 = 0x1161;
+const HANGUL_T_BASE: u32 = 0x11A7;
+const HANGUL_V_COUNT: u32 = 21;
+const HANGUL_T_COUNT: u32 = 28;
+
+fn compose_pair(base: char, mark: char) -> Option<char> {
+    let (b, m) = (base as u32, mark as u32);
+    // Hangul syllables compose algorithmically rather than by table
+    if (HANGUL_L_BASE..HANGUL_L_BASE + 19).contains(&b)
+        && (HANGUL_V_BASE..HANGUL_V_BASE + HANGUL_V_COUNT).contains(&m)
+    {
+        let index = (b - HANGUL_L_BASE) * HANGUL_V_COUNT + (m - HANGUL_V_BASE);
+        return char::from_u32(HANGUL_S_BASE + index * HANGUL_T_COUNT);
+    }
+    let s_index = b.wrapping_sub(HANGUL_S_BASE);
+    if s_index < 11172
+        && s_index % HANGUL_T_COUNT == 0
+        && (HANGUL_T_BASE + 1..HANGUL_T_BASE + HANGUL_T_COUNT).contains(&m)
+    {
+        return char::from_u32(b + (m - HANGUL_T_BASE));
+    }
+    COMPOSITIONS
+        .binary_search_by(|(b, m, _)| (*b, *m).cmp(&(base, mark)))
+        .ok()
+        .map(|i| COMPOSITIONS[i].2)
+}
+
+/// The canonical combining class of a character, 0 for starters
+fn combining_class(c: char) -> u8 {
+    COMBINING_CLASSES
+        .binary_search_by(|(first, last, _)| {
+            if c < *first {
+                std::cmp::Ordering::Greater
+            } else if c > *last {
+                std::cmp::Ordering::Less
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .map_or(0, |i| COMBINING_CLASSES[i].2)
+}
+
+/// Appends the full canonical decomposition of a character
+fn decompose_into(c: char, out: &mut Vec<char>) {
+    let s_index = (c as u32).wrapping_sub(HANGUL_S_BASE);
+    if s_index < 11172 {
+        let n_count = HANGUL_V_COUNT * HANGUL_T_COUNT;
+        let to_char = |u| char::from_u32(u).expect("Hangul jamo are characters");
+        out.push(to_char(HANGUL_L_BASE + s_index / n_count));
+        out.push(to_char(
+            HANGUL_V_BASE + (s_index % n_count) / HANGUL_T_COUNT,
+        ));
+        if !s_index.is_multiple_of(HANGUL_T_COUNT) {
+            out.push(to_char(HANGUL_T_BASE + s_index % HANGUL_T_COUNT));
+        }
+        return;
+    }
+    match DECOMPOSITIONS.binary_search_by_key(&c, |(composed, _, _)| *composed) {
+        Ok(i) => {
+            let (_, first, second) = DECOMPOSITIONS[i];
+            decompose_into(first, out);
+            if let Some(second) = second {
+                decompose_into(second, out);
+            }
+        }
+        Err(_) => out.push(c),
+    }
+}
+
+/// Composes text to NFC: each character is canonically decomposed, each run of combining marks
+/// is put in the canonical order, and the result is composed again wherever a mark isn't
+/// blocked from its starter by another of the same or higher class. Text that is already NFC is
+/// returned unchanged.
+pub fn compose(text: &str) -> String {
+    let mut decomposed = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        decompose_into(c, &mut decomposed);
+    }
+    // A stable sort keeps marks of the same class in their order, as canonical ordering requires
+    for run in decomposed.chunk_by_mut(|_, b| combining_class(*b) != 0) {
+        let start = usize::from(combining_class(run[0]) == 0);
+        run[start..].sort_by_key(|c| combining_class(*c));
+    }
+
+    let mut out: Vec<char> = Vec::with_capacity(decomposed.len());
+    let mut starter: Option<usize> = None;
+    let mut last_class = 0;
+    for c in decomposed {
+        let class = combining_class(c);
+        if let Some(s) = starter {
+            let blocked = out.len() > s + 1 && last_class >= class;
+            if let Some(composed) = compose_pair(out[s], c).filter(|_| !blocked) {
+                out[s] = composed;
+                continue;
+            }
+        }
+        if class == 0 {
+            starter = Some(out.len());
+        }
+        last_class = class;
+        out.push(c);
+    }
+    out.into_iter().collect()
+}
+
+/// Replaces every run of whitespace with a single space and trims both ends
+pub fn clean_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Fully normalizes a text frame value. Each NUL-separated value is cleaned on its own
+pub fn normalize_text(text: &str) -> String {
+    text.split('\0')
+        .map(|v| clean_whitespace(&compose(v)))
+        .collect::<Vec<_>>()
+        .join("\0")
+}
+
+/// Normalizes every text frame in the tag, returning whether anything changed
+pub fn normalize_tag(tag: &mut Tag) -> bool {
+    let changes: Vec<(String, String)> = tag
+        .frames()
+        .filter_map(|f| {
+            let text = f.content().text()?;
+            let normalized = normalize_text(text);
+            (normalized != text).then(|| (f.id().to_owned(), normalized))
+        })
+        .collect();
+    for (id, text) in &changes {
+        tag.add_frame(Frame::with_content(id, Content::Text(text.clone())));
+    }
+    !changes.is_empty()
+}
+
+/// Normalizes the text frames of files in place, and rewrites their artists to the names in the
+/// `authority` list if one is given, reporting each file changed and each name the list lacks
+pub fn clean_text(
+    files: &[PathBuf],
+    authority: Option<&Path>,
+    opts: &WriteOpts,
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+) -> StrResult<BatchReport> {
+    let authority = authority.map(Authority::load).transpose()?;
+    let mut report = BatchReport::default();
+    let cancelled = batch::for_each_file(files, cancel, progress, |file| {
+        let mut tag = match crate::read_tag(file) {
+            Ok(t) => t,
+            Err(e) => {
+                report.fail(file, e);
+                return;
+            }
+        };
+        let mut changed = normalize_tag(&mut tag);
+        if let Some(authority) = &authority {
+            let before = tag.clone();
+            for name in authority.rewrite_tag(&mut tag) {
+                report.warn(file, format!("\"{name}\" is not in the authority list"));
+            }
+            changed |= tag != before;
+        }
+        if !changed {
+            return;
+        }
+        match crate::write_tag(file, &tag, opts) {
+            Ok(()) => report.succeed(file, ""),
+            Err(e) => report.fail(file, e),
+        }
+    });
+    report.cancelled = cancelled;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_sorted() {
+        assert!(COMPOSITIONS
+            .windows(2)
+            .all(|w| (w[0].0, w[0].1) < (w[1].0, w[1].1)));
+        assert!(DECOMPOSITIONS.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(COMBINING_CLASSES.windows(2).all(|w| w[0].1 < w[1].0));
+    }
+
+    #[test]
+    fn every_composition_round_trips() {
+        for (base, mark, composed) in COMPOSITIONS {
+            assert_eq!(compose(&format!("{base}{mark}")), composed.to_string());
+            assert_eq!(compose(&composed.to_string()), composed.to_string());
+            let mut decomposed = vec![];
+            decompose_into(composed, &mut decomposed);
+            let decomposed: String = decomposed.into_iter().collect();
+            assert_eq!(compose(&decomposed), composed.to_string());
+        }
+    }
+
+    #[test]
+    fn composes_to_nfc() {
+        // Marks out of canonical order, below then above and above then below
+        assert_eq!(compose("a\u{0323}\u{0302}"), "\u{1ead}");
+        assert_eq!(compose("a\u{0302}\u{0323}"), "\u{1ead}");
+        assert_eq!(compose("\u{00e2}\u{0323}"), "\u{1ead}");
+        // A second mark of the same class is blocked by the first
+        assert_eq!(compose("e\u{0301}\u{0301}"), "\u{00e9}\u{0301}");
+        // Singletons and composition exclusions stay decomposed
+        assert_eq!(compose("\u{212b}"), "\u{00c5}");
+        assert_eq!(compose("\u{2126}"), "\u{03a9}");
+        assert_eq!(compose("\u{0958}"), "\u{0915}\u{093c}");
+        assert_eq!(compose("\u{0915}\u{093c}"), "\u{0915}\u{093c}");
+        // Hangul, by jamo and already composed
+        assert_eq!(compose("\u{1100}\u{1161}\u{11a8}"), "\u{ac01}");
+        assert_eq!(compose("\u{ac01}"), "\u{ac01}");
+        assert_eq!(compose("\u{0301}e"), "\u{0301}e");
+        assert_eq!(compose("Beyonce\u{0301} – Déjà Vu"), "Beyoncé – Déjà Vu");
+    }
+}
@@ -0,0 +1,551 @@
+//! Ogg Vorbis and Opus files. Their tags are Vorbis comments, as in FLAC, but carried in the
+//! second header packet with cover art embedded as METADATA_BLOCK_PICTURE fields.
+
+use crate::backend::{self, Backend};
+use crate::sniff::Format;
+use crate::{base64, flac, splice, Error, StrResult};
+use id3::Tag;
+use json::JsonValue;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const CAPTURE: &[u8; 4] = b"OggS";
+const HEADER_LEN: usize = 27;
+/// Set on a page whose first packet began on an earlier page
+const CONTINUED: u8 = 0x01;
+/// The most data one page can hold, over its 255 lacing values
+const MAX_PAGE_DATA: usize = 255 * 255;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Codec {
+    Vorbis,
+    Opus,
+}
+
+impl Codec {
+    fn detect(id_packet: &[u8]) -> StrResult<Codec> {
+        if id_packet.starts_with(b"\x01vorbis") {
+            Ok(Codec::Vorbis)
+        } else if id_packet.starts_with(b"OpusHead") {
+            Ok(Codec::Opus)
+        } else {
+            Err(Error::Unsupported(
+                "Ogg stream is neither Vorbis nor Opus".to_owned(),
+            ))
+        }
+    }
+
+    /// Identification and comments, then Vorbis's codebooks
+    fn header_packets(self) -> usize {
+        match self {
+            Codec::Vorbis => 3,
+            Codec::Opus => 2,
+        }
+    }
+
+    fn comment_magic(self) -> &'static [u8] {
+        match self {
+            Codec::Vorbis => b"\x03vorbis",
+            Codec::Opus => b"OpusTags",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Vorbis => "vorbis",
+            Codec::Opus => "opus",
+        }
+    }
+}
+
+/// The CRC Ogg uses: polynomial 0x04C11DB7, most significant bit first, with no inversion
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, b| {
+        crc << 8 ^ CRC_TABLE[((crc >> 24) as u8 ^ b) as usize]
+    })
+}
+
+struct Page {
+    header_type: u8,
+    granule: u64,
+    serial: u32,
+    sequence: u32,
+    /// The lacing values, each the length of a segment. One under 255 ends a packet.
+    segments: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl Page {
+    fn read(reader: &mut impl Read) -> StrResult<Option<Page>> {
+        let mut header = [0u8; HEADER_LEN];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(Error::Io(format!("Cannot read Ogg page: {e}"))),
+        }
+        if &header[..4] != CAPTURE || header[4] != 0 {
+            return Err(Error::Parse("Invalid Ogg page".to_owned()));
+        }
+        let le = |range: std::ops::Range<usize>| {
+            header[range]
+                .iter()
+                .rev()
+                .fold(0u64, |acc, b| acc << 8 | *b as u64)
+        };
+        let mut segments = vec![0u8; header[26] as usize];
+        reader
+            .read_exact(&mut segments)
+            .map_err(|e| format!("Truncated Ogg page: {e}"))?;
+        let mut data = vec![0u8; segments.iter().map(|s| *s as usize).sum()];
+        reader
+            .read_exact(&mut data)
+            .map_err(|e| format!("Truncated Ogg page: {e}"))?;
+        Ok(Some(Page {
+            header_type: header[5],
+            granule: le(6..14),
+            serial: le(14..18) as u32,
+            sequence: le(18..22) as u32,
+            segments,
+            data,
+        }))
+    }
+
+    fn len(&self) -> u64 {
+        (HEADER_LEN + self.segments.len() + self.data.len()) as u64
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = CAPTURE.to_vec();
+        out.push(0);
+        out.push(self.header_type);
+        out.extend(self.granule.to_le_bytes());
+        out.extend(self.serial.to_le_bytes());
+        out.extend(self.sequence.to_le_bytes());
+        out.extend([0; 4]);
+        out.push(self.segments.len() as u8);
+        out.extend(&self.segments);
+        out.extend(&self.data);
+        let checksum = crc(&out);
+        out[22..26].copy_from_slice(&checksum.to_le_bytes());
+        out
+    }
+}
+
+/// The header packets of the first logical stream, and where they lie in the file
+struct Headers {
+    codec: Codec,
+    serial: u32,
+    packets: Vec<Vec<u8>>,
+    /// The length of the first page, which holds only the identification header
+    first_page_len: u64,
+    /// How many pages the headers take, and where the audio begins after them
+    pages: u32,
+    end: u64,
+}
+
+fn read_headers(path: &Path) -> StrResult<Headers> {
+    let file = File::open(path)
+        .map_err(|e| Error::Io(format!("Cannot open {}: {e}", path.to_string_lossy())))?;
+    let mut reader = std::io::BufReader::new(file);
+    let mut packets: Vec<Vec<u8>> = vec![];
+    let mut partial = vec![];
+    let mut end = 0;
+    let mut first_page_len = 0;
+    let mut serial = None;
+    let mut codec = None;
+    let mut pages = 0;
+    while let Some(page) = Page::read(&mut reader)? {
+        if *serial.get_or_insert(page.serial) != page.serial {
+            return Err(Error::Unsupported(
+                "Multiplexed Ogg streams are not supported".to_owned(),
+            ));
+        }
+        pages += 1;
+        end += page.len();
+        if pages == 1 {
+            first_page_len = page.len();
+        }
+        let mut pos = 0;
+        for segment in &page.segments {
+            let len = *segment as usize;
+            partial.extend(&page.data[pos..pos + len]);
+            pos += len;
+            if len < 255 {
+                packets.push(std::mem::take(&mut partial));
+            }
+        }
+        if codec.is_none() {
+            codec = Some(Codec::detect(
+                packets.first().ok_or("Empty first Ogg page")?,
+            )?);
+        }
+        let needed = codec.map_or(usize::MAX, Codec::header_packets);
+        if packets.len() >= needed {
+            if packets.len() > needed || !partial.is_empty() {
+                return Err(Error::Unsupported(
+                    "Ogg headers share a page with audio".to_owned(),
+                ));
+            }
+            return Ok(Headers {
+                codec: codec.unwrap_or(Codec::Vorbis),
+                serial: serial.unwrap_or_default(),
+                packets,
+                first_page_len,
+                pages,
+                end,
+            });
+        }
+    }
+    Err(Error::Parse("Ogg file ends within its headers".to_owned()))
+}
+
+/// The comment packet's vendor string and fields
+fn parse_comment_packet(headers: &Headers) -> StrResult<(String, Vec<(String, String)>)> {
+    let magic = headers.codec.comment_magic();
+    let packet = &headers.packets[1];
+    let body = packet
+        .strip_prefix(magic)
+        .ok_or("Ogg comment header has the wrong signature")?;
+    flac::parse_comments(body)
+}
+
+fn read_tag(path: &Path) -> StrResult<Tag> {
+    let headers = read_headers(path)?;
+    let mut tag = Tag::new();
+    flac::add_fields(&mut tag, parse_comment_packet(&headers)?.1, path);
+    Ok(tag)
+}
+
+/// Splits packets into pages, continuing sequence numbers from `first_sequence`
+fn paginate(packets: &[Vec<u8>], serial: u32, first_sequence: u32) -> Vec<Page> {
+    let mut pages = vec![];
+    let mut page = Page {
+        header_type: 0,
+        granule: 0,
+        serial,
+        sequence: first_sequence,
+        segments: vec![],
+        data: vec![],
+    };
+    for packet in packets {
+        let mut rest = &packet[..];
+        loop {
+            if page.segments.len() == 255 {
+                let continues = page.segments.last() == Some(&255);
+                let next = Page {
+                    header_type: if continues { CONTINUED } else { 0 },
+                    granule: 0,
+                    serial,
+                    sequence: page.sequence + 1,
+                    segments: vec![],
+                    data: vec![],
+                };
+                pages.push(std::mem::replace(&mut page, next));
+            }
+            let len = rest.len().min(255);
+            page.segments.push(len as u8);
+            page.data.extend(&rest[..len]);
+            rest = &rest[len..];
+            // A packet whose length is a multiple of 255 still needs a short segment to end it
+            if len < 255 {
+                break;
+            }
+        }
+    }
+    pages.push(page);
+    pages
+}
+
+/// The new header pages after the first, for a tag, and the headers they replace
+fn encode(path: &Path, tag: &Tag) -> StrResult<(Headers, Vec<Page>)> {
+    let headers = read_headers(path)?;
+    let vendor = parse_comment_packet(&headers).map_or_else(|_| "tag2json".to_owned(), |(v, _)| v);
+    let (mut fields, skipped) = flac::fields_of(tag);
+    if !skipped.is_empty() {
+        eprintln!(
+            "{}: Vorbis comments have no place for {}, which were not written",
+            path.to_string_lossy(),
+            skipped.join(", ")
+        );
+    }
+    for picture in tag.pictures() {
+        let block = flac::picture_block(picture);
+        fields.push(("METADATA_BLOCK_PICTURE".to_owned(), base64::encode(&block)));
+    }
+    let mut comments = headers.codec.comment_magic().to_vec();
+    comments.extend(flac::comment_block(&vendor, &fields));
+    if headers.codec == Codec::Vorbis {
+        // The framing bit
+        comments.push(1);
+    }
+    let mut packets = vec![comments];
+    packets.extend(headers.packets[2..].iter().cloned());
+    let pages = paginate(&packets, headers.serial, 1);
+    Ok((headers, pages))
+}
+
+/// The number of bytes a tag would occupy in the file, and the number it occupies now
+fn encoded_len(path: &Path, tag: &Tag) -> StrResult<(u64, u64)> {
+    let (headers, pages) = encode(path, tag)?;
+    let new_len = pages.iter().map(Page::len).sum();
+    Ok((headers.end - headers.first_page_len, new_len))
+}
+
+/// Replaces the header pages after the first. If the page count changes, every later page is
+/// renumbered and so rewritten too.
+fn write_tag(path: &Path, file: &mut File, tag: &Tag, buffer_size: usize) -> StrResult<()> {
+    let (headers, pages) = encode(path, tag)?;
+    let mut data: Vec<u8> = pages.iter().flat_map(Page::encode).collect();
+    let start = headers.first_page_len;
+    let mut old_len = headers.end - start;
+    let shift = pages.len() as i64 - (headers.pages as i64 - 1);
+    if shift != 0 {
+        let mut rest = vec![];
+        file.seek(SeekFrom::Start(headers.end))
+            .and_then(|_| file.read_to_end(&mut rest))
+            .map_err(|e| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy())))?;
+        old_len += rest.len() as u64;
+        let mut reader = &rest[..];
+        while let Some(mut page) = Page::read(&mut reader)? {
+            if page.serial == headers.serial {
+                page.sequence = (page.sequence as i64 + shift) as u32;
+            }
+            data.extend(page.encode());
+        }
+    }
+    splice::splice_at(file, start, old_len, &data, buffer_size)
+        .map_err(|e| Error::Io(format!("Could not write tags: {e}")))
+}
+
+/// The granule position of the stream's last page, which counts samples decoded by its end
+fn last_granule(path: &Path, serial: u32) -> StrResult<Option<u64>> {
+    let read_error =
+        |e: std::io::Error| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy()));
+    let mut file = File::open(path).map_err(read_error)?;
+    let len = file.metadata().map_err(read_error)?.len();
+    // The last page starts within its maximum size of the end
+    let tail_len = len.min((HEADER_LEN + 255 + MAX_PAGE_DATA) as u64);
+    let mut tail = vec![0u8; tail_len as usize];
+    file.seek(SeekFrom::Start(len - tail_len))
+        .and_then(|_| file.read_exact(&mut tail))
+        .map_err(read_error)?;
+    let mut search = &tail[..];
+    while let Some(pos) = search.windows(4).rposition(|w| w == CAPTURE) {
+        if let Ok(Some(page)) = Page::read(&mut &search[pos..]) {
+            if page.serial == serial {
+                return Ok(Some(page.granule));
+            }
+        }
+        search = &search[..pos];
+    }
+    Ok(None)
+}
+
+fn properties_json(path: &Path) -> StrResult<JsonValue> {
+    let headers = read_headers(path)?;
+    let id = &headers.packets[0];
+    let le = |range: std::ops::Range<usize>| {
+        id.get(range)
+            .map(|b| b.iter().rev().fold(0u64, |acc, b| acc << 8 | *b as u64))
+    };
+    // Opus always decodes at 48kHz, whatever the input's rate was, and skips some samples at the start
+    let (channels, sample_rate, granule_rate, skip) = match headers.codec {
+        Codec::Vorbis => (id.get(11).copied(), le(12..16), le(12..16), 0),
+        Codec::Opus => (
+            id.get(9).copied(),
+            le(12..16),
+            Some(48000),
+            le(10..12).unwrap_or(0),
+        ),
+    };
+    let mut json = JsonValue::new_object();
+    json["format"] = headers.codec.name().into();
+    if let Some(rate) = sample_rate.filter(|r| *r > 0) {
+        json["sample_rate"] = rate.into();
+    }
+    if let Some(channels) = channels {
+        json["channels"] = channels.into();
+    }
+    if let (Some(granule), Some(rate)) = (last_granule(path, headers.serial)?, granule_rate) {
+        if rate > 0 && granule != u64::MAX {
+            let duration = granule.saturating_sub(skip) as f64 / rate as f64;
+            json["duration"] = ((duration * 1000.0).round() / 1000.0).into();
+        }
+    }
+    Ok(json)
+}
+
+pub struct Ogg;
+
+impl Backend for Ogg {
+    fn handles(&self, format: Format) -> bool {
+        format == Format::Ogg
+    }
+
+    fn read_tag(&self, path: &Path) -> StrResult<Tag> {
+        read_tag(path)
+    }
+
+    fn write_tag(
+        &self,
+        path: &Path,
+        file: &mut File,
+        tag: &Tag,
+        buffer_size: usize,
+    ) -> StrResult<()> {
+        write_tag(path, file, tag, buffer_size)
+    }
+
+    fn properties(&self, path: &Path) -> StrResult<JsonValue> {
+        properties_json(path)
+    }
+
+    fn tag_sizes(&self, path: &Path, tag: &Tag) -> StrResult<(u64, u64)> {
+        encoded_len(path, tag)
+    }
+
+    /// The pages after the headers. Their sequence numbers, and so their checksums, change if a
+    /// rewrite makes the comment take a different number of pages
+    fn audio_span(&self, path: &Path) -> StrResult<(u64, u64)> {
+        Ok((read_headers(path)?.end, backend::file_len(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use id3::frame::{Picture, PictureType};
+    use id3::TagLike;
+
+    const SERIAL: u32 = 0x1234_5678;
+    const AUDIO: &[u8] = b"an audio packet";
+
+    fn page(sequence: u32, header_type: u8, granule: u64, packet: &[u8]) -> Vec<u8> {
+        let mut pages = paginate(&[packet.to_vec()], SERIAL, sequence);
+        pages[0].header_type = header_type;
+        pages[0].granule = granule;
+        pages[0].encode()
+    }
+
+    /// An Ogg Vorbis file of two seconds of 44.1 kHz stereo, with an empty comment header
+    fn vorbis() -> Vec<u8> {
+        let mut id = b"\x01vorbis\0\0\0\0\x02".to_vec();
+        id.extend(44100u32.to_le_bytes());
+        id.extend([0; 12]);
+        id.extend([0xB8, 1]);
+        let mut comments = Codec::Vorbis.comment_magic().to_vec();
+        comments.extend(flac::comment_block("test", &[]));
+        comments.push(1);
+        let setup = b"\x05vorbis codebooks".to_vec();
+
+        let mut data = page(0, 0x02, 0, &id);
+        let headers = paginate(&[comments, setup], SERIAL, 1);
+        data.extend(headers.iter().flat_map(Page::encode));
+        data.extend(page(2, 0x04, 88200, AUDIO));
+        data
+    }
+
+    /// Every page of a file, checking each one's CRC
+    fn pages(path: &Path) -> Vec<Page> {
+        let data = std::fs::read(path).unwrap();
+        let mut reader = &data[..];
+        let mut pages = vec![];
+        while let Some(page) = Page::read(&mut reader).unwrap() {
+            let encoded = page.encode();
+            let start = data.len() - reader.len() - encoded.len();
+            assert_eq!(&data[start..start + encoded.len()], encoded, "page CRC");
+            pages.push(page);
+        }
+        pages
+    }
+
+    #[test]
+    fn crc_known_answer() {
+        // The check value of CRC-32/MPEG-2 without its initial inversion
+        assert_eq!(crc(b"123456789"), 0x89A1_897F);
+        assert_eq!(crc(b""), 0);
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        let dir = testing::scratch_dir("ogg-round-trip");
+        let path = dir.join("t.ogg");
+        std::fs::write(&path, vorbis()).unwrap();
+        crate::write_tag(&path, &testing::tag(), &crate::WriteOpts::default()).unwrap();
+        testing::assert_round_trip(&crate::read_tag(&path).unwrap());
+        let properties = Ogg.properties(&path).unwrap();
+        assert_eq!(properties["format"], "vorbis");
+        assert_eq!(properties["sample_rate"], 44100);
+        assert_eq!(properties["channels"], 2);
+        assert_eq!(properties["duration"], 2.0);
+        assert_eq!(pages(&path).last().unwrap().data, AUDIO);
+    }
+
+    #[test]
+    fn long_comments_renumber_the_audio() {
+        let dir = testing::scratch_dir("ogg-renumber");
+        let path = dir.join("t.ogg");
+        std::fs::write(&path, vorbis()).unwrap();
+        let mut tag = testing::tag();
+        let mut art = testing::png();
+        art.resize(100_000, 0);
+        tag.remove_all_pictures();
+        tag.add_frame(Picture {
+            mime_type: "image/png".to_owned(),
+            picture_type: PictureType::CoverFront,
+            description: String::new(),
+            data: art.clone(),
+        });
+        crate::write_tag(&path, &tag, &crate::WriteOpts::default()).unwrap();
+
+        let pages = pages(&path);
+        assert!(pages.len() > 3);
+        let sequences: Vec<u32> = pages.iter().map(|p| p.sequence).collect();
+        assert_eq!(sequences, (0..pages.len() as u32).collect::<Vec<_>>());
+        assert_eq!(pages.last().unwrap().data, AUDIO);
+        let read = read_tag(&path).unwrap();
+        assert_eq!(read.title(), Some("Title"));
+        assert_eq!(read.pictures().next().unwrap().data, art);
+    }
+
+    #[test]
+    fn damaged_files_are_errors() {
+        let dir = testing::scratch_dir("ogg-damaged");
+        let path = dir.join("t.ogg");
+        std::fs::write(&path, vorbis()).unwrap();
+        crate::write_tag(&path, &testing::tag(), &crate::WriteOpts::default()).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let end = read_headers(&path).unwrap().end as usize;
+
+        testing::each_truncation(&path, &data, end, |path, len| {
+            assert!(read_tag(path).is_err(), "{len} bytes");
+            assert!(properties_json(path).is_err(), "{len} bytes");
+        });
+        testing::each_corruption(&path, &data, end, |path| {
+            let _ = read_tag(path);
+            let _ = properties_json(path);
+        });
+    }
+}
@@ -0,0 +1,27 @@
+//! Controlling the order frames are written in, for players that only read the first few
+
+use id3::{Frame, Tag, TagLike};
+
+/// Where a frame goes: the position of its ID in the list, or of `*` for frames not listed,
+/// which otherwise go last
+fn rank(order: &[String], id: &str) -> usize {
+    let position = |entry: &str| order.iter().position(|o| o.eq_ignore_ascii_case(entry));
+    position(id)
+        .or_else(|| position("*"))
+        .unwrap_or(order.len())
+}
+
+/// Sorts the tag's frames into the given order of frame IDs, keeping the existing order among
+/// frames of equal rank
+pub fn reorder(tag: &mut Tag, order: &[String]) {
+    if order.is_empty() {
+        return;
+    }
+    let mut frames: Vec<Frame> = tag.frames().cloned().collect();
+    frames.sort_by_key(|frame| rank(order, frame.id()));
+    let mut ordered = Tag::with_version(tag.version());
+    for frame in frames {
+        ordered.add_frame(frame);
+    }
+    *tag = ordered;
+}
@@ -0,0 +1,258 @@
+//! Extra caution for running over untrusted files: tags are validated against the ID3v2
+//! specification before the id3 crate sees them, symbolic links are never followed, and nothing
+//! is written or read through a path that resolves somewhere other than it appears to
+
+use crate::{Error, StrResult};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// No real tag comes near the 256MB the size field allows, so anything larger is taken as hostile
+const MAX_TAG_SIZE: u64 = 64 * 1024 * 1024;
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Whether a path found while walking directories should be skipped as a link
+pub fn skip_link(path: &Path) -> bool {
+    if enabled() && path.is_symlink() {
+        eprintln!("Skipping {}: symbolic link", path.to_string_lossy());
+        true
+    } else {
+        false
+    }
+}
+
+/// The absolute form of a path with `.` and `..` resolved by name alone, without touching the file system
+fn lexical_absolute(path: &Path) -> StrResult<PathBuf> {
+    let absolute = std::path::absolute(path)
+        .map_err(|e| Error::Io(format!("Cannot resolve {}: {e}", path.to_string_lossy())))?;
+    let mut normal = PathBuf::new();
+    for part in absolute.components() {
+        match part {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            part => normal.push(part),
+        }
+    }
+    Ok(normal)
+}
+
+/// Refuses a path that is a symbolic link or passes through one, so it can't be made to point
+/// outside the directory it appears to be in
+pub fn check_path(path: &Path) -> StrResult<()> {
+    if !enabled() {
+        return Ok(());
+    }
+    if path.is_symlink() {
+        return Err(Error::Other(format!(
+            "Refusing to follow symbolic link {}",
+            path.to_string_lossy()
+        )));
+    }
+    let expected = lexical_absolute(path)?;
+    let Some(dir) = expected.parent() else {
+        return Ok(());
+    };
+    let resolved = dir
+        .canonicalize()
+        .map_err(|e| Error::Io(format!("Cannot resolve {}: {e}", dir.to_string_lossy())))?;
+    if resolved != dir {
+        return Err(Error::Other(format!(
+            "Refusing to use {}, which resolves to {}",
+            path.to_string_lossy(),
+            resolved
+                .join(path.file_name().unwrap_or_default())
+                .to_string_lossy()
+        )));
+    }
+    Ok(())
+}
+
+/// Refuses a file named by a document, such as a chapter image, unless it is a plain relative
+/// path below the document's directory
+pub fn check_reference(reference: &Path) -> StrResult<()> {
+    if enabled()
+        && !reference
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(Error::Other(format!(
+            "Refusing to read {}, which is outside the document's directory",
+            reference.to_string_lossy()
+        )));
+    }
+    Ok(())
+}
+
+/// Checks a file about to be read, including that any ID3v2 tag at its start is well formed
+pub fn check_input(path: &Path) -> StrResult<()> {
+    if !enabled() {
+        return Ok(());
+    }
+    check_path(path)?;
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| Error::Io(format!("Cannot open {}: {e}", path.to_string_lossy())))?;
+    let file_len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut header = [0u8; 10];
+    if file.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+        return Ok(());
+    }
+    let size = syncsafe(&header[6..10])
+        .ok_or_else(|| Error::Parse("Tag size is not a valid syncsafe integer".to_owned()))?;
+    if size > MAX_TAG_SIZE {
+        return Err(Error::Parse(format!(
+            "Tag claims to be {size} bytes, more than the allowed {MAX_TAG_SIZE}"
+        )));
+    }
+    let footer = if header[3] == 4 && header[5] & 0x10 != 0 {
+        10
+    } else {
+        0
+    };
+    if 10 + size + footer > file_len {
+        return Err(Error::Parse(format!(
+            "Tag claims to be {size} bytes but the file is only {file_len}"
+        )));
+    }
+    let mut body = vec![0u8; size as usize];
+    file.read_exact(&mut body)
+        .map_err(|e| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy())))?;
+    validate_tag(header[3], header[4], header[5], &body)
+}
+
+fn syncsafe(bytes: &[u8]) -> Option<u64> {
+    bytes
+        .iter()
+        .try_fold(0u64, |acc, b| (*b < 0x80).then_some(acc << 7 | *b as u64))
+}
+
+fn be_u64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0u64, |acc, b| acc << 8 | *b as u64)
+}
+
+/// Checks the tag body frame by frame: every size must stay within the tag, IDs must be made of
+/// capitals and digits, text encodings must exist in the version, and padding must be zeros
+fn validate_tag(major: u8, revision: u8, flags: u8, body: &[u8]) -> StrResult<()> {
+    let known_flags = match major {
+        2 => 0xC0,
+        3 => 0xE0,
+        4 => 0xF0,
+        v => return Err(Error::Unsupported(format!("Unknown ID3v2 version 2.{v}"))),
+    };
+    validate_structure(major, revision, flags, known_flags, body).map_err(Error::Parse)
+}
+
+/// The checks of [`validate_tag`] once the version is known, giving why the tag is malformed
+fn validate_structure(
+    major: u8,
+    revision: u8,
+    flags: u8,
+    known_flags: u8,
+    body: &[u8],
+) -> Result<(), String> {
+    if revision == 0xFF {
+        Err("Invalid tag revision 0xFF")?;
+    }
+    if flags & !known_flags != 0 {
+        Err(format!("Undefined tag header flags {flags:#04x}"))?;
+    }
+    if major == 2 && flags & 0x40 != 0 {
+        Err("Compressed ID3v2.2 tags have no defined compression scheme")?;
+    }
+    // Before 2.4 unsynchronisation applies to the whole tag, so undo it before reading frame sizes
+    let unsynced;
+    let body = if major < 4 && flags & 0x80 != 0 {
+        let mut out = Vec::with_capacity(body.len());
+        for (i, b) in body.iter().enumerate() {
+            if !(*b == 0 && i > 0 && body[i - 1] == 0xFF) {
+                out.push(*b);
+            }
+        }
+        unsynced = out;
+        &unsynced[..]
+    } else {
+        body
+    };
+
+    let mut pos = 0usize;
+    if major > 2 && flags & 0x40 != 0 {
+        let len = body.get(..4).ok_or("Extended header is truncated")?;
+        let len = if major == 3 {
+            be_u64(len) + 4
+        } else {
+            syncsafe(len).ok_or("Extended header size is not a valid syncsafe integer")?
+        };
+        if len < 6 || len > body.len() as u64 {
+            Err(format!(
+                "Extended header size {len} does not fit in the tag"
+            ))?;
+        }
+        pos = len as usize;
+    }
+
+    let (id_len, header_len) = if major == 2 { (3, 6) } else { (4, 10) };
+    while pos < body.len() {
+        if body[pos] == 0 {
+            if body[pos..].iter().any(|b| *b != 0) {
+                Err(format!("Non-zero bytes in the padding at offset {pos}"))?;
+            }
+            break;
+        }
+        let Some(frame_header) = body.get(pos..pos + header_len) else {
+            Err(format!(
+                "Frame header at offset {pos} runs past the end of the tag"
+            ))?
+        };
+        let id = &frame_header[..id_len];
+        if !id
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        {
+            Err(format!(
+                "Invalid frame ID {:?} at offset {pos}",
+                String::from_utf8_lossy(id)
+            ))?;
+        }
+        let size_bytes = &frame_header[id_len..if major == 2 { 6 } else { 8 }];
+        let size = match major {
+            4 => syncsafe(size_bytes).ok_or_else(|| {
+                format!(
+                    "Frame {} size is not a valid syncsafe integer",
+                    String::from_utf8_lossy(id)
+                )
+            })?,
+            _ => be_u64(size_bytes),
+        };
+        let id = String::from_utf8_lossy(id);
+        let start = pos + header_len;
+        if size == 0 {
+            Err(format!("Frame {id} at offset {pos} is empty"))?;
+        }
+        if size > (body.len() - start) as u64 {
+            Err(format!(
+                "Frame {id} claims {size} bytes, more than remain in the tag"
+            ))?;
+        }
+        // Compression, encryption and the like put other data before the encoding byte
+        let transformed = major > 2 && frame_header[9] != 0;
+        let max_encoding = if major == 4 { 3 } else { 1 };
+        if id.starts_with('T') && !transformed && body[start] > max_encoding {
+            Err(format!(
+                "Frame {id} uses undefined text encoding {}",
+                body[start]
+            ))?;
+        }
+        pos = start + size as usize;
+    }
+    Ok(())
+}
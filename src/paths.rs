@@ -0,0 +1,315 @@
+//! Path handling that stays correct for names which aren't valid UTF-8 or exceed `MAX_PATH`
+
+use crate::{filter, paranoid, sniff, Error, StrResult};
+use json::JsonValue;
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes `dir` the root of the run: relative paths are resolved against it, and paths read or
+/// emitted are expressed relative to it so runs on different hosts produce identical output
+pub fn set_root(dir: &Path) -> StrResult<()> {
+    let unusable = |e: std::io::Error| {
+        Error::Io(format!(
+            "Cannot use {} as the root: {e}",
+            dir.to_string_lossy()
+        ))
+    };
+    let root = dir.canonicalize().map_err(unusable)?;
+    std::env::set_current_dir(&root).map_err(unusable)?;
+    let _ = ROOT.set(root);
+    Ok(())
+}
+
+/// Expresses a path relative to the root if one was set, or `None` if it lies outside it.
+/// Without a root the path is returned unchanged.
+fn under_root(path: &Path) -> Option<PathBuf> {
+    let Some(root) = ROOT.get() else {
+        return Some(path.to_owned());
+    };
+    let absolute = path.canonicalize().ok()?;
+    let relative = absolute.strip_prefix(root).ok()?;
+    if relative.as_os_str().is_empty() {
+        Some(PathBuf::from("."))
+    } else {
+        Some(relative.to_owned())
+    }
+}
+
+/// Whether the path has the given extension, ignoring ASCII case and without a lossy string conversion
+pub fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(OsStr::new(ext)))
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase(),
+        _ => false,
+    }
+}
+
+/// Renames a file whose extension is in mixed or upper case, such as `.MP3`, to the lowercase
+/// form, returning the new path. The file is left alone if another file already has that name.
+pub fn lowercase_extension(path: &Path) -> PathBuf {
+    let Some(ext) = path.extension().and_then(OsStr::to_str) else {
+        return path.to_owned();
+    };
+    let lower = ext.to_ascii_lowercase();
+    if lower == ext {
+        return path.to_owned();
+    }
+    let target = path.with_extension(&lower);
+    // On case-insensitive file systems the target "exists" because it is this file
+    if target.exists() && !same_file(path, &target) {
+        eprintln!(
+            "Not renaming {}: {} already exists",
+            path.to_string_lossy(),
+            target.to_string_lossy()
+        );
+        return path.to_owned();
+    }
+    let change = crate::manifest::Change::begin_from(path, &target, "moved_from");
+    match std::fs::rename(path, &target) {
+        Ok(()) => {
+            change.finish();
+            eprintln!(
+                "renamed {} to {}",
+                path.to_string_lossy(),
+                target.to_string_lossy()
+            );
+            target
+        }
+        Err(e) => {
+            eprintln!("Could not rename {}: {e}", path.to_string_lossy());
+            path.to_owned()
+        }
+    }
+}
+
+/// Expands the given paths into the audio files they name, descending into directories if `recurse` is set.
+/// Files are recognised by their content, so ones with no extension or the wrong one are included.
+pub fn collect_audio_files(paths: &[PathBuf], recurse: bool) -> Vec<PathBuf> {
+    collect(paths, recurse, &|path| sniff::format(path).is_some())
+}
+
+/// Expands the given paths into the files they name with any of the extensions
+pub fn collect_files(paths: &[PathBuf], recurse: bool, extensions: &[&str]) -> Vec<PathBuf> {
+    collect(paths, recurse, &|path| {
+        extensions.iter().any(|e| has_extension(path, e))
+    })
+}
+
+fn collect(paths: &[PathBuf], recurse: bool, wanted: &dyn Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut found = vec![];
+    for path in paths {
+        let Some(path) = under_root(path) else {
+            eprintln!("Skipping {}: outside the root", path.to_string_lossy());
+            continue;
+        };
+        let path = &*long_path(&path);
+        if paranoid::skip_link(path) {
+            continue;
+        }
+        if path.is_dir() && recurse {
+            let contents = match path.read_dir() {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("Could not read {}: {e}", path.to_string_lossy());
+                    continue;
+                }
+            };
+            let mut children: Vec<_> = contents.filter_map(Result::ok).map(|d| d.path()).collect();
+            // Sorted, so every host walks a library in the same order whatever its file system
+            children.sort();
+            found.extend(collect(&children, recurse, wanted));
+        } else if path.is_file() && wanted(path) && filter::matches(path) {
+            found.push(path.to_owned());
+        }
+    }
+    found
+}
+
+/// Checks that the file can be opened for writing, giving a readable reason if not (such as it
+/// being on a read-only mount). Nothing is written.
+pub fn check_writable(path: &Path) -> StrResult<()> {
+    match std::fs::OpenOptions::new().write(true).open(path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::ReadOnlyFilesystem => {
+            Err(Error::Io("file system is read-only".to_owned()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            Err(Error::Io("permission denied".to_owned()))
+        }
+        Err(e) => Err(Error::Io(e.to_string())),
+    }
+}
+
+/// Prefixes long absolute paths with `\\?\` on Windows so they aren't limited to `MAX_PATH`. Elsewhere this does nothing
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    #[cfg(windows)]
+    {
+        const MAX_PATH: usize = 260;
+        let s = path.as_os_str();
+        if path.is_absolute() && s.len() >= MAX_PATH && !path.to_string_lossy().starts_with(r"\\?\")
+        {
+            let mut prefixed = std::ffi::OsString::from(r"\\?\");
+            match path.to_string_lossy().strip_prefix(r"\\") {
+                Some(unc) if path.to_str().is_some() => prefixed.push(format!(r"UNC\{unc}")),
+                _ => prefixed.push(s),
+            }
+            return Cow::Owned(prefixed.into());
+        }
+    }
+    Cow::Borrowed(path)
+}
+
+/// Prints a path followed by a NUL, for `xargs -0` and the like. On Unix the raw bytes are
+/// written, so names that aren't UTF-8 survive
+pub fn print0(path: &Path) {
+    let mut out = std::io::stdout().lock();
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes()
+    };
+    #[cfg(not(unix))]
+    let lossy = path.to_string_lossy();
+    #[cfg(not(unix))]
+    let bytes = lossy.as_bytes();
+    // As with println!, a closed pipe isn't worth reporting
+    let _ = out.write_all(bytes).and_then(|_| out.write_all(b"\0"));
+}
+
+/// Hex encoding of the platform's native representation of the path: raw bytes on Unix, UTF-16 code units on Windows
+fn raw_path(path: &Path) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str()
+            .as_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        path.as_os_str()
+            .encode_wide()
+            .map(|u| format!("{u:04x}"))
+            .collect()
+    }
+}
+
+/// The path whose native representation [`raw_path`] encoded, or `None` if it isn't valid hex
+pub fn path_from_raw(hex: &str) -> Option<PathBuf> {
+    let digits = |width: usize| {
+        (0..hex.len())
+            .step_by(width)
+            .map(|i| u16::from_str_radix(hex.get(i..i + width)?, 16).ok())
+            .collect::<Option<Vec<u16>>>()
+    };
+    #[cfg(unix)]
+    {
+        let bytes: Vec<u8> = digits(2)?.into_iter().map(|b| b as u8).collect();
+        Some(path_from_bytes(&bytes))
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStringExt;
+        Some(std::ffi::OsString::from_wide(&digits(4)?).into())
+    }
+}
+
+/// Returns the key to use for a file in aggregate output. If the path isn't valid UTF-8 the key
+/// is necessarily lossy, so the exact path is also returned to be stored alongside the tags.
+pub fn json_key(path: &Path) -> (String, Option<JsonValue>) {
+    let relative = under_root(path).unwrap_or_else(|| path.to_owned());
+    let path = &*relative;
+    let lossy = path.to_string_lossy();
+    let key = lossy.strip_prefix(r"\\?\").unwrap_or(&lossy).to_owned();
+    match path.to_str() {
+        Some(_) => (key, None),
+        None => (key, Some(raw_path(path).into())),
+    }
+}
+
+/// Reads a list of paths, one per line or NUL-separated, from a file or from stdin if `source` is
+/// `-`. On Unix the names are taken as raw bytes, so non-UTF-8 names survive.
+pub fn read_file_list(source: &Path, null_separated: bool) -> StrResult<Vec<PathBuf>> {
+    let mut data = vec![];
+    let result = if source == Path::new("-") {
+        std::io::stdin().lock().read_to_end(&mut data)
+    } else {
+        std::fs::File::open(source).and_then(|mut f| f.read_to_end(&mut data))
+    };
+    if let Err(e) = result {
+        return Err(Error::Io(format!(
+            "Cannot read file list {}: {e}",
+            source.to_string_lossy()
+        )));
+    }
+    let separator = if null_separated { b'\0' } else { b'\n' };
+    let paths = data
+        .split(|b| *b == separator)
+        .map(|entry| {
+            if null_separated {
+                entry
+            } else {
+                entry.strip_suffix(b"\r").unwrap_or(entry)
+            }
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect();
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn directories_are_walked_in_sorted_order() {
+        let dir = testing::scratch_dir("collect-order");
+        std::fs::create_dir(dir.join("b")).unwrap();
+        for name in ["z.mp3", "b/y.mp3", "a.mp3", "b/c.mp3", "m.mp3"] {
+            testing::write_mp3(&dir.join(name));
+        }
+        std::fs::write(dir.join("notes.txt"), "not audio").unwrap();
+        let found: Vec<_> = collect_audio_files(std::slice::from_ref(&dir), true)
+            .iter()
+            .map(|p| p.strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(found, ["a.mp3", "b/c.mp3", "b/y.mp3", "m.mp3", "z.mp3"]);
+        let shallow = collect_audio_files(&[dir.join("b")], false);
+        assert!(shallow.is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+//! The JSON form of APIC frames: an array under "APIC" of objects giving each picture's type,
+//! MIME type and description, and either the image file it was extracted to or its data as base64
+//!
+//! ```json
+//! "APIC": [{ "type": "back-cover", "mime_type": "image/jpeg", "description": "", "file": "track.back-cover.jpg" }]
+//! ```
+//!
+//! Files are relative to the JSON document, as with chapter images.
+
+use crate::{base64, image, paranoid, sha256, write_data_to_path, Error, StrResult};
+use id3::frame::{Picture, PictureType};
+use json::JsonValue;
+use std::path::{Path, PathBuf};
+
+/// Names for the picture types, in the order of their numeric codes
+const TYPE_NAMES: [(PictureType, &str); 21] = [
+    (PictureType::Other, "other"),
+    (PictureType::Icon, "icon"),
+    (PictureType::OtherIcon, "other-icon"),
+    (PictureType::CoverFront, "front-cover"),
+    (PictureType::CoverBack, "back-cover"),
+    (PictureType::Leaflet, "leaflet"),
+    (PictureType::Media, "media"),
+    (PictureType::LeadArtist, "lead-artist"),
+    (PictureType::Artist, "artist"),
+    (PictureType::Conductor, "conductor"),
+    (PictureType::Band, "band"),
+    (PictureType::Composer, "composer"),
+    (PictureType::Lyricist, "lyricist"),
+    (PictureType::RecordingLocation, "recording-location"),
+    (PictureType::DuringRecording, "during-recording"),
+    (PictureType::DuringPerformance, "during-performance"),
+    (PictureType::ScreenCapture, "screen-capture"),
+    (PictureType::BrightFish, "bright-fish"),
+    (PictureType::Illustration, "illustration"),
+    (PictureType::BandLogo, "band-logo"),
+    (PictureType::PublisherLogo, "publisher-logo"),
+];
+
+/// The name of a picture type, or its number if it's one the standard doesn't define
+pub fn type_name(picture_type: PictureType) -> String {
+    match TYPE_NAMES.iter().find(|(t, _)| *t == picture_type) {
+        Some((_, name)) => (*name).to_owned(),
+        None => match picture_type {
+            PictureType::Undefined(code) => code.to_string(),
+            _ => "other".to_owned(),
+        },
+    }
+}
+
+/// The stem of an image sidecar less the picture type [`sidecar_paths`] puts after the audio file's
+/// stem, so `track.back-cover` and `track.back-cover-2` give `track`. Other stems are unchanged
+pub fn without_type_suffix(stem: &str) -> &str {
+    let Some((audio, kind)) = stem.rsplit_once('.') else {
+        return stem;
+    };
+    let kind = match kind.rsplit_once('-') {
+        Some((kind, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => kind,
+        _ => kind,
+    };
+    let known = TYPE_NAMES.iter().any(|(_, name)| *name == kind)
+        || kind
+            .parse::<u8>()
+            .is_ok_and(|code| usize::from(code) >= TYPE_NAMES.len());
+    match known {
+        true => audio,
+        false => stem,
+    }
+}
+
+fn parse_type(name: &str) -> StrResult<PictureType> {
+    if let Some((t, _)) = TYPE_NAMES
+        .iter()
+        .find(|(_, n)| n.eq_ignore_ascii_case(name))
+    {
+        return Ok(*t);
+    }
+    match name.parse::<u8>() {
+        Ok(code) => Ok(TYPE_NAMES
+            .get(usize::from(code))
+            .map_or(PictureType::Undefined(code), |(t, _)| *t)),
+        Err(_) => Err(Error::Parse(format!("\"{name}\" is not a picture type"))),
+    }
+}
+
+fn picture_to_json(picture: &Picture) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    json["type"] = type_name(picture.picture_type).into();
+    json["mime_type"] = picture.mime_type.as_str().into();
+    json["description"] = picture.description.as_str().into();
+    json
+}
+
+/// The pictures of a tag in their JSON form with their data embedded, or None if it has none
+pub fn to_json(pictures: &[Picture]) -> Option<JsonValue> {
+    let pictures: Vec<JsonValue> = pictures
+        .iter()
+        .map(|picture| {
+            let mut json = picture_to_json(picture);
+            json["data"] = base64::encode(&picture.data).into();
+            json
+        })
+        .collect();
+    (!pictures.is_empty()).then(|| pictures.into())
+}
+
+/// What a review of the art needs without opening the images: each picture's type, format,
+/// dimensions where they can be measured, size in bytes and SHA-256, for the `_art` key
+pub fn provenance(pictures: &[Picture]) -> Option<JsonValue> {
+    let entries: Vec<JsonValue> = pictures
+        .iter()
+        .map(|picture| {
+            let mut json = JsonValue::new_object();
+            json["type"] = type_name(picture.picture_type).into();
+            json["format"] = image::mime_type(&picture.data)
+                .unwrap_or(&picture.mime_type)
+                .into();
+            if let Some((width, height)) = image::dimensions(&picture.data) {
+                json["width"] = width.into();
+                json["height"] = height.into();
+            }
+            json["bytes"] = picture.data.len().into();
+            json["sha256"] = sha256::hex_digest(&picture.data).into();
+            json
+        })
+        .collect();
+    (!entries.is_empty()).then(|| entries.into())
+}
+
+/// The format of a picture as sniffed from its data, or as declared if it isn't recognised
+fn format(picture: &Picture) -> &str {
+    image::mime_type(&picture.data).unwrap_or(&picture.mime_type)
+}
+
+/// Where the main picture is extracted to: `art` for a JPEG, keeping the name sidecars have always
+/// had, and otherwise `art` with the extension of its format, such as `track.png`
+pub fn primary_path(art: &Path, picture: &Picture) -> PathBuf {
+    match format(picture) {
+        "image/jpeg" => art.to_owned(),
+        mime => art.with_extension(image::extension(mime)),
+    }
+}
+
+/// Where each picture is extracted to: the front cover, or failing that the first picture, to its
+/// [`primary_path`], and the rest beside it named by type, such as `track.back-cover.jpg`, each
+/// with the extension of the format its data is in
+pub fn sidecar_paths(art: &Path, pictures: &[Picture]) -> Vec<PathBuf> {
+    let stem = art.with_extension("").into_os_string();
+    let primary = pictures
+        .iter()
+        .position(|p| p.picture_type == PictureType::CoverFront)
+        .unwrap_or(0);
+    let Some(first) = pictures.get(primary) else {
+        return vec![];
+    };
+    let primary_path = primary_path(art, first);
+    let mut paths: Vec<PathBuf> = vec![primary_path.clone()];
+    let mut named = vec![];
+    for (i, picture) in pictures.iter().enumerate() {
+        if i == primary {
+            named.push(primary_path.clone());
+            continue;
+        }
+        let kind = type_name(picture.picture_type);
+        let ext = image::extension(format(picture));
+        let mut path = PathBuf::from(format!("{}.{kind}.{ext}", stem.to_string_lossy()));
+        let mut n = 1;
+        while paths.contains(&path) {
+            n += 1;
+            path = PathBuf::from(format!("{}.{kind}-{n}.{ext}", stem.to_string_lossy()));
+        }
+        paths.push(path.clone());
+        named.push(path);
+    }
+    named
+}
+
+/// Writes every picture to its sidecar and lists them under "APIC" in the JSON, which is to be
+/// written in `json_dir`, with their [`provenance`] under "_art"
+pub fn extract(
+    json: &mut JsonValue,
+    json_dir: &Path,
+    art: &Path,
+    pictures: &[Picture],
+) -> StrResult<()> {
+    if pictures.is_empty() {
+        return Ok(());
+    }
+    let mut entries = vec![];
+    for (picture, path) in pictures.iter().zip(sidecar_paths(art, pictures)) {
+        write_data_to_path(&path, &picture.data)?;
+        let file = match path.strip_prefix(json_dir) {
+            Ok(relative) => relative.to_owned(),
+            Err(_) => path.canonicalize().unwrap_or(path),
+        };
+        let mut entry = picture_to_json(picture);
+        entry["file"] = file.to_string_lossy().as_ref().into();
+        entries.push(entry);
+    }
+    json["APIC"] = entries.into();
+    if let Some(art) = provenance(pictures) {
+        json["_art"] = art;
+    }
+    Ok(())
+}
+
+fn read_file(reference: &str, base: &Path) -> StrResult<Vec<u8>> {
+    paranoid::check_reference(Path::new(reference))?;
+    let path = base.join(reference);
+    paranoid::check_path(&path)?;
+    std::fs::read(&path).map_err(|e| {
+        Error::Io(format!(
+            "Cannot read picture {}: {e}",
+            path.to_string_lossy()
+        ))
+    })
+}
+
+fn picture_from_json(entry: &JsonValue, base: &Path) -> StrResult<Picture> {
+    let data = match (entry["data"].as_str(), entry["file"].as_str()) {
+        (Some(data), _) => base64::decode(data)?,
+        (None, Some(file)) => read_file(file, base)?,
+        (None, None) => {
+            return Err(Error::Parse(
+                "Pictures need a file or their data as base64".to_owned(),
+            ))
+        }
+    };
+    let mime_type = match entry["mime_type"].as_str() {
+        Some(mime) => mime.to_owned(),
+        None => image::mime_type(&data).unwrap_or("image/jpeg").to_owned(),
+    };
+    Ok(Picture {
+        mime_type,
+        picture_type: match entry["type"].as_str() {
+            Some(name) => parse_type(name)?,
+            None => PictureType::CoverFront,
+        },
+        description: entry["description"].as_str().unwrap_or("").to_owned(),
+        data,
+    })
+}
+
+/// The pictures described by an "APIC" array, with files found relative to `base`
+pub fn from_json(json: &JsonValue, base: &Path) -> StrResult<Vec<Picture>> {
+    if !json.is_array() {
+        return Err(Error::Parse("APIC must be an array of pictures".to_owned()));
+    }
+    json.members()
+        .map(|entry| picture_from_json(entry, base).map_err(|e| e.context("Invalid picture")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn picture(picture_type: PictureType, mime_type: &str, data: Vec<u8>) -> Picture {
+        Picture {
+            mime_type: mime_type.to_owned(),
+            picture_type,
+            description: String::new(),
+            data,
+        }
+    }
+
+    #[test]
+    fn extensions_follow_the_data() {
+        let art = Path::new("dir/t.jpeg");
+        let names = |pictures: &[Picture]| -> Vec<String> {
+            sidecar_paths(art, pictures)
+                .iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect()
+        };
+        // Mislabelled pictures are named for what they hold, and unrecognised ones for their label
+        let pictures = [
+            picture(PictureType::CoverBack, "image/png", testing::jpeg()),
+            picture(PictureType::CoverFront, "image/jpeg", testing::png()),
+            picture(PictureType::Artist, "image/gif", b"not an image".to_vec()),
+        ];
+        assert_eq!(
+            names(&pictures),
+            ["dir/t.back-cover.jpg", "dir/t.png", "dir/t.artist.gif"]
+        );
+        let cover = [picture(PictureType::Other, "image/png", testing::jpeg())];
+        assert_eq!(names(&cover), ["dir/t.jpeg"]);
+        assert!(names(&[]).is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+//! Library policy: rules a batch of files must meet before its tags are written, loaded from a JSON file
+//!
+//! ```json
+//! {
+//!     "art": { "max_bytes": 1000000, "max_width": 1000, "max_height": 1000, "oversize": "downscale" },
+//!     "publish": { "strip": ["COMM", "PRIV", "TXXX:RIPPER"], "keep": ["POPM"] }
+//! }
+//! ```
+
+use crate::{image, pictures, Error, StrResult};
+use id3::frame::Picture;
+use id3::{Tag, TagLike};
+use json::JsonValue;
+use std::path::Path;
+
+/// What to do with embedded art that exceeds the limits
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Oversize {
+    #[default]
+    Reject,
+    Downscale,
+}
+
+#[derive(Clone, Default)]
+pub struct ArtPolicy {
+    pub max_bytes: Option<usize>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub oversize: Oversize,
+}
+
+/// Frames the publish profile strips unless the policy says otherwise: ripper notes, private
+/// application data, identifiers, embedded files and ratings keyed by email address
+const PUBLISH_STRIP: [&str; 5] = ["COMM", "PRIV", "UFID", "GEOB", "POPM"];
+
+/// The frames to remove before files are shared. Entries are frame IDs, or `TXXX:NAME` for a
+/// single user-defined text frame.
+#[derive(Clone)]
+pub struct PublishPolicy {
+    pub strip: Vec<String>,
+}
+
+impl Default for PublishPolicy {
+    fn default() -> Self {
+        PublishPolicy {
+            strip: PUBLISH_STRIP.iter().map(|id| (*id).to_owned()).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Policy {
+    pub art: ArtPolicy,
+    pub publish: PublishPolicy,
+}
+
+fn frame_list(section: &JsonValue, key: &str) -> StrResult<Option<Vec<String>>> {
+    match &section[key] {
+        JsonValue::Null => Ok(None),
+        JsonValue::Array(items) => items
+            .iter()
+            .map(|item| match item.as_str() {
+                Some(id) => Ok(id.to_owned()),
+                None => Err(Error::Parse(format!(
+                    "Policy value {key} must be a list of frame IDs"
+                ))),
+            })
+            .collect::<StrResult<_>>()
+            .map(Some),
+        _ => Err(Error::Parse(format!(
+            "Policy value {key} must be a list of frame IDs"
+        ))),
+    }
+}
+
+fn limit(section: &JsonValue, key: &str) -> StrResult<Option<u64>> {
+    match &section[key] {
+        JsonValue::Null => Ok(None),
+        value => match value.as_u64() {
+            Some(n) => Ok(Some(n)),
+            None => Err(Error::Parse(format!(
+                "Policy value {key} must be a positive integer"
+            ))),
+        },
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> StrResult<Policy> {
+        let json = crate::read_json_file(path)?;
+        let art = &json["art"];
+        let oversize = match art["oversize"].as_str() {
+            None | Some("reject") => Oversize::Reject,
+            Some("downscale") => Oversize::Downscale,
+            Some(other) => Err(Error::Parse(format!(
+                "Unknown oversize action \"{other}\", expected reject or downscale"
+            )))?,
+        };
+        let publish = &json["publish"];
+        let mut strip = frame_list(publish, "strip")?.unwrap_or(PublishPolicy::default().strip);
+        if let Some(keep) = frame_list(publish, "keep")? {
+            strip.retain(|id| !keep.contains(id));
+        }
+        Ok(Policy {
+            publish: PublishPolicy { strip },
+            art: ArtPolicy {
+                max_bytes: limit(art, "max_bytes")?.map(|n| n as usize),
+                max_width: limit(art, "max_width")?.map(|n| n as u32),
+                max_height: limit(art, "max_height")?.map(|n| n as u32),
+                oversize,
+            },
+        })
+    }
+}
+
+impl ArtPolicy {
+    /// Why the image breaks the policy, if it does
+    fn violation(&self, data: &[u8]) -> Option<String> {
+        if let Some(max) = self.max_bytes {
+            if data.len() > max {
+                return Some(format!("art is {} bytes, over the {max} limit", data.len()));
+            }
+        }
+        if self.max_width.is_none() && self.max_height.is_none() {
+            return None;
+        }
+        let Some((width, height)) = image::dimensions(data) else {
+            return Some("art dimensions cannot be determined".to_owned());
+        };
+        let too_wide = self.max_width.is_some_and(|max| width > max);
+        let too_tall = self.max_height.is_some_and(|max| height > max);
+        (too_wide || too_tall).then(|| format!("art is {width}x{height}, over the size limit"))
+    }
+
+    /// Returns the art to embed: unchanged if it's within the limits, downscaled if the policy
+    /// allows, or an error if it must be rejected
+    pub fn enforce(&self, data: Vec<u8>) -> StrResult<Vec<u8>> {
+        let Some(problem) = self.violation(&data) else {
+            return Ok(data);
+        };
+        if self.oversize == Oversize::Reject {
+            return Err(Error::Other(problem));
+        }
+        // Lower the quality until the size limit is met, if there is one
+        for quality in [85, 70, 55, 40] {
+            let scaled = self.downscale(&data, quality)?;
+            if self.violation(&scaled).is_none() {
+                return Ok(scaled);
+            }
+        }
+        Err(Error::Other(format!(
+            "{problem}, and downscaling could not bring it within the policy"
+        )))
+    }
+
+    /// Holds every picture in the tag to the policy, whether it came from the document or a sidecar,
+    /// replacing any that were downscaled
+    pub fn enforce_tag(&self, tag: &mut Tag) -> StrResult<()> {
+        let pictures: Vec<Picture> = tag
+            .pictures()
+            .filter(|p| self.violation(&p.data).is_some())
+            .cloned()
+            .collect();
+        for picture in pictures {
+            let kind = pictures::type_name(picture.picture_type);
+            let data = self
+                .enforce(picture.data)
+                .map_err(|e| e.context(&format!("The {kind} picture")))?;
+            tag.remove_picture_by_type(picture.picture_type);
+            tag.add_frame(Picture {
+                mime_type: image::mime_type(&data)
+                    .unwrap_or(&picture.mime_type)
+                    .to_owned(),
+                data,
+                ..picture
+            });
+        }
+        Ok(())
+    }
+
+    /// Re-encodes the image as a JPEG within the dimension limits using ImageMagick
+    fn downscale(&self, data: &[u8], quality: u32) -> StrResult<Vec<u8>> {
+        let geometry = match (self.max_width, self.max_height) {
+            (Some(w), Some(h)) => format!("{w}x{h}>"),
+            (Some(w), None) => format!("{w}x>"),
+            (None, Some(h)) => format!("x{h}>"),
+            (None, None) => "100%".to_owned(),
+        };
+        image::magick(
+            data,
+            &[
+                "-resize",
+                &geometry,
+                "-quality",
+                &quality.to_string(),
+                "jpg:-",
+            ],
+        )
+    }
+}
@@ -0,0 +1,92 @@
+//! Track and disc positions, stored in TRCK and TPOS as free-form text such as "3", "03/12" or
+//! "3 of 12". In JSON they're `{"number": 3, "total": 12}`, and they're written back as "3/12".
+
+use crate::{Error, StrResult};
+use json::JsonValue;
+
+/// The frames holding a position
+pub const FRAMES: [&str; 2] = ["TRCK", "TPOS"];
+
+/// A position's number and, if given, the total it's out of
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Position {
+    pub number: u32,
+    pub total: Option<u32>,
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.total {
+            Some(total) => write!(f, "{}/{total}", self.number),
+            None => write!(f, "{}", self.number),
+        }
+    }
+}
+
+fn parse_number(part: &str) -> Option<u32> {
+    let part = part.trim();
+    if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    part.parse().ok()
+}
+
+/// Parses "3", "03/12" or "3 of 12", allowing leading zeros and surrounding space. Anything
+/// else, including signs, separators for thousands and other scripts' digits, is rejected
+pub fn parse(text: &str) -> StrResult<Position> {
+    let malformed = || {
+        Error::Parse(format!(
+            "\"{text}\" is not a position such as 3, 3/12 or 3 of 12"
+        ))
+    };
+    let (number, total) = match text.split_once('/') {
+        Some((number, total)) => (number, Some(total)),
+        None => match text.split_once(" of ") {
+            Some((number, total)) => (number, Some(total)),
+            None => (text, None),
+        },
+    };
+    let number = parse_number(number).ok_or_else(malformed)?;
+    let total = match total {
+        Some(total) => Some(parse_number(total).ok_or_else(malformed)?),
+        None => None,
+    };
+    Ok(Position { number, total })
+}
+
+/// The JSON form of a frame's text. Text that doesn't parse is kept as it is, so nothing is lost
+pub fn to_json(text: &str) -> JsonValue {
+    let Ok(position) = parse(text) else {
+        return text.into();
+    };
+    let mut json = JsonValue::new_object();
+    json["number"] = position.number.into();
+    if let Some(total) = position.total {
+        json["total"] = total.into();
+    }
+    json
+}
+
+/// A position given in JSON as an object or as text in any form [`parse`] accepts
+pub fn from_json(value: &JsonValue) -> StrResult<Position> {
+    if let Some(text) = value.as_str() {
+        return parse(text);
+    }
+    let field = |name: &str| match &value[name] {
+        JsonValue::Null => Ok(None),
+        v => v.as_u32().map(Some).ok_or_else(|| {
+            Error::Parse(format!("Position {name} must be a whole number, not {v}"))
+        }),
+    };
+    if !value.is_object() {
+        return Err(Error::Parse(format!(
+            "Position must be an object or text, not {value}"
+        )));
+    }
+    let number =
+        field("number")?.ok_or_else(|| Error::Parse("Position has no number".to_owned()))?;
+    Ok(Position {
+        number,
+        total: field("total")?,
+    })
+}
@@ -0,0 +1,79 @@
+//! Capturing and restoring file system metadata across a tag rewrite
+//!
+//! Tags are always rewritten in place through the original file handle, so extended attributes
+//! (Finder tags, quarantine flags) and NTFS alternate data streams stay attached to the file. If
+//! the file on disk is ever replaced by a different one, [`FileState::restore`] warns that they
+//! may have been dropped.
+
+use crate::{Error, StrResult};
+use std::fs::{self, FileTimes, Metadata, OpenOptions};
+use std::path::Path;
+
+/// The metadata of an audio file as it was before its tags were rewritten
+pub struct FileState {
+    metadata: Metadata,
+}
+
+impl FileState {
+    pub fn capture(path: &Path) -> StrResult<FileState> {
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(FileState { metadata }),
+            Err(e) => Err(Error::Io(format!(
+                "Cannot stat {}: {e}",
+                path.to_string_lossy()
+            ))),
+        }
+    }
+
+    /// Puts back permissions and ownership if they changed, plus the access and modification times if requested
+    pub fn restore(&self, path: &Path, times: bool) -> StrResult<()> {
+        let err = |e: std::io::Error| {
+            Error::Io(format!(
+                "Cannot restore metadata of {}: {e}",
+                path.to_string_lossy()
+            ))
+        };
+        let current = fs::metadata(path).map_err(err)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if (current.dev(), current.ino()) != (self.metadata.dev(), self.metadata.ino()) {
+                eprintln!(
+                    "Warning: {} was replaced rather than rewritten in place; extended attributes may have been lost",
+                    path.to_string_lossy()
+                );
+            }
+        }
+
+        if current.permissions() != self.metadata.permissions() {
+            fs::set_permissions(path, self.metadata.permissions()).map_err(err)?;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if (current.uid(), current.gid()) != (self.metadata.uid(), self.metadata.gid()) {
+                std::os::unix::fs::chown(
+                    path,
+                    Some(self.metadata.uid()),
+                    Some(self.metadata.gid()),
+                )
+                .map_err(err)?;
+            }
+        }
+
+        if times {
+            let mut file_times = FileTimes::new();
+            if let Ok(modified) = self.metadata.modified() {
+                file_times = file_times.set_modified(modified);
+            }
+            if let Ok(accessed) = self.metadata.accessed() {
+                file_times = file_times.set_accessed(accessed);
+            }
+            let file = OpenOptions::new().write(true).open(path).map_err(err)?;
+            file.set_times(file_times).map_err(err)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! Filling in frames a set of files lacks from one reference file, typically album-level fields
+//! that were only tagged on the first track
+
+use crate::batch::{self, BatchReport, Cancel, Progress};
+use crate::{StrResult, WriteOpts};
+use id3::TagLike;
+use std::path::{Path, PathBuf};
+
+/// Copies each of `fields` from `source` into every file that has no frame with that ID. All
+/// frames of an ID are copied, so several pictures or comments carry over together. Each file
+/// filled in is reported with the frames it was given
+pub fn propagate(
+    source: &Path,
+    fields: &[String],
+    files: &[PathBuf],
+    opts: &WriteOpts,
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+) -> StrResult<BatchReport> {
+    let reference = match crate::read_tag(source) {
+        Ok(t) => t,
+        Err(e) => Err(e.context("Unable to open id3 file"))?,
+    };
+    let mut report = BatchReport::default();
+    for field in fields {
+        if reference.get(field).is_none() {
+            report.warn(source, format!("has no {field} frame to copy"));
+        }
+    }
+    let source = source.canonicalize().unwrap_or_else(|_| source.to_owned());
+
+    let cancelled = batch::for_each_file(files, cancel, progress, |file| {
+        if file.canonicalize().is_ok_and(|f| f == source) {
+            return;
+        }
+        let mut tag = match crate::read_tag_or_empty(file) {
+            Ok(t) => t,
+            Err(e) => {
+                report.fail(file, e);
+                return;
+            }
+        };
+        let missing: Vec<&String> = fields.iter().filter(|f| tag.get(f).is_none()).collect();
+        let mut copied = vec![];
+        for field in missing {
+            let frames: Vec<_> = reference.frames().filter(|f| f.id() == field).collect();
+            if frames.is_empty() {
+                return;
+            }
+            for frame in frames {
+                tag.add_frame(frame.clone());
+            }
+            copied.push(field.as_str());
+        }
+        if copied.is_empty() {
+            return;
+        }
+        match crate::write_tag(file, &tag, opts) {
+            Ok(()) => report.succeed(file, copied.join(", ")),
+            Err(e) => report.fail(file, e),
+        }
+    });
+    report.cancelled = cancelled;
+    Ok(report)
+}
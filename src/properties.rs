@@ -0,0 +1,394 @@
+//! Audio stream properties read from the first MPEG frame after the tag, including the
+//! Xing/Info and LAME headers written by most encoders.
+
+use crate::{Error, StrResult};
+use json::JsonValue;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// How far past the tag to look for the first frame sync before giving up
+const SYNC_SEARCH_LIMIT: usize = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+enum MpegVersion {
+    V1,
+    V2,
+    V25,
+}
+
+/// The parts of a LAME info tag that describe how a file was encoded
+pub struct LameHeader {
+    pub encoder: String,
+    pub vbr_method: &'static str,
+    pub lowpass: Option<u32>,
+    pub preset: Option<String>,
+}
+
+pub struct AudioProperties {
+    version: MpegVersion,
+    pub layer: u8,
+    /// Nominal bitrate in kbps, taken from the first frame header
+    pub bitrate: u32,
+    pub sample_rate: u32,
+    pub channel_mode: &'static str,
+    /// Duration in seconds, exact when a Xing/VBRI frame count is present and estimated otherwise
+    pub duration: Option<f64>,
+    pub lame: Option<LameHeader>,
+}
+
+const BITRATES_V1: [[u32; 16]; 3] = [
+    [
+        0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448, 0,
+    ],
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 0,
+    ],
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+    ],
+];
+const BITRATES_V2: [[u32; 16]; 3] = [
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256, 0,
+    ],
+    [
+        0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+    ],
+    [
+        0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+    ],
+];
+
+struct FrameHeader {
+    version: MpegVersion,
+    layer: u8,
+    bitrate: u32,
+    sample_rate: u32,
+    mono: bool,
+    channel_mode: &'static str,
+}
+
+impl FrameHeader {
+    fn parse(b: &[u8]) -> Option<FrameHeader> {
+        if b.len() < 4 || b[0] != 0xFF || b[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+        let version = match (b[1] >> 3) & 0x3 {
+            0 => MpegVersion::V25,
+            2 => MpegVersion::V2,
+            3 => MpegVersion::V1,
+            _ => return None,
+        };
+        let layer = match (b[1] >> 1) & 0x3 {
+            1 => 3,
+            2 => 2,
+            3 => 1,
+            _ => return None,
+        };
+        let index = (b[2] >> 4) as usize;
+        let bitrate = match version {
+            MpegVersion::V1 => BITRATES_V1[layer as usize - 1][index],
+            _ => BITRATES_V2[layer as usize - 1][index],
+        };
+        let base_rate = match (b[2] >> 2) & 0x3 {
+            0 => 44100,
+            1 => 48000,
+            2 => 32000,
+            _ => return None,
+        };
+        let sample_rate = match version {
+            MpegVersion::V1 => base_rate,
+            MpegVersion::V2 => base_rate / 2,
+            MpegVersion::V25 => base_rate / 4,
+        };
+        if bitrate == 0 {
+            return None;
+        }
+        let channel_mode = match b[3] >> 6 {
+            0 => "stereo",
+            1 => "joint stereo",
+            2 => "dual channel",
+            _ => "mono",
+        };
+        Some(FrameHeader {
+            version,
+            layer,
+            bitrate,
+            sample_rate,
+            mono: b[3] >> 6 == 3,
+            channel_mode,
+        })
+    }
+
+    fn samples_per_frame(&self) -> u32 {
+        match (self.layer, self.version) {
+            (1, _) => 384,
+            (3, MpegVersion::V2 | MpegVersion::V25) => 576,
+            _ => 1152,
+        }
+    }
+
+    /// Offset of the Xing/Info header from the start of the frame, which sits after the side information
+    fn xing_offset(&self) -> usize {
+        match (self.version == MpegVersion::V1, self.mono) {
+            (true, false) => 36,
+            (true, true) => 21,
+            (false, false) => 21,
+            (false, true) => 13,
+        }
+    }
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// Returns the offset of the audio data, skipping an ID3v2 tag (and its footer) if present
+fn audio_start(file: &mut File) -> std::io::Result<u64> {
+    let mut header = [0u8; 10];
+    file.seek(SeekFrom::Start(0))?;
+    if file.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+        return Ok(0);
+    }
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |acc, b| (acc << 7) | (*b as u64 & 0x7F));
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Ok(10 + size + footer)
+}
+
+fn vbr_method_name(method: u8) -> &'static str {
+    match method {
+        1 => "cbr",
+        2 => "abr",
+        3 => "vbr-rh",
+        4 => "vbr-mtrh",
+        5 => "vbr-mt",
+        8 => "cbr-2pass",
+        9 => "abr-2pass",
+        _ => "unknown",
+    }
+}
+
+fn preset_name(preset: u16) -> Option<String> {
+    let name = match preset {
+        0 => return None,
+        8..=320 => return Some(format!("ABR {preset}")),
+        410..=500 if preset.is_multiple_of(10) => return Some(format!("V{}", (500 - preset) / 10)),
+        1000 => "r3mix",
+        1001 => "standard",
+        1002 => "extreme",
+        1003 => "insane",
+        1004 => "standard/fast",
+        1005 => "extreme/fast",
+        1006 => "medium",
+        1007 => "medium/fast",
+        _ => return Some(format!("unknown ({preset})")),
+    };
+    Some(name.to_owned())
+}
+
+/// Parses the LAME extension that follows the Xing/Info fields
+fn parse_lame(b: &[u8]) -> Option<LameHeader> {
+    if b.len() < 36 || !b[0..9].iter().all(|c| c.is_ascii_graphic() || *c == b' ') {
+        return None;
+    }
+    let encoder = String::from_utf8_lossy(&b[0..9]).trim().to_owned();
+    if !["LAME", "L3.", "GOGO"]
+        .iter()
+        .any(|p| encoder.starts_with(p))
+    {
+        return None;
+    }
+    let lowpass = match b[10] {
+        0 => None,
+        n => Some(n as u32 * 100),
+    };
+    let preset = u16::from_be_bytes([b[26], b[27]]) & 0x7FF;
+    Some(LameHeader {
+        encoder,
+        vbr_method: vbr_method_name(b[9] & 0x0F),
+        lowpass,
+        preset: preset_name(preset),
+    })
+}
+
+/// Reads the stream properties of an MPEG audio file. Returns `None` if no frame sync could be found
+pub fn read_properties(path: &Path) -> StrResult<Option<AudioProperties>> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => Err(Error::Io(format!(
+            "Cannot open {}: {e}",
+            path.to_string_lossy()
+        )))?,
+    };
+    let io_err = |e: std::io::Error| Error::Io(format!("Cannot read audio stream: {e}"));
+    let start = audio_start(&mut file).map_err(io_err)?;
+    let file_len = file.metadata().map_err(io_err)?.len();
+
+    let mut buf = Vec::with_capacity(SYNC_SEARCH_LIMIT);
+    file.seek(SeekFrom::Start(start)).map_err(io_err)?;
+    (&mut file)
+        .take(SYNC_SEARCH_LIMIT as u64)
+        .read_to_end(&mut buf)
+        .map_err(io_err)?;
+
+    let Some((offset, header)) = (0..buf.len().saturating_sub(4))
+        .find_map(|i| FrameHeader::parse(&buf[i..]).map(|h| (i, h)))
+    else {
+        return Ok(None);
+    };
+    let frame = &buf[offset..];
+
+    let mut frame_count = None;
+    let mut lame = None;
+    let xing = header.xing_offset();
+    if frame.len() >= xing + 8 && matches!(&frame[xing..xing + 4], b"Xing" | b"Info") {
+        let flags = be_u32(&frame[xing + 4..]);
+        let mut pos = xing + 8;
+        if flags & 0x1 != 0 && frame.len() >= pos + 4 {
+            frame_count = Some(be_u32(&frame[pos..]));
+            pos += 4;
+        }
+        if flags & 0x2 != 0 {
+            pos += 4;
+        }
+        if flags & 0x4 != 0 {
+            pos += 100;
+        }
+        if flags & 0x8 != 0 {
+            pos += 4;
+        }
+        lame = frame.get(pos..).and_then(parse_lame);
+    } else if frame.len() >= 36 + 18 && &frame[36..40] == b"VBRI" {
+        frame_count = Some(be_u32(&frame[36 + 14..]));
+    }
+
+    let duration = match frame_count {
+        Some(frames) => {
+            Some(frames as f64 * header.samples_per_frame() as f64 / header.sample_rate as f64)
+        }
+        None => {
+            let audio_bytes = file_len.saturating_sub(start + offset as u64);
+            Some(audio_bytes as f64 * 8.0 / (header.bitrate as f64 * 1000.0))
+        }
+    };
+
+    Ok(Some(AudioProperties {
+        version: header.version,
+        layer: header.layer,
+        bitrate: header.bitrate,
+        sample_rate: header.sample_rate,
+        channel_mode: header.channel_mode,
+        duration,
+        lame,
+    }))
+}
+
+impl AudioProperties {
+    pub fn to_json(&self) -> JsonValue {
+        let mut json = JsonValue::new_object();
+        json["mpeg_version"] = match self.version {
+            MpegVersion::V1 => "1",
+            MpegVersion::V2 => "2",
+            MpegVersion::V25 => "2.5",
+        }
+        .into();
+        json["layer"] = self.layer.into();
+        json["bitrate"] = self.bitrate.into();
+        json["sample_rate"] = self.sample_rate.into();
+        json["channel_mode"] = self.channel_mode.into();
+        if let Some(duration) = self.duration {
+            json["duration"] = ((duration * 1000.0).round() / 1000.0).into();
+        }
+        if let Some(lame) = &self.lame {
+            json["encoder"] = lame.encoder.as_str().into();
+            json["vbr_method"] = lame.vbr_method.into();
+            if let Some(lowpass) = lame.lowpass {
+                json["lowpass"] = lowpass.into();
+            }
+            if let Some(preset) = &lame.preset {
+                json["preset"] = preset.as_str().into();
+            }
+        }
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use id3::{TagLike, Version};
+
+    /// A VBR file whose first frame carries a Xing header counting 100 frames and a LAME tag
+    fn xing_mp3() -> Vec<u8> {
+        let mut first = vec![0xFF, 0xFB, 0x90, 0x64];
+        first.resize(36, 0);
+        first.extend(b"Xing\0\0\0\x01");
+        first.extend(100u32.to_be_bytes());
+        let mut lame = b"LAME3.100".to_vec();
+        lame.extend([0x02, 190]);
+        lame.resize(26, 0);
+        lame.extend(1001u16.to_be_bytes());
+        lame.resize(36, 0);
+        first.extend(lame);
+        first.resize(417, 0);
+        first.extend(testing::mp3_audio());
+        first
+    }
+
+    #[test]
+    fn frame_headers_are_read() {
+        let path = testing::scratch_dir("properties").join("t.mp3");
+        testing::write_mp3(&path);
+        let mut tag = id3::Tag::new();
+        tag.set_title("Title");
+        tag.write_to_path(&path, Version::Id3v24).unwrap();
+
+        let properties = read_properties(&path).unwrap().unwrap();
+        let json = properties.to_json();
+        assert_eq!(json["mpeg_version"], "1");
+        assert_eq!(json["layer"], 3);
+        assert_eq!(json["bitrate"], 128);
+        assert_eq!(json["sample_rate"], 44100);
+        assert_eq!(json["channel_mode"], "joint stereo");
+        // Estimated from the bitrate over the audio alone, not the tag before it
+        assert_eq!(json["duration"], 0.104);
+        assert!(properties.lame.is_none());
+    }
+
+    #[test]
+    fn xing_and_lame_headers_are_read() {
+        let path = testing::scratch_dir("properties-xing").join("t.mp3");
+        std::fs::write(&path, xing_mp3()).unwrap();
+        let json = read_properties(&path).unwrap().unwrap().to_json();
+        assert_eq!(json["duration"], 2.612);
+        assert_eq!(json["encoder"], "LAME3.100");
+        assert_eq!(json["vbr_method"], "abr");
+        assert_eq!(json["lowpass"], 19000);
+        assert_eq!(json["preset"], "standard");
+    }
+
+    #[test]
+    fn damaged_files_are_handled() {
+        let dir = testing::scratch_dir("properties-damaged");
+        assert!(matches!(
+            read_properties(&dir.join("missing.mp3")),
+            Err(Error::Io(_))
+        ));
+        let path = dir.join("t.mp3");
+        std::fs::write(&path, b"ID3\x04\0\0\x7f\x7f\x7f\x7fnot audio").unwrap();
+        assert!(read_properties(&path).unwrap().is_none());
+
+        let data = xing_mp3();
+        testing::each_truncation(&path, &data, 500, |path, len| {
+            let properties = read_properties(path).unwrap();
+            assert_eq!(properties.is_some(), len > 4, "{len} bytes");
+        });
+        testing::each_corruption(&path, &data, 200, |path| {
+            let _ = read_properties(path).unwrap();
+        });
+    }
+}
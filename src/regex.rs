@@ -0,0 +1,488 @@
+//! A small regular expression engine, enough for searching tag values: literals, `.`, classes
+//! such as `[a-z]` and `\d`, anchors, groups, alternation and the usual quantifiers.
+//!
+//! Patterns are compiled to a program that is run over every possible match at once, one character
+//! at a time, as a Pike VM does. Matching never backtracks or recurses, so it takes time in
+//! proportion to the length of the text times the length of the program, whatever the pattern.
+
+use crate::{Error, StrResult};
+
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    Alt(Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, usize),
+}
+
+/// An instruction of a compiled pattern. Those that match a character move on to the next
+/// instruction at the next character
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    /// Continues only at the start of the text
+    Start,
+    /// Continues only at the end of the text
+    End,
+    /// Continues at both instructions
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+pub struct Regex {
+    program: Vec<Inst>,
+    ignore_case: bool,
+}
+
+struct Parser<'a> {
+    pattern: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize,
+}
+
+/// How deeply groups may nest, which bounds the recursion in parsing and compiling
+const MAX_DEPTH: usize = 64;
+
+/// The most instructions a pattern may compile to. Counted repetitions copy what they repeat, so
+/// this is what limits `{n}`
+const MAX_PROGRAM: usize = 10_000;
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+impl Parser<'_> {
+    fn error(&self, problem: &str) -> Error {
+        Error::Parse(format!("Invalid regex \"{}\": {problem}", self.pattern))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn alternatives(&mut self) -> StrResult<Vec<Vec<Node>>> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.eat('|') {
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> StrResult<Vec<Node>> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn escape(&mut self) -> StrResult<Node> {
+        let c = self
+            .peek()
+            .ok_or_else(|| self.error("trailing backslash"))?;
+        self.pos += 1;
+        Ok(match c {
+            'd' => Node::Class(DIGIT.to_vec(), false),
+            'D' => Node::Class(DIGIT.to_vec(), true),
+            'w' => Node::Class(WORD.to_vec(), false),
+            'W' => Node::Class(WORD.to_vec(), true),
+            's' => Node::Class(SPACE.to_vec(), false),
+            'S' => Node::Class(SPACE.to_vec(), true),
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            c => Node::Char(c),
+        })
+    }
+
+    fn class(&mut self) -> StrResult<Node> {
+        let negated = self.eat('^');
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("unclosed '['"))?;
+            self.pos += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let low = if c == '\\' {
+                match self.escape()? {
+                    Node::Class(set, false) => {
+                        ranges.extend(set);
+                        continue;
+                    }
+                    Node::Char(c) => c,
+                    _ => return Err(self.error("negated escapes can't be used in a class")),
+                }
+            } else {
+                c
+            };
+            let high = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                let high = self.peek().ok_or_else(|| self.error("unclosed '['"))?;
+                self.pos += 1;
+                if high < low {
+                    return Err(self.error("range out of order"));
+                }
+                high
+            } else {
+                low
+            };
+            ranges.push((low, high));
+        }
+        Ok(Node::Class(ranges, negated))
+    }
+
+    fn atom(&mut self) -> StrResult<Node> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end"))?;
+        self.pos += 1;
+        Ok(match c {
+            '(' => {
+                // Groups don't capture, so (?:...) means the same
+                if self.eat('?') && !self.eat(':') {
+                    return Err(self.error("unsupported group syntax"));
+                }
+                self.depth += 1;
+                if self.depth > MAX_DEPTH {
+                    return Err(self.error("groups nested too deeply"));
+                }
+                let alternatives = self.alternatives()?;
+                self.depth -= 1;
+                if !self.eat(')') {
+                    return Err(self.error("unclosed '('"));
+                }
+                Node::Alt(alternatives)
+            }
+            '[' => self.class()?,
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '\\' => self.escape()?,
+            '*' | '+' | '?' => return Err(self.error(&format!("nothing to repeat before '{c}'"))),
+            c => Node::Char(c),
+        })
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits.parse().ok()
+    }
+
+    fn quantified(&mut self, atom: Node) -> StrResult<Node> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, usize::MAX),
+            Some('+') => (1, usize::MAX),
+            Some('?') => (0, 1),
+            Some('{') => {
+                let start = self.pos;
+                self.pos += 1;
+                let Some(min) = self.number() else {
+                    // Not a repetition, so the brace is literal
+                    self.pos = start;
+                    return Ok(atom);
+                };
+                let max = if self.eat(',') {
+                    self.number().unwrap_or(usize::MAX)
+                } else {
+                    min
+                };
+                if self.peek() != Some('}') || max < min {
+                    return Err(self.error("malformed repetition"));
+                }
+                (min, max)
+            }
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        // Whether a match exists doesn't depend on laziness
+        self.eat('?');
+        Ok(Node::Repeat(Box::new(atom), min, max))
+    }
+}
+
+/// Turns the parsed pattern into instructions
+struct Compiler<'a> {
+    parser: &'a Parser<'a>,
+    program: Vec<Inst>,
+}
+
+impl Compiler<'_> {
+    fn push(&mut self, inst: Inst) -> StrResult<usize> {
+        if self.program.len() >= MAX_PROGRAM {
+            return Err(self
+                .parser
+                .error("too large, such as from a big repetition count"));
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    /// Points a placeholder jump or split at where the program has got to
+    fn patch(&mut self, at: usize, second: bool) {
+        let here = self.program.len();
+        match &mut self.program[at] {
+            Inst::Jump(to) => *to = here,
+            Inst::Split(_, to) if second => *to = here,
+            Inst::Split(to, _) => *to = here,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+
+    fn alternatives(&mut self, alternatives: &[Vec<Node>]) -> StrResult<()> {
+        let mut ends = vec![];
+        for (n, sequence) in alternatives.iter().enumerate() {
+            if n + 1 == alternatives.len() {
+                self.sequence(sequence)?;
+                break;
+            }
+            let split = self.push(Inst::Split(0, 0))?;
+            self.patch(split, false);
+            self.sequence(sequence)?;
+            ends.push(self.push(Inst::Jump(0))?);
+            self.patch(split, true);
+        }
+        for end in ends {
+            self.patch(end, false);
+        }
+        Ok(())
+    }
+
+    fn sequence(&mut self, nodes: &[Node]) -> StrResult<()> {
+        nodes.iter().try_for_each(|node| self.node(node))
+    }
+
+    fn node(&mut self, node: &Node) -> StrResult<()> {
+        let inst = match node {
+            Node::Char(c) => Inst::Char(*c),
+            Node::Any => Inst::Any,
+            Node::Class(ranges, negated) => Inst::Class(ranges.clone(), *negated),
+            Node::Start => Inst::Start,
+            Node::End => Inst::End,
+            Node::Alt(alternatives) => return self.alternatives(alternatives),
+            Node::Repeat(inner, min, max) => return self.repeat(inner, *min, *max),
+        };
+        self.push(inst).map(|_| ())
+    }
+
+    fn repeat(&mut self, inner: &Node, min: usize, max: usize) -> StrResult<()> {
+        for _ in 0..min {
+            self.node(inner)?;
+        }
+        if max == usize::MAX {
+            let split = self.push(Inst::Split(0, 0))?;
+            self.patch(split, false);
+            self.node(inner)?;
+            self.push(Inst::Jump(split))?;
+            self.patch(split, true);
+            return Ok(());
+        }
+        // Each optional copy may be skipped, which skips the rest as well
+        let mut skips = vec![];
+        for _ in min..max {
+            let split = self.push(Inst::Split(0, 0))?;
+            self.patch(split, false);
+            self.node(inner)?;
+            skips.push(split);
+        }
+        for split in skips {
+            self.patch(split, true);
+        }
+        Ok(())
+    }
+}
+
+/// The instructions waiting for the next character, each once
+struct Threads {
+    waiting: Vec<usize>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Threads {
+        Threads {
+            waiting: vec![],
+            seen: vec![false; len],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.waiting.clear();
+        self.seen.fill(false);
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str, ignore_case: bool) -> StrResult<Regex> {
+        let mut parser = Parser {
+            pattern,
+            chars: pattern.chars().collect(),
+            pos: 0,
+            depth: 0,
+        };
+        let alternatives = parser.alternatives()?;
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("unmatched ')'"));
+        }
+        let mut compiler = Compiler {
+            parser: &parser,
+            program: vec![],
+        };
+        compiler.alternatives(&alternatives)?;
+        compiler.push(Inst::Match)?;
+        Ok(Regex {
+            program: compiler.program,
+            ignore_case,
+        })
+    }
+
+    /// Whether the pattern matches anywhere in the text
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        for i in 0..=text.len() {
+            // A match may start at any character
+            if self.add(&mut current, 0, i, text.len()) {
+                return true;
+            }
+            let Some(&c) = text.get(i) else {
+                break;
+            };
+            for &pc in &current.waiting {
+                let matched = match &self.program[pc] {
+                    Inst::Char(expected) => self.same(c, *expected),
+                    Inst::Any => true,
+                    Inst::Class(ranges, negated) => self.in_class(c, ranges) != *negated,
+                    _ => false,
+                };
+                if matched && self.add(&mut next, pc + 1, i + 1, text.len()) {
+                    return true;
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        false
+    }
+
+    /// Adds the instructions that consume a character reachable from `pc` at position `i`,
+    /// following jumps and splits and checking anchors, returning whether the match is reached
+    fn add(&self, threads: &mut Threads, pc: usize, i: usize, len: usize) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if std::mem::replace(&mut threads.seen[pc], true) {
+                continue;
+            }
+            match &self.program[pc] {
+                Inst::Match => return true,
+                Inst::Jump(to) => stack.push(*to),
+                Inst::Split(first, second) => stack.extend([*second, *first]),
+                Inst::Start if i == 0 => stack.push(pc + 1),
+                Inst::End if i == len => stack.push(pc + 1),
+                Inst::Start | Inst::End => {}
+                _ => threads.waiting.push(pc),
+            }
+        }
+        false
+    }
+
+    fn same(&self, a: char, b: char) -> bool {
+        a == b || self.ignore_case && a.to_lowercase().eq(b.to_lowercase())
+    }
+
+    fn in_class(&self, c: char, ranges: &[(char, char)]) -> bool {
+        let within = |c: char| ranges.iter().any(|(low, high)| (*low..=*high).contains(&c));
+        within(c)
+            || self.ignore_case && (c.to_lowercase().any(within) || c.to_uppercase().any(within))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern, false).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn basics() {
+        assert!(matches("abc", "xxabcxx"));
+        assert!(!matches("^abc", "xabc"));
+        assert!(matches("abc$", "xabc"));
+        assert!(!matches("abc$", "abcx"));
+        assert!(matches("^$", ""));
+        assert!(matches("a.c", "abc"));
+        assert!(matches("[a-c]+x", "zzbacx"));
+        assert!(!matches("[^a-c]x", "ax"));
+        assert!(matches(r"\d{4}-\d{2}", "on 2021-07"));
+        assert!(!matches(r"^\d{4}$", "202"));
+        assert!(matches("^a{2,3}$", "aaa"));
+        assert!(!matches("^a{2,3}$", "aaaa"));
+        assert!(matches("^a{2,}$", "aaaaa"));
+        assert!(matches("^(cat|dog)s?$", "dogs"));
+        assert!(!matches("^(cat|dog)s?$", "cow"));
+        assert!(matches("^(?:ab)+$", "ababab"));
+        assert!(matches("x{y", "x{y"));
+        assert!(Regex::new("LIVE", true).unwrap().is_match("Live at Leeds"));
+        assert!(Regex::new("[A-Z]+", true).unwrap().is_match("é live"));
+    }
+
+    #[test]
+    fn empty_loops_end() {
+        assert!(matches("^(a?)*$", "aaa"));
+        assert!(matches("^(a*)*b$", "aab"));
+        assert!(matches("(|a)+z", "aaz"));
+        assert!(!matches("^(a?)*$", "aab"));
+    }
+
+    #[test]
+    fn nested_quantifiers_are_quick() {
+        let started = Instant::now();
+        let text = "a".repeat(28);
+        assert!(!matches("(a*)*b", &text));
+        assert!(!matches("^(a|aa)+$", &format!("{text}c")));
+        assert!(!matches("(a+)+(b+)+c", &format!("{text}{text}")));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn long_text() {
+        let text = "y".repeat(30_000);
+        assert!(!matches(".*z", &text));
+        assert!(matches(".*z", &format!("{text}z")));
+        assert!(matches("^y+$", &text));
+    }
+
+    #[test]
+    fn errors() {
+        for pattern in ["(", "a)", "[a", "*a", "a{3,1}", r"a\", "(?=a)", "[z-a]"] {
+            assert!(Regex::new(pattern, false).is_err(), "{pattern}");
+        }
+        assert!(Regex::new(&"(".repeat(100), false).is_err());
+        assert!(Regex::new("(a{100}){100}{100}", false).is_err());
+        assert!(Regex::new("a{1000}", false).is_ok());
+    }
+}
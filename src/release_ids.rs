@@ -0,0 +1,135 @@
+//! Release-level identifiers kept in TXXX frames under the descriptions MusicBrainz and beets
+//! use. In JSON they appear as `TXXX:BARCODE`, `TXXX:CATALOGNUMBER`, `TXXX:MusicBrainz Album Id`
+//! and so on, so they can sit at the top of an album document alongside the ordinary frames. The
+//! MusicBrainz recording ID, which Picard keeps in a UFID frame, is `UFID:http://musicbrainz.org`.
+
+use crate::{Error, StrResult};
+use id3::frame::{Content, ExtendedText, Frame, UniqueFileIdentifier};
+use id3::Tag;
+
+type Check = fn(&str) -> StrResult<()>;
+
+/// The TXXX descriptions understood, with the check each value must pass
+const IDENTIFIERS: [(&str, Check); 8] = [
+    ("BARCODE", check_barcode),
+    ("CATALOGNUMBER", check_catalog_number),
+    ("MusicBrainz Artist Id", check_musicbrainz_ids),
+    ("MusicBrainz Album Id", check_musicbrainz_ids),
+    ("MusicBrainz Album Artist Id", check_musicbrainz_ids),
+    ("MusicBrainz Release Group Id", check_musicbrainz_ids),
+    ("MusicBrainz Release Track Id", check_musicbrainz_ids),
+    ("MusicBrainz Work Id", check_musicbrainz_ids),
+];
+
+const KEY_PREFIX: &str = "TXXX:";
+
+/// The UFID owner MusicBrainz recording IDs are kept under
+const MUSICBRAINZ_OWNER: &str = "http://musicbrainz.org";
+
+/// The JSON key of the MusicBrainz recording ID
+const RECORDING_KEY: &str = "UFID:http://musicbrainz.org";
+
+/// A UPC or EAN barcode: 8, 12, 13 or 14 digits ending in a valid GS1 check digit
+fn check_barcode(value: &str) -> StrResult<()> {
+    let digits: Vec<u32> = value.chars().filter_map(|c| c.to_digit(10)).collect();
+    if digits.len() != value.len() || ![8, 12, 13, 14].contains(&digits.len()) {
+        return Err(Error::Parse(format!(
+            "Barcode \"{value}\" must be 8, 12, 13 or 14 digits"
+        )));
+    }
+    let (check, body) = digits.split_last().unwrap_or((&0, &[]));
+    // Weights alternate 3, 1, ... starting from the digit next to the check digit
+    let sum: u32 = body
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d })
+        .sum();
+    if (10 - sum % 10) % 10 != *check {
+        return Err(Error::Parse(format!(
+            "Barcode \"{value}\" has an incorrect check digit"
+        )));
+    }
+    Ok(())
+}
+
+fn check_catalog_number(value: &str) -> StrResult<()> {
+    if value.trim().is_empty() || value.chars().any(char::is_control) {
+        return Err(Error::Parse(format!(
+            "Catalog number \"{value}\" must be non-empty printable text"
+        )));
+    }
+    Ok(())
+}
+
+fn is_uuid(id: &str) -> bool {
+    let groups: Vec<&str> = id.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(g, len)| g.len() == len && g.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// One MusicBrainz ID, a UUID, or several separated as multiple artists' are
+fn check_musicbrainz_ids(value: &str) -> StrResult<()> {
+    match value.split(['\0', '/']).all(is_uuid) {
+        true => Ok(()),
+        false => Err(Error::Parse(format!(
+            "MusicBrainz ID \"{value}\" must be a UUID, or several separated by /"
+        ))),
+    }
+}
+
+/// The MusicBrainz recording ID a UFID frame holds, if it is one
+pub fn recording_id(ufid: &UniqueFileIdentifier) -> Option<&str> {
+    let id = std::str::from_utf8(&ufid.identifier).ok()?;
+    (ufid.owner_identifier == MUSICBRAINZ_OWNER && is_uuid(id)).then_some(id)
+}
+
+/// Whether a TXXX description is one of the identifiers, which have keys of their own
+pub fn is_identifier(description: &str) -> bool {
+    IDENTIFIERS
+        .iter()
+        .any(|(d, _)| description.eq_ignore_ascii_case(d))
+}
+
+/// The JSON keys and values of the tag's release identifiers
+pub fn extract(tag: &Tag) -> Vec<(String, String)> {
+    let texts = tag.extended_texts().filter_map(|t| {
+        let (description, _) = IDENTIFIERS
+            .iter()
+            .find(|(d, _)| t.description.eq_ignore_ascii_case(d))?;
+        Some((format!("{KEY_PREFIX}{description}"), t.value.clone()))
+    });
+    let recordings = tag
+        .unique_file_identifiers()
+        .filter_map(recording_id)
+        .map(|id| (RECORDING_KEY.to_owned(), id.to_owned()));
+    texts.chain(recordings).collect()
+}
+
+/// The frame for a `TXXX:` or `UFID:` JSON key, if it names a known identifier, after checking
+/// its value
+pub fn frame_for(key: &str, value: &str) -> Option<StrResult<Frame>> {
+    if key.eq_ignore_ascii_case(RECORDING_KEY) {
+        return Some(check_musicbrainz_ids(value).map(|()| {
+            let ufid = UniqueFileIdentifier {
+                owner_identifier: MUSICBRAINZ_OWNER.to_owned(),
+                identifier: value.as_bytes().to_vec(),
+            };
+            Frame::with_content("UFID", Content::UniqueFileIdentifier(ufid))
+        }));
+    }
+    let description = key.strip_prefix(KEY_PREFIX)?;
+    let (description, check) = IDENTIFIERS
+        .iter()
+        .find(|(d, _)| d.eq_ignore_ascii_case(description))?;
+    Some(check(value).map(|()| {
+        ExtendedText {
+            description: (*description).to_owned(),
+            value: value.to_owned(),
+        }
+        .into()
+    }))
+}
@@ -0,0 +1,201 @@
+//! Sorting a flat dump of files into a directory tree named from their tags, such as
+//! `Artist/Album/`, by the same templates check-paths validates
+
+use crate::batch::{self, BatchReport, Cancel, Progress};
+use crate::check_paths::{collision_key, component_problem, relative_path, Target};
+use crate::{manifest, sha256, Error, StrResult};
+use clap::ValueEnum;
+use id3::TagLike;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// How each file gets to its place in the tree
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Move the file, copying and deleting it when the destination is on another file system
+    Move,
+    /// Leave the file where it is and link to it from the tree
+    Symlink,
+    /// Copy the file, leaving the original
+    Copy,
+}
+
+/// What to do when a file's place is already taken, on disk or by another file in the run
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum Collision {
+    /// Leave the file where it is and report it
+    Skip,
+    /// Number the name, as in "Title (2).mp3"
+    Number,
+    /// Add the track number, as in "Title (track 3).mp3", numbering files without one
+    Track,
+    /// Add the start of the file's SHA-256, as in "Title (9f86d081).mp3", numbering identical files
+    Hash,
+    /// Ask on the terminal for another name, skipping the file if none is given
+    Prompt,
+}
+
+/// The path with " (suffix)" added before its extension
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem} ({suffix}).{}", ext.to_string_lossy()),
+        None => format!("{stem} ({suffix})"),
+    };
+    path.with_file_name(name)
+}
+
+/// What --on-collision track or hash adds to tell the file apart, if it has anything to add
+fn distinguishing_suffix(file: &Path, collision: Collision) -> StrResult<Option<String>> {
+    match collision {
+        Collision::Track => Ok(crate::read_tag(file)?.track().map(|n| format!("track {n}"))),
+        Collision::Hash => {
+            let data = std::fs::read(file)
+                .map_err(|e| Error::Io(format!("Cannot read {}: {e}", file.to_string_lossy())))?;
+            Ok(Some(sha256::hex_digest(&data)[..8].to_owned()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Asks for a name to give the file in place of the one taken, which keeps the file's extension
+/// if it's left off. None if the answer is empty or stdin has closed
+fn prompt_name(file: &Path, taken: &Path, target: Target) -> StrResult<Option<String>> {
+    loop {
+        eprint!(
+            "{} is already taken. Name for {} (empty to skip): ",
+            taken.to_string_lossy(),
+            file.to_string_lossy()
+        );
+        let _ = std::io::stderr().flush();
+        let mut answer = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut answer) {
+            return Err(Error::Io(format!("Cannot read a name from stdin: {e}")));
+        }
+        let mut name = answer.trim().to_owned();
+        if name.is_empty() {
+            return Ok(None);
+        }
+        if Path::new(&name).extension().is_none() {
+            if let Some(ext) = file.extension() {
+                name.push('.');
+                name.push_str(&ext.to_string_lossy());
+            }
+        }
+        match component_problem(&name, target) {
+            Some(problem) => eprintln!("{problem}"),
+            None => return Ok(Some(name)),
+        }
+    }
+}
+
+fn place(file: &Path, dest: &Path, action: Action) -> StrResult<()> {
+    let failed = |e: std::io::Error| {
+        Error::Io(format!(
+            "Cannot place {} at {}: {e}",
+            file.to_string_lossy(),
+            dest.to_string_lossy()
+        ))
+    };
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent).map_err(failed)?;
+    }
+    let how = match action {
+        Action::Move => "moved_from",
+        Action::Symlink => "linked_from",
+        Action::Copy => "copied_from",
+    };
+    let change = manifest::Change::begin_from(file, dest, how);
+    match action {
+        Action::Move => {
+            if std::fs::rename(file, dest).is_err() {
+                // Most likely another file system, where a rename can't work
+                std::fs::copy(file, dest).map_err(failed)?;
+                std::fs::remove_file(file).map_err(failed)?;
+            }
+        }
+        Action::Copy => {
+            std::fs::copy(file, dest).map_err(failed)?;
+        }
+        Action::Symlink => {
+            let target = file.canonicalize().map_err(failed)?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&target, dest).map_err(failed)?;
+            #[cfg(windows)]
+            std::os::windows::fs::symlink_file(&target, dest).map_err(failed)?;
+        }
+    }
+    change.finish();
+    Ok(())
+}
+
+/// Places each file at the path the template gives it under `dest`, reporting where each went.
+/// Files whose names would be invalid on the target are left where they are
+#[allow(clippy::too_many_arguments)]
+pub fn reorganize(
+    files: &[PathBuf],
+    template_str: &str,
+    dest: &Path,
+    action: Action,
+    collision: Collision,
+    target: Target,
+    dry_run: bool,
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+) -> BatchReport {
+    let key = |path: &Path| collision_key(path, target);
+    let mut claimed: HashSet<String> = HashSet::new();
+    let mut report = BatchReport::default();
+    let cancelled = batch::for_each_file(files, cancel, progress, |file| {
+        let result = relative_path(file, template_str).and_then(|(relative, components)| {
+            if let Some(problem) = components.iter().find_map(|c| component_problem(c, target)) {
+                return Err(Error::Other(problem));
+            }
+            let wanted = dest.join(relative);
+            if wanted.exists() && wanted.canonicalize().ok() == file.canonicalize().ok() {
+                return Ok(None);
+            }
+            let taken =
+                |path: &Path| Error::Other(format!("{} is already taken", path.to_string_lossy()));
+            // What numbering starts from, which track and hash first add their suffix to
+            let mut base = wanted.clone();
+            let mut path = wanted.clone();
+            let mut n = 1;
+            let mut suffix_tried = false;
+            while claimed.contains(&key(&path)) || path.symlink_metadata().is_ok() {
+                match collision {
+                    Collision::Skip => return Err(taken(&path)),
+                    Collision::Prompt => match prompt_name(file, &path, target)? {
+                        Some(name) => path = wanted.with_file_name(name),
+                        None => return Err(taken(&path)),
+                    },
+                    Collision::Track | Collision::Hash if !suffix_tried => {
+                        suffix_tried = true;
+                        // Without a suffix, the next time round numbers the name instead
+                        if let Some(suffix) = distinguishing_suffix(file, collision)? {
+                            base = suffixed(&wanted, &suffix);
+                            path = base.clone();
+                        }
+                    }
+                    _ => {
+                        n += 1;
+                        path = suffixed(&base, &n.to_string());
+                    }
+                }
+            }
+            claimed.insert(key(&path));
+            if !dry_run {
+                place(file, &path, action)?;
+            }
+            Ok(Some(path))
+        });
+        match result {
+            Ok(Some(path)) => report.succeed(file, format!("-> {}", path.to_string_lossy())),
+            Ok(None) => {}
+            Err(e) => report.fail(file, e),
+        }
+    });
+    report.cancelled = cancelled;
+    report
+}
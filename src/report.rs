@@ -0,0 +1,221 @@
+//! Library-wide statistics about the tags in a set of files
+
+use crate::{paths, properties, StrResult};
+use clap::ValueEnum;
+use id3::{Tag, TagLike};
+use json::JsonValue;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const MAX_EXAMPLES: usize = 3;
+const MAX_EXAMPLE_LEN: usize = 60;
+
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// A property of each file to count the distribution of
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Histogram {
+    Year,
+    Decade,
+    Genre,
+    Artist,
+    /// Nominal bitrate of the audio stream in kbps
+    Bitrate,
+    /// Languages from TLAN and the lyrics' language codes
+    Language,
+}
+
+const BAR_WIDTH: usize = 40;
+
+#[derive(Default)]
+struct FrameStats {
+    files: usize,
+    examples: Vec<String>,
+}
+
+/// The languages a track is in, as lowercase ISO 639-2 codes from TLAN and any lyrics. USLT's
+/// placeholder for an unknown language is left out.
+pub fn languages(tag: &Tag) -> Vec<String> {
+    let declared = tag.get("TLAN").and_then(|f| f.content().text());
+    let declared = declared.into_iter().flat_map(|t| t.split('\0'));
+    let lyrics = tag.lyrics().map(|l| l.lang.as_str());
+    let mut found: Vec<String> = vec![];
+    for code in declared.chain(lyrics) {
+        let code = code.trim().to_lowercase();
+        if !code.is_empty() && code != "xxx" && !found.contains(&code) {
+            found.push(code);
+        }
+    }
+    found
+}
+
+fn example_text(value: &str) -> String {
+    let value = value.replace('\0', " / ");
+    if value.chars().count() > MAX_EXAMPLE_LEN {
+        let truncated: String = value.chars().take(MAX_EXAMPLE_LEN).collect();
+        format!("{truncated}…")
+    } else {
+        value
+    }
+}
+
+/// Counts how many files carry each frame ID, keeping a few distinct example values of each
+fn frame_stats(tags: &[Tag]) -> BTreeMap<String, FrameStats> {
+    let mut stats: BTreeMap<String, FrameStats> = BTreeMap::new();
+    for tag in tags {
+        let mut seen = vec![];
+        for frame in tag.frames() {
+            let entry = stats.entry(frame.id().to_owned()).or_default();
+            if !seen.contains(&frame.id()) {
+                entry.files += 1;
+                seen.push(frame.id());
+            }
+            let example = example_text(&frame.content().to_string());
+            if entry.examples.len() < MAX_EXAMPLES && !entry.examples.contains(&example) {
+                entry.examples.push(example);
+            }
+        }
+    }
+    stats
+}
+
+fn print_frames(stats: BTreeMap<String, FrameStats>, total: usize, format: Format) {
+    let mut stats: Vec<_> = stats.into_iter().collect();
+    stats.sort_by(|a, b| b.1.files.cmp(&a.1.files).then(a.0.cmp(&b.0)));
+    match format {
+        Format::Text => {
+            println!("{total} files");
+            for (id, s) in stats {
+                println!("{id:<5} {:>7}  {}", s.files, s.examples.join(" | "));
+            }
+        }
+        Format::Json => {
+            let mut frames = JsonValue::new_object();
+            for (id, s) in stats {
+                let mut entry = JsonValue::new_object();
+                entry["files"] = s.files.into();
+                entry["examples"] = s.examples.into();
+                frames[id.as_str()] = entry;
+            }
+            let mut json = JsonValue::new_object();
+            json["files"] = total.into();
+            json["frames"] = frames;
+            println!("{}", json::stringify_pretty(json, 4));
+        }
+    }
+}
+
+/// Reads the tags of every file, warning about (and skipping) any that can't be read
+fn read_tags(files: &[PathBuf]) -> Vec<(PathBuf, Tag)> {
+    paths::collect_audio_files(files, true)
+        .into_iter()
+        .filter_map(|file| match crate::read_tag(&file) {
+            Ok(tag) => Some((file, tag)),
+            Err(e) => {
+                eprintln!("Could not handle {}: {e}", file.to_string_lossy());
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn frame_report(files: &[PathBuf], format: Format) -> StrResult<()> {
+    let tags: Vec<Tag> = read_tags(files).into_iter().map(|(_, t)| t).collect();
+    print_frames(frame_stats(&tags), tags.len(), format);
+    Ok(())
+}
+
+/// The values a file contributes to a histogram. Files can count towards several genres
+fn histogram_values(kind: Histogram, path: &Path, tag: &Tag) -> Vec<String> {
+    // TYER in v2.3 tags, TDRC in v2.4
+    let year = tag.year().or_else(|| tag.date_recorded().map(|d| d.year));
+    match kind {
+        Histogram::Year => year.map(|y| y.to_string()).into_iter().collect(),
+        Histogram::Decade => year
+            .map(|y| format!("{}s", y - y.rem_euclid(10)))
+            .into_iter()
+            .collect(),
+        Histogram::Genre => tag
+            .genres_parsed()
+            .into_iter()
+            .map(|g| g.into_owned())
+            .collect(),
+        Histogram::Artist => tag.artist().map(str::to_owned).into_iter().collect(),
+        Histogram::Bitrate => match properties::read_properties(path) {
+            Ok(Some(props)) => vec![props.bitrate.to_string()],
+            _ => vec![],
+        },
+        Histogram::Language => languages(tag),
+    }
+}
+
+/// Prints a text histogram, or returns it as JSON to be combined with the others
+fn render_histogram(
+    name: &str,
+    kind: Histogram,
+    counts: BTreeMap<String, usize>,
+    format: Format,
+) -> Option<JsonValue> {
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    match kind {
+        // Numeric keys read best in order, names by popularity
+        Histogram::Year | Histogram::Decade | Histogram::Bitrate => counts.sort_by(|a, b| {
+            let number = |s: &str| s.trim_end_matches('s').parse::<i64>().unwrap_or(i64::MAX);
+            number(&a.0).cmp(&number(&b.0))
+        }),
+        Histogram::Genre | Histogram::Artist | Histogram::Language => {
+            counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)))
+        }
+    }
+    match format {
+        Format::Text => {
+            println!("{name}:");
+            let max = counts.iter().map(|(_, c)| *c).max().unwrap_or(1);
+            let width = counts
+                .iter()
+                .map(|(v, _)| v.chars().count())
+                .max()
+                .unwrap_or(0);
+            for (value, count) in counts {
+                let bar = "#".repeat((count * BAR_WIDTH).div_ceil(max));
+                println!("  {value:<width$} {count:>6} {bar}");
+            }
+            None
+        }
+        Format::Json => {
+            let mut json = JsonValue::new_object();
+            for (value, count) in counts {
+                json[value.as_str()] = count.into();
+            }
+            Some(json)
+        }
+    }
+}
+
+pub fn histogram_report(files: &[PathBuf], kinds: &[Histogram], format: Format) -> StrResult<()> {
+    let tags = read_tags(files);
+    let mut combined = JsonValue::new_object();
+    for kind in kinds {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for (path, tag) in &tags {
+            for value in histogram_values(*kind, path, tag) {
+                *counts.entry(value).or_default() += 1;
+            }
+        }
+        let name = kind
+            .to_possible_value()
+            .map(|v| v.get_name().to_owned())
+            .unwrap_or_default();
+        if let Some(json) = render_histogram(&name, *kind, counts, format) {
+            combined[name.as_str()] = json;
+        }
+    }
+    if format == Format::Json {
+        println!("{}", json::stringify_pretty(combined, 4));
+    }
+    Ok(())
+}
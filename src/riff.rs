@@ -0,0 +1,349 @@
+//! WAV files and their big-endian AIFF counterparts, which carry ID3 tags in a chunk of their own.
+//! Files tagged only with a RIFF INFO list, or AIFF's NAME and AUTH chunks, are read from those.
+
+use crate::backend::Backend;
+use crate::sniff::{self, Format};
+use crate::{Error, StrResult};
+use id3::frame::Comment;
+use id3::{Tag, TagLike};
+use json::JsonValue;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// INFO list items with an ID3 equivalent
+const INFO_FIELDS: &[(&[u8; 4], &str)] = &[
+    (b"INAM", "TIT2"),
+    (b"IART", "TPE1"),
+    (b"IPRD", "TALB"),
+    (b"ICRD", "TDRC"),
+    (b"IGNR", "TCON"),
+    (b"ITRK", "TRCK"),
+    (b"IPRT", "TRCK"),
+    (b"ICOP", "TCOP"),
+    (b"ISFT", "TSSE"),
+    (b"ICMT", "COMM"),
+];
+
+/// AIFF's own text chunks
+const AIFF_FIELDS: &[(&[u8; 4], &str)] = &[
+    (b"NAME", "TIT2"),
+    (b"AUTH", "TPE1"),
+    (b"(c) ", "TCOP"),
+    (b"ANNO", "COMM"),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Container {
+    Wav,
+    Aiff,
+}
+
+fn container(path: &Path) -> Option<Container> {
+    match sniff::format(path)? {
+        Format::Wav => Some(Container::Wav),
+        Format::Aiff => Some(Container::Aiff),
+        _ => None,
+    }
+}
+
+struct Chunk<'a> {
+    id: [u8; 4],
+    data: &'a [u8],
+}
+
+/// The chunks of a file's outer RIFF or FORM chunk, in order
+fn chunks(data: &[u8], kind: Container) -> StrResult<Vec<Chunk<'_>>> {
+    let (magic, forms): (&[u8], &[&[u8]]) = match kind {
+        Container::Wav => (b"RIFF", &[b"WAVE"]),
+        Container::Aiff => (b"FORM", &[b"AIFF", b"AIFC"]),
+    };
+    if data.get(..4) != Some(magic) || !forms.iter().any(|f| data.get(8..12) == Some(f)) {
+        return Err(Error::Unsupported("Not a WAV or AIFF file".to_owned()));
+    }
+    Ok(sub_chunks(&data[12..], kind))
+}
+
+fn sub_chunks(mut data: &[u8], kind: Container) -> Vec<Chunk<'_>> {
+    let mut found = vec![];
+    while data.len() >= 8 {
+        let size: [u8; 4] = data[4..8].try_into().unwrap_or_default();
+        let size = match kind {
+            Container::Wav => u32::from_le_bytes(size),
+            Container::Aiff => u32::from_be_bytes(size),
+        } as usize;
+        let end = (8 + size).min(data.len());
+        found.push(Chunk {
+            id: data[..4].try_into().unwrap_or_default(),
+            data: &data[8..end],
+        });
+        // Chunks are padded to an even length
+        data = &data[(end + size % 2).min(data.len())..];
+    }
+    found
+}
+
+fn read_file(path: &Path) -> StrResult<(Vec<u8>, Container)> {
+    let kind = container(path).ok_or("Not a WAV or AIFF file")?;
+    let mut data = vec![];
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| Error::Io(format!("Cannot read {}: {e}", path.to_string_lossy())))?;
+    Ok((data, kind))
+}
+
+fn text(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_owned()
+}
+
+/// A tag built from the INFO list or AIFF text chunks, for files without an ID3 chunk
+fn fallback_tag(path: &Path) -> StrResult<Tag> {
+    let (data, kind) = read_file(path)?;
+    let mut items = vec![];
+    for chunk in chunks(&data, kind)? {
+        match kind {
+            Container::Wav if &chunk.id == b"LIST" && chunk.data.get(..4) == Some(b"INFO") => {
+                for item in sub_chunks(&chunk.data[4..], kind) {
+                    items.push((item.id, INFO_FIELDS, text(item.data)));
+                }
+            }
+            Container::Aiff => items.push((chunk.id, AIFF_FIELDS, text(chunk.data))),
+            Container::Wav => {}
+        }
+    }
+    let mut tag = Tag::new();
+    for (id, fields, value) in items {
+        let Some((_, frame)) = fields.iter().find(|(f, _)| **f == id) else {
+            continue;
+        };
+        if value.is_empty() || tag.get(frame).is_some() {
+            continue;
+        }
+        match *frame {
+            "COMM" => {
+                tag.add_frame(Comment {
+                    lang: "XXX".to_owned(),
+                    description: String::new(),
+                    text: value,
+                });
+            }
+            frame => tag.set_text(frame, value),
+        }
+    }
+    Ok(tag)
+}
+
+/// Reads the ID3 chunk, falling back to the file's native text fields if there isn't one
+fn read_tag(path: &Path) -> StrResult<Tag> {
+    // The id3 crate finds the chunk in either container by itself
+    match Tag::read_from_path(path) {
+        Ok(tag) => Ok(tag),
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => fallback_tag(path),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_tag(file: &mut File, tag: &Tag) -> StrResult<()> {
+    tag.write_to_file(file, id3::Version::Id3v24)
+        .map_err(|e| Error::Io(format!("Could not write tags: {e}")))
+}
+
+/// The length of the existing ID3 chunk with its header, or 0 if there is none
+fn tag_chunk_len(path: &Path) -> StrResult<u64> {
+    let (data, kind) = read_file(path)?;
+    let tag = chunks(&data, kind)?
+        .into_iter()
+        .find(|c| c.id.eq_ignore_ascii_case(b"id3 "));
+    Ok(tag.map_or(0, |c| 8 + c.data.len() as u64 + c.data.len() as u64 % 2))
+}
+
+/// An IEEE 754 80-bit extended float, as AIFF gives its sample rate
+fn extended(bytes: &[u8]) -> Option<f64> {
+    let bytes: [u8; 10] = bytes.get(..10)?.try_into().ok()?;
+    let exponent = i32::from(u16::from_be_bytes([bytes[0] & 0x7F, bytes[1]])) - 16383;
+    let mantissa = u64::from_be_bytes(bytes[2..].try_into().ok()?);
+    let sign = if bytes[0] & 0x80 != 0 { -1.0 } else { 1.0 };
+    Some(sign * mantissa as f64 * 2f64.powi(exponent - 63))
+}
+
+fn properties_json(path: &Path) -> StrResult<JsonValue> {
+    let (data, kind) = read_file(path)?;
+    let chunks = chunks(&data, kind)?;
+    let find = |id: &[u8; 4]| chunks.iter().find(|c| &c.id == id).map(|c| c.data);
+    let mut json = JsonValue::new_object();
+    let (channels, sample_rate, bits, duration) = match kind {
+        Container::Wav => {
+            json["format"] = "wav".into();
+            let Some(fmt) = find(b"fmt ").filter(|f| f.len() >= 16) else {
+                return Ok(json);
+            };
+            let u16_at = |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+            let u32_at =
+                |i: usize| u32::from_le_bytes(fmt[i..i + 4].try_into().unwrap_or_default());
+            let byte_rate = u32_at(8);
+            let duration = find(b"data")
+                .filter(|_| byte_rate > 0)
+                .map(|d| d.len() as f64 / byte_rate as f64);
+            (u16_at(2), f64::from(u32_at(4)), u16_at(14), duration)
+        }
+        Container::Aiff => {
+            json["format"] = "aiff".into();
+            let Some(comm) = find(b"COMM").filter(|c| c.len() >= 18) else {
+                return Ok(json);
+            };
+            let frames = u32::from_be_bytes(comm[2..6].try_into().unwrap_or_default());
+            let rate = extended(&comm[8..18]).unwrap_or(0.0);
+            let duration = (rate > 0.0).then(|| f64::from(frames) / rate);
+            let channels = u16::from_be_bytes([comm[0], comm[1]]);
+            (
+                channels,
+                rate,
+                u16::from_be_bytes([comm[6], comm[7]]),
+                duration,
+            )
+        }
+    };
+    json["sample_rate"] = (sample_rate.round() as u64).into();
+    json["channels"] = channels.into();
+    json["bits_per_sample"] = bits.into();
+    if let Some(duration) = duration {
+        json["duration"] = ((duration * 1000.0).round() / 1000.0).into();
+    }
+    Ok(json)
+}
+
+pub struct Riff;
+
+impl Backend for Riff {
+    fn handles(&self, format: Format) -> bool {
+        matches!(format, Format::Wav | Format::Aiff)
+    }
+
+    fn read_tag(&self, path: &Path) -> StrResult<Tag> {
+        read_tag(path)
+    }
+
+    fn write_tag(&self, _: &Path, file: &mut File, tag: &Tag, _: usize) -> StrResult<()> {
+        write_tag(file, tag)
+    }
+
+    fn properties(&self, path: &Path) -> StrResult<JsonValue> {
+        properties_json(path)
+    }
+
+    fn tag_sizes(&self, path: &Path, tag: &Tag) -> StrResult<(u64, u64)> {
+        let mut encoded = vec![];
+        if let Err(e) = tag.write_to(&mut encoded, id3::Version::Id3v24) {
+            return Err(Error::Other(format!("Could not encode tags: {e}")));
+        }
+        // The chunk header, and a pad byte if the tag has an odd length
+        let new_len = 8 + encoded.len() as u64 + encoded.len() as u64 % 2;
+        Ok((tag_chunk_len(path)?, new_len))
+    }
+
+    /// The `data` or `SSND` chunk holding the samples, without its header
+    fn audio_span(&self, path: &Path) -> StrResult<(u64, u64)> {
+        let (data, kind) = read_file(path)?;
+        let audio = chunks(&data, kind)?
+            .into_iter()
+            .find(|c| &c.id == b"data" || &c.id == b"SSND")
+            .ok_or("File has no audio chunk")?;
+        let start = (audio.data.as_ptr() as usize - data.as_ptr() as usize) as u64;
+        Ok((start, start + audio.data.len() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn chunk(id: &[u8; 4], data: &[u8], kind: Container) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend(match kind {
+            Container::Wav => (data.len() as u32).to_le_bytes(),
+            Container::Aiff => (data.len() as u32).to_be_bytes(),
+        });
+        out.extend(data);
+        if data.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    /// A WAV file of half a second of 8-bit mono at 8 kHz, titled by its INFO list
+    fn wav() -> Vec<u8> {
+        let mut fmt = vec![1, 0, 1, 0];
+        fmt.extend(8000u32.to_le_bytes());
+        fmt.extend(8000u32.to_le_bytes());
+        fmt.extend([1, 0, 8, 0]);
+        let mut info = b"INFO".to_vec();
+        info.extend(chunk(b"INAM", b"Info Title\0", Container::Wav));
+        let mut body = b"WAVE".to_vec();
+        body.extend(chunk(b"fmt ", &fmt, Container::Wav));
+        body.extend(chunk(b"LIST", &info, Container::Wav));
+        body.extend(chunk(b"data", &[0x80; 4000], Container::Wav));
+        chunk(b"RIFF", &body, Container::Wav)
+    }
+
+    /// An AIFF file of one second of 16-bit stereo at 44.1 kHz, titled by its NAME chunk
+    fn aiff() -> Vec<u8> {
+        let mut comm = vec![0, 2];
+        comm.extend(44100u32.to_be_bytes());
+        comm.extend([0, 16, 0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        let mut body = b"AIFF".to_vec();
+        body.extend(chunk(b"COMM", &comm, Container::Aiff));
+        body.extend(chunk(b"NAME", b"Name Title", Container::Aiff));
+        body.extend(chunk(b"SSND", &[0; 24], Container::Aiff));
+        chunk(b"FORM", &body, Container::Aiff)
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        let dir = testing::scratch_dir("riff-round-trip");
+        for (name, data, title, duration) in [
+            ("t.wav", wav(), "Info Title", 0.5),
+            ("t.aiff", aiff(), "Name Title", 1.0),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            assert_eq!(read_tag(&path).unwrap().title(), Some(title));
+            assert_eq!(Riff.properties(&path).unwrap()["duration"], duration);
+            let (start, end) = Riff.audio_span(&path).unwrap();
+
+            crate::write_tag(&path, &testing::tag(), &crate::WriteOpts::default()).unwrap();
+            testing::assert_round_trip(&crate::read_tag(&path).unwrap());
+            assert_eq!(Riff.properties(&path).unwrap()["duration"], duration);
+            let (_, new_len) = Riff.tag_sizes(&path, &testing::tag()).unwrap();
+            assert_eq!(tag_chunk_len(&path).unwrap(), new_len);
+            let (new_start, new_end) = Riff.audio_span(&path).unwrap();
+            assert_eq!(new_end - new_start, end - start);
+        }
+    }
+
+    #[test]
+    fn damaged_files_are_errors() {
+        let dir = testing::scratch_dir("riff-damaged");
+        for (name, data) in [("t.wav", wav()), ("t.aiff", aiff())] {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            crate::write_tag(&path, &testing::tag(), &crate::WriteOpts::default()).unwrap();
+            let data = std::fs::read(&path).unwrap();
+            // Chunks are read as far as they go, so only a missing header is certain to fail
+            testing::each_truncation(&path, &data, data.len(), |path, len| {
+                let result = (read_tag(path), properties_json(path));
+                if len < 12 {
+                    assert!(result.0.is_err() && result.1.is_err(), "{len} bytes");
+                }
+            });
+            testing::each_corruption(&path, &data, 200, |path| {
+                let _ = read_tag(path);
+                let _ = properties_json(path);
+            });
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Edits made to every file whose tag and path meet a rule's conditions, in place of the one-off
+//! scripts a library otherwise collects. Rules are a JSON array, applied in order:
+//!
+//! ```json
+//! [
+//!     { "if": { "TCON": { "empty": true }, "path": { "contains": "/Podcasts/" } },
+//!       "set": { "TCON": "Podcast" } },
+//!     { "if": { "TPE2": "Various Artists" }, "set": { "TCMP": "1" }, "remove": ["TSO2"] }
+//! ]
+//! ```
+//!
+//! Each key of `if` is a frame ID, `TXXX:DESCRIPTION`, or `path` for the file's path. Its test is
+//! a string the value must equal, or an object of `empty` (true or false), `equals`, `contains` or
+//! `matches`, a regular expression, all of which must pass. A rule without `if` applies to every
+//! file. Values set may hold `{FRAME}` placeholders, filled in from the tag as it stands.
+
+use crate::batch::{self, BatchReport, Cancel, Progress};
+use crate::regex::Regex;
+use crate::{frames, migrate, scrub, template, Error, StrResult, WriteOpts};
+use id3::{Frame, Tag, TagLike};
+use json::JsonValue;
+use std::path::{Path, PathBuf};
+
+enum Test {
+    Empty(bool),
+    Equals(String),
+    Contains(String),
+    Matches(Regex),
+}
+
+impl Test {
+    fn passes(&self, value: Option<&str>) -> bool {
+        let value = value.unwrap_or("");
+        match self {
+            Test::Empty(empty) => value.trim().is_empty() == *empty,
+            Test::Equals(expected) => value == expected,
+            Test::Contains(part) => value.contains(part.as_str()),
+            Test::Matches(regex) => regex.is_match(value),
+        }
+    }
+}
+
+struct Rule {
+    conditions: Vec<(String, Vec<Test>)>,
+    set: Vec<(String, String)>,
+    remove: Vec<String>,
+}
+
+/// Checks a key names a frame that can be set or removed
+fn checked_key(key: &str) -> StrResult<String> {
+    if key.strip_prefix("TXXX:").is_some_and(|d| !d.is_empty()) {
+        return Ok(key.to_owned());
+    }
+    migrate::checked_id(key).ok_or_else(|| Error::Parse(format!("\"{key}\" is not a frame ID")))
+}
+
+fn text(value: &JsonValue, what: &str) -> StrResult<String> {
+    value
+        .as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| Error::Parse(format!("{what} must be text")))
+}
+
+fn tests_from_json(key: &str, json: &JsonValue) -> StrResult<Vec<Test>> {
+    if let Some(expected) = json.as_str() {
+        return Ok(vec![Test::Equals(expected.to_owned())]);
+    }
+    if !json.is_object() {
+        return Err(Error::Parse(format!(
+            "The test of \"{key}\" must be text or an object"
+        )));
+    }
+    json.entries()
+        .map(|(name, value)| match name {
+            "empty" => value
+                .as_bool()
+                .map(Test::Empty)
+                .ok_or_else(|| Error::Parse("\"empty\" must be true or false".to_owned())),
+            "equals" => Ok(Test::Equals(text(value, "\"equals\"")?)),
+            "contains" => Ok(Test::Contains(text(value, "\"contains\"")?)),
+            "matches" => Ok(Test::Matches(Regex::new(
+                &text(value, "\"matches\"")?,
+                false,
+            )?)),
+            _ => Err(Error::Parse(format!(
+                "Unknown test \"{name}\" on \"{key}\""
+            ))),
+        })
+        .collect()
+}
+
+fn rule_from_json(json: &JsonValue) -> StrResult<Rule> {
+    if !json.is_object() {
+        return Err(Error::Parse("A rule must be an object".to_owned()));
+    }
+    if let Some((key, _)) = json
+        .entries()
+        .find(|(k, _)| !["if", "set", "remove"].contains(k))
+    {
+        return Err(Error::Parse(format!("Unknown key \"{key}\"")));
+    }
+    let mut conditions = vec![];
+    for (key, test) in json["if"].entries() {
+        let key = match key {
+            "path" => key.to_owned(),
+            key => checked_key(key)?,
+        };
+        conditions.push((key.clone(), tests_from_json(&key, test)?));
+    }
+    let mut set = vec![];
+    for (key, value) in json["set"].entries() {
+        set.push((
+            checked_key(key)?,
+            text(value, &format!("The value set for {key}"))?,
+        ));
+    }
+    let mut remove = vec![];
+    for key in json["remove"].members() {
+        remove.push(checked_key(&text(key, "Each frame to remove")?)?);
+    }
+    if set.is_empty() && remove.is_empty() {
+        return Err(Error::Parse(
+            "A rule must set or remove something".to_owned(),
+        ));
+    }
+    Ok(Rule {
+        conditions,
+        set,
+        remove,
+    })
+}
+
+/// A rules file, ready to be applied to tags
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn from_json(json: &JsonValue) -> StrResult<Rules> {
+        if !json.is_array() {
+            return Err(Error::Parse("Rules must be an array of objects".to_owned()));
+        }
+        let rules = json
+            .members()
+            .enumerate()
+            .map(|(n, rule)| {
+                rule_from_json(rule).map_err(|e| e.context(&format!("Rule {}", n + 1)))
+            })
+            .collect::<StrResult<_>>()?;
+        Ok(Rules { rules })
+    }
+
+    pub fn load(path: &Path) -> StrResult<Rules> {
+        Rules::from_json(&crate::read_json_file(path)?)
+    }
+
+    /// Applies each rule whose conditions the tag and path meet, returning the keys of the frames
+    /// set or removed
+    pub fn apply(&self, tag: &mut Tag, path: &Path) -> StrResult<Vec<String>> {
+        let mut changed: Vec<String> = vec![];
+        for rule in &self.rules {
+            let meets = rule.conditions.iter().all(|(key, tests)| {
+                let value = match key.as_str() {
+                    "path" => Some(path.to_string_lossy().into_owned()),
+                    key => template::frame_text(tag, key),
+                };
+                tests.iter().all(|test| test.passes(value.as_deref()))
+            });
+            if !meets {
+                continue;
+            }
+            let mut touched = scrub::scrub_tag(tag, &rule.remove);
+            for (key, value) in &rule.set {
+                let value = template::render(value, |k| template::frame_text(tag, k))?;
+                if template::frame_text(tag, key).as_deref() == Some(value.as_str()) {
+                    continue;
+                }
+                let frame = match frames::user_defined(key, &value) {
+                    Some(frame) => frame,
+                    None if key.starts_with('W') => Frame::link(key, value),
+                    None => Frame::text(key, value),
+                };
+                if key.starts_with("TXXX:") {
+                    scrub::scrub_tag(tag, std::slice::from_ref(key));
+                }
+                tag.add_frame(frame);
+                touched.push(key.clone());
+            }
+            for key in touched {
+                if !changed.contains(&key) {
+                    changed.push(key);
+                }
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// Applies the rules to every file in place, reporting each file changed with the frames set or
+/// removed
+pub fn apply_rules(
+    files: &[PathBuf],
+    rules: &Rules,
+    opts: &WriteOpts,
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+) -> BatchReport {
+    let mut report = BatchReport::default();
+    let cancelled = batch::for_each_file(files, cancel, progress, |file| {
+        let result = crate::read_tag_or_empty(file).and_then(|mut tag| {
+            let changed = rules.apply(&mut tag, file)?;
+            if !changed.is_empty() {
+                crate::write_tag(file, &tag, opts)?;
+            }
+            Ok(changed)
+        });
+        match result {
+            Ok(changed) if changed.is_empty() => {}
+            Ok(changed) => report.succeed(file, changed.join(", ")),
+            Err(e) => report.fail(file, e),
+        }
+    });
+    report.cancelled = cancelled;
+    report
+}
@@ -0,0 +1,73 @@
+//! Removing frames that shouldn't leave the machine, per a profile and the policy file
+
+use crate::batch::{self, BatchReport, Cancel, Progress};
+use crate::policy::Policy;
+use crate::{ignore, WriteOpts};
+use clap::ValueEnum;
+use id3::{Content, Frame, Tag, TagLike};
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Profile {
+    /// Strip private frames, as listed under "publish" in the policy file
+    Publish,
+}
+
+/// Removes the frames the profile strips, returning the keys of those removed
+pub fn scrub_tag(tag: &mut Tag, strip: &[String]) -> Vec<String> {
+    let mut removed = vec![];
+    let kept: Vec<Frame> = tag
+        .frames()
+        .filter(|frame| {
+            let strip = strip.iter().any(|entry| ignore::matches(frame, entry));
+            let key = match frame.content() {
+                Content::ExtendedText(t) => format!("TXXX:{}", t.description),
+                _ => frame.id().to_owned(),
+            };
+            if strip && !removed.contains(&key) {
+                removed.push(key);
+            }
+            !strip
+        })
+        .cloned()
+        .collect();
+    if !removed.is_empty() {
+        let mut scrubbed = Tag::with_version(tag.version());
+        for frame in kept {
+            scrubbed.add_frame(frame);
+        }
+        *tag = scrubbed;
+    }
+    removed
+}
+
+/// Strips the profile's frames from every file in place, reporting each file changed and what went
+pub fn scrub(
+    files: &[PathBuf],
+    profile: Profile,
+    policy: &Policy,
+    opts: &WriteOpts,
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+) -> BatchReport {
+    let strip = match profile {
+        Profile::Publish => &policy.publish.strip,
+    };
+    let mut report = BatchReport::default();
+    let cancelled = batch::for_each_file(files, cancel, progress, |file| {
+        let result = crate::read_tag(file).and_then(|mut tag| {
+            let removed = scrub_tag(&mut tag, strip);
+            if !removed.is_empty() {
+                crate::write_tag(file, &tag, opts)?;
+            }
+            Ok(removed)
+        });
+        match result {
+            Ok(removed) if removed.is_empty() => {}
+            Ok(removed) => report.succeed(file, format!("removed {}", removed.join(", "))),
+            Err(e) => report.fail(file, e),
+        }
+    });
+    report.cancelled = cancelled;
+    report
+}
@@ -0,0 +1,76 @@
+//! Splitting an aggregate document across several files, for libraries too big to handle as one
+
+use crate::{frames, slug, Error, StrResult};
+use clap::ValueEnum;
+use json::JsonValue;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// How entries are grouped into shards
+#[derive(ValueEnum, Clone, Copy)]
+pub enum ShardBy {
+    /// The album artist, or the artist if there isn't one
+    Artist,
+    /// The first letter or digit of the artist, with everything else under "other"
+    FirstLetter,
+    /// The directory the file is in
+    Dir,
+}
+
+/// The artist of an entry, which for an album document may only be given per track
+fn artist(entry: &JsonValue) -> Option<&str> {
+    let own = ["TPE2", "TPE1"]
+        .iter()
+        .find_map(|k| Some(frames::text_values(&entry[*k])?[0]));
+    match entry["tracks"].members().next() {
+        Some(track) => own.or_else(|| artist(track)),
+        None => own,
+    }
+}
+
+fn shard_name(by: ShardBy, key: &str, entry: &JsonValue) -> String {
+    let name = match by {
+        ShardBy::Artist => slug::slugify(artist(entry).unwrap_or_default()),
+        ShardBy::FirstLetter => slug::slugify(artist(entry).unwrap_or_default())
+            .chars()
+            .next()
+            .map(String::from)
+            .unwrap_or_default(),
+        // Album documents are keyed by their directory already
+        ShardBy::Dir if entry.has_key("tracks") => slug::slugify(key),
+        ShardBy::Dir => slug::slugify(
+            &Path::new(key)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .to_string_lossy(),
+        ),
+    };
+    match name.is_empty() {
+        true => "other".to_owned(),
+        false => name,
+    }
+}
+
+/// Writes the aggregate's entries into one file per shard in `dir`, named after the shard. The
+/// entries are looked up in `canonical`, a copy of the aggregate with frame IDs as keys.
+pub fn write_shards(
+    blob: JsonValue,
+    canonical: &JsonValue,
+    by: ShardBy,
+    dir: &Path,
+) -> StrResult<()> {
+    let mut shards: BTreeMap<String, JsonValue> = BTreeMap::new();
+    for (key, entry) in blob.entries() {
+        let name = shard_name(by, key, &canonical[key]);
+        let shard = shards.entry(name).or_insert_with(JsonValue::new_object);
+        shard[key] = entry.clone();
+    }
+    std::fs::create_dir_all(dir)
+        .map_err(|e| Error::Io(format!("Cannot create {}: {e}", dir.to_string_lossy())))?;
+    for (name, shard) in shards {
+        let path = dir.join(format!("{name}.json"));
+        println!("{} ({} entries)", path.to_string_lossy(), shard.len());
+        crate::write_data_to_path(&path, json::stringify_pretty(shard, 4).as_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! URL- and email-safe slugs of titles and artists, for publishing pipelines that build a page per track
+
+use crate::compose::COMPOSITIONS;
+use crate::frames;
+use id3::frame::ExtendedText;
+use id3::{Tag, TagLike};
+use json::JsonValue;
+
+/// The frames slugs are derived from, with the TXXX description and JSON key each is stored under
+const SLUG_FIELDS: [(&str, &str, &str); 2] = [
+    ("TIT2", "TITLE_SLUG", "title"),
+    ("TPE1", "ARTIST_SLUG", "artist"),
+];
+
+/// Strips accents by undoing canonical composition, so "é" becomes "e"
+fn base_letter(c: char) -> char {
+    let mut c = c;
+    while let Some(&(base, _, _)) = COMPOSITIONS.iter().find(|(_, _, composed)| *composed == c) {
+        c = base;
+    }
+    c
+}
+
+/// Lowercase ASCII letters and digits separated by single hyphens. Letters with no ASCII
+/// equivalent are dropped, so the slug can be empty
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    let mut pending_hyphen = false;
+    for c in text.chars() {
+        let ascii = match base_letter(c) {
+            'ß' => "ss".to_owned(),
+            'æ' | 'Æ' => "ae".to_owned(),
+            'œ' | 'Œ' => "oe".to_owned(),
+            'ø' | 'Ø' => "o".to_owned(),
+            'đ' | 'Đ' | 'ð' | 'Ð' => "d".to_owned(),
+            'ł' | 'Ł' => "l".to_owned(),
+            'þ' | 'Þ' => "th".to_owned(),
+            '&' => "and".to_owned(),
+            // Apostrophes join rather than split words: "don't" becomes "dont"
+            '\'' | '’' => continue,
+            c if c.is_ascii_alphanumeric() => c.to_ascii_lowercase().to_string(),
+            c if c.is_alphanumeric() => continue,
+            _ => {
+                pending_hyphen = !slug.is_empty();
+                continue;
+            }
+        };
+        if pending_hyphen {
+            slug.push('-');
+            pending_hyphen = false;
+        }
+        slug.push_str(&ascii);
+    }
+    slug
+}
+
+/// The slugs of a tag as (description, key, slug), omitting any that would be empty
+fn slugs(text_of: impl Fn(&str) -> Option<String>) -> Vec<(&'static str, &'static str, String)> {
+    SLUG_FIELDS
+        .iter()
+        .filter_map(|(frame, description, key)| {
+            let slug = slugify(&text_of(frame)?);
+            (!slug.is_empty()).then_some((*description, *key, slug))
+        })
+        .collect()
+}
+
+/// Stores slugs of the title and artist in TXXX frames, replacing any already there
+pub fn add_slug_frames(tag: &mut Tag) {
+    let text_of = |id: &str| tag.get(id)?.content().text().map(str::to_owned);
+    for (description, _, value) in slugs(text_of) {
+        tag.remove_extended_text(Some(description), None);
+        tag.add_frame(ExtendedText {
+            description: description.to_owned(),
+            value,
+        });
+    }
+}
+
+/// The slugs of an extracted tag as an informational object, for pipelines that only read the JSON
+pub fn slug_json(json: &JsonValue) -> JsonValue {
+    let mut out = JsonValue::new_object();
+    for (_, key, slug) in slugs(|id| frames::joined_text(&json[id], "\0")) {
+        out[key] = slug.into();
+    }
+    out
+}
@@ -0,0 +1,142 @@
+//! Whole-library snapshots of extracted tags, and comparing tags against an earlier snapshot
+
+use crate::batch::{self, BatchReport, Cancel, Progress};
+use crate::report::Format;
+use crate::{ignore, paths, Error, StrResult};
+use json::JsonValue;
+use std::path::{Path, PathBuf};
+
+/// Finds a file's entry in a snapshot. An aggregate snapshot is keyed by path; anything else is
+/// taken to be the file's own earlier sidecar.
+pub fn entry_for<'a>(snapshot: &'a JsonValue, path: &Path) -> &'a JsonValue {
+    let (key, _) = paths::json_key(path);
+    if snapshot.has_key(&key) && snapshot[key.as_str()].is_object() {
+        &snapshot[key.as_str()]
+    } else {
+        snapshot
+    }
+}
+
+/// Returns only the frames of `current` that differ from `previous`, with frames that have since
+/// been removed given as null. Informational underscore keys are left out.
+pub fn changed_frames(current: &JsonValue, previous: &JsonValue) -> JsonValue {
+    let mut patch = JsonValue::new_object();
+    for (key, value) in current.entries() {
+        if !key.starts_with('_') && previous[key] != *value {
+            patch[key] = value.clone();
+        }
+    }
+    for (key, _) in previous.entries() {
+        if !key.starts_with('_') && !current.has_key(key) {
+            patch[key] = JsonValue::Null;
+        }
+    }
+    patch
+}
+
+/// Extracts every file into one aggregate object keyed by path, without writing any sidecars,
+/// along with the report of which files couldn't be. If cancelled, the files extracted so far are
+/// returned
+pub fn snapshot(
+    files: &[PathBuf],
+    cancel: &Cancel,
+    progress: &dyn Fn(Progress),
+) -> (JsonValue, BatchReport) {
+    let mut blob = JsonValue::new_object();
+    let mut report = BatchReport::default();
+    report.cancelled =
+        batch::for_each_file(
+            files,
+            cancel,
+            progress,
+            |file| match crate::extract_tags_pic(file) {
+                Ok((json, _)) => {
+                    crate::insert_keyed(&mut blob, file, json);
+                    report.succeed(file, "");
+                }
+                Err(e) => report.fail(file, e),
+            },
+        );
+    (blob, report)
+}
+
+fn quoted(value: &JsonValue) -> String {
+    if value.is_null() {
+        "(none)".to_owned()
+    } else {
+        value.dump()
+    }
+}
+
+/// Compares two aggregate snapshots, listing the files added and removed and, for files in both,
+/// each frame whose value changed. Frames named in `ignore` aren't compared
+pub fn diff(old: &JsonValue, new: &JsonValue, ignore: &[String], format: Format) -> StrResult<()> {
+    if !old.is_object() || !new.is_object() {
+        return Err(Error::Parse(
+            "Snapshots must be aggregate JSON objects keyed by path".to_owned(),
+        ));
+    }
+    let added: Vec<&str> = new
+        .entries()
+        .map(|(k, _)| k)
+        .filter(|k| !old.has_key(k))
+        .collect();
+    let removed: Vec<&str> = old
+        .entries()
+        .map(|(k, _)| k)
+        .filter(|k| !new.has_key(k))
+        .collect();
+    let mut changed = JsonValue::new_object();
+    for (path, current) in new.entries() {
+        if !old.has_key(path) {
+            continue;
+        }
+        let previous = &ignore::strip(&old[path], ignore);
+        let current = &ignore::strip(current, ignore);
+        let mut frames = JsonValue::new_object();
+        for (key, value) in changed_frames(current, previous).entries() {
+            let mut change = JsonValue::new_object();
+            change["old"] = previous[key].clone();
+            change["new"] = value.clone();
+            frames[key] = change;
+        }
+        if !frames.is_empty() {
+            changed[path] = frames;
+        }
+    }
+
+    match format {
+        Format::Text => {
+            for path in &added {
+                println!("+ {path}");
+            }
+            for path in &removed {
+                println!("- {path}");
+            }
+            for (path, frames) in changed.entries() {
+                println!("~ {path}");
+                for (key, change) in frames.entries() {
+                    println!(
+                        "    {key}: {} -> {}",
+                        quoted(&change["old"]),
+                        quoted(&change["new"])
+                    );
+                }
+            }
+            println!(
+                "{} added, {} removed, {} changed",
+                added.len(),
+                removed.len(),
+                changed.len()
+            );
+        }
+        Format::Json => {
+            let mut json = JsonValue::new_object();
+            json["added"] = added.into();
+            json["removed"] = removed.into();
+            json["changed"] = changed;
+            println!("{}", json::stringify_pretty(json, 4));
+        }
+    }
+    Ok(())
+}
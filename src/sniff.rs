@@ -0,0 +1,112 @@
+//! Telling what kind of audio a file holds from its first bytes, so files with a missing or wrong
+//! extension are still parsed correctly. The extension is only consulted when the content is
+//! inconclusive.
+
+use crate::paths;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Mp3,
+    Flac,
+    Ogg,
+    Wav,
+    Aiff,
+    Mp4,
+    MonkeysAudio,
+    Musepack,
+}
+
+/// The extensions each format is conventionally stored under
+const EXTENSIONS: &[(&str, Format)] = &[
+    ("mp3", Format::Mp3),
+    ("flac", Format::Flac),
+    ("ogg", Format::Ogg),
+    ("oga", Format::Ogg),
+    ("opus", Format::Ogg),
+    ("wav", Format::Wav),
+    ("aif", Format::Aiff),
+    ("aiff", Format::Aiff),
+    ("aifc", Format::Aiff),
+    ("m4a", Format::Mp4),
+    ("m4b", Format::Mp4),
+    ("mp4", Format::Mp4),
+    ("ape", Format::MonkeysAudio),
+    ("mpc", Format::Musepack),
+];
+
+/// Major brands of ISO media files holding still images rather than audio, such as AVIF art
+const IMAGE_BRANDS: [&[u8; 4]; 6] = [b"avif", b"avis", b"heic", b"heix", b"mif1", b"msf1"];
+
+/// The format the first bytes of a file announce, if any
+fn from_magic(head: &[u8]) -> Option<Format> {
+    let at = |i: usize, magic: &[u8]| head.get(i..i + magic.len()) == Some(magic);
+    if at(0, b"fLaC") {
+        Some(Format::Flac)
+    } else if at(0, b"OggS") {
+        Some(Format::Ogg)
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        Some(Format::Wav)
+    } else if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+        Some(Format::Aiff)
+    } else if at(4, b"ftyp") && !IMAGE_BRANDS.iter().any(|brand| at(8, *brand)) {
+        Some(Format::Mp4)
+    } else if at(0, b"MAC ") {
+        Some(Format::MonkeysAudio)
+    } else if at(0, b"MPCK") || at(0, b"MP+") {
+        Some(Format::Musepack)
+    } else if head.len() >= 2 && head[0] == 0xFF && head[1] & 0xE0 == 0xE0 && head[1] & 0x06 != 0 {
+        // An MPEG audio frame sync with a layer set, which rules out AAC's ADTS headers
+        Some(Format::Mp3)
+    } else {
+        None
+    }
+}
+
+fn from_extension(path: &Path) -> Option<Format> {
+    EXTENSIONS
+        .iter()
+        .find(|(e, _)| paths::has_extension(path, e))
+        .map(|(_, format)| *format)
+}
+
+/// Up to the first 12 bytes from the current position
+fn read_head(file: &mut File) -> Vec<u8> {
+    let mut head = [0u8; 12];
+    let mut len = 0;
+    while len < head.len() {
+        match file.read(&mut head[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+    }
+    head[..len].to_vec()
+}
+
+/// The format of the stream after a leading ID3v2 tag. Tools sometimes prepend one to FLAC and
+/// other streams, but, failing anything else, it means MP3.
+fn after_id3(file: &mut File, header: &[u8]) -> Format {
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |size, b| size << 7 | u64::from(b & 0x7F));
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    file.seek(SeekFrom::Start(10 + size + footer))
+        .ok()
+        .and_then(|_| from_magic(&read_head(file)))
+        .unwrap_or(Format::Mp3)
+}
+
+/// The file's format, judged by its content and failing that by its extension. Files that can't be
+/// read are judged by extension alone, leaving the error to whatever opens them next.
+pub fn format(path: &Path) -> Option<Format> {
+    let Ok(mut file) = File::open(path) else {
+        return from_extension(path);
+    };
+    let head = read_head(&mut file);
+    if head.len() >= 10 && head.starts_with(b"ID3") {
+        return Some(after_id3(&mut file, &head));
+    }
+    from_magic(&head).or_else(|| from_extension(path))
+}
@@ -0,0 +1,101 @@
+//! Replacing part of a file in place, moving the rest of it through a fixed-size buffer so
+//! memory use stays bounded however large the audio is
+
+use crate::{header, Error, StrResult};
+use id3::Tag;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Replaces the first `old_len` bytes of the file with `head`, shifting everything after them
+pub fn splice(file: &mut File, old_len: u64, head: &[u8], buffer_size: usize) -> io::Result<()> {
+    splice_at(file, 0, old_len, head, buffer_size)
+}
+
+/// Replaces `old_len` bytes at `start` with `data`, shifting everything after them
+pub fn splice_at(
+    file: &mut File,
+    start: u64,
+    old_len: u64,
+    data: &[u8],
+    buffer_size: usize,
+) -> io::Result<()> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let old_end = start + old_len;
+    let new_end = start + data.len() as u64;
+    let mut buf = vec![0u8; buffer_size.max(1)];
+    if new_end > old_end {
+        // Growing: extend first, then move the tail backwards from its end so nothing is overwritten before it's copied
+        let grow = new_end - old_end;
+        file.set_len(file_len + grow)?;
+        let mut end = file_len;
+        while end > old_end {
+            let chunk = (end - old_end).min(buf.len() as u64);
+            let from = end - chunk;
+            file.seek(SeekFrom::Start(from))?;
+            file.read_exact(&mut buf[..chunk as usize])?;
+            file.seek(SeekFrom::Start(from + grow))?;
+            file.write_all(&buf[..chunk as usize])?;
+            end = from;
+        }
+    } else if new_end < old_end {
+        let shrink = old_end - new_end;
+        let mut from = old_end;
+        while from < file_len {
+            let chunk = (file_len - from).min(buf.len() as u64);
+            file.seek(SeekFrom::Start(from))?;
+            file.read_exact(&mut buf[..chunk as usize])?;
+            file.seek(SeekFrom::Start(from - shrink))?;
+            file.write_all(&buf[..chunk as usize])?;
+            from += chunk;
+        }
+        file.set_len(file_len - shrink)?;
+    }
+    file.seek(SeekFrom::Start(start))?;
+    file.write_all(data)?;
+    file.flush()
+}
+
+/// The tag encoded as ID3v2.4, with how many bytes of the file it replaces. When it fits in the
+/// space of the existing tag and its padding it is padded to fill that space exactly, so writing
+/// it leaves the audio where it is rather than moving the whole file. With `crc` set the tag gets
+/// an extended header holding its CRC.
+pub fn encode_id3(path: &Path, tag: &Tag, crc: bool) -> StrResult<(u64, Vec<u8>)> {
+    let (tag_len, padding) = header::tag_span(path)?;
+    let old_len = tag_len + padding;
+    let mut encoded = vec![];
+    if let Err(e) = tag.write_to(&mut encoded, id3::Version::Id3v24) {
+        return Err(Error::Other(format!("Could not encode tags: {e}")));
+    }
+    if crc {
+        header::add_crc(&mut encoded);
+    }
+    // The size in the header covers padding, as long as there's no footer after it
+    let has_footer = encoded.get(5).is_some_and(|flags| flags & 0x10 != 0);
+    let size = old_len.saturating_sub(10);
+    if (encoded.len() as u64) < old_len && encoded.len() >= 10 && !has_footer && size < 1 << 28 {
+        encoded.resize(old_len as usize, 0);
+        for (i, byte) in encoded[6..10].iter_mut().enumerate() {
+            *byte = (size >> (7 * (3 - i)) & 0x7F) as u8;
+        }
+    }
+    if crc {
+        header::fill_crc(&mut encoded);
+    }
+    Ok((old_len, encoded))
+}
+
+/// Writes the tag as ID3v2.4 over the file's existing tag and padding
+pub fn replace_tag(
+    path: &Path,
+    file: &mut File,
+    tag: &Tag,
+    buffer_size: usize,
+    crc: bool,
+) -> StrResult<()> {
+    let (old_len, encoded) = encode_id3(path, tag, crc)?;
+    splice(file, old_len, &encoded, buffer_size)
+        .map_err(|e| Error::Io(format!("Could not write tags: {e}")))
+}
@@ -0,0 +1,106 @@
+//! Expansion of `{FRAME}` placeholders from tag values, as used for naming files after their tags
+
+use crate::{frames, position, Error, StrResult};
+use id3::{Content, Frame, Tag, TagLike};
+use json::JsonValue;
+
+/// Expands each `{KEY}` in the template with the value `lookup` gives for it, or nothing if it has
+/// none. Literal braces are written `{{` and `}}`.
+pub fn render(template: &str, lookup: impl Fn(&str) -> Option<String>) -> StrResult<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut key = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    key.push(c);
+                }
+                if !closed {
+                    return Err(Error::Parse(format!(
+                        "Unclosed placeholder in template \"{template}\""
+                    )));
+                }
+                if key.is_empty() {
+                    return Err(Error::Parse(format!(
+                        "Empty placeholder in template \"{template}\""
+                    )));
+                }
+                if let Some(value) = lookup(&key) {
+                    out.push_str(&value);
+                }
+            }
+            '}' => {
+                return Err(Error::Parse(format!(
+                    "Unmatched '}}' in template \"{template}\""
+                )))
+            }
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+/// Looks a key up in an extracted tag object, for use with [`render`]
+pub fn json_lookup(json: &JsonValue) -> impl Fn(&str) -> Option<String> + '_ {
+    |key| match &json[key] {
+        value if value.is_object() && position::FRAMES.contains(&key) => {
+            position::from_json(value).ok().map(|p| p.to_string())
+        }
+        value => frames::joined_text(value, "; "),
+    }
+}
+
+/// The text of a frame, with `TXXX:DESCRIPTION` naming a user-defined text frame
+pub fn frame_text(tag: &Tag, key: &str) -> Option<String> {
+    if let Some(description) = key.strip_prefix("TXXX:") {
+        return tag
+            .extended_texts()
+            .find(|t| t.description == description)
+            .map(|t| t.value.clone());
+    }
+    match tag.get(key)?.content() {
+        Content::Text(text) => Some(text.replace('\0', "; ")),
+        Content::Link(link) => Some(link.clone()),
+        _ => None,
+    }
+}
+
+/// Expands placeholders in the text of every frame being applied. A placeholder takes the value
+/// given for that frame in the same document, or failing that the file's existing tag. Values are
+/// taken as written, so a placeholder is never expanded twice.
+pub fn expand_tag(tag: &mut Tag, existing: &Tag) -> StrResult<()> {
+    let lookup = |key: &str| frame_text(tag, key).or_else(|| frame_text(existing, key));
+    let mut expanded = vec![];
+    for frame in tag.frames() {
+        let frame = match frame.content() {
+            Content::Text(text) if text.contains(['{', '}']) => {
+                Frame::text(frame.id(), render(text, lookup)?)
+            }
+            Content::ExtendedText(text) if text.value.contains(['{', '}']) => {
+                let mut text = text.clone();
+                text.value = render(&text.value, lookup)?;
+                Frame::with_content("TXXX", Content::ExtendedText(text))
+            }
+            _ => continue,
+        };
+        expanded.push(frame);
+    }
+    for frame in expanded {
+        tag.add_frame(frame);
+    }
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! Fixtures shared by the unit tests: scratch directories and the smallest files each format
+//! recognises
+
+use id3::frame::{Picture, PictureType};
+use id3::{Tag, TagLike};
+use std::path::{Path, PathBuf};
+
+/// An empty directory for one test, under the system's temporary directory
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tag2json-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A few silent MPEG-1 layer III frames at 128 kbit/s and 44.1 kHz, with no tag
+pub fn mp3_audio() -> Vec<u8> {
+    let mut frame = vec![0xFF, 0xFB, 0x90, 0x64];
+    frame.resize(417, 0);
+    frame.repeat(4)
+}
+
+/// Writes an untagged MP3 to the path
+pub fn write_mp3(path: &Path) {
+    std::fs::write(path, mp3_audio()).unwrap();
+}
+
+/// The start of a JPEG, enough to be recognised as one
+pub fn jpeg() -> Vec<u8> {
+    vec![0xFF, 0xD8, 0xFF, 0xE0, 0, 16, b'J', b'F', b'I', b'F', 0]
+}
+
+/// A PNG signature and header for a 1x1 image
+pub fn png() -> Vec<u8> {
+    let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    data.extend([0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]);
+    data.extend([0; 4]);
+    data
+}
+
+/// The names of the files in a directory, sorted
+pub fn file_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = dir
+        .read_dir()
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// A tag with the frames every container can hold: text, a track number with its total and a
+/// front cover
+pub fn tag() -> Tag {
+    let mut tag = Tag::new();
+    tag.set_title("Title");
+    tag.set_artist("Artist");
+    tag.set_album("Album");
+    tag.set_text("TRCK", "3/12");
+    tag.add_frame(Picture {
+        mime_type: "image/png".to_owned(),
+        picture_type: PictureType::CoverFront,
+        description: String::new(),
+        data: png(),
+    });
+    tag
+}
+
+/// Asserts that a tag read back holds what [`tag`] wrote
+pub fn assert_round_trip(read: &Tag) {
+    assert_eq!(read.title(), Some("Title"));
+    assert_eq!(read.artist(), Some("Artist"));
+    assert_eq!(read.album(), Some("Album"));
+    assert_eq!(
+        read.get("TRCK").and_then(|f| f.content().text()),
+        Some("3/12")
+    );
+    let pictures: Vec<&Picture> = read.pictures().collect();
+    assert_eq!(pictures.len(), 1);
+    assert_eq!(pictures[0].data, png());
+    assert_eq!(pictures[0].picture_type, PictureType::CoverFront);
+}
+
+/// Calls `check` on a file holding each prefix of `data` shorter than `up_to` bytes
+pub fn each_truncation(path: &Path, data: &[u8], up_to: usize, check: impl Fn(&Path, usize)) {
+    for len in 0..up_to.min(data.len()) {
+        std::fs::write(path, &data[..len]).unwrap();
+        check(path, len);
+    }
+}
+
+/// Calls `check` on a file holding `data` with each of its first `up_to` bytes in turn replaced
+/// by values likely to upset a parser: zero, the top bit alone and all bits set
+pub fn each_corruption(path: &Path, data: &[u8], up_to: usize, check: impl Fn(&Path)) {
+    for pos in 0..up_to.min(data.len()) {
+        for value in [0x00, 0x80, 0xFF] {
+            let mut corrupt = data.to_vec();
+            corrupt[pos] = value;
+            std::fs::write(path, &corrupt).unwrap();
+            check(path);
+        }
+    }
+}
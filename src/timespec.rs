@@ -0,0 +1,106 @@
+//! Parsing the dates and lengths of time given to filters such as `--modified-since` and `--min-duration`,
+//! and writing dates in the form feeds use
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Days since the Unix epoch of a civil date, after Howard Hinnant's algorithm
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The civil date of a day since the Unix epoch, the inverse of [`days_from_civil`]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// A time in the RFC 2822 form RSS dates use, such as `Fri, 05 Jan 2024 12:00:00 +0000`
+pub fn rfc2822(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let days = secs.div_euclid(86400);
+    let of_day = secs.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {day:02} {} {year} {:02}:{:02}:{:02} +0000",
+        DAYS[days.rem_euclid(7) as usize],
+        MONTHS[(month - 1) as usize],
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60
+    )
+}
+
+/// A UTC date as `YYYY-MM-DD`, optionally followed by a time as `HH:MM` or `HH:MM:SS`
+pub fn parse_date(s: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("{s:?} is not a date like 2024-01-01 or 2024-01-01T12:00:00");
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+    let numbers = |part: &str, sep| -> Result<Vec<i64>, String> {
+        part.split(sep)
+            .map(|n| n.parse().map_err(|_| invalid()))
+            .collect()
+    };
+    let [year, month, day] = numbers(date, '-')?[..] else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    let (hour, minute, second) = match time.map(|t| numbers(t, ':')).transpose()?.as_deref() {
+        None => (0, 0, 0),
+        Some(&[h, m]) => (h, m, 0),
+        Some(&[h, m, s]) => (h, m, s),
+        Some(_) => return Err(invalid()),
+    };
+    if hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    let secs = u64::try_from(secs).map_err(|_| format!("{s:?} is before 1970"))?;
+    Ok(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// A length of time such as `90m`, `12h`, `7d` or `2w`. A bare number is in seconds
+pub fn parse_age(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("{s:?} is not an age like 12h or 7d"))?;
+    let unit = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => {
+            return Err(format!(
+                "Unknown unit {unit:?} in {s:?}; use s, m, h, d or w"
+            ))
+        }
+    };
+    Ok(Duration::from_secs(number * unit))
+}
@@ -0,0 +1,116 @@
+//! Typographic house styles: straight ASCII punctuation, or curly quotes, proper dashes and ellipses
+
+use clap::ValueEnum;
+use id3::{Content, Frame, Tag, TagLike};
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Style {
+    /// Plain ASCII: ' " - and ...
+    Straight,
+    /// Typographic: ‘ ’ “ ” – and …
+    Curly,
+}
+
+/// Turns curly quotes and primes into ASCII ones, leaving everything else alone
+fn straighten_quotes(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '‘' | '’' | '‚' | '‛' | '′' => '\'',
+            '“' | '”' | '„' | '‟' | '″' => '"',
+            c => c,
+        })
+        .collect()
+}
+
+fn straighten(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in straighten_quotes(text).chars() {
+        match c {
+            '‐' | '‑' | '‒' | '–' | '—' | '―' | '−' => out.push('-'),
+            '…' => out.push_str("..."),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Whether a quote following this character opens rather than closes
+fn opens_after(prev: Option<char>) -> bool {
+    match prev {
+        None => true,
+        Some(c) => c.is_whitespace() || "([{‘“-–—".contains(c),
+    }
+}
+
+fn curl(text: &str) -> String {
+    // Quotes are straightened first so ones already curled, perhaps the wrong way round, come out
+    // matching the rest. Dashes are kept, as a hyphen can't be told from a straightened dash
+    let text = straighten_quotes(text)
+        .replace("...", "…")
+        .replace("--", "—")
+        .replace(" - ", " – ");
+    let mut out = String::with_capacity(text.len());
+    let mut prev = None;
+    for c in text.chars() {
+        match c {
+            '\'' if opens_after(prev) => out.push('‘'),
+            '\'' => out.push('’'),
+            '"' if opens_after(prev) => out.push('“'),
+            '"' => out.push('”'),
+            c => out.push(c),
+        }
+        prev = Some(c);
+    }
+    out
+}
+
+/// Restyles the text. Curly turns `--` into an em dash and ` - ` into an en dash, leaving dashes
+/// already in place as they are
+pub fn apply_style(text: &str, style: Style) -> String {
+    match style {
+        Style::Straight => straighten(text),
+        Style::Curly => curl(text),
+    }
+}
+
+/// Restyles the text frames named in `frames`, or every text frame if it's empty
+pub fn restyle_tag(tag: &mut Tag, style: Style, frames: &[String]) {
+    let changes: Vec<(String, String)> = tag
+        .frames()
+        .filter(|f| frames.is_empty() || frames.iter().any(|id| id == f.id()))
+        .filter_map(|f| {
+            let text = f.content().text()?;
+            let styled = apply_style(text, style);
+            (styled != text).then(|| (f.id().to_owned(), styled))
+        })
+        .collect();
+    for (id, text) in changes {
+        tag.add_frame(Frame::with_content(&id, Content::Text(text)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curling_normalises_first() {
+        let curly = |text| apply_style(text, Style::Curly);
+        assert_eq!(curly("Wait -- what?"), "Wait — what?");
+        assert_eq!(curly("Live - 1999"), "Live – 1999");
+        assert_eq!(curly("Now—or never"), "Now—or never");
+        assert_eq!(curly("Well-known"), "Well-known");
+        assert_eq!(curly("Hmm..."), "Hmm…");
+        // Quotes already curled, or curled the wrong way, match the ones around them
+        assert_eq!(curly("“Don't” say \"it's\""), "“Don’t” say “it’s”");
+        assert_eq!(curly("”Backwards“ and 'mixed’"), "“Backwards” and ‘mixed’");
+        assert_eq!(curly("(“quoted\")"), "(“quoted”)");
+    }
+
+    #[test]
+    fn straightening_is_plain_ascii() {
+        let straight = |text| apply_style(text, Style::Straight);
+        assert_eq!(straight("“Don’t” – now—…"), "\"Don't\" - now-...");
+        assert_eq!(straight(&apply_style("'a' -- b", Style::Curly)), "'a' - b");
+    }
+}
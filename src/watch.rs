@@ -0,0 +1,256 @@
+//! A long-running sync daemon: applies JSON sidecars to their audio files whenever they change,
+//! with an optional HTTP listener exposing `/healthz` and Prometheus `/metrics` for monitoring
+
+use crate::{album, check, keys, paths, Error, StrResult, WriteOpts};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Default)]
+struct Metrics {
+    processed: AtomicU64,
+    errors: AtomicU64,
+    queue_depth: AtomicU64,
+    scans: AtomicU64,
+    last_scan: AtomicU64,
+}
+
+impl Metrics {
+    fn render(&self) -> String {
+        let metrics = [
+            (
+                "files_processed_total",
+                "counter",
+                "Files whose tags were written",
+                &self.processed,
+            ),
+            (
+                "errors_total",
+                "counter",
+                "Sidecars that could not be applied",
+                &self.errors,
+            ),
+            (
+                "queue_depth",
+                "gauge",
+                "Changed sidecars waiting to be applied",
+                &self.queue_depth,
+            ),
+            (
+                "scans_total",
+                "counter",
+                "Completed scans of the watched paths",
+                &self.scans,
+            ),
+            (
+                "last_scan_timestamp_seconds",
+                "gauge",
+                "Unix time the last scan finished",
+                &self.last_scan,
+            ),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            out.push_str(&format!(
+                "# HELP tag2json_{name} {help}\n# TYPE tag2json_{name} {kind}\ntag2json_{name} {}\n",
+                value.load(Ordering::Relaxed)
+            ));
+        }
+        out
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// How long a client may take to send its request or read the response before it is dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest request line read, far more than the paths served need
+const MAX_REQUEST_LINE: u64 = 8192;
+
+fn respond(mut stream: TcpStream, metrics: &Metrics, stale_after: u64) {
+    let timeouts = stream
+        .set_read_timeout(Some(CLIENT_TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(CLIENT_TIMEOUT)));
+    let mut request_line = String::new();
+    if timeouts.is_err()
+        || BufReader::new(&stream)
+            .take(MAX_REQUEST_LINE)
+            .read_line(&mut request_line)
+            .is_err()
+    {
+        return;
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match path {
+        "/healthz" => {
+            let last = metrics.last_scan.load(Ordering::Relaxed);
+            if last > 0 && now().saturating_sub(last) <= stale_after {
+                ("200 OK", "ok\n".to_owned())
+            } else {
+                ("503 Service Unavailable", "no recent scan\n".to_owned())
+            }
+        }
+        "/metrics" => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "not found\n".to_owned()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    // The client going away mid-response is no concern of ours
+    let _ = stream.write_all(response.as_bytes());
+}
+
+fn serve(listener: TcpListener, metrics: Arc<Metrics>, stale_after: u64) {
+    // A thread per connection, so a client that connects and sends nothing can't hold up a health
+    // check behind it
+    for stream in listener.incoming().flatten() {
+        let metrics = Arc::clone(&metrics);
+        std::thread::spawn(move || respond(stream, &metrics, stale_after));
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+/// The sidecars below the watched paths, each with the audio files it applies to
+fn find_sidecars(roots: &[PathBuf]) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let mut files = vec![];
+    check::all_files(roots, &mut files);
+    let mut sidecars = vec![];
+    for file in files
+        .into_iter()
+        .filter(|f| paths::has_extension(f, "json"))
+    {
+        if file.file_name() == Some(OsStr::new(album::ALBUM_FILE)) {
+            let dir = file.parent().unwrap_or(Path::new("")).to_owned();
+            sidecars.push((file, paths::collect_audio_files(&[dir], false)));
+        } else if let Some(audio) = check::audio_candidates(&file).into_iter().next() {
+            sidecars.push((file, vec![audio]));
+        }
+    }
+    sidecars
+}
+
+/// Applies a sidecar, per-track or album, returning how many files were written
+fn apply(sidecar: &Path, key_map: &keys::KeyMap, opts: &WriteOpts) -> StrResult<u64> {
+    let json = key_map.canonicalize(crate::read_json_file(sidecar)?);
+    let dir = sidecar.parent().unwrap_or(Path::new(""));
+    let planned = if sidecar.file_name() == Some(OsStr::new(album::ALBUM_FILE)) {
+        album::plan_album(dir, &json)
+    } else {
+        let audio = check::audio_candidates(sidecar).into_iter().next();
+        audio.into_iter().map(|a| (a, Ok(json.clone()))).collect()
+    };
+    let mut written = 0;
+    for (path, json) in planned {
+        let tag = crate::json_to_tag(&json?, dir)?;
+        crate::write_tag(&path, &tag, opts)?;
+        println!(
+            "applied {} to {}",
+            sidecar.to_string_lossy(),
+            path.to_string_lossy()
+        );
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Scans the paths every `interval`, applying any sidecar that is newer than its audio or has
+/// changed since it was last applied. Runs until killed.
+pub fn watch(
+    roots: &[PathBuf],
+    interval: Duration,
+    listen: Option<&str>,
+    keys: Option<&str>,
+    opts: &WriteOpts,
+) -> StrResult<()> {
+    let key_map = keys::KeyMap::load(keys)?;
+    let metrics = Arc::new(Metrics::default());
+    if let Some(address) = listen {
+        let listener = TcpListener::bind(address)
+            .map_err(|e| Error::Io(format!("Cannot listen on {address}: {e}")))?;
+        let metrics = Arc::clone(&metrics);
+        // Healthy means a scan has finished within the last few intervals
+        let stale_after = interval.as_secs().max(1) * 3 + 60;
+        std::thread::spawn(move || serve(listener, metrics, stale_after));
+        eprintln!("Serving /healthz and /metrics on {address}");
+    }
+
+    let mut applied: HashMap<PathBuf, SystemTime> = HashMap::new();
+    loop {
+        let mut queue = vec![];
+        for (sidecar, audio) in find_sidecars(roots) {
+            let Some(changed) = modified(&sidecar) else {
+                continue;
+            };
+            let pending = match applied.get(&sidecar) {
+                Some(last) => changed > *last,
+                None => audio
+                    .iter()
+                    .any(|a| modified(a).is_some_and(|m| changed > m)),
+            };
+            if pending {
+                queue.push((sidecar, changed));
+            } else {
+                applied.entry(sidecar).or_insert(changed);
+            }
+        }
+        metrics
+            .queue_depth
+            .store(queue.len() as u64, Ordering::Relaxed);
+        for (sidecar, changed) in queue {
+            match apply(&sidecar, &key_map, opts) {
+                Ok(n) => {
+                    metrics.processed.fetch_add(n, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    eprintln!("Could not apply {}: {e}", sidecar.to_string_lossy());
+                    metrics.errors.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            // Failures aren't retried until the sidecar is edited again
+            applied.insert(sidecar, changed);
+            metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
+        }
+        metrics.scans.fetch_add(1, Ordering::Relaxed);
+        metrics.last_scan.store(now(), Ordering::Relaxed);
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silent_clients_dont_block_health_checks() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let metrics = Arc::new(Metrics::default());
+        metrics.last_scan.store(now(), Ordering::Relaxed);
+        std::thread::spawn(move || serve(listener, metrics, 60));
+
+        let _silent = TcpStream::connect(address).unwrap();
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        client.write_all(b"GET /healthz HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("ok\n"));
+    }
+}